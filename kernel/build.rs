@@ -0,0 +1,106 @@
+//! Makes the initial ramdisk linked into the kernel, see `fs::initrd`: the
+//! archive named by the `FERROUS_INITRD` environment variable, or the `initrd`
+//! directory packed as a cpio `newc` archive.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Types of files, in their mode.
+const S_IFMT: u32 = 0o170_000;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFREG: u32 = 0o100_000;
+const S_IFLNK: u32 = 0o120_000;
+
+fn main() -> io::Result<()> {
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("initrd");
+    println!("cargo:rerun-if-env-changed=FERROUS_INITRD");
+
+    if let Some(archive) = env::var_os("FERROUS_INITRD") {
+        println!("cargo:rerun-if-changed={}", Path::new(&archive).display());
+        fs::copy(archive, out)?;
+        return Ok(());
+    }
+
+    let root = Path::new(&env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("initrd");
+    println!("cargo:rerun-if-changed={}", root.display());
+    let mut archive = Vec::new();
+    let mut ino = 0;
+    if root.is_dir() {
+        pack(&root, "", &mut archive, &mut ino)?;
+    }
+    entry(&mut archive, 0, "TRAILER!!!", 0, b"");
+    fs::write(out, archive)
+}
+
+/// Appends the entries of the directory `dir`, named from `prefix`.
+fn pack(dir: &Path, prefix: &str, archive: &mut Vec<u8>, ino: &mut u32) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for file in entries {
+        let name = file.file_name().into_string().map_err(|name| {
+            let message = format!("{:?} is not UTF-8", name);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })?;
+        let name = format!("{}{}", prefix, name);
+        let kind = file.file_type()?;
+        *ino += 1;
+
+        if kind.is_dir() {
+            entry(archive, *ino, &name, S_IFDIR | 0o755, b"");
+            pack(&file.path(), &format!("{}/", name), archive, ino)?;
+        } else if kind.is_symlink() {
+            let target = fs::read_link(file.path())?;
+            let target = target.to_string_lossy();
+            entry(archive, *ino, &name, S_IFLNK | 0o777, target.as_bytes());
+        } else {
+            entry(
+                archive,
+                *ino,
+                &name,
+                S_IFREG | 0o644,
+                &fs::read(file.path())?,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Appends a `newc` entry, its header and name, then its data, each padded
+/// to 4 bytes.
+fn entry(archive: &mut Vec<u8>, ino: u32, name: &str, mode: u32, data: &[u8]) {
+    let nlink = if mode & S_IFMT == S_IFDIR { 2 } else { 1 };
+    let fields = [
+        ino,
+        mode,
+        0,
+        0,
+        nlink,
+        0,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+
+    archive.extend_from_slice(b"070701");
+    for field in &fields {
+        archive.extend_from_slice(format!("{:08X}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    pad(archive);
+    archive.extend_from_slice(data);
+    pad(archive);
+}
+
+fn pad(archive: &mut Vec<u8>) {
+    while archive.len() % 4 != 0 {
+        archive.push(0);
+    }
+}
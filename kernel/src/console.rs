@@ -0,0 +1,261 @@
+//! # Console output
+//!
+//! Printing path behind the printing macros, safe to use from interrupt,
+//! exception and panic context.
+//!
+//! Output normally goes through `VGA` and `SERIAL1`, under their locks. The
+//! locks are never waited for: if one is already held, which on a single core
+//! with the interrupts disabled means a fault interrupted whoever holds it, or
+//! if printing re-entered itself, the text goes through a lock-free fallback
+//! writer straight to the hardware instead.
+//!
+//! Once the kernel is going down (panic, double fault), `enter_emergency`
+//! makes every print use the fallback writers, since the state behind the
+//! locks cannot be trusted anymore.
+
+use core::{
+    fmt::{self, Write},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
+
+use spin::{Mutex, MutexGuard};
+use x86_64::instructions::interrupts;
+
+use crate::{
+    init::vga::VGA,
+    io::{Mmio, MmioRegion},
+    uart::m16550::{PortAddress, SerialPort},
+    vga::{Color, Vga, COLS, ROWS},
+};
+
+/// Physical (and identity mapped) address of the VGA text buffer.
+const VGA_BUFFER: usize = 0xb8000;
+
+/// Set once the kernel is going down.
+static EMERGENCY: AtomicBool = AtomicBool::new(false);
+
+/// Number of consoles currently locked through `lock`.
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Column of the fallback VGA writer, that always writes on the last row.
+static FALLBACK_COLUMN: AtomicUsize = AtomicUsize::new(0);
+
+/// Attribute byte of the fallback VGA writer, white on black by default.
+static FALLBACK_ATTRIBUTE: AtomicU8 = AtomicU8::new(Color::White as u8);
+
+/// Lock-free writers, used when a console cannot be locked.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fallback {
+    /// Writes straight to the VGA text buffer, on its last row.
+    Vga,
+    /// Writes straight to COM1.
+    Serial,
+}
+
+impl Fallback {
+    /// Prints without locking anything.
+    pub fn print(self, args: fmt::Arguments) {
+        let _ = match self {
+            Fallback::Vga => FallbackVga::new().write_fmt(args),
+            // Only a pair of port numbers, nothing is shared with `SERIAL1`.
+            Fallback::Serial => unsafe { SerialPort::new(PortAddress::COM1) }.write_fmt(args),
+        };
+    }
+}
+
+/// Device the console prints to.
+pub trait Console: Write {
+    /// Writer used when the device cannot be locked.
+    const FALLBACK: Fallback;
+
+    /// Makes what was written visible, for devices that buffer it.
+    fn flush(&mut self) {}
+}
+
+impl<M: Mmio> Console for Vga<M> {
+    const FALLBACK: Fallback = Fallback::Vga;
+
+    fn flush(&mut self) {
+        Vga::flush(self);
+    }
+}
+
+impl Console for SerialPort {
+    const FALLBACK: Fallback = Fallback::Serial;
+}
+
+/// A locked console. Printing again while it is held uses the fallback
+/// writers.
+pub struct ConsoleGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T> Deref for ConsoleGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for ConsoleGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for ConsoleGuard<'_, T> {
+    fn drop(&mut self) {
+        DEPTH.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Locks a console without waiting.
+///
+/// Returns `None` if the lock is held, if another console is locked by the
+/// same path (re-entrancy) or in emergency mode.
+pub fn lock<T>(console: &Mutex<T>) -> Option<ConsoleGuard<'_, T>> {
+    if in_emergency() {
+        return None;
+    }
+
+    if DEPTH.fetch_add(1, Ordering::SeqCst) > 0 {
+        DEPTH.fetch_sub(1, Ordering::SeqCst);
+        return None;
+    }
+
+    match console.try_lock() {
+        Some(guard) => Some(ConsoleGuard { guard }),
+        None => {
+            DEPTH.fetch_sub(1, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+/// Prints to a console, flushing it, or to its fallback writer if it cannot
+/// be locked.
+pub fn print<C: Console>(console: &Mutex<C>, args: fmt::Arguments) {
+    interrupts::without_interrupts(|| match lock(console) {
+        Some(mut console) => {
+            let _ = console.write_fmt(args);
+            console.flush();
+        }
+        None => C::FALLBACK.print(args),
+    });
+}
+
+/// Changes the foreground color of `VGA` and of its fallback writer.
+pub fn set_foreground(color: Color) {
+    interrupts::without_interrupts(|| {
+        if let Some(mut vga) = lock(&*VGA) {
+            vga.set_foreground(color);
+        }
+    });
+
+    let attribute = FALLBACK_ATTRIBUTE.load(Ordering::Relaxed);
+    FALLBACK_ATTRIBUTE.store((attribute & 0xF0) | color as u8, Ordering::Relaxed);
+}
+
+/// Changes the background color of `VGA` and of its fallback writer.
+pub fn set_background(color: Color) {
+    interrupts::without_interrupts(|| {
+        if let Some(mut vga) = lock(&*VGA) {
+            vga.set_background(color);
+        }
+    });
+
+    let attribute = FALLBACK_ATTRIBUTE.load(Ordering::Relaxed);
+    FALLBACK_ATTRIBUTE.store((attribute & 0x0F) | (color as u8) << 4, Ordering::Relaxed);
+}
+
+/// Makes every print use the fallback writers from now on.
+///
+/// Meant for when the kernel is going down, it cannot be undone.
+pub fn enter_emergency() {
+    EMERGENCY.store(true, Ordering::SeqCst);
+}
+
+/// Returns `true` once `enter_emergency` was called.
+pub fn in_emergency() -> bool {
+    EMERGENCY.load(Ordering::SeqCst)
+}
+
+/// Writes straight to the VGA text buffer, scrolling it up on line breaks.
+struct FallbackVga {
+    buffer: MmioRegion,
+}
+
+impl FallbackVga {
+    fn new() -> FallbackVga {
+        // Only accessed byte by byte, sharing it with `VGA` is harmless.
+        let buffer = unsafe { MmioRegion::new(VGA_BUFFER, ROWS * COLS * 2) };
+        FallbackVga { buffer }
+    }
+
+    fn new_line(&mut self) {
+        for i in 0..(ROWS - 1) * COLS * 2 {
+            let byte = self.buffer.read(i + COLS * 2);
+            self.buffer.write(i, byte);
+        }
+
+        let attribute = FALLBACK_ATTRIBUTE.load(Ordering::Relaxed);
+        for i in (ROWS - 1) * COLS..ROWS * COLS {
+            self.buffer.write(i * 2, b' ');
+            self.buffer.write(i * 2 + 1, attribute);
+        }
+
+        FALLBACK_COLUMN.store(0, Ordering::Relaxed);
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.new_line();
+            return;
+        }
+
+        let column = FALLBACK_COLUMN.load(Ordering::Relaxed).min(COLS - 1);
+        let cell = ((ROWS - 1) * COLS + column) * 2;
+        self.buffer.write(cell, byte);
+        self.buffer
+            .write(cell + 1, FALLBACK_ATTRIBUTE.load(Ordering::Relaxed));
+
+        if column + 1 == COLS {
+            self.new_line();
+        } else {
+            FALLBACK_COLUMN.store(column + 1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Write for FallbackVga {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn reentrancy() {
+    let first = Mutex::new(0);
+    let second = Mutex::new(0);
+
+    {
+        let _guard = lock(&first).unwrap();
+        // Same lock, or any other console while one is held
+        assert!(lock(&first).is_none());
+        assert!(lock(&second).is_none());
+    }
+    assert!(lock(&second).is_some());
+
+    // A lock held by someone else is not waited for
+    let held = first.lock();
+    assert!(lock(&first).is_none());
+    drop(held);
+    assert!(lock(&first).is_some());
+}
@@ -0,0 +1,223 @@
+//! Loading of executables in a new address space.
+
+use core::mem;
+
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+use super::{Elf, ProgramHeader, PAGE_SIZE, PF_W, PF_X, PROGRAM_HEADER_SIZE};
+use crate::{
+    mem::{AddressSpace, FRAME_SIZE},
+    user::USER_END,
+};
+
+/// Top of the stack of user programs, leaving the last page of user memory
+/// unmapped.
+pub const STACK_TOP: u64 = USER_END - FRAME_SIZE;
+
+/// Size of the stack of user programs, in bytes.
+pub const STACK_SIZE: u64 = 16 * FRAME_SIZE;
+
+/// Types of the entries of the auxiliary vector.
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+const WORD: u64 = mem::size_of::<u64>() as u64;
+
+/// A program loaded in its own address space, ready to run, see
+/// `process::spawn`.
+#[derive(Debug)]
+pub struct Image {
+    space: AddressSpace,
+    entry: VirtAddr,
+    stack: VirtAddr,
+    thread_pointer: Option<VirtAddr>,
+}
+
+impl Image {
+    /// Address space the program is loaded in.
+    pub fn space(&self) -> &AddressSpace {
+        &self.space
+    }
+
+    /// Address of the first instruction of the program.
+    pub fn entry(&self) -> VirtAddr {
+        self.entry
+    }
+
+    /// Stack pointer at the entry point, where the argument count is.
+    pub fn stack(&self) -> VirtAddr {
+        self.stack
+    }
+
+    /// Thread pointer, loaded in the FS base, if the program has thread local
+    /// storage.
+    pub fn thread_pointer(&self) -> Option<VirtAddr> {
+        self.thread_pointer
+    }
+
+    /// Gives the address space up, to the process running the program.
+    pub fn into_space(self) -> AddressSpace {
+        self.space
+    }
+}
+
+/// Loads `elf` in a new address space, with a stack holding the arguments
+/// `argv`, the environment `envp` and the auxiliary vector.
+///
+/// The segments are mapped with the permissions of their flags, readable
+/// always, and the thread local storage right after the last one.
+pub fn load(elf: &Elf, argv: &[&str], envp: &[&str]) -> Result<Image, &'static str> {
+    let mut space = AddressSpace::new()?;
+
+    let mut end = 0;
+    for header in elf.segments().filter(|header| header.mem_size > 0) {
+        load_segment(&mut space, elf, &header)?;
+        end = end.max(header.end());
+    }
+
+    let thread_pointer = match elf.tls() {
+        Some(tls) => Some(load_tls(&mut space, elf, &tls, align_up(end, PAGE_SIZE))?),
+        None => None,
+    };
+    let stack = push_arguments(&mut space, elf, argv, envp)?;
+
+    Ok(Image {
+        space,
+        entry: VirtAddr::new(elf.entry()),
+        stack,
+        thread_pointer,
+    })
+}
+
+/// Maps the pages of a `PT_LOAD` segment and copies its contents, the rest
+/// staying zeroed.
+fn load_segment(
+    space: &mut AddressSpace,
+    elf: &Elf,
+    header: &ProgramHeader,
+) -> Result<(), &'static str> {
+    let mut flags = PageTableFlags::empty();
+    if header.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if header.flags & PF_X == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    map_range(space, header.vaddr, header.end(), flags)?;
+    space.write(VirtAddr::new(header.vaddr), elf.data(header))
+}
+
+/// Maps the thread local storage from `base`, as x86_64 lays it out: the
+/// block initialized from `tls` ends at the thread pointer, that points to
+/// itself. Returns the thread pointer.
+fn load_tls(
+    space: &mut AddressSpace,
+    elf: &Elf,
+    tls: &ProgramHeader,
+    base: u64,
+) -> Result<VirtAddr, &'static str> {
+    let block_size = align_up(tls.mem_size, tls.align.max(1));
+    let thread_pointer = base + block_size;
+    if thread_pointer + WORD > STACK_TOP - STACK_SIZE {
+        return Err("No room for the thread local storage");
+    }
+
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    map_range(space, base, thread_pointer + WORD, flags)?;
+    space.write(VirtAddr::new(base), elf.data(tls))?;
+    space.write(VirtAddr::new(thread_pointer), &thread_pointer.to_le_bytes())?;
+
+    Ok(VirtAddr::new(thread_pointer))
+}
+
+/// Maps the stack, then lays out what the System V ABI expects at the entry
+/// point: from the stack pointer up, the argument count, the arguments and the
+/// environment, each ending with a null pointer, then the auxiliary vector.
+/// The strings are at the top of the stack. Returns the stack pointer.
+fn push_arguments(
+    space: &mut AddressSpace,
+    elf: &Elf,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<VirtAddr, &'static str> {
+    let bottom = STACK_TOP - STACK_SIZE;
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    map_range(space, bottom, STACK_TOP, flags)?;
+
+    let auxv = [
+        (AT_PHDR, elf.program_headers_address()),
+        (AT_PHENT, Some(PROGRAM_HEADER_SIZE as u64)),
+        (AT_PHNUM, Some(elf.program_header_count() as u64)),
+        (AT_PAGESZ, Some(PAGE_SIZE)),
+        (AT_ENTRY, Some(elf.entry())),
+        (AT_NULL, Some(0)),
+    ];
+    let auxv = auxv
+        .iter()
+        .filter_map(|&(kind, value)| Some((kind, value?)));
+
+    let strings_size: u64 = argv
+        .iter()
+        .chain(envp)
+        .map(|string| string.len() as u64 + 1)
+        .sum();
+    let words =
+        1 + (argv.len() as u64 + 1) + (envp.len() as u64 + 1) + 2 * auxv.clone().count() as u64;
+    if strings_size + words * WORD + 16 > STACK_SIZE {
+        return Err("Arguments too large for the stack");
+    }
+
+    let mut string = STACK_TOP - strings_size;
+    let stack = (string - words * WORD) & !0xF;
+    let mut word = stack;
+    let mut push = |space: &mut AddressSpace, value: u64| {
+        let result = space.write(VirtAddr::new(word), &value.to_le_bytes());
+        word += WORD;
+        result
+    };
+
+    push(space, argv.len() as u64)?;
+    for list in &[argv, envp] {
+        for value in list.iter() {
+            // The terminating NUL is already there, the stack being zeroed.
+            space.write(VirtAddr::new(string), value.as_bytes())?;
+            push(space, string)?;
+            string += value.len() as u64 + 1;
+        }
+        push(space, 0)?;
+    }
+    for (kind, value) in auxv {
+        push(space, kind)?;
+        push(space, value)?;
+    }
+
+    Ok(VirtAddr::new(stack))
+}
+
+/// Maps the pages from `start` to `end`, excluded.
+fn map_range(
+    space: &mut AddressSpace,
+    start: u64,
+    end: u64,
+    flags: PageTableFlags,
+) -> Result<(), &'static str> {
+    let first = Page::containing_address(VirtAddr::new(start));
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        space.map_user_page(page, flags)?;
+    }
+
+    Ok(())
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
@@ -0,0 +1,414 @@
+//! # ELF
+//!
+//! Parsing of the ELF64 executables of x86_64 user programs, and their loading
+//! in a new address space, see `load`.
+//!
+//! `Elf::parse` checks everything the loader relies on: the headers, that the
+//! program headers and the contents of the segments are in the file, that the
+//! loadable segments are in the user part of the address space and do not
+//! share pages, and that the entry point is in an executable segment.
+//!
+//! # Examples
+//! ```no_run
+//! let elf = Elf::parse(bytes)?;
+//! let image = elf::load(&elf, &["init"], &["HOME=/"])?;
+//! kprintln!("Entry point: {:?}", image.entry());
+//! ```
+
+use core::convert::TryInto;
+
+use crate::user::USER_END;
+
+mod load;
+
+pub use self::load::{load, Image, STACK_SIZE, STACK_TOP};
+
+/// Segment types.
+pub const PT_NULL: u32 = 0;
+pub const PT_LOAD: u32 = 1;
+pub const PT_PHDR: u32 = 6;
+pub const PT_TLS: u32 = 7;
+
+/// Segment permissions.
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+pub(crate) const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+pub(crate) const CLASS_64: u8 = 2;
+pub(crate) const DATA_LITTLE_ENDIAN: u8 = 1;
+pub(crate) const VERSION_CURRENT: u8 = 1;
+/// Type of executable files.
+pub(crate) const ET_EXEC: u16 = 2;
+pub(crate) const EM_X86_64: u16 = 62;
+
+/// Size of the file header.
+pub(crate) const HEADER_SIZE: usize = 64;
+/// Size of a program header.
+pub const PROGRAM_HEADER_SIZE: usize = 56;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Header of a segment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProgramHeader {
+    /// Type of the segment, `PT_LOAD` for example.
+    pub kind: u32,
+    /// Permissions, `PF_R`, `PF_W` and `PF_X`.
+    pub flags: u32,
+    /// Offset of the contents in the file.
+    pub offset: u64,
+    /// Address of the segment in memory.
+    pub vaddr: u64,
+    /// Size of the contents in the file.
+    pub file_size: u64,
+    /// Size in memory, the bytes past the contents being zeroes.
+    pub mem_size: u64,
+    /// Alignment of the segment, a power of two or 0.
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(bytes: &[u8]) -> ProgramHeader {
+        ProgramHeader {
+            kind: u32_at(bytes, 0),
+            flags: u32_at(bytes, 4),
+            offset: u64_at(bytes, 8),
+            vaddr: u64_at(bytes, 16),
+            file_size: u64_at(bytes, 32),
+            mem_size: u64_at(bytes, 40),
+            align: u64_at(bytes, 48),
+        }
+    }
+
+    /// End of the segment in memory.
+    pub fn end(&self) -> u64 {
+        self.vaddr + self.mem_size
+    }
+
+    /// First and last page numbers of the segment in memory.
+    fn pages(&self) -> (u64, u64) {
+        (self.vaddr / PAGE_SIZE, (self.end() - 1) / PAGE_SIZE)
+    }
+}
+
+/// A valid ELF64 executable for x86_64.
+#[derive(Debug, Copy, Clone)]
+pub struct Elf<'a> {
+    bytes: &'a [u8],
+    entry: u64,
+    program_headers: u64,
+    program_header_count: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// Parses and checks the executable in `bytes`.
+    pub fn parse(bytes: &'a [u8]) -> Result<Elf<'a>, &'static str> {
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err("Not an ELF file");
+        }
+        if bytes[4] != CLASS_64 || bytes[5] != DATA_LITTLE_ENDIAN || bytes[6] != VERSION_CURRENT {
+            return Err("Not a little endian ELF64 file");
+        }
+        if u16_at(bytes, 16) != ET_EXEC {
+            return Err("Not an executable");
+        }
+        if u16_at(bytes, 18) != EM_X86_64 {
+            return Err("Not an x86_64 executable");
+        }
+        if usize::from(u16_at(bytes, 54)) != PROGRAM_HEADER_SIZE {
+            return Err("Bad program header size");
+        }
+
+        let elf = Elf {
+            bytes,
+            entry: u64_at(bytes, 24),
+            program_headers: u64_at(bytes, 32),
+            program_header_count: usize::from(u16_at(bytes, 56)),
+        };
+        let table_size = (elf.program_header_count * PROGRAM_HEADER_SIZE) as u64;
+        if !in_file(bytes, elf.program_headers, table_size) {
+            return Err("Program headers past the end of the file");
+        }
+
+        let mut entry_found = false;
+        let mut tls_count = 0;
+        for header in elf.program_headers() {
+            match header.kind {
+                PT_LOAD => {
+                    check_segment(bytes, &header)?;
+                    let executable = header.flags & PF_X != 0;
+                    entry_found |=
+                        executable && header.vaddr <= elf.entry && elf.entry < header.end();
+                }
+                PT_TLS => {
+                    check_tls(bytes, &header)?;
+                    tls_count += 1;
+                }
+                _ => {}
+            }
+        }
+        if !entry_found {
+            return Err("Entry point not in an executable segment");
+        }
+        if tls_count > 1 {
+            return Err("More than one TLS segment");
+        }
+
+        // Loaded at once, every segment on its own pages.
+        let loaded = elf.segments().filter(|header| header.mem_size > 0);
+        for (index, header) in loaded.clone().enumerate() {
+            let (first, last) = header.pages();
+            let overlaps = loaded.clone().skip(index + 1).any(|other| {
+                let (other_first, other_last) = other.pages();
+                first <= other_last && other_first <= last
+            });
+            if overlaps {
+                return Err("Segments share a page");
+            }
+        }
+
+        Ok(elf)
+    }
+
+    /// Address of the first instruction of the program.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Offset of the program headers in the file.
+    pub fn program_headers_offset(&self) -> u64 {
+        self.program_headers
+    }
+
+    /// All the program headers.
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + Clone + 'a {
+        let start = self.program_headers as usize;
+        let end = start + self.program_header_count * PROGRAM_HEADER_SIZE;
+        self.bytes[start..end]
+            .chunks_exact(PROGRAM_HEADER_SIZE)
+            .map(ProgramHeader::parse)
+    }
+
+    /// Headers of the `PT_LOAD` segments, to map in memory.
+    pub fn segments(&self) -> impl Iterator<Item = ProgramHeader> + Clone + 'a {
+        self.program_headers()
+            .filter(|header| header.kind == PT_LOAD)
+    }
+
+    /// Header of the `PT_TLS` segment, template of the thread local storage.
+    pub fn tls(&self) -> Option<ProgramHeader> {
+        self.program_headers().find(|header| header.kind == PT_TLS)
+    }
+
+    /// Contents of the segment of `header` in the file.
+    pub fn data(&self, header: &ProgramHeader) -> &'a [u8] {
+        let start = header.offset as usize;
+        &self.bytes[start..start + header.file_size as usize]
+    }
+
+    /// Address of the program headers in memory, if a segment loads them.
+    pub fn program_headers_address(&self) -> Option<u64> {
+        let offset = self.program_headers;
+        let table_size = (self.program_header_count * PROGRAM_HEADER_SIZE) as u64;
+        self.segments()
+            .find(|header| {
+                header.offset <= offset && offset + table_size <= header.offset + header.file_size
+            })
+            .map(|header| header.vaddr + (offset - header.offset))
+    }
+
+    /// Number of program headers.
+    pub fn program_header_count(&self) -> usize {
+        self.program_header_count
+    }
+}
+
+/// Checks that a `PT_LOAD` segment is in the file and in user memory.
+fn check_segment(bytes: &[u8], header: &ProgramHeader) -> Result<(), &'static str> {
+    if header.file_size > header.mem_size {
+        return Err("Segment larger in the file than in memory");
+    }
+    if !in_file(bytes, header.offset, header.file_size) {
+        return Err("Segment past the end of the file");
+    }
+    if header.align > 1 && header.vaddr % PAGE_SIZE != header.offset % PAGE_SIZE {
+        return Err("Segment not aligned with its offset");
+    }
+    match header.vaddr.checked_add(header.mem_size) {
+        Some(end) if end <= USER_END => Ok(()),
+        _ => Err("Segment outside user memory"),
+    }
+}
+
+/// Checks that the `PT_TLS` segment is in the file, with an alignment the
+/// loader supports.
+fn check_tls(bytes: &[u8], header: &ProgramHeader) -> Result<(), &'static str> {
+    if header.file_size > header.mem_size {
+        return Err("Segment larger in the file than in memory");
+    }
+    if !in_file(bytes, header.offset, header.file_size) {
+        return Err("Segment past the end of the file");
+    }
+    if header.align > PAGE_SIZE || (header.align != 0 && !header.align.is_power_of_two()) {
+        return Err("Bad TLS alignment");
+    }
+    if header.mem_size > USER_END {
+        return Err("TLS segment too large");
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if `size` bytes from `offset` are in `bytes`.
+fn in_file(bytes: &[u8], offset: u64, size: u64) -> bool {
+    offset
+        .checked_add(size)
+        .map_or(false, |end| end <= bytes.len() as u64)
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Executable of 0x300 bytes for the tests, see `testing::elf::build`.
+#[cfg(test)]
+fn build(entry: u64, headers: &[ProgramHeader]) -> [u8; 0x300] {
+    let mut bytes = [0; 0x300];
+    crate::testing::elf::build(&mut bytes, entry, headers);
+    bytes
+}
+
+#[cfg(test)]
+const TEXT: ProgramHeader = ProgramHeader {
+    kind: PT_LOAD,
+    flags: PF_R | PF_X,
+    offset: 0,
+    vaddr: 0x4000_0000_0000,
+    file_size: 0x200,
+    mem_size: 0x200,
+    align: PAGE_SIZE,
+};
+
+#[cfg(test)]
+const DATA: ProgramHeader = ProgramHeader {
+    kind: PT_LOAD,
+    flags: PF_R | PF_W,
+    offset: 0x200,
+    vaddr: 0x4000_0000_1200,
+    file_size: 0x10,
+    mem_size: 0x3000,
+    align: PAGE_SIZE,
+};
+
+#[cfg(test)]
+const TLS: ProgramHeader = ProgramHeader {
+    kind: PT_TLS,
+    flags: PF_R,
+    offset: 0x208,
+    vaddr: 0x4000_0000_1208,
+    file_size: 8,
+    mem_size: 0x20,
+    align: 16,
+};
+
+#[cfg(test)]
+#[test_case]
+fn parse_executable() {
+    let bytes = build(TEXT.vaddr + 0x100, &[TEXT, DATA, TLS]);
+    let elf = Elf::parse(&bytes).unwrap();
+
+    assert_eq!(elf.entry(), 0x4000_0000_0100);
+    assert_eq!(elf.program_header_count(), 3);
+    let mut segments = elf.segments();
+    assert_eq!(segments.next(), Some(TEXT));
+    assert_eq!(segments.next(), Some(DATA));
+    assert_eq!(segments.next(), None);
+    assert_eq!(elf.tls(), Some(TLS));
+    assert_eq!(elf.data(&TLS), &bytes[0x208..0x210]);
+    // In the first page of the text segment
+    assert_eq!(elf.program_headers_address(), Some(TEXT.vaddr + 64));
+}
+
+#[cfg(test)]
+#[test_case]
+fn reject_bad_headers() {
+    let good = build(TEXT.vaddr, &[TEXT]);
+
+    let mut bytes = good;
+    bytes[0] = 0;
+    assert_eq!(Elf::parse(&bytes).err(), Some("Not an ELF file"));
+    let mut bytes = good;
+    bytes[4] = 1;
+    assert_eq!(
+        Elf::parse(&bytes).err(),
+        Some("Not a little endian ELF64 file")
+    );
+    let mut bytes = good;
+    bytes[16] = 3;
+    assert_eq!(Elf::parse(&bytes).err(), Some("Not an executable"));
+    let mut bytes = good;
+    bytes[18] = 3;
+    assert_eq!(Elf::parse(&bytes).err(), Some("Not an x86_64 executable"));
+    let mut bytes = good;
+    bytes[56] = 100;
+    assert_eq!(
+        Elf::parse(&bytes).err(),
+        Some("Program headers past the end of the file")
+    );
+    assert_eq!(Elf::parse(&good[..32]).err(), Some("Not an ELF file"));
+}
+
+#[cfg(test)]
+#[test_case]
+fn reject_bad_segments() {
+    let parse = |entry, headers: &[ProgramHeader]| Elf::parse(&build(entry, headers)).err();
+
+    let past_end = ProgramHeader {
+        file_size: 0x400,
+        mem_size: 0x400,
+        ..TEXT
+    };
+    assert_eq!(
+        parse(TEXT.vaddr, &[past_end]),
+        Some("Segment past the end of the file")
+    );
+
+    let kernel = ProgramHeader {
+        vaddr: 0xffff_8000_0000_0000,
+        ..TEXT
+    };
+    assert_eq!(
+        parse(kernel.vaddr, &[kernel]),
+        Some("Segment outside user memory")
+    );
+
+    let sharing = ProgramHeader {
+        vaddr: TEXT.vaddr + 0x200,
+        ..DATA
+    };
+    assert_eq!(
+        parse(TEXT.vaddr, &[TEXT, sharing]),
+        Some("Segments share a page")
+    );
+
+    // In a segment, but not an executable one
+    assert_eq!(
+        parse(DATA.vaddr, &[TEXT, DATA]),
+        Some("Entry point not in an executable segment")
+    );
+
+    let misaligned = ProgramHeader { align: 3, ..TLS };
+    assert_eq!(
+        parse(TEXT.vaddr, &[TEXT, misaligned]),
+        Some("Bad TLS alignment")
+    );
+}
@@ -0,0 +1,163 @@
+//! The console as a file, that the standard descriptors and `/dev/console`
+//! refer to: it reads from `SERIAL1` and the keyboard, and writes to the
+//! screen and `SERIAL1`.
+
+use alloc::sync::Arc;
+use core::{str, time::Duration};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use super::{
+    devfs::{IOCTL_FLUSH_INPUT, IOCTL_WINSIZE},
+    File, FileType, OpenFile, Stat, O_RDONLY, O_WRONLY,
+};
+use crate::{
+    hid::input,
+    prelude::*,
+    syscall::Errno,
+    thread,
+    uart::rx,
+    vga::{COLS, ROWS},
+};
+
+/// How often a blocked read checks for input.
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The console.
+pub struct Console;
+
+/// Bytes of the last character typed that did not fit in the buffer of a read,
+/// and how many of them are left.
+static PENDING: Mutex<([u8; 4], usize)> = Mutex::new(([0; 4], 0));
+
+lazy_static! {
+    static ref CONSOLE: Arc<dyn File> = Arc::new(Console);
+}
+
+impl File for Console {
+    /// Waits for input, then reads what is available.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(wait_for_input(buf, read_input))
+    }
+
+    /// Writes `buf`, invalid UTF-8 sequences replaced.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        write_lossy(buf, |text| kprint!("{}", text));
+        Ok(buf.len())
+    }
+
+    fn stat(&self) -> Result<Stat, Errno> {
+        Ok(Stat {
+            ino: 0,
+            size: 0,
+            kind: FileType::CharDevice,
+            nlink: 1,
+        })
+    }
+
+    fn ioctl(&self, request: u64, _arg: u64) -> Result<u64, Errno> {
+        match request {
+            IOCTL_WINSIZE => Ok(winsize()),
+            IOCTL_FLUSH_INPUT => {
+                PENDING.lock().1 = 0;
+                while rx::read_byte().is_some() || input::poll_key().is_some() {}
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
+
+/// Passes `buf` to `write` as text, in pieces, its invalid UTF-8 sequences
+/// replaced by `char::REPLACEMENT_CHARACTER`.
+pub(super) fn write_lossy(buf: &[u8], mut write: impl FnMut(&str)) {
+    let mut bytes = buf;
+    while !bytes.is_empty() {
+        match str::from_utf8(bytes) {
+            Ok(text) => {
+                write(text);
+                break;
+            }
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                write(str::from_utf8(valid).unwrap());
+                write(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 4]));
+                bytes = &rest[err.error_len().unwrap_or(rest.len())..];
+            }
+        }
+    }
+}
+
+/// Result of `IOCTL_WINSIZE`: rows of the screen in the upper 16 bits, and
+/// columns in the lower ones.
+pub(super) fn winsize() -> u64 {
+    ((ROWS as u64) << 16) | COLS as u64
+}
+
+/// Calls `read` until it puts something in `buf`, sleeping in between.
+/// Returns what it read, 0 only if `buf` is empty.
+pub(super) fn wait_for_input(buf: &mut [u8], mut read: impl FnMut(&mut [u8]) -> usize) -> usize {
+    if buf.is_empty() {
+        return 0;
+    }
+
+    loop {
+        let read = read(buf);
+        if read > 0 {
+            return read;
+        }
+        thread::sleep(READ_POLL_INTERVAL);
+    }
+}
+
+/// Fills `buffer` with the rest of a character typed before, the bytes
+/// received on `SERIAL1`, then the characters typed on the keyboard, UTF-8
+/// encoded. What does not fit of a character is kept for the next read.
+fn read_input(buffer: &mut [u8]) -> usize {
+    let mut pending = PENDING.lock();
+    let (bytes, left) = &mut *pending;
+    let mut read = (*left).min(buffer.len());
+    let start = bytes.len() - *left;
+    buffer[..read].copy_from_slice(&bytes[start..][..read]);
+    *left -= read;
+    if *left > 0 {
+        return read;
+    }
+
+    while read < buffer.len() {
+        match rx::read_byte() {
+            Some(byte) => buffer[read] = byte,
+            None => break,
+        }
+        read += 1;
+    }
+
+    while read < buffer.len() {
+        match input::poll_key().map(|key| key.char()) {
+            Some(Some(character)) => {
+                // Kept at the end of `bytes`, where the next read finds them.
+                let len = character.len_utf8();
+                character.encode_utf8(&mut bytes[4 - len..]);
+                let taken = len.min(buffer.len() - read);
+                buffer[read..][..taken].copy_from_slice(&bytes[4 - len..][..taken]);
+                read += taken;
+                *left = len - taken;
+            }
+            Some(None) => {}
+            None => break,
+        }
+    }
+
+    read
+}
+
+/// The standard descriptor `fd` on the console: the input for 0, the output
+/// for 1 and the error for 2.
+pub fn standard(fd: u64) -> Result<OpenFile, Errno> {
+    match fd {
+        0 => Ok(OpenFile::new(CONSOLE.clone(), O_RDONLY)),
+        1 | 2 => Ok(OpenFile::new(CONSOLE.clone(), O_WRONLY)),
+        _ => Err(Errno::EBADF),
+    }
+}
@@ -0,0 +1,234 @@
+//! Resolution of paths, across mount points and symbolic links.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use super::{FileSystem, FileType, Inode};
+use crate::{sync::Mutex, syscall::Errno};
+
+/// Longest path, in bytes.
+pub const PATH_MAX: usize = 4096;
+
+/// Longest component of a path, in bytes.
+pub const NAME_MAX: usize = 255;
+
+/// Most symbolic links followed resolving a path.
+pub const MAX_SYMLINKS: usize = 8;
+
+/// A file system mounted on a directory.
+struct Mount {
+    /// Of the directory, without `.`, `..` or symbolic links.
+    path: String,
+    fs: Arc<dyn FileSystem>,
+}
+
+/// Mounted file systems, the root one first.
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// An inode reached by a path, that knows the way back.
+pub struct Dentry {
+    /// Path that reaches it without `.`, `..` or symbolic links.
+    path: String,
+    inode: Arc<dyn Inode>,
+    /// Directory it is in, `None` for the root.
+    parent: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Last component of the path, `/` for the root.
+    pub fn name(&self) -> &str {
+        match self.path.rfind('/') {
+            Some(index) if self.path.len() > 1 => &self.path[index + 1..],
+            _ => &self.path,
+        }
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Dentry>> {
+        self.parent.as_ref()
+    }
+
+    /// Returns `true` if a file system is mounted on it.
+    pub fn is_mount_point(&self) -> bool {
+        mounted(&self.path).is_some()
+    }
+
+    /// The entry `name` of the directory, that is `inode` unless a file system
+    /// is mounted on it.
+    pub(super) fn child(self: &Arc<Dentry>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        let path = match self.parent {
+            Some(_) => format!("{}/{}", self.path, name),
+            None => format!("/{}", name),
+        };
+        let inode = mounted(&path).unwrap_or(inode);
+
+        Arc::new(Dentry {
+            path,
+            inode,
+            parent: Some(self.clone()),
+        })
+    }
+}
+
+/// Root of the file system mounted on `path`.
+fn mounted(path: &str) -> Option<Arc<dyn Inode>> {
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|mount| mount.path == path)
+        .map(|mount| mount.fs.root())
+}
+
+fn root() -> Result<Arc<Dentry>, Errno> {
+    Ok(Arc::new(Dentry {
+        path: "/".to_string(),
+        inode: mounted("/").ok_or(Errno::ENOENT)?,
+        parent: None,
+    }))
+}
+
+/// Mounts `fs` on the directory `path`, or as the root if `path` is `/`.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), Errno> {
+    check(path)?;
+    let path = match components(path).next() {
+        None => "/".to_string(),
+        Some(_) => {
+            let dentry = resolve(path, true)?;
+            if dentry.inode.stat().kind != FileType::Directory {
+                return Err(Errno::ENOTDIR);
+            }
+            dentry.path.clone()
+        }
+    };
+
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mount| mount.path == path) {
+        return Err(Errno::EBUSY);
+    }
+    log::info!("Mounted {} on {}", fs.name(), path);
+    mounts.push(Mount { path, fs });
+    Ok(())
+}
+
+/// The dentry `path` leads to, following symbolic links but the last
+/// component if not `follow`.
+pub fn resolve(path: &str, follow: bool) -> Result<Arc<Dentry>, Errno> {
+    check(path)?;
+    let mut dentry = root()?;
+    // Components left, the next one last.
+    let mut left: Vec<String> = components(path).rev().map(String::from).collect();
+    let mut links = 0;
+
+    while let Some(name) = left.pop() {
+        match name.as_str() {
+            "." => continue,
+            ".." => {
+                if let Some(parent) = dentry.parent.clone() {
+                    dentry = parent;
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if dentry.inode.stat().kind != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        let inode = dentry.inode.lookup(&name)?;
+
+        if inode.stat().kind == FileType::Symlink && (follow || !left.is_empty()) {
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(Errno::ELOOP);
+            }
+            let target = inode.readlink()?;
+            check(&target)?;
+            if target.starts_with('/') {
+                dentry = root()?;
+            }
+            left.extend(components(&target).rev().map(String::from));
+            continue;
+        }
+
+        dentry = dentry.child(&name, inode);
+    }
+
+    Ok(dentry)
+}
+
+/// The directory `path` is in, and the last component of `path`, that must
+/// not be `.` or `..`.
+pub(super) fn resolve_parent(path: &str) -> Result<(Arc<Dentry>, &str), Errno> {
+    check(path)?;
+    let (directory, name) = split(path);
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Errno::EINVAL);
+    }
+
+    let parent = resolve(directory, true)?;
+    if parent.inode.stat().kind != FileType::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok((parent, name))
+}
+
+/// Checks the lengths of `path` and its components.
+fn check(path: &str) -> Result<(), Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.len() > PATH_MAX || components(path).any(|name| name.len() > NAME_MAX) {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    Ok(())
+}
+
+/// Components of `path`, without empty ones.
+fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty())
+}
+
+/// `path` split into the directory it is in and its last component.
+fn split(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(index) => match path[..index].trim_end_matches('/') {
+            "" => ("/", &path[index + 1..]),
+            directory => (directory, &path[index + 1..]),
+        },
+        None => ("/", path),
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn paths() {
+    assert_eq!(split("/etc/motd"), ("/etc", "motd"));
+    assert_eq!(split("etc//motd/"), ("etc", "motd"));
+    assert_eq!(split("/motd"), ("/", "motd"));
+    assert_eq!(split("motd"), ("/", "motd"));
+    assert_eq!(split("/"), ("/", ""));
+
+    let mut names = components("//usr/./lib/");
+    assert_eq!(names.next(), Some("usr"));
+    assert_eq!(names.next_back(), Some("lib"));
+    assert_eq!(names.next(), Some("."));
+    assert_eq!(names.next(), None);
+
+    let long = [b'a'; NAME_MAX + 1];
+    let long = core::str::from_utf8(&long).unwrap();
+    assert_eq!(check(""), Err(Errno::ENOENT));
+    assert_eq!(check(long), Err(Errno::ENAMETOOLONG));
+    assert_eq!(check(&long[1..]), Ok(()));
+}
@@ -0,0 +1,479 @@
+//! File system of the devices, mounted on `/dev` by `fs::init`. Its files are
+//! the drivers of the kernel:
+//!
+//! | File      | Device                                                    |
+//! |-----------|-----------------------------------------------------------|
+//! | `console` | The console of the standard descriptors, see `console`    |
+//! | `ttyS0`   | `SERIAL1`, reading what its interrupt handler received    |
+//! | `ttyS1`   | `SERIAL2`, reading what its port holds                    |
+//! | `vga`     | `VGA`, only written to                                    |
+//! | `kbd`     | The keyboard, only read from, as `KeyRecord`s             |
+//! | `null`    | Reads nothing, and discards what is written               |
+//! | `zero`    | Reads zeros, and discards what is written                 |
+//! | `random`  | Reads random bytes, and mixes in what is written          |
+//!
+//! Reading the terminals or the keyboard waits for input. Devices have no
+//! offset, everyone opening one shares its file. Their `ioctl` requests are
+//! the `IOCTL_*` constants, that take and return values, not pointers.
+
+use alloc::{boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use core::mem;
+
+use spin::Mutex as SpinMutex;
+use x86_64::instructions::{interrupts, random::RdRand};
+
+use super::{
+    console::{self, Console},
+    DirEntry, File, FileSystem, FileType, Inode, Stat,
+};
+use crate::{
+    hid::{input, layout::LAYOUTS},
+    init::{
+        serial::{SERIAL1, SERIAL2},
+        vga::VGA,
+    },
+    sync::Mutex,
+    syscall::Errno,
+    uart::{m16550::SerialPort, rx},
+    vga::Color,
+};
+
+/// `ioctl` of `console` and `vga`: size of the screen, the rows in the upper
+/// 16 bits of the result and the columns in the lower ones.
+pub const IOCTL_WINSIZE: u64 = 0x5413;
+/// `ioctl` of the terminals and `kbd`: discards the input not read yet.
+pub const IOCTL_FLUSH_INPUT: u64 = 0x540B;
+/// `ioctl` of `vga`: colors of what is written next, the foreground in the
+/// lower 4 bits of the argument and the background in the next 4.
+pub const IOCTL_SET_COLORS: u64 = 0x5601;
+/// `ioctl` of `kbd`: index of the layout in `hid::layout::LAYOUTS`.
+pub const IOCTL_GET_LAYOUT: u64 = 0x4B01;
+/// `ioctl` of `kbd`: changes the layout to the one at the index given in
+/// `hid::layout::LAYOUTS`.
+pub const IOCTL_SET_LAYOUT: u64 = 0x4B02;
+/// `ioctl` of `kbd`: bits of the active `hid::input::Modifiers`.
+pub const IOCTL_MODIFIERS: u64 = 0x4B03;
+
+/// Bytes sent to a serial port with the interrupts disabled.
+const SERIAL_CHUNK: usize = 64;
+
+/// Inode number of the root directory, the devices following it.
+const ROOT_INO: u64 = 1;
+
+/// A key event, as `kbd` reads it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct KeyRecord {
+    /// The `hid::input::KeyCode`, as a number.
+    pub code: u8,
+    /// 1 if the key went down, 0 if it went up.
+    pub pressed: u8,
+    /// Bits of the `hid::input::Modifiers` active after the event.
+    pub modifiers: u16,
+    /// Character of the key in the layout, or 0.
+    pub character: u32,
+}
+
+impl KeyRecord {
+    pub const SIZE: usize = mem::size_of::<KeyRecord>();
+
+    pub fn to_bytes(self) -> [u8; KeyRecord::SIZE] {
+        let mut bytes = [0; KeyRecord::SIZE];
+        bytes[0] = self.code;
+        bytes[1] = self.pressed;
+        bytes[2..4].copy_from_slice(&self.modifiers.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.character.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; KeyRecord::SIZE]) -> KeyRecord {
+        KeyRecord {
+            code: bytes[0],
+            pressed: bytes[1],
+            modifiers: u16::from_le_bytes([bytes[2], bytes[3]]),
+            character: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+/// The devices, in a file system.
+pub struct DevFs {
+    root: Arc<DevDirectory>,
+}
+
+impl DevFs {
+    pub fn new() -> DevFs {
+        let mut devices: Vec<(&'static str, Box<dyn Device>)> = alloc::vec![
+            ("console", Box::new(Console)),
+            ("ttyS0", Box::new(Serial::new(&SERIAL1, rx::read_byte))),
+            ("ttyS1", Box::new(Serial::new(&SERIAL2, com2_byte))),
+            ("vga", Box::new(Screen)),
+            ("kbd", Box::new(Keyboard)),
+            ("null", Box::new(Null)),
+            ("zero", Box::new(Zero)),
+            ("random", Box::new(Random::new())),
+        ];
+        devices.sort_by_key(|&(name, _)| name);
+
+        let devices = devices
+            .into_iter()
+            .zip(ROOT_INO + 1..)
+            .map(|((name, device), ino)| {
+                let file = Arc::new(DevFile { ino, device });
+                (name, Arc::new(DevInode { file }))
+            })
+            .collect();
+        DevFs {
+            root: Arc::new(DevDirectory { devices }),
+        }
+    }
+}
+
+impl Default for DevFs {
+    fn default() -> DevFs {
+        DevFs::new()
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// The root, and only directory.
+struct DevDirectory {
+    /// Inodes of the devices, by name.
+    devices: Vec<(&'static str, Arc<DevInode>)>,
+}
+
+impl Inode for DevDirectory {
+    fn stat(&self) -> Stat {
+        Stat {
+            ino: ROOT_INO,
+            size: self.devices.len() as u64,
+            kind: FileType::Directory,
+            nlink: 2,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match self.devices.iter().find(|&&(device, _)| device == name) {
+            Some((_, inode)) => Ok(inode.clone()),
+            None => Err(Errno::ENOENT),
+        }
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        Ok(self.devices.get(index).map(|(name, inode)| DirEntry {
+            ino: inode.file.ino,
+            kind: FileType::CharDevice,
+            name: name.to_string(),
+        }))
+    }
+}
+
+/// What a device does when its file is used. Each operation fails by
+/// default, as on a device that can not be read, written or controlled.
+trait Device: Send + Sync {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    fn ioctl(&self, _request: u64, _arg: u64) -> Result<u64, Errno> {
+        Err(Errno::ENOTTY)
+    }
+}
+
+/// Inode of a device, opened as its file.
+struct DevInode {
+    file: Arc<DevFile>,
+}
+
+impl Inode for DevInode {
+    fn stat(&self) -> Stat {
+        self.file.stat_of()
+    }
+
+    fn open(&self, _flags: u32) -> Result<Option<Arc<dyn File>>, Errno> {
+        Ok(Some(self.file.clone()))
+    }
+}
+
+/// File of a device, shared by whoever opens it.
+struct DevFile {
+    ino: u64,
+    device: Box<dyn Device>,
+}
+
+impl DevFile {
+    fn stat_of(&self) -> Stat {
+        Stat {
+            ino: self.ino,
+            size: 0,
+            kind: FileType::CharDevice,
+            nlink: 1,
+        }
+    }
+}
+
+impl File for DevFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.device.read(buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        self.device.write(buf)
+    }
+
+    fn stat(&self) -> Result<Stat, Errno> {
+        Ok(self.stat_of())
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<u64, Errno> {
+        self.device.ioctl(request, arg)
+    }
+}
+
+impl Device for Console {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        File::read(self, buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        File::write(self, buf)
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<u64, Errno> {
+        File::ioctl(self, request, arg)
+    }
+}
+
+/// A serial port, and where the bytes it received are taken from.
+struct Serial {
+    port: &'static SpinMutex<SerialPort>,
+    receive: fn() -> Option<u8>,
+}
+
+impl Serial {
+    fn new(port: &'static SpinMutex<SerialPort>, receive: fn() -> Option<u8>) -> Serial {
+        Serial { port, receive }
+    }
+}
+
+impl Device for Serial {
+    /// Waits for a byte, then reads those received.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(console::wait_for_input(buf, |buf| {
+            let mut read = 0;
+            while read < buf.len() {
+                match (self.receive)() {
+                    Some(byte) => buf[read] = byte,
+                    None => break,
+                }
+                read += 1;
+            }
+            read
+        }))
+    }
+
+    /// Sends `buf` as it is, a chunk at a time, as printing locks the port with
+    /// the interrupts disabled.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        for chunk in buf.chunks(SERIAL_CHUNK) {
+            interrupts::without_interrupts(|| {
+                let mut port = self.port.lock();
+                chunk.iter().for_each(|&byte| port.send(byte));
+            });
+        }
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, request: u64, _arg: u64) -> Result<u64, Errno> {
+        match request {
+            IOCTL_FLUSH_INPUT => {
+                while (self.receive)().is_some() {}
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
+
+/// Takes a byte received on `COM2`, whose interrupt is not handled.
+fn com2_byte() -> Option<u8> {
+    interrupts::without_interrupts(|| SERIAL2.lock().try_receive())
+}
+
+/// The screen, through `VGA`.
+struct Screen;
+
+impl Device for Screen {
+    /// Writes `buf`, invalid UTF-8 sequences replaced.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        console::write_lossy(buf, |text| {
+            crate::console::print(&*VGA, format_args!("{}", text));
+        });
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<u64, Errno> {
+        match request {
+            IOCTL_WINSIZE => Ok(console::winsize()),
+            IOCTL_SET_COLORS if arg <= 0xFF => {
+                let foreground = Color::from_index(arg as u8 & 0xF).unwrap();
+                let background = Color::from_index(arg as u8 >> 4).unwrap();
+                crate::console::set_foreground(foreground);
+                crate::console::set_background(background);
+                Ok(0)
+            }
+            IOCTL_SET_COLORS => Err(Errno::EINVAL),
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
+
+/// The keyboard, read as `KeyRecord`s.
+struct Keyboard;
+
+impl Device for Keyboard {
+    /// Waits for a key event, then reads the records of those that fit.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.len() < KeyRecord::SIZE {
+            return Err(Errno::EINVAL);
+        }
+
+        Ok(console::wait_for_input(buf, |buf| {
+            let mut read = 0;
+            while buf.len() - read >= KeyRecord::SIZE {
+                let event = match input::poll_key() {
+                    Some(event) => event,
+                    None => break,
+                };
+                let record = KeyRecord {
+                    code: event.code as u8,
+                    pressed: (event.state == input::KeyState::Down) as u8,
+                    modifiers: event.modifiers.bits(),
+                    character: event.char().map_or(0, u32::from),
+                };
+                buf[read..][..KeyRecord::SIZE].copy_from_slice(&record.to_bytes());
+                read += KeyRecord::SIZE;
+            }
+            read
+        }))
+    }
+
+    fn ioctl(&self, request: u64, arg: u64) -> Result<u64, Errno> {
+        match request {
+            IOCTL_FLUSH_INPUT => {
+                while input::poll_key().is_some() {}
+                Ok(0)
+            }
+            IOCTL_GET_LAYOUT => {
+                let layout = input::layout();
+                let index = LAYOUTS.iter().position(|&(_, known)| known == layout);
+                Ok(index.unwrap() as u64)
+            }
+            IOCTL_SET_LAYOUT => {
+                let &(_, layout) = LAYOUTS.get(arg as usize).ok_or(Errno::EINVAL)?;
+                input::set_layout(layout);
+                Ok(0)
+            }
+            IOCTL_MODIFIERS => Ok(input::modifiers().bits().into()),
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
+
+struct Null;
+
+impl Device for Null {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(buf.len())
+    }
+}
+
+struct Zero;
+
+impl Device for Zero {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        buf.iter_mut().for_each(|byte| *byte = 0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(buf.len())
+    }
+}
+
+/// Random bytes from a xorshift generator seeded with the time stamp counter,
+/// xored with `RDRAND` when the processor has it. Not fit for cryptography.
+struct Random {
+    rdrand: Option<RdRand>,
+    /// State of the generator, never 0.
+    state: Mutex<u64>,
+}
+
+impl Random {
+    fn new() -> Random {
+        let seed = unsafe { core::arch::x86_64::_rdtsc() };
+        Random {
+            rdrand: RdRand::new(),
+            state: Mutex::new(seed | 1),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let mut state = self.state.lock();
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        value ^ self.rdrand.and_then(RdRand::get_u64).unwrap_or(0)
+    }
+}
+
+impl Device for Random {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        for chunk in buf.chunks_mut(8) {
+            let value = self.next().to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    /// Mixes `buf` into the state of the generator.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut state = self.state.lock();
+        for chunk in buf.chunks(8) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *state = (*state ^ u64::from_le_bytes(bytes)).rotate_left(17) | 1;
+        }
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,253 @@
+//! Open files and the descriptors of processes.
+
+use alloc::sync::Arc;
+use core::fmt;
+
+use super::{console, DirEntry, File, FileType, Inode, SeekFrom, Stat};
+use crate::{sync::Mutex, syscall::Errno};
+
+/// Open for reading only.
+pub const O_RDONLY: u32 = 0;
+/// Open for writing only.
+pub const O_WRONLY: u32 = 1;
+/// Open for reading and writing.
+pub const O_RDWR: u32 = 2;
+/// Bits of the flags that give the access mode.
+pub const O_ACCMODE: u32 = 3;
+/// Create the file if it does not exist.
+pub const O_CREAT: u32 = 0o100;
+/// With `O_CREAT`, fail if the file exists.
+pub const O_EXCL: u32 = 0o200;
+/// Cut a regular file opened for writing to 0 bytes.
+pub const O_TRUNC: u32 = 0o1000;
+/// Write at the end of the file.
+pub const O_APPEND: u32 = 0o2000;
+/// Fail if the file is not a directory.
+pub const O_DIRECTORY: u32 = 0o200000;
+
+/// Most descriptors a process has open.
+pub const MAX_FILES: usize = 32;
+
+/// File of an inode that has none of its own, read and written from an offset,
+/// that is the index of the next entry for directories.
+pub struct InodeFile {
+    inode: Arc<dyn Inode>,
+    offset: Mutex<u64>,
+    append: bool,
+}
+
+impl InodeFile {
+    pub fn new(inode: Arc<dyn Inode>, append: bool) -> InodeFile {
+        InodeFile {
+            inode,
+            offset: Mutex::new(0),
+            append,
+        }
+    }
+
+    fn is_directory(&self) -> bool {
+        self.inode.stat().kind == FileType::Directory
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.is_directory() {
+            return Err(Errno::EISDIR);
+        }
+        let mut offset = self.offset.lock();
+        let read = self.inode.read_at(*offset, buf)?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if self.is_directory() {
+            return Err(Errno::EISDIR);
+        }
+        let mut offset = self.offset.lock();
+        if self.append {
+            *offset = self.inode.stat().size;
+        }
+        let written = self.inode.write_at(*offset, buf)?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Result<u64, Errno> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(start) => Some(start),
+            SeekFrom::Current(delta) => add(*offset, delta),
+            SeekFrom::End(delta) => add(self.inode.stat().size, delta),
+        };
+        // Offsets past `i64::MAX` could not be returned.
+        *offset = new
+            .filter(|&new| new <= i64::MAX as u64)
+            .ok_or(Errno::EINVAL)?;
+        Ok(*offset)
+    }
+
+    fn stat(&self) -> Result<Stat, Errno> {
+        Ok(self.inode.stat())
+    }
+
+    fn readdir(&self) -> Result<Option<DirEntry>, Errno> {
+        let mut offset = self.offset.lock();
+        let entry = self.inode.readdir(*offset as usize)?;
+        if entry.is_some() {
+            *offset += 1;
+        }
+        Ok(entry)
+    }
+}
+
+/// `offset` moved by `delta`, if not negative.
+pub(super) fn add(offset: u64, delta: i64) -> Option<u64> {
+    if delta < 0 {
+        offset.checked_sub(delta.wrapping_neg() as u64)
+    } else {
+        offset.checked_add(delta as u64)
+    }
+}
+
+/// A file as a descriptor refers to it, with the flags it was opened with.
+#[derive(Clone)]
+pub struct OpenFile {
+    file: Arc<dyn File>,
+    flags: u32,
+}
+
+impl OpenFile {
+    pub fn new(file: Arc<dyn File>, flags: u32) -> OpenFile {
+        OpenFile { file, flags }
+    }
+
+    /// Opens `inode` with `flags`, through its own file if it has one.
+    pub(super) fn open(inode: Arc<dyn Inode>, flags: u32) -> Result<OpenFile, Errno> {
+        if flags & O_ACCMODE == O_ACCMODE {
+            return Err(Errno::EINVAL);
+        }
+        let file = match inode.open(flags)? {
+            Some(file) => file,
+            None => Arc::new(InodeFile::new(inode, flags & O_APPEND != 0)),
+        };
+        Ok(OpenFile::new(file, flags))
+    }
+
+    pub fn file(&self) -> &Arc<dyn File> {
+        &self.file
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if !self.readable() {
+            return Err(Errno::EBADF);
+        }
+        self.file.read(buf)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if !self.writable() {
+            return Err(Errno::EBADF);
+        }
+        self.file.write(buf)
+    }
+
+    pub fn seek(&self, pos: SeekFrom) -> Result<u64, Errno> {
+        self.file.seek(pos)
+    }
+
+    pub fn stat(&self) -> Result<Stat, Errno> {
+        self.file.stat()
+    }
+
+    pub fn readdir(&self) -> Result<Option<DirEntry>, Errno> {
+        if !self.readable() {
+            return Err(Errno::EBADF);
+        }
+        self.file.readdir()
+    }
+
+    pub fn ioctl(&self, request: u64, arg: u64) -> Result<u64, Errno> {
+        self.file.ioctl(request, arg)
+    }
+}
+
+impl fmt::Debug for OpenFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenFile")
+            .field("flags", &format_args!("{:#o}", self.flags))
+            .finish()
+    }
+}
+
+/// Files open in a process, by descriptor.
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: [Option<OpenFile>; MAX_FILES],
+}
+
+impl FileTable {
+    /// A table without files.
+    pub fn new() -> FileTable {
+        FileTable::default()
+    }
+
+    /// A table with the standard input, output and error on the console.
+    pub fn with_console() -> FileTable {
+        let mut table = FileTable::new();
+        for (fd, file) in table.files.iter_mut().enumerate().take(3) {
+            *file = console::standard(fd as u64).ok();
+        }
+        table
+    }
+
+    /// The file of `fd`.
+    pub fn get(&self, fd: u64) -> Result<OpenFile, Errno> {
+        self.files
+            .get(fd as usize)
+            .and_then(Option::clone)
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Gives `file` the lowest free descriptor, and returns it.
+    pub fn insert(&mut self, file: OpenFile) -> Result<u64, Errno> {
+        let fd = self
+            .files
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Errno::EMFILE)?;
+        self.files[fd] = Some(file);
+        Ok(fd as u64)
+    }
+
+    /// Frees `fd`, returning its file. The file is closed once the last
+    /// descriptor for it is, that is when it is dropped.
+    pub fn remove(&mut self, fd: u64) -> Result<OpenFile, Errno> {
+        self.files
+            .get_mut(fd as usize)
+            .and_then(Option::take)
+            .ok_or(Errno::EBADF)
+    }
+}
+
+impl fmt::Debug for FileTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let open = self.files.iter().enumerate();
+        f.debug_map()
+            .entries(open.filter_map(|(fd, file)| Some((fd, file.as_ref()?))))
+            .finish()
+    }
+}
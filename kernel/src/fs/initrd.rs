@@ -0,0 +1,447 @@
+//! Initial ramdisk: an archive of the files the system starts with, unpacked
+//! into the root by `fs::init`.
+//!
+//! The bootloader only loads the kernel, so the archive is linked into it, see
+//! `ARCHIVE`. It is made when building the kernel, by packing the `initrd`
+//! directory as a cpio `newc` archive, or is the archive named by the
+//! `FERROUS_INITRD` environment variable:
+//! ```sh
+//! (cd root && find . | cpio -o -H newc) > initrd.cpio
+//! FERROUS_INITRD=$PWD/initrd.cpio bootimage build --target kernel.json
+//! ```
+//!
+//! Both cpio `newc` and ustar archives are read, but only their directories,
+//! regular files and symbolic links are unpacked. Owners, permissions and
+//! times are not kept.
+
+use alloc::string::String;
+use core::str;
+
+use super::{FileType, OpenFile, O_CREAT, O_TRUNC, O_WRONLY};
+use crate::syscall::Errno;
+
+/// The archive linked into the kernel.
+pub static ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initrd"));
+
+/// Size of the header of a cpio `newc` entry.
+const CPIO_HEADER_SIZE: usize = 110;
+/// Name of the entry ending a cpio archive.
+const CPIO_TRAILER: &str = "TRAILER!!!";
+
+/// Types of files, in a cpio mode.
+const S_IFMT: u32 = 0o170_000;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFREG: u32 = 0o100_000;
+const S_IFLNK: u32 = 0o120_000;
+
+/// Size of the blocks of a tar archive.
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Formats of archives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Cpio,
+    Tar,
+}
+
+/// A file of an archive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// Directories the name is in, only used by tar archives for long paths.
+    pub prefix: &'a str,
+    /// Path of the file in the archive.
+    pub name: &'a str,
+    pub kind: FileType,
+    /// Contents of a regular file, target of a symbolic link.
+    pub data: &'a [u8],
+}
+
+/// Iterator over the directories, regular files and symbolic links of an
+/// archive, see `entries`. The other files are skipped, and an error ends it.
+pub struct Entries<'a> {
+    archive: &'a [u8],
+    format: Format,
+    offset: usize,
+    done: bool,
+}
+
+/// Files of `archive`, whose format is told by its first header.
+pub fn entries(archive: &[u8]) -> Result<Entries<'_>, &'static str> {
+    let format = if archive.starts_with(b"0707") {
+        Format::Cpio
+    } else if archive.get(257..262) == Some(&b"ustar"[..]) {
+        Format::Tar
+    } else {
+        return Err("Unknown archive format");
+    };
+
+    Ok(Entries {
+        archive,
+        format,
+        offset: 0,
+        done: false,
+    })
+}
+
+/// An entry, and its type if it is one that is unpacked.
+type Parsed<'a> = (Entry<'a>, Option<FileType>);
+
+impl<'a> Entries<'a> {
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Bytes at `start`, or an error if the archive ends before them.
+    fn bytes(&self, start: usize, len: usize) -> Result<&'a [u8], &'static str> {
+        let archive = self.archive;
+        archive.get(start..start + len).ok_or("Truncated archive")
+    }
+
+    /// Next entry of a cpio archive, or `None` after the trailer.
+    fn next_cpio(&mut self) -> Result<Option<Parsed<'a>>, &'static str> {
+        let header = self.bytes(self.offset, CPIO_HEADER_SIZE)?;
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return Err("Bad cpio magic");
+        }
+        let field = |index: usize| number(&header[6 + 8 * index..][..8], 16);
+        let (mode, size, name_size) = (field(1)?, field(6)? as usize, field(11)? as usize);
+
+        let start = self.offset + CPIO_HEADER_SIZE;
+        let name = match self.bytes(start, name_size)?.split_last() {
+            Some((0, name)) => str::from_utf8(name).map_err(|_| "Name not UTF-8")?,
+            _ => return Err("Name not terminated"),
+        };
+        let start = align_up(start + name_size, 4);
+        let data = self.bytes(start, size)?;
+        self.offset = align_up(start + size, 4);
+
+        if name == CPIO_TRAILER {
+            return Ok(None);
+        }
+        let kind = match mode & S_IFMT {
+            S_IFDIR => Some(FileType::Directory),
+            S_IFREG => Some(FileType::Regular),
+            S_IFLNK => Some(FileType::Symlink),
+            _ => None,
+        };
+        let entry = Entry {
+            prefix: "",
+            name,
+            kind: kind.unwrap_or(FileType::Regular),
+            data,
+        };
+        Ok(Some((entry, kind)))
+    }
+
+    /// Next entry of a tar archive, or `None` at the end.
+    fn next_tar(&mut self) -> Result<Option<Parsed<'a>>, &'static str> {
+        // Ended by zeroed blocks, that some archives leave out.
+        if self.offset >= self.archive.len() {
+            return Ok(None);
+        }
+        let header = self.bytes(self.offset, TAR_BLOCK_SIZE)?;
+        if header.iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
+        if &header[257..262] != b"ustar" {
+            return Err("Bad tar magic");
+        }
+        // Summed with the checksum field as spaces.
+        let sum = header
+            .iter()
+            .enumerate()
+            .map(|(index, &byte)| match index {
+                148..=155 => b' ' as u32,
+                _ => byte as u32,
+            })
+            .sum::<u32>();
+        if number(&header[148..156], 8)? != sum {
+            return Err("Bad tar checksum");
+        }
+
+        let size = number(&header[124..136], 8)? as usize;
+        let start = self.offset + TAR_BLOCK_SIZE;
+        let mut data = self.bytes(start, size)?;
+        self.offset = start + align_up(size, TAR_BLOCK_SIZE);
+
+        let name = c_str(&header[..100])?;
+        let kind = match header[156] {
+            // Directories of old archives are regular files ending with a slash.
+            b'0' | 0 if name.ends_with('/') => Some(FileType::Directory),
+            b'0' | 0 => Some(FileType::Regular),
+            b'5' => Some(FileType::Directory),
+            b'2' => {
+                data = c_str(&header[157..257])?.as_bytes();
+                Some(FileType::Symlink)
+            }
+            _ => None,
+        };
+        let entry = Entry {
+            prefix: c_str(&header[345..500])?,
+            name,
+            kind: kind.unwrap_or(FileType::Regular),
+            data,
+        };
+        Ok(Some((entry, kind)))
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let next = match self.format {
+                Format::Cpio => self.next_cpio(),
+                Format::Tar => self.next_tar(),
+            };
+            match next {
+                Ok(Some((entry, Some(_)))) => return Some(Ok(entry)),
+                Ok(Some((_, None))) => {}
+                Ok(None) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Unpacks the files of `archive` into the directory `root`, creating the
+/// directories missing on their paths. Returns how many files were unpacked.
+///
+/// Fails with `EINVAL` if the archive is broken, or has a path out of it.
+pub fn unpack(archive: &[u8], root: &str) -> Result<usize, Errno> {
+    let mut count = 0;
+    for entry in entries(archive).map_err(broken)? {
+        let entry = entry.map_err(broken)?;
+        let mut components = entry
+            .prefix
+            .split('/')
+            .chain(entry.name.split('/'))
+            .filter(|&component| !component.is_empty() && component != ".")
+            .peekable();
+        // The directory the archive was made from.
+        if components.peek().is_none() {
+            continue;
+        }
+
+        let mut path = String::from(root.trim_end_matches('/'));
+        while let Some(component) = components.next() {
+            if component == ".." {
+                return Err(broken("Path out of the archive"));
+            }
+            path.push('/');
+            path.push_str(component);
+            if components.peek().is_some() {
+                make_directory(&path)?;
+            }
+        }
+
+        match entry.kind {
+            FileType::Directory => make_directory(&path)?,
+            FileType::Regular => {
+                let file = super::open(&path, O_WRONLY | O_CREAT | O_TRUNC)?;
+                write_all(&file, entry.data)?;
+            }
+            FileType::Symlink => {
+                let target = str::from_utf8(entry.data).map_err(|_| broken("Link not UTF-8"))?;
+                super::symlink(target, &path)?;
+            }
+            FileType::CharDevice => continue,
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn broken(err: &'static str) -> Errno {
+    log::warn!("Broken archive: {}", err);
+    Errno::EINVAL
+}
+
+/// Creates the directory `path`, if it does not exist.
+fn make_directory(path: &str) -> Result<(), Errno> {
+    match super::mkdir(path) {
+        Ok(()) | Err(Errno::EEXIST) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_all(file: &OpenFile, mut data: &[u8]) -> Result<(), Errno> {
+    while !data.is_empty() {
+        match file.write(data)? {
+            0 => return Err(Errno::ENOSPC),
+            written => data = &data[written..],
+        }
+    }
+    Ok(())
+}
+
+/// Number written in ASCII digits of `radix`, maybe after spaces, and ended
+/// by the end of the field, a space or a NUL.
+fn number(field: &[u8], radix: u32) -> Result<u32, &'static str> {
+    let start = field
+        .iter()
+        .position(|&byte| byte != b' ')
+        .unwrap_or(field.len());
+    let field = &field[start..];
+    let end = field
+        .iter()
+        .position(|&byte| byte == b' ' || byte == 0)
+        .unwrap_or(field.len());
+    match str::from_utf8(&field[..end]) {
+        Ok("") => Ok(0),
+        Ok(digits) => u32::from_str_radix(digits, radix).map_err(|_| "Bad number"),
+        Err(_) => Err("Bad number"),
+    }
+}
+
+/// String of a field, ended by a NUL or the end of the field.
+fn c_str(field: &[u8]) -> Result<&str, &'static str> {
+    let end = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+    str::from_utf8(&field[..end]).map_err(|_| "Name not UTF-8")
+}
+
+fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
+/// Writes `value` in the digits of `radix`, a power of two, filling `field`.
+#[cfg(test)]
+fn write_number(field: &mut [u8], value: usize, radix: usize) {
+    let bits = radix.trailing_zeros() as usize;
+    for (digit, byte) in field.iter_mut().rev().enumerate() {
+        *byte = b"0123456789ABCDEF"[(value >> (bits * digit)) & (radix - 1)];
+    }
+}
+
+/// Writes a cpio entry at `offset` of the zeroed `archive`. Returns the offset
+/// of the next one.
+#[cfg(test)]
+fn cpio_entry(archive: &mut [u8], offset: usize, name: &str, mode: u32, data: &[u8]) -> usize {
+    let header = &mut archive[offset..offset + CPIO_HEADER_SIZE];
+    header[..6].copy_from_slice(b"070701");
+    for index in 0..13 {
+        write_number(&mut header[6 + 8 * index..][..8], 0, 16);
+    }
+    write_number(&mut header[6 + 8..][..8], mode as usize, 16);
+    write_number(&mut header[6 + 8 * 6..][..8], data.len(), 16);
+    write_number(&mut header[6 + 8 * 11..][..8], name.len() + 1, 16);
+
+    let start = offset + CPIO_HEADER_SIZE;
+    archive[start..start + name.len()].copy_from_slice(name.as_bytes());
+    let start = align_up(start + name.len() + 1, 4);
+    archive[start..start + data.len()].copy_from_slice(data);
+    align_up(start + data.len(), 4)
+}
+
+/// Writes a tar entry of type `kind` at `offset` of the zeroed `archive`, the
+/// `data` of a link being its target. Returns the offset of the next
+/// one.
+#[cfg(test)]
+fn tar_entry(
+    archive: &mut [u8],
+    offset: usize,
+    (prefix, name): (&str, &str),
+    kind: u8,
+    data: &[u8],
+) -> usize {
+    let link = kind == b'1' || kind == b'2';
+    let size = if link { 0 } else { data.len() };
+    let header = &mut archive[offset..offset + TAR_BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    write_number(&mut header[124..135], size, 8);
+    header[156] = kind;
+    if link {
+        header[157..157 + data.len()].copy_from_slice(data);
+    }
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    let sum = header.iter().map(|&byte| byte as usize).sum::<usize>() + 8 * b' ' as usize;
+    write_number(&mut header[148..154], sum, 8);
+    header[155] = b' ';
+
+    let start = offset + TAR_BLOCK_SIZE;
+    archive[start..start + size].copy_from_slice(&data[..size]);
+    start + align_up(size, TAR_BLOCK_SIZE)
+}
+
+#[cfg(test)]
+fn entry<'a>(prefix: &'a str, name: &'a str, kind: FileType, data: &'a [u8]) -> Entry<'a> {
+    Entry {
+        prefix,
+        name,
+        kind,
+        data,
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn cpio_archives() {
+    let mut archive = [0; 1024];
+    let mut end = cpio_entry(&mut archive, 0, ".", S_IFDIR | 0o755, b"");
+    end = cpio_entry(&mut archive, end, "etc", S_IFDIR | 0o755, b"");
+    end = cpio_entry(&mut archive, end, "etc/motd", S_IFREG | 0o644, b"Hello");
+    // A device, that is skipped.
+    end = cpio_entry(&mut archive, end, "null", 0o020_666, b"");
+    end = cpio_entry(&mut archive, end, "motd", S_IFLNK | 0o777, b"etc/motd");
+    end = cpio_entry(&mut archive, end, CPIO_TRAILER, 0, b"");
+
+    let mut files = entries(&archive[..end]).unwrap();
+    assert_eq!(files.format(), Format::Cpio);
+    let expected = [
+        entry("", ".", FileType::Directory, b""),
+        entry("", "etc", FileType::Directory, b""),
+        entry("", "etc/motd", FileType::Regular, b"Hello"),
+        entry("", "motd", FileType::Symlink, b"etc/motd"),
+    ];
+    for expected in &expected {
+        assert_eq!(files.next(), Some(Ok(*expected)));
+    }
+    assert_eq!(files.next(), None);
+
+    let mut truncated = entries(&archive[..end - 8]).unwrap();
+    assert_eq!(truncated.nth(4), Some(Err("Truncated archive")));
+    assert_eq!(truncated.next(), None);
+    assert!(entries(b"Not an archive").is_err());
+}
+
+#[cfg(test)]
+#[test_case]
+fn tar_archives() {
+    let mut archive = [0; 8 * TAR_BLOCK_SIZE];
+    let mut end = tar_entry(&mut archive, 0, ("", "etc/"), b'5', b"");
+    end = tar_entry(&mut archive, end, ("", "etc/motd"), b'0', b"Hello");
+    end = tar_entry(&mut archive, end, ("usr/share", "doc/"), 0, b"");
+    end = tar_entry(&mut archive, end, ("", "motd"), b'2', b"etc/motd");
+    // A hard link, that is skipped.
+    end = tar_entry(&mut archive, end, ("", "welcome"), b'1', b"etc/motd");
+
+    let mut files = entries(&archive[..end + 2 * TAR_BLOCK_SIZE]).unwrap();
+    assert_eq!(files.format(), Format::Tar);
+    let expected = [
+        entry("", "etc/", FileType::Directory, b""),
+        entry("", "etc/motd", FileType::Regular, b"Hello"),
+        entry("usr/share", "doc/", FileType::Directory, b""),
+        entry("", "motd", FileType::Symlink, b"etc/motd"),
+    ];
+    for expected in &expected {
+        assert_eq!(files.next(), Some(Ok(*expected)));
+    }
+    assert_eq!(files.next(), None);
+
+    // The zeroed blocks at the end can be left out.
+    assert_eq!(entries(&archive[..end]).unwrap().count(), 4);
+    archive[TAR_BLOCK_SIZE + 5] ^= 1;
+    let mut corrupted = entries(&archive[..end]).unwrap();
+    assert_eq!(corrupted.nth(1), Some(Err("Bad tar checksum")));
+}
@@ -0,0 +1,306 @@
+//! # Virtual filesystem
+//!
+//! Files are reached by path through the file systems mounted on the tree,
+//! all seen through the same traits: a `FileSystem` has a root `Inode`, the
+//! inodes of directories look up and create the others, and opening an inode
+//! gives a `File`, that reads and writes from an offset of its own.
+//!
+//! Paths are resolved one component at a time, each giving a `Dentry` that
+//! knows its parent, for `..`. A file system mounted on a directory covers it,
+//! and symbolic links are followed, up to `MAX_SYMLINKS` of them. Relative
+//! paths start at the root, as there is no working directory.
+//!
+//! The root is a `RamFs`, mounted by `init` and filled with the files of the
+//! initial ramdisk, see `initrd`. The devices are files of the `DevFs` on
+//! `/dev`, and the state of the kernel is read from the `ProcFs` on `/proc`.
+//!
+//! Processes use their files through the descriptors of their `FileTable`,
+//! inherited by forked children. The first three are the console, see
+//! `console`.
+//!
+//! # Examples
+//! ```no_run
+//! fs::mount("/", Arc::new(RamFs::new()))?;
+//! fs::mkdir("/etc")?;
+//! let file = fs::open("/etc/motd", O_WRONLY | O_CREAT)?;
+//! file.write(b"Welcome\n")?;
+//! assert_eq!(fs::stat("/etc/motd")?.size, 8);
+//! ```
+
+pub mod console;
+mod dentry;
+pub mod devfs;
+mod file;
+pub mod initrd;
+pub mod procfs;
+pub mod ramfs;
+
+use alloc::{string::String, sync::Arc};
+
+use crate::syscall::Errno;
+
+pub use self::{
+    dentry::{mount, resolve, Dentry, MAX_SYMLINKS, NAME_MAX, PATH_MAX},
+    devfs::DevFs,
+    file::{
+        FileTable, InodeFile, OpenFile, MAX_FILES, O_ACCMODE, O_APPEND, O_CREAT, O_DIRECTORY,
+        O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY,
+    },
+    procfs::ProcFs,
+    ramfs::RamFs,
+};
+
+/// Kind of an inode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum FileType {
+    Regular = 1,
+    Directory = 2,
+    Symlink = 3,
+    CharDevice = 4,
+}
+
+/// What `stat` tells of an inode, as the system call stores it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct Stat {
+    /// Number of the inode, unique in its file system.
+    pub ino: u64,
+    /// Bytes of a regular file or symbolic link, entries of a directory.
+    pub size: u64,
+    pub kind: FileType,
+    /// Directory entries linking to it.
+    pub nlink: u32,
+}
+
+/// Entry of a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub ino: u64,
+    pub kind: FileType,
+    pub name: String,
+}
+
+/// Where `seek` moves the offset of a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// To this offset.
+    Start(u64),
+    /// This far from the offset.
+    Current(i64),
+    /// This far from the end of the file.
+    End(i64),
+}
+
+/// A file system, that can be mounted.
+pub trait FileSystem: Send + Sync {
+    /// Name of the type of file system.
+    fn name(&self) -> &str;
+
+    /// Its root directory.
+    fn root(&self) -> Arc<dyn Inode>;
+}
+
+/// A file, directory or link of a file system.
+///
+/// Only `stat` is required, the other operations fail by default, as they do
+/// on inodes of the wrong kind.
+pub trait Inode: Send + Sync {
+    fn stat(&self) -> Stat;
+
+    /// Reads from `offset` into `buf`. Returns how many bytes were read, 0 at
+    /// the end of the file.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Writes `buf` at `offset`, growing the file if needed. Returns how many
+    /// bytes were written.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Cuts or grows the file to `size` bytes.
+    fn truncate(&self, _size: u64) -> Result<(), Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// Inode of the entry `name` of the directory.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Adds to the directory an empty inode of `kind`, named `name`.
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Adds to the directory a symbolic link to `target`, named `name`.
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Removes the entry `name` from the directory, if it is not a non-empty
+    /// directory.
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Entry number `index` of the directory, or `None` past the last one.
+    /// `.` and `..` are not listed.
+    fn readdir(&self, _index: usize) -> Result<Option<DirEntry>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Target of the symbolic link.
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// File of its own for the inode opened with `flags`, or `None` for an
+    /// `InodeFile`. Devices use it.
+    fn open(&self, _flags: u32) -> Result<Option<Arc<dyn File>>, Errno> {
+        Ok(None)
+    }
+}
+
+/// An open file, shared by the descriptors it was duplicated to.
+pub trait File: Send + Sync {
+    /// Reads into `buf`. Returns how many bytes were read, 0 at the end of
+    /// the file.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno>;
+
+    /// Writes `buf`. Returns how many bytes were written.
+    fn write(&self, buf: &[u8]) -> Result<usize, Errno>;
+
+    /// Moves the offset of the file. Returns the new offset.
+    fn seek(&self, _pos: SeekFrom) -> Result<u64, Errno> {
+        Err(Errno::ESPIPE)
+    }
+
+    fn stat(&self) -> Result<Stat, Errno>;
+
+    /// Next entry of the directory, or `None` past the last one.
+    fn readdir(&self) -> Result<Option<DirEntry>, Errno> {
+        Err(Errno::ENOTDIR)
+    }
+
+    /// Runs the device specific `request`, with `arg`. Returns its result.
+    fn ioctl(&self, _request: u64, _arg: u64) -> Result<u64, Errno> {
+        Err(Errno::ENOTTY)
+    }
+}
+
+/// Mounts a `RamFs` as the root, unpacks the initial ramdisk into it, then
+/// mounts a `DevFs` on `/dev` and a `ProcFs` on `/proc`.
+pub fn init() -> Result<(), &'static str> {
+    mount("/", Arc::new(RamFs::new())).map_err(|_| "Root file system already mounted")?;
+    let count = initrd::unpack(initrd::ARCHIVE, "/").map_err(|_| "Initial ramdisk not unpacked")?;
+    log::info!("Unpacked {} files of the initial ramdisk", count);
+
+    match mkdir("/dev") {
+        Ok(()) | Err(Errno::EEXIST) => {}
+        Err(_) => return Err("/dev not created"),
+    }
+    mount("/dev", Arc::new(DevFs::new())).map_err(|_| "Devices not mounted")?;
+
+    match mkdir("/proc") {
+        Ok(()) | Err(Errno::EEXIST) => {}
+        Err(_) => return Err("/proc not created"),
+    }
+    mount("/proc", Arc::new(ProcFs::new())).map_err(|_| "Kernel state not mounted")
+}
+
+/// Opens the file at `path` with the `O_*` `flags`, creating it with
+/// `O_CREAT`.
+pub fn open(path: &str, flags: u32) -> Result<OpenFile, Errno> {
+    let create = flags & O_CREAT != 0;
+    let dentry = match resolve(path, true) {
+        Ok(_) if create && flags & O_EXCL != 0 => return Err(Errno::EEXIST),
+        Ok(dentry) => dentry,
+        Err(Errno::ENOENT) if create => {
+            let (parent, name) = dentry::resolve_parent(path)?;
+            let inode = parent.inode().create(name, FileType::Regular)?;
+            parent.child(name, inode)
+        }
+        Err(err) => return Err(err),
+    };
+
+    let inode = dentry.inode();
+    let kind = inode.stat().kind;
+    let write = flags & O_ACCMODE != O_RDONLY;
+    if flags & O_DIRECTORY != 0 && kind != FileType::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+    if kind == FileType::Directory && write {
+        return Err(Errno::EISDIR);
+    }
+    if flags & O_TRUNC != 0 && write && kind == FileType::Regular {
+        inode.truncate(0)?;
+    }
+
+    OpenFile::open(inode.clone(), flags)
+}
+
+/// What `stat` tells of the file at `path`, symbolic links followed.
+pub fn stat(path: &str) -> Result<Stat, Errno> {
+    Ok(resolve(path, true)?.inode().stat())
+}
+
+/// What `stat` tells of the file at `path`, or of the symbolic link there.
+pub fn lstat(path: &str) -> Result<Stat, Errno> {
+    Ok(resolve(path, false)?.inode().stat())
+}
+
+/// Cuts or grows the regular file at `path` to `size` bytes.
+pub fn truncate(path: &str, size: u64) -> Result<(), Errno> {
+    let dentry = resolve(path, true)?;
+    match dentry.inode().stat().kind {
+        FileType::Regular => dentry.inode().truncate(size),
+        FileType::Directory => Err(Errno::EISDIR),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+/// Creates the directory `path`.
+pub fn mkdir(path: &str) -> Result<(), Errno> {
+    let (parent, name) = dentry::resolve_parent(path)?;
+    match parent.inode().lookup(name) {
+        Ok(_) => Err(Errno::EEXIST),
+        Err(Errno::ENOENT) => parent.inode().create(name, FileType::Directory).map(|_| ()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the file at `path`, that must not be a directory.
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    let (parent, name) = dentry::resolve_parent(path)?;
+    if parent.inode().lookup(name)?.stat().kind == FileType::Directory {
+        return Err(Errno::EISDIR);
+    }
+    parent.inode().unlink(name)
+}
+
+/// Removes the empty directory `path`, that nothing is mounted on.
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    let (parent, name) = dentry::resolve_parent(path)?;
+    let inode = parent.inode().lookup(name)?;
+    if inode.stat().kind != FileType::Directory {
+        return Err(Errno::ENOTDIR);
+    }
+    if parent.child(name, inode).is_mount_point() {
+        return Err(Errno::EBUSY);
+    }
+    parent.inode().unlink(name)
+}
+
+/// Creates at `path` a symbolic link to `target`.
+pub fn symlink(target: &str, path: &str) -> Result<(), Errno> {
+    let (parent, name) = dentry::resolve_parent(path)?;
+    parent.inode().symlink(name, target).map(|_| ())
+}
+
+/// Target of the symbolic link at `path`.
+pub fn readlink(path: &str) -> Result<String, Errno> {
+    resolve(path, false)?.inode().readlink()
+}
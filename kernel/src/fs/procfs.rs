@@ -0,0 +1,419 @@
+//! File system of the kernel state, mounted on `/proc` by `fs::init`. Its
+//! files are text, written when they are opened:
+//!
+//! | File         | Contents                                               |
+//! |--------------|--------------------------------------------------------|
+//! | `cpuinfo`    | Vendor, model and features of the CPU, from `cpuid`    |
+//! | `interrupts` | Times each interrupt vector was handled, and its name  |
+//! | `kmsg`       | The lines of the kernel log, see `logger::dmesg`       |
+//! | `meminfo`    | Use of the physical frames and of the kernel heap      |
+//! | `memmap`     | Regions of the memory map given by the bootloader      |
+//! | `processes`  | The process table, as `process::ps` prints it          |
+//! | `uptime`     | Seconds since boot                                     |
+//!
+//! An open file keeps what it read when it was opened, so it can be read in
+//! pieces and seeked in. Its size is 0 until then. The files can not be
+//! written.
+
+use alloc::{string::String, sync::Arc};
+use core::{
+    arch::x86_64::{__cpuid_count, CpuidResult},
+    fmt::{self, Write},
+    str,
+};
+
+use super::{
+    file, DirEntry, File, FileSystem, FileType, Inode, SeekFrom, Stat, O_ACCMODE, O_RDONLY,
+};
+use crate::{
+    init::idt,
+    logger::dmesg,
+    mem::{self, heap, FRAME_SIZE},
+    process,
+    sync::Mutex,
+    syscall::Errno,
+    time,
+};
+
+/// Inode number of the root directory, the files following it.
+const ROOT_INO: u64 = 1;
+
+/// Writes the contents of a file.
+type Generate = fn(&mut String) -> fmt::Result;
+
+/// The files, by name.
+const FILES: [(&str, Generate); 7] = [
+    ("cpuinfo", cpuinfo),
+    ("interrupts", interrupts),
+    ("kmsg", kmsg),
+    ("meminfo", meminfo),
+    ("memmap", memmap),
+    ("processes", processes),
+    ("uptime", uptime),
+];
+
+/// The kernel state, in a file system.
+pub struct ProcFs {
+    root: Arc<ProcDirectory>,
+}
+
+impl ProcFs {
+    pub fn new() -> ProcFs {
+        ProcFs {
+            root: Arc::new(ProcDirectory),
+        }
+    }
+}
+
+impl Default for ProcFs {
+    fn default() -> ProcFs {
+        ProcFs::new()
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &str {
+        "procfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// The root, and only directory.
+struct ProcDirectory;
+
+impl Inode for ProcDirectory {
+    fn stat(&self) -> Stat {
+        Stat {
+            ino: ROOT_INO,
+            size: FILES.len() as u64,
+            kind: FileType::Directory,
+            nlink: 2,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        match FILES.iter().position(|&(file, _)| file == name) {
+            Some(index) => Ok(Arc::new(ProcInode::new(index))),
+            None => Err(Errno::ENOENT),
+        }
+    }
+
+    fn create(&self, _name: &str, _kind: FileType) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        Ok(FILES.get(index).map(|&(name, _)| DirEntry {
+            ino: ProcInode::new(index).ino,
+            kind: FileType::Regular,
+            name: name.into(),
+        }))
+    }
+}
+
+/// Inode of a file, writing its contents when opened.
+struct ProcInode {
+    ino: u64,
+    generate: Generate,
+}
+
+impl ProcInode {
+    fn new(index: usize) -> ProcInode {
+        ProcInode {
+            ino: ROOT_INO + 1 + index as u64,
+            generate: FILES[index].1,
+        }
+    }
+}
+
+impl Inode for ProcInode {
+    fn stat(&self) -> Stat {
+        Stat {
+            ino: self.ino,
+            size: 0,
+            kind: FileType::Regular,
+            nlink: 1,
+        }
+    }
+
+    fn open(&self, flags: u32) -> Result<Option<Arc<dyn File>>, Errno> {
+        if flags & O_ACCMODE != O_RDONLY {
+            return Err(Errno::EPERM);
+        }
+        let mut text = String::new();
+        (self.generate)(&mut text).map_err(|_| Errno::ENOMEM)?;
+        Ok(Some(Arc::new(ProcFile {
+            ino: self.ino,
+            text,
+            offset: Mutex::new(0),
+        })))
+    }
+}
+
+/// An open file, with the contents written when it was opened.
+struct ProcFile {
+    ino: u64,
+    text: String,
+    offset: Mutex<u64>,
+}
+
+impl File for ProcFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let text = self.text.as_bytes();
+        let start = (*offset as usize).min(text.len());
+        let read = buf.len().min(text.len() - start);
+        buf[..read].copy_from_slice(&text[start..start + read]);
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    fn seek(&self, pos: SeekFrom) -> Result<u64, Errno> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(start) => Some(start),
+            SeekFrom::Current(delta) => file::add(*offset, delta),
+            SeekFrom::End(delta) => file::add(self.text.len() as u64, delta),
+        };
+        *offset = new
+            .filter(|&new| new <= i64::MAX as u64)
+            .ok_or(Errno::EINVAL)?;
+        Ok(*offset)
+    }
+
+    fn stat(&self) -> Result<Stat, Errno> {
+        Ok(Stat {
+            ino: self.ino,
+            size: self.text.len() as u64,
+            kind: FileType::Regular,
+            nlink: 1,
+        })
+    }
+}
+
+/// Kibibytes in `frames`.
+fn frames_kb(frames: usize) -> u64 {
+    frames as u64 * FRAME_SIZE / 1024
+}
+
+fn meminfo(out: &mut String) -> fmt::Result {
+    if let Some(frames) = mem::frame_stats() {
+        writeln!(out, "MemTotal:        {:>8} kB", frames_kb(frames.usable))?;
+        let free = frames.usable.saturating_sub(frames.allocated);
+        writeln!(out, "MemFree:         {:>8} kB", frames_kb(free))?;
+        writeln!(out, "FramesUsable:    {:>8}", frames.usable)?;
+        writeln!(out, "FramesAllocated: {:>8}", frames.allocated)?;
+        writeln!(out, "FramesFreed:     {:>8}", frames.free)?;
+    }
+    let heap = heap::stats();
+    writeln!(out, "HeapTotal:       {:>8} kB", heap.size / 1024)?;
+    writeln!(out, "HeapUsed:        {:>8} kB", heap.used / 1024)
+}
+
+/// Vectors with a handler, and those handled without one.
+fn interrupts(out: &mut String) -> fmt::Result {
+    for vector in 0..=u8::MAX {
+        let count = idt::interrupt_count(vector);
+        match idt::interrupt_name(vector) {
+            Some(name) => writeln!(out, "{:>3}: {:>10} {}", vector, count, name)?,
+            None if count > 0 => writeln!(out, "{:>3}: {:>10}", vector, count)?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Seconds since boot, to the hundredth.
+fn uptime(out: &mut String) -> fmt::Result {
+    let uptime = time::uptime();
+    writeln!(
+        out,
+        "{}.{:02}",
+        uptime.as_secs(),
+        uptime.subsec_millis() / 10
+    )
+}
+
+fn processes(out: &mut String) -> fmt::Result {
+    process::write_table(out)
+}
+
+/// The lines of the kernel log, each copied out of it before being written,
+/// so the log is not locked while allocating.
+fn kmsg(out: &mut String) -> fmt::Result {
+    for seq in dmesg::first_seq()..dmesg::next_seq() {
+        if let Some(line) = dmesg::get(seq) {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// The regions, one per line: their physical addresses, then their type.
+fn memmap(out: &mut String) -> fmt::Result {
+    for region in mem::memory_map().into_iter().flat_map(|map| map.iter()) {
+        writeln!(
+            out,
+            "{:016x}-{:016x} {:?}",
+            region.range.start_addr(),
+            region.range.end_addr(),
+            region.region_type
+        )?;
+    }
+    Ok(())
+}
+
+/// Features, by register of the `cpuid` leaf giving them and bit.
+const LEAF1_EDX: &[(u32, &str)] = &[
+    (0, "fpu"),
+    (1, "vme"),
+    (2, "de"),
+    (3, "pse"),
+    (4, "tsc"),
+    (5, "msr"),
+    (6, "pae"),
+    (7, "mce"),
+    (8, "cx8"),
+    (9, "apic"),
+    (11, "sep"),
+    (12, "mtrr"),
+    (13, "pge"),
+    (14, "mca"),
+    (15, "cmov"),
+    (16, "pat"),
+    (17, "pse36"),
+    (19, "clflush"),
+    (23, "mmx"),
+    (24, "fxsr"),
+    (25, "sse"),
+    (26, "sse2"),
+    (28, "ht"),
+];
+const LEAF1_ECX: &[(u32, &str)] = &[
+    (0, "sse3"),
+    (1, "pclmulqdq"),
+    (9, "ssse3"),
+    (12, "fma"),
+    (13, "cx16"),
+    (19, "sse4_1"),
+    (20, "sse4_2"),
+    (21, "x2apic"),
+    (22, "movbe"),
+    (23, "popcnt"),
+    (25, "aes"),
+    (26, "xsave"),
+    (28, "avx"),
+    (29, "f16c"),
+    (30, "rdrand"),
+    (31, "hypervisor"),
+];
+const LEAF7_EBX: &[(u32, &str)] = &[
+    (0, "fsgsbase"),
+    (3, "bmi1"),
+    (5, "avx2"),
+    (7, "smep"),
+    (8, "bmi2"),
+    (18, "rdseed"),
+    (20, "smap"),
+];
+const EXTENDED_EDX: &[(u32, &str)] = &[
+    (11, "syscall"),
+    (20, "nx"),
+    (26, "pdpe1gb"),
+    (27, "rdtscp"),
+    (29, "lm"),
+];
+const EXTENDED_ECX: &[(u32, &str)] = &[(0, "lahf_lm"), (5, "abm")];
+
+/// Leaf `leaf` of `cpuid`, its first subleaf for those that have some.
+fn cpuid(leaf: u32) -> CpuidResult {
+    // Every x86_64 CPU has `cpuid`.
+    unsafe { __cpuid_count(leaf, 0) }
+}
+
+fn cpuinfo(out: &mut String) -> fmt::Result {
+    let leaf0 = cpuid(0);
+    let mut vendor = [0; 12];
+    vendor[..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+    vendor[8..].copy_from_slice(&leaf0.ecx.to_le_bytes());
+    writeln!(
+        out,
+        "vendor_id  : {}",
+        str::from_utf8(&vendor).unwrap_or("?")
+    )?;
+
+    let extended = cpuid(0x8000_0000).eax;
+    if extended >= 0x8000_0004 {
+        let mut brand = [0; 48];
+        for (index, chunk) in brand.chunks_mut(16).enumerate() {
+            let leaf = cpuid(0x8000_0002 + index as u32);
+            chunk[..4].copy_from_slice(&leaf.eax.to_le_bytes());
+            chunk[4..8].copy_from_slice(&leaf.ebx.to_le_bytes());
+            chunk[8..12].copy_from_slice(&leaf.ecx.to_le_bytes());
+            chunk[12..].copy_from_slice(&leaf.edx.to_le_bytes());
+        }
+        let brand = str::from_utf8(&brand).unwrap_or("?");
+        writeln!(
+            out,
+            "model name : {}",
+            brand.trim_matches(|c| c == '\0' || c == ' ')
+        )?;
+    }
+
+    let leaf1 = cpuid(1);
+    let mut family = (leaf1.eax >> 8) & 0xF;
+    let mut model = (leaf1.eax >> 4) & 0xF;
+    if family == 0xF {
+        family += (leaf1.eax >> 20) & 0xFF;
+    }
+    if family == 0x6 || family >= 0xF {
+        model += ((leaf1.eax >> 16) & 0xF) << 4;
+    }
+    writeln!(out, "family     : {}", family)?;
+    writeln!(out, "model      : {}", model)?;
+    writeln!(out, "stepping   : {}", leaf1.eax & 0xF)?;
+
+    let leaf7_ebx = match leaf0.eax {
+        max if max >= 7 => cpuid(7).ebx,
+        _ => 0,
+    };
+    let (extended_ecx, extended_edx) = match extended {
+        max if max >= 0x8000_0001 => {
+            let leaf = cpuid(0x8000_0001);
+            (leaf.ecx, leaf.edx)
+        }
+        _ => (0, 0),
+    };
+    out.push_str("flags      :");
+    let registers = [
+        (leaf1.edx, LEAF1_EDX),
+        (leaf1.ecx, LEAF1_ECX),
+        (leaf7_ebx, LEAF7_EBX),
+        (extended_edx, EXTENDED_EDX),
+        (extended_ecx, EXTENDED_ECX),
+    ];
+    for &(register, features) in registers.iter() {
+        for &(bit, name) in features {
+            if register & (1 << bit) != 0 {
+                write!(out, " {}", name)?;
+            }
+        }
+    }
+    writeln!(out)
+}
@@ -0,0 +1,355 @@
+//! File system in memory: its directories, files and symbolic links are kept on
+//! the kernel heap, and gone when it is dropped.
+//!
+//! Files are stored by pages, allocated when written to. The holes left by
+//! writing past the end, or growing a file with `truncate`, read as zeros
+//! without taking memory. The pages of every `RamFs` together are limited to
+//! `MAX_PAGES`, so files can not fill the heap: past it, writes fail with
+//! `ENOSPC`.
+
+use alloc::{
+    alloc::{alloc_zeroed, Layout},
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
+use core::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use super::{DirEntry, FileSystem, FileType, Inode, Stat};
+use crate::{mem::heap::HEAP_SIZE, sync::Mutex, syscall::Errno};
+
+/// Bytes of the pages of files.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Largest size of a file.
+pub const MAX_FILE_SIZE: u64 = 1 << 32;
+
+/// Most pages the files of every `RamFs` take together, a quarter of the
+/// heap.
+pub const MAX_PAGES: usize = HEAP_SIZE / PAGE_SIZE / 4;
+
+type Page = [u8; PAGE_SIZE];
+
+/// Numbers of the inodes of every `RamFs`.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+/// Pages of the files of every `RamFs`.
+static PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of pages the files of every `RamFs` take, at most `MAX_PAGES`.
+pub fn pages() -> usize {
+    PAGES.load(Ordering::Relaxed)
+}
+
+/// A file system in memory, empty when created.
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> RamFs {
+        RamFs {
+            root: RamInode::new(Content::Directory(BTreeMap::new())),
+        }
+    }
+}
+
+impl Default for RamFs {
+    fn default() -> RamFs {
+        RamFs::new()
+    }
+}
+
+impl FileSystem for RamFs {
+    fn name(&self) -> &str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+enum Content {
+    Regular {
+        size: u64,
+        /// Pages written to, by index.
+        pages: BTreeMap<u64, FilePage>,
+    },
+    /// Entries, by name.
+    Directory(BTreeMap<String, Arc<RamInode>>),
+    /// Target of the link.
+    Symlink(String),
+}
+
+impl Content {
+    fn kind(&self) -> FileType {
+        match self {
+            Content::Regular { .. } => FileType::Regular,
+            Content::Directory(_) => FileType::Directory,
+            Content::Symlink(_) => FileType::Symlink,
+        }
+    }
+
+    fn entries(&mut self) -> Result<&mut BTreeMap<String, Arc<RamInode>>, Errno> {
+        match self {
+            Content::Directory(entries) => Ok(entries),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    fn file(&mut self) -> Result<(&mut u64, &mut BTreeMap<u64, FilePage>), Errno> {
+        match self {
+            Content::Regular { size, pages } => Ok((size, pages)),
+            Content::Directory(_) => Err(Errno::EISDIR),
+            Content::Symlink(_) => Err(Errno::EINVAL),
+        }
+    }
+}
+
+/// An inode of a `RamFs`.
+pub struct RamInode {
+    ino: u64,
+    content: Mutex<Content>,
+}
+
+impl RamInode {
+    fn new(content: Content) -> Arc<RamInode> {
+        Arc::new(RamInode {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            content: Mutex::new(content),
+        })
+    }
+
+    /// Adds the entry `name`, with `content`, to the directory.
+    fn add(&self, name: &str, content: Content) -> Result<Arc<dyn Inode>, Errno> {
+        check_name(name)?;
+        let mut directory = self.content.lock();
+        let entries = directory.entries()?;
+        if entries.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+
+        let inode = RamInode::new(content);
+        entries.insert(name.to_string(), inode.clone());
+        Ok(inode)
+    }
+
+    fn kind(&self) -> FileType {
+        self.content.lock().kind()
+    }
+}
+
+impl Inode for RamInode {
+    fn stat(&self) -> Stat {
+        let content = self.content.lock();
+        let (size, nlink) = match &*content {
+            Content::Regular { size, .. } => (*size, 1),
+            // Linked from its parent, itself and its subdirectories.
+            Content::Directory(entries) => {
+                let subdirectories = entries
+                    .values()
+                    .filter(|inode| inode.kind() == FileType::Directory)
+                    .count();
+                (entries.len() as u64, 2 + subdirectories as u32)
+            }
+            Content::Symlink(target) => (target.len() as u64, 1),
+        };
+
+        Stat {
+            ino: self.ino,
+            size,
+            kind: content.kind(),
+            nlink,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut content = self.content.lock();
+        let (&mut size, pages) = content.file()?;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((size - offset) as usize);
+        let mut done = 0;
+        while done < len {
+            let position = offset + done as u64;
+            let start = position as usize % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - start).min(len - done);
+            let buf = &mut buf[done..][..chunk];
+            match pages.get(&(position / PAGE_SIZE as u64)) {
+                Some(page) => buf.copy_from_slice(&page[start..][..chunk]),
+                None => buf.iter_mut().for_each(|byte| *byte = 0),
+            }
+            done += chunk;
+        }
+
+        Ok(len)
+    }
+
+    /// Writes `buf` at `offset`, or what fits before the memory runs out.
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, Errno> {
+        let mut content = self.content.lock();
+        let (size, pages) = content.file()?;
+        match offset.checked_add(buf.len() as u64) {
+            Some(end) if end <= MAX_FILE_SIZE => {}
+            _ => return Err(Errno::EFBIG),
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let start = position as usize % PAGE_SIZE;
+            let chunk = (PAGE_SIZE - start).min(buf.len() - done);
+            let index = position / PAGE_SIZE as u64;
+            if !pages.contains_key(&index) {
+                match new_page() {
+                    Ok(page) => pages.insert(index, page),
+                    Err(_) if done > 0 => break,
+                    Err(err) => return Err(err),
+                };
+            }
+
+            let page = pages.get_mut(&index).unwrap();
+            page[start..][..chunk].copy_from_slice(&buf[done..][..chunk]);
+            done += chunk;
+        }
+
+        *size = (*size).max(offset + done as u64);
+        Ok(done)
+    }
+
+    fn truncate(&self, new_size: u64) -> Result<(), Errno> {
+        let mut content = self.content.lock();
+        let (size, pages) = content.file()?;
+        if new_size > MAX_FILE_SIZE {
+            return Err(Errno::EFBIG);
+        }
+
+        if new_size < *size {
+            let page_size = PAGE_SIZE as u64;
+            pages.split_off(&((new_size + page_size - 1) / page_size));
+            // So the bytes cut off read as zeros if the file grows again.
+            let end = new_size as usize % PAGE_SIZE;
+            if let Some(page) = pages.get_mut(&(new_size / page_size)) {
+                page[end..].iter_mut().for_each(|byte| *byte = 0);
+            }
+        }
+        *size = new_size;
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        let mut directory = self.content.lock();
+        match directory.entries()?.get(name) {
+            Some(inode) => Ok(inode.clone()),
+            None => Err(Errno::ENOENT),
+        }
+    }
+
+    fn create(&self, name: &str, kind: FileType) -> Result<Arc<dyn Inode>, Errno> {
+        let content = match kind {
+            FileType::Regular => Content::Regular {
+                size: 0,
+                pages: BTreeMap::new(),
+            },
+            FileType::Directory => Content::Directory(BTreeMap::new()),
+            FileType::Symlink | FileType::CharDevice => return Err(Errno::EINVAL),
+        };
+        self.add(name, content)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        if target.is_empty() {
+            return Err(Errno::ENOENT);
+        }
+        self.add(name, Content::Symlink(target.to_string()))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let mut directory = self.content.lock();
+        let entries = directory.entries()?;
+        let inode = entries.get(name).ok_or(Errno::ENOENT)?;
+        if let Content::Directory(children) = &*inode.content.lock() {
+            if !children.is_empty() {
+                return Err(Errno::ENOTEMPTY);
+            }
+        }
+
+        // Open files keep the inode until they are closed.
+        entries.remove(name);
+        Ok(())
+    }
+
+    /// Entries are listed by name.
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        let mut directory = self.content.lock();
+        let entry = directory.entries()?.iter().nth(index);
+        Ok(entry.map(|(name, inode)| DirEntry {
+            ino: inode.ino,
+            kind: inode.kind(),
+            name: name.clone(),
+        }))
+    }
+
+    fn readlink(&self) -> Result<String, Errno> {
+        match &*self.content.lock() {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
+
+/// A page of a file, counted in `PAGES` until dropped.
+struct FilePage(Box<Page>);
+
+impl Deref for FilePage {
+    type Target = Page;
+
+    fn deref(&self) -> &Page {
+        &self.0
+    }
+}
+
+impl DerefMut for FilePage {
+    fn deref_mut(&mut self) -> &mut Page {
+        &mut self.0
+    }
+}
+
+impl Drop for FilePage {
+    fn drop(&mut self) {
+        PAGES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A zeroed page, or `ENOSPC` if the files already take `MAX_PAGES`, or the
+/// heap is full.
+fn new_page() -> Result<FilePage, Errno> {
+    if PAGES.fetch_add(1, Ordering::Relaxed) >= MAX_PAGES {
+        PAGES.fetch_sub(1, Ordering::Relaxed);
+        return Err(Errno::ENOSPC);
+    }
+
+    let page = unsafe { alloc_zeroed(Layout::new::<Page>()) } as *mut Page;
+    if page.is_null() {
+        PAGES.fetch_sub(1, Ordering::Relaxed);
+        return Err(Errno::ENOSPC);
+    }
+    Ok(FilePage(unsafe { Box::from_raw(page) }))
+}
+
+/// Checks that `name` can name an entry.
+fn check_name(name: &str) -> Result<(), Errno> {
+    match name {
+        "" | "." | ".." => Err(Errno::EINVAL),
+        _ if name.contains('/') => Err(Errno::EINVAL),
+        _ if name.len() > super::NAME_MAX => Err(Errno::ENAMETOOLONG),
+        _ => Ok(()),
+    }
+}
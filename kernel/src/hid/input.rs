@@ -0,0 +1,306 @@
+//! # Kernel input layer
+//!
+//! Turns what the HID drivers collected during interrupts into events that
+//! any part of the kernel can consume, either by polling or as an async
+//! `Stream`.
+//!
+//! Each device can be read on its own (`poll_key`, `KeyStream`, `poll_mouse`,
+//! `MouseStream`), or all of them together as `InputEvent`s (`poll_event`,
+//! `EventStream`).
+//!
+//! # Examples
+//! ```no_run
+//! // Polling
+//! while let Some(event) = input::poll_key() {
+//!     kprintln!("{:?}", event);
+//! }
+//!
+//! // Async
+//! let mut keyboard = input::KeyStream::new();
+//! let event = keyboard.next_key().await;
+//!
+//! let mut events = input::EventStream::new();
+//! while let Some(event) = events.next().await {
+//!     kprintln!("{:?}", event);
+//! }
+//! ```
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{stream::Stream, task::AtomicWaker};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+pub use crate::hid::{pckbd::Modifiers, ps2mouse::MouseButtons};
+pub use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+use crate::hid::{
+    layout::Layout,
+    pckbd::{self, PCKeyboard},
+    ps2mouse::{self, Ps2Mouse},
+};
+
+/// A key pressed or released.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// Physical key.
+    pub code: KeyCode,
+    /// Whether the key went down or up.
+    pub state: KeyState,
+    /// Modifiers active after this event was processed.
+    pub modifiers: Modifiers,
+    /// What the key means in the current layout. Only present when a
+    /// non-modifier key is pressed.
+    pub key: Option<DecodedKey>,
+}
+
+impl KeyEvent {
+    /// The decoded character, if the key has one.
+    pub fn char(&self) -> Option<char> {
+        match self.key {
+            Some(DecodedKey::Unicode(c)) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+/// Mouse movement and buttons state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Horizontal movement, positive to the right.
+    pub dx: i16,
+    /// Vertical movement, positive upwards.
+    pub dy: i16,
+    /// Scroll wheel movement, positive downwards. Always 0 without a wheel.
+    pub wheel: i8,
+    /// Buttons held.
+    pub buttons: MouseButtons,
+}
+
+/// Any input event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+lazy_static! {
+    /// Decoder for the scancodes of the PS/2 keyboard.
+    ///
+    /// Only consumers of input lock it, never the interrupt handlers.
+    static ref KEYBOARD: Mutex<PCKeyboard> = Mutex::new(PCKeyboard::new(Layout::from_cmdline()));
+}
+
+/// The PS/2 mouse and its packet decoder.
+///
+/// Only consumers of input lock it, never the interrupt handlers.
+static MOUSE: Mutex<Ps2Mouse> = Mutex::new(Ps2Mouse::new());
+
+/// Wakes the task waiting on a `KeyStream`.
+static KEYBOARD_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Wakes the task waiting on a `MouseStream`.
+static MOUSE_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Wakes the task waiting on an `EventStream`.
+static EVENT_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Notifies the keyboard consumers that there is new input.
+///
+/// Safe to call from interrupt handlers.
+pub fn wake_keyboard() {
+    KEYBOARD_WAKER.wake();
+    EVENT_WAKER.wake();
+}
+
+/// Notifies the mouse consumers that there is new input.
+///
+/// Safe to call from interrupt handlers.
+pub fn wake_mouse() {
+    MOUSE_WAKER.wake();
+    EVENT_WAKER.wake();
+}
+
+/// Initialize the PS/2 mouse and start receiving its interrupts.
+///
+/// Must be called after the IDT and the PICs are initialized.
+pub fn init_mouse() -> Result<(), &'static str> {
+    use crate::init::{idt::MOUSE_INTERRUPT_ID, pic};
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        unsafe { MOUSE.lock().init()? };
+        unsafe { pic::unmask(MOUSE_INTERRUPT_ID - pic::PIC_1_OFFSET) };
+
+        Ok(())
+    })
+}
+
+/// Returns the next key event, or `None` if there are no pending scancodes.
+pub fn poll_key() -> Option<KeyEvent> {
+    let mut keyboard = KEYBOARD.lock();
+
+    if let Some(event) = keyboard.take_pending() {
+        return Some(event);
+    }
+    while let Some(scancode) = pckbd::read_scancode() {
+        if let Some(event) = keyboard.add_byte(scancode) {
+            return Some(event);
+        }
+    }
+
+    None
+}
+
+/// Returns the next mouse event, or `None` if there is no complete packet.
+pub fn poll_mouse() -> Option<MouseEvent> {
+    let mut mouse = MOUSE.lock();
+
+    while let Some(byte) = ps2mouse::read_byte() {
+        if let Some(event) = mouse.add_byte(byte) {
+            return Some(event);
+        }
+    }
+
+    None
+}
+
+/// Returns the next event of any input device.
+pub fn poll_event() -> Option<InputEvent> {
+    poll_key()
+        .map(InputEvent::Key)
+        .or_else(|| poll_mouse().map(InputEvent::Mouse))
+}
+
+/// Modifiers currently active on the keyboard.
+pub fn modifiers() -> Modifiers {
+    KEYBOARD.lock().modifiers()
+}
+
+/// Layout used to decode the keyboard.
+pub fn layout() -> Layout {
+    KEYBOARD.lock().layout()
+}
+
+/// Changes the layout used to decode the keyboard.
+///
+/// The initial layout comes from the `kbd.layout` boot parameter.
+pub fn set_layout(layout: Layout) {
+    KEYBOARD.lock().set_layout(layout);
+}
+
+/// Polls `poll` and, if there is nothing, registers the waker and polls again
+/// in case the input arrived in between.
+fn poll_with<T>(
+    cx: &mut Context,
+    waker: &AtomicWaker,
+    poll: impl Fn() -> Option<T>,
+) -> Poll<Option<T>> {
+    if let Some(event) = poll() {
+        return Poll::Ready(Some(event));
+    }
+
+    waker.register(cx.waker());
+
+    match poll() {
+        Some(event) => {
+            waker.take();
+            Poll::Ready(Some(event))
+        }
+        None => Poll::Pending,
+    }
+}
+
+/// Asynchronous stream of key events.
+///
+/// All the streams share the same source, so each event is handed to only
+/// one of them; in practice a single consumer should own the keyboard.
+#[derive(Debug, Default)]
+pub struct KeyStream {
+    _private: (),
+}
+
+impl KeyStream {
+    /// Creates a new instance of KeyStream.
+    pub fn new() -> KeyStream {
+        KeyStream { _private: () }
+    }
+
+    /// Waits for the next key event.
+    pub fn next_key(&mut self) -> NextKey<'_> {
+        NextKey { _stream: self }
+    }
+}
+
+impl Stream for KeyStream {
+    type Item = KeyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        poll_with(cx, &KEYBOARD_WAKER, poll_key)
+    }
+}
+
+/// Future returned by `KeyStream::next_key`.
+#[derive(Debug)]
+pub struct NextKey<'a> {
+    _stream: &'a mut KeyStream,
+}
+
+impl Future for NextKey<'_> {
+    type Output = KeyEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<KeyEvent> {
+        match poll_with(cx, &KEYBOARD_WAKER, poll_key) {
+            Poll::Ready(Some(event)) => Poll::Ready(event),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+/// Asynchronous stream of mouse events.
+///
+/// Like `KeyStream`, a single consumer should own the mouse.
+#[derive(Debug, Default)]
+pub struct MouseStream {
+    _private: (),
+}
+
+impl MouseStream {
+    /// Creates a new instance of MouseStream.
+    pub fn new() -> MouseStream {
+        MouseStream { _private: () }
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        poll_with(cx, &MOUSE_WAKER, poll_mouse)
+    }
+}
+
+/// Asynchronous stream of the events of every input device.
+#[derive(Debug, Default)]
+pub struct EventStream {
+    _private: (),
+}
+
+impl EventStream {
+    /// Creates a new instance of EventStream.
+    pub fn new() -> EventStream {
+        EventStream { _private: () }
+    }
+}
+
+impl Stream for EventStream {
+    type Item = InputEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<InputEvent>> {
+        poll_with(cx, &EVENT_WAKER, poll_event)
+    }
+}
@@ -0,0 +1,351 @@
+//! Keyboard layouts selectable at runtime.
+//!
+//! The layouts from `pc_keyboard` are reused when they exist, the others are
+//! written here on top of the US layout, only handling the keys that differ.
+//! Dead keys are reported as `Mapped::Dead` and composed by the keyboard
+//! decoder with the next key pressed.
+
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyboardLayout};
+
+use crate::hid::pckbd::Modifiers;
+
+/// Keyboard layouts known by the kernel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// United States, 104 keys
+    Us104,
+    /// United Kingdom, 105 keys
+    Uk105,
+    /// German QWERTZ, 105 keys
+    De105,
+    /// French AZERTY, 105 keys
+    Fr105,
+    /// Dvorak, 104 keys
+    Dvorak104,
+    /// Brazilian ABNT2
+    Abnt2,
+}
+
+/// Every layout available, with the name used to select it.
+pub const LAYOUTS: [(&str, Layout); 6] = [
+    ("us", Layout::Us104),
+    ("uk", Layout::Uk105),
+    ("de", Layout::De105),
+    ("fr", Layout::Fr105),
+    ("dvorak", Layout::Dvorak104),
+    ("abnt2", Layout::Abnt2),
+];
+
+/// Result of mapping a key through a layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mapped {
+    /// The key maps directly to something.
+    Key(DecodedKey),
+    /// The key is an accent to combine with the next key.
+    Dead(DeadKey),
+}
+
+/// Accents produced by dead keys.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeadKey {
+    Acute,
+    Grave,
+    Circumflex,
+    Tilde,
+    Diaeresis,
+}
+
+impl DeadKey {
+    /// The accent by itself, used when it is followed by a space or by a key
+    /// it does not combine with.
+    pub fn spacing(self) -> char {
+        match self {
+            DeadKey::Acute => '´',
+            DeadKey::Grave => '`',
+            DeadKey::Circumflex => '^',
+            DeadKey::Tilde => '~',
+            DeadKey::Diaeresis => '¨',
+        }
+    }
+
+    /// Combines the accent with a character, if such combination exists.
+    pub fn compose(self, c: char) -> Option<char> {
+        let composed = match (self, c) {
+            (_, ' ') => self.spacing(),
+            (DeadKey::Acute, 'a') => 'á',
+            (DeadKey::Acute, 'e') => 'é',
+            (DeadKey::Acute, 'i') => 'í',
+            (DeadKey::Acute, 'o') => 'ó',
+            (DeadKey::Acute, 'u') => 'ú',
+            (DeadKey::Acute, 'y') => 'ý',
+            (DeadKey::Acute, 'A') => 'Á',
+            (DeadKey::Acute, 'E') => 'É',
+            (DeadKey::Acute, 'I') => 'Í',
+            (DeadKey::Acute, 'O') => 'Ó',
+            (DeadKey::Acute, 'U') => 'Ú',
+            (DeadKey::Acute, 'Y') => 'Ý',
+            (DeadKey::Grave, 'a') => 'à',
+            (DeadKey::Grave, 'e') => 'è',
+            (DeadKey::Grave, 'i') => 'ì',
+            (DeadKey::Grave, 'o') => 'ò',
+            (DeadKey::Grave, 'u') => 'ù',
+            (DeadKey::Grave, 'A') => 'À',
+            (DeadKey::Grave, 'E') => 'È',
+            (DeadKey::Grave, 'I') => 'Ì',
+            (DeadKey::Grave, 'O') => 'Ò',
+            (DeadKey::Grave, 'U') => 'Ù',
+            (DeadKey::Circumflex, 'a') => 'â',
+            (DeadKey::Circumflex, 'e') => 'ê',
+            (DeadKey::Circumflex, 'i') => 'î',
+            (DeadKey::Circumflex, 'o') => 'ô',
+            (DeadKey::Circumflex, 'u') => 'û',
+            (DeadKey::Circumflex, 'A') => 'Â',
+            (DeadKey::Circumflex, 'E') => 'Ê',
+            (DeadKey::Circumflex, 'I') => 'Î',
+            (DeadKey::Circumflex, 'O') => 'Ô',
+            (DeadKey::Circumflex, 'U') => 'Û',
+            (DeadKey::Tilde, 'a') => 'ã',
+            (DeadKey::Tilde, 'o') => 'õ',
+            (DeadKey::Tilde, 'n') => 'ñ',
+            (DeadKey::Tilde, 'A') => 'Ã',
+            (DeadKey::Tilde, 'O') => 'Õ',
+            (DeadKey::Tilde, 'N') => 'Ñ',
+            (DeadKey::Diaeresis, 'a') => 'ä',
+            (DeadKey::Diaeresis, 'e') => 'ë',
+            (DeadKey::Diaeresis, 'i') => 'ï',
+            (DeadKey::Diaeresis, 'o') => 'ö',
+            (DeadKey::Diaeresis, 'u') => 'ü',
+            (DeadKey::Diaeresis, 'y') => 'ÿ',
+            (DeadKey::Diaeresis, 'A') => 'Ä',
+            (DeadKey::Diaeresis, 'E') => 'Ë',
+            (DeadKey::Diaeresis, 'I') => 'Ï',
+            (DeadKey::Diaeresis, 'O') => 'Ö',
+            (DeadKey::Diaeresis, 'U') => 'Ü',
+            _ => return None,
+        };
+
+        Some(composed)
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::Us104
+    }
+}
+
+impl Layout {
+    /// Looks up a layout by the name used in `LAYOUTS`.
+    pub fn from_name(name: &str) -> Option<Layout> {
+        LAYOUTS
+            .iter()
+            .find(|(layout_name, _)| layout_name.eq_ignore_ascii_case(name))
+            .map(|&(_, layout)| layout)
+    }
+
+    /// Name of the layout as in `LAYOUTS`.
+    pub fn name(self) -> &'static str {
+        LAYOUTS
+            .iter()
+            .find(|&&(_, layout)| layout == self)
+            .map(|&(name, _)| name)
+            .unwrap_or("us")
+    }
+
+    /// Layout selected by the `kbd.layout` boot parameter, or the default one
+    /// if it is missing or unknown.
+    pub fn from_cmdline() -> Layout {
+        crate::init::cmdline::get("kbd.layout")
+            .and_then(Layout::from_name)
+            .unwrap_or_default()
+    }
+
+    /// Maps a pressed key through the layout.
+    pub fn map(self, code: KeyCode, modifiers: Modifiers) -> Mapped {
+        match self {
+            Layout::Us104 => us_like::<layouts::Us104Key>(code, modifiers),
+            Layout::Uk105 => delegate::<layouts::Uk105Key>(code, modifiers),
+            Layout::De105 => de105(code, modifiers),
+            Layout::Fr105 => fr105(code, modifiers),
+            Layout::Dvorak104 => us_like::<layouts::Dvorak104Key>(code, modifiers),
+            Layout::Abnt2 => abnt2(code, modifiers),
+        }
+    }
+}
+
+/// Maps through a `pc_keyboard` layout.
+fn delegate<L: KeyboardLayout>(code: KeyCode, modifiers: Modifiers) -> Mapped {
+    Mapped::Key(L::map_keycode(
+        code,
+        &modifiers.to_pc_keyboard(),
+        HandleControl::MapLettersToUnicode,
+    ))
+}
+
+/// Maps through a `pc_keyboard` layout without the key next to enter of ISO
+/// keyboards, which these layouts put in the backslash key instead.
+fn us_like<L: KeyboardLayout>(code: KeyCode, modifiers: Modifiers) -> Mapped {
+    match code {
+        KeyCode::HashTilde => delegate::<L>(KeyCode::BackSlash, modifiers),
+        code => delegate::<L>(code, modifiers),
+    }
+}
+
+fn unicode(c: char) -> Option<Mapped> {
+    Some(Mapped::Key(DecodedKey::Unicode(c)))
+}
+
+fn dead(key: DeadKey) -> Option<Mapped> {
+    Some(Mapped::Dead(key))
+}
+
+/// A symbol depending only on shift.
+fn symbol(modifiers: Modifiers, normal: char, shifted: char) -> Option<Mapped> {
+    if modifiers.is_shifted() {
+        unicode(shifted)
+    } else {
+        unicode(normal)
+    }
+}
+
+/// A letter, affected by both shift and caps lock.
+fn letter(modifiers: Modifiers, lower: char, upper: char) -> Option<Mapped> {
+    if modifiers.is_caps() {
+        unicode(upper)
+    } else {
+        unicode(lower)
+    }
+}
+
+/// German QWERTZ layout.
+fn de105(code: KeyCode, modifiers: Modifiers) -> Mapped {
+    let alt_gr = modifiers.contains(Modifiers::ALT_GR);
+    let shifted = modifiers.is_shifted();
+
+    let mapped = match code {
+        KeyCode::BackTick if shifted => unicode('°'),
+        KeyCode::BackTick => dead(DeadKey::Circumflex),
+        KeyCode::Key2 if alt_gr => unicode('²'),
+        KeyCode::Key2 if shifted => unicode('"'),
+        KeyCode::Key3 if alt_gr => unicode('³'),
+        KeyCode::Key3 if shifted => unicode('§'),
+        KeyCode::Key6 if shifted => unicode('&'),
+        KeyCode::Key7 if alt_gr => unicode('{'),
+        KeyCode::Key7 if shifted => unicode('/'),
+        KeyCode::Key8 if alt_gr => unicode('['),
+        KeyCode::Key8 if shifted => unicode('('),
+        KeyCode::Key9 if alt_gr => unicode(']'),
+        KeyCode::Key9 if shifted => unicode(')'),
+        KeyCode::Key0 if alt_gr => unicode('}'),
+        KeyCode::Key0 if shifted => unicode('='),
+        KeyCode::Minus if alt_gr => unicode('\\'),
+        KeyCode::Minus => symbol(modifiers, 'ß', '?'),
+        KeyCode::Equals if shifted => dead(DeadKey::Grave),
+        KeyCode::Equals => dead(DeadKey::Acute),
+        KeyCode::Q if alt_gr => unicode('@'),
+        KeyCode::E if alt_gr => unicode('€'),
+        KeyCode::M if alt_gr => unicode('µ'),
+        KeyCode::Y => return delegate::<layouts::Us104Key>(KeyCode::Z, modifiers),
+        KeyCode::Z => return delegate::<layouts::Us104Key>(KeyCode::Y, modifiers),
+        KeyCode::BracketSquareLeft => letter(modifiers, 'ü', 'Ü'),
+        KeyCode::BracketSquareRight if alt_gr => unicode('~'),
+        KeyCode::BracketSquareRight => symbol(modifiers, '+', '*'),
+        KeyCode::SemiColon => letter(modifiers, 'ö', 'Ö'),
+        KeyCode::Quote => letter(modifiers, 'ä', 'Ä'),
+        KeyCode::HashTilde => symbol(modifiers, '#', '\''),
+        KeyCode::BackSlash if alt_gr => unicode('|'),
+        KeyCode::BackSlash => symbol(modifiers, '<', '>'),
+        KeyCode::Comma => symbol(modifiers, ',', ';'),
+        KeyCode::Fullstop => symbol(modifiers, '.', ':'),
+        KeyCode::Slash => symbol(modifiers, '-', '_'),
+        _ => None,
+    };
+
+    mapped.unwrap_or_else(|| delegate::<layouts::Us104Key>(code, modifiers))
+}
+
+/// French AZERTY layout, adding the dead keys missing from `pc_keyboard`.
+fn fr105(code: KeyCode, modifiers: Modifiers) -> Mapped {
+    let alt_gr = modifiers.contains(Modifiers::ALT_GR);
+
+    let mapped = match code {
+        KeyCode::Key2 if alt_gr => dead(DeadKey::Tilde),
+        KeyCode::Key7 if alt_gr => dead(DeadKey::Grave),
+        KeyCode::BracketSquareLeft if modifiers.is_shifted() => dead(DeadKey::Diaeresis),
+        KeyCode::BracketSquareLeft if !alt_gr => dead(DeadKey::Circumflex),
+        _ => None,
+    };
+
+    mapped.unwrap_or_else(|| delegate::<layouts::Azerty>(code, modifiers))
+}
+
+/// Brazilian ABNT2 layout.
+///
+/// The extra `/?` key next to the right shift has no `KeyCode`, so those are
+/// only reachable with AltGr + Q and AltGr + W, as on ABNT keyboards.
+fn abnt2(code: KeyCode, modifiers: Modifiers) -> Mapped {
+    let alt_gr = modifiers.contains(Modifiers::ALT_GR);
+    let shifted = modifiers.is_shifted();
+
+    let mapped = match code {
+        KeyCode::BackTick => symbol(modifiers, '\'', '"'),
+        KeyCode::Key1 if alt_gr => unicode('¹'),
+        KeyCode::Key2 if alt_gr => unicode('²'),
+        KeyCode::Key3 if alt_gr => unicode('³'),
+        KeyCode::Key4 if alt_gr => unicode('£'),
+        KeyCode::Key5 if alt_gr => unicode('¢'),
+        KeyCode::Key6 if alt_gr => unicode('¬'),
+        KeyCode::Key6 if shifted => dead(DeadKey::Diaeresis),
+        KeyCode::Equals if alt_gr => unicode('§'),
+        KeyCode::Q if alt_gr => unicode('/'),
+        KeyCode::W if alt_gr => unicode('?'),
+        KeyCode::E if alt_gr => unicode('°'),
+        KeyCode::BracketSquareLeft if shifted => dead(DeadKey::Grave),
+        KeyCode::BracketSquareLeft => dead(DeadKey::Acute),
+        KeyCode::BracketSquareRight if alt_gr => unicode('ª'),
+        KeyCode::BracketSquareRight => symbol(modifiers, '[', '{'),
+        KeyCode::SemiColon => letter(modifiers, 'ç', 'Ç'),
+        KeyCode::Quote if shifted => dead(DeadKey::Circumflex),
+        KeyCode::Quote => dead(DeadKey::Tilde),
+        KeyCode::HashTilde if alt_gr => unicode('º'),
+        KeyCode::HashTilde => symbol(modifiers, ']', '}'),
+        KeyCode::BackSlash => symbol(modifiers, '\\', '|'),
+        KeyCode::Slash => symbol(modifiers, ';', ':'),
+        _ => None,
+    };
+
+    mapped.unwrap_or_else(|| delegate::<layouts::Us104Key>(code, modifiers))
+}
+
+#[cfg(test)]
+#[test_case]
+fn layouts() {
+    let none = Modifiers::empty();
+    let shift = Modifiers::LSHIFT;
+
+    for &(name, layout) in LAYOUTS.iter() {
+        assert_eq!(Layout::from_name(name), Some(layout));
+        assert_eq!(layout.name(), name);
+    }
+    assert_eq!(Layout::from_name("ABNT2"), Some(Layout::Abnt2));
+    assert_eq!(Layout::from_name("klingon"), None);
+
+    let key = |c| Mapped::Key(DecodedKey::Unicode(c));
+    assert_eq!(Layout::Us104.map(KeyCode::Y, none), key('y'));
+    assert_eq!(Layout::De105.map(KeyCode::Y, none), key('z'));
+    assert_eq!(Layout::De105.map(KeyCode::SemiColon, shift), key('Ö'));
+    assert_eq!(Layout::Abnt2.map(KeyCode::SemiColon, none), key('ç'));
+    assert_eq!(
+        Layout::Abnt2.map(KeyCode::Quote, none),
+        Mapped::Dead(DeadKey::Tilde)
+    );
+    assert_eq!(
+        Layout::Abnt2.map(KeyCode::BracketSquareLeft, shift),
+        Mapped::Dead(DeadKey::Grave)
+    );
+
+    assert_eq!(DeadKey::Tilde.compose('a'), Some('ã'));
+    assert_eq!(DeadKey::Acute.compose('E'), Some('É'));
+    assert_eq!(DeadKey::Circumflex.compose(' '), Some('^'));
+    assert_eq!(DeadKey::Tilde.compose('x'), None);
+}
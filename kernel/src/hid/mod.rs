@@ -1,2 +1,5 @@
-//pub mod pckbd;
-//mod queue;
+pub mod input;
+pub mod layout;
+pub mod pckbd;
+pub mod ps2mouse;
+pub mod queue;
@@ -1,27 +1,294 @@
 //! # PS/2 Keyboard Driver
 //!
-//! Allows for simple keyboard input
+//! The interrupt handler only stores the raw scancodes, the decoding into key
+//! events is done later by whoever reads the keyboard through `hid::input`.
 
-use crate::hid::queue::Queue;
+use bitflags::bitflags;
+use pc_keyboard::{DecodeState, DecodedKey, KeyCode, KeyState, ScancodeSet, ScancodeSet1};
 
-pub enum KeyCode {
-    At, Excl, Slash, BackSlash, Dot, Comma, Tick,
-    N0, N1, N2, N3, N4, N5, N6, N7, N8, N9,
-    A, B, C, D, E, F, G, H, I, J, K, L, M,
-    N, O, P, Q, R, S, T, U, V, W, X, Y, Z
+use crate::hid::{
+    input::{self, KeyEvent},
+    layout::{DeadKey, Layout, Mapped},
+    queue::{Overflow, Queue},
+};
+
+/// Data port of the PS/2 controller, where the scancodes are read from.
+pub const DATA_PORT: u16 = 0x60;
+
+/// Raw scancodes received by the interrupt handler and not yet decoded.
+///
+/// When the queue is full the newest scancodes are dropped, losing the oldest
+/// ones could leave a modifier key stuck.
+static SCANCODES: Queue<u8, 128> = Queue::new(Overflow::DropNewest);
+
+/// Stores a scancode read from the keyboard.
+///
+/// Meant to be called from the keyboard interrupt handler, it does not lock.
+pub fn add_scancode(scancode: u8) {
+    if SCANCODES.push(scancode).is_ok() {
+        input::wake_keyboard();
+    }
+}
+
+/// Takes the oldest scancode not yet decoded.
+pub fn read_scancode() -> Option<u8> {
+    SCANCODES.pop()
 }
 
-pub enum ScanCode {
-    Press(KeyCode),
-    Release(KeyCode)
+bitflags! {
+    /// Modifier keys and locks active when a key event happened
+    pub struct Modifiers: u16 {
+        const LSHIFT = 1;
+        const RSHIFT = 1 << 1;
+        const LCTRL = 1 << 2;
+        const RCTRL = 1 << 3;
+        const ALT = 1 << 4;
+        const ALT_GR = 1 << 5;
+        const CAPSLOCK = 1 << 6;
+        const NUMLOCK = 1 << 7;
+        const SCROLLLOCK = 1 << 8;
+    }
 }
 
+impl Modifiers {
+    /// Either shift key is held.
+    pub fn is_shifted(self) -> bool {
+        self.intersects(Modifiers::LSHIFT | Modifiers::RSHIFT)
+    }
+
+    /// Either control key is held.
+    pub fn is_ctrl(self) -> bool {
+        self.intersects(Modifiers::LCTRL | Modifiers::RCTRL)
+    }
+
+    /// Letters are in upper case: either shift is held or caps lock is on.
+    pub fn is_caps(self) -> bool {
+        self.is_shifted() ^ self.contains(Modifiers::CAPSLOCK)
+    }
+
+    /// Converts to the representation used by the `pc_keyboard` layouts.
+    pub(crate) fn to_pc_keyboard(self) -> pc_keyboard::Modifiers {
+        pc_keyboard::Modifiers {
+            lshift: self.contains(Modifiers::LSHIFT),
+            rshift: self.contains(Modifiers::RSHIFT),
+            lctrl: self.contains(Modifiers::LCTRL),
+            rctrl: self.contains(Modifiers::RCTRL),
+            numlock: self.contains(Modifiers::NUMLOCK),
+            capslock: self.contains(Modifiers::CAPSLOCK),
+            alt_gr: self.contains(Modifiers::ALT_GR),
+        }
+    }
+}
+
+/// Scancode Set 1 decoder keeping track of the modifier keys.
+#[derive(Debug)]
 pub struct PCKeyboard {
-    command_queue: Queue<'static, u8>
+    state: DecodeState,
+    modifiers: Modifiers,
+    layout: Layout,
+    dead_key: Option<DeadKey>,
+    /// Event of a key that followed a dead key it does not combine with,
+    /// given after the accent.
+    pending: Option<KeyEvent>,
 }
 
 impl PCKeyboard {
-    pub fn new() -> PCKeyboard {
-        unimplemented!()
+    /// Creates a new instance of PCKeyboard, with num lock on.
+    pub const fn new(layout: Layout) -> PCKeyboard {
+        PCKeyboard {
+            state: DecodeState::Start,
+            modifiers: Modifiers::NUMLOCK,
+            layout,
+            dead_key: None,
+            pending: None,
+        }
+    }
+
+    /// Modifiers currently active.
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
     }
+
+    /// Layout used to decode the keys.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Changes the layout used to decode the keys, forgetting any pending
+    /// dead key.
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        self.dead_key = None;
+    }
+
+    /// Takes the event left by the last key after the accent of a dead key,
+    /// see `add_byte`.
+    pub fn take_pending(&mut self) -> Option<KeyEvent> {
+        self.pending.take()
+    }
+
+    /// Feeds one scancode to the decoder, returning a key event once a
+    /// complete scancode sequence is received.
+    ///
+    /// A key that does not combine with the dead key before it gives the
+    /// accent first, its own event being left for `take_pending`.
+    pub fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        let (code, state) = match (self.state, byte) {
+            // Keys `pc_keyboard` maps differently from the Scancode Set 2, or
+            // not at all: the key next to enter and the one next to the left
+            // shift of ISO keyboards.
+            (DecodeState::Start, 0x2B) => (KeyCode::HashTilde, KeyState::Down),
+            (DecodeState::Start, 0xAB) => (KeyCode::HashTilde, KeyState::Up),
+            (DecodeState::Start, 0x56) => (KeyCode::BackSlash, KeyState::Down),
+            (DecodeState::Start, 0xD6) => (KeyCode::BackSlash, KeyState::Up),
+            _ => {
+                let event = ScancodeSet1::advance_state(&mut self.state, byte).ok()??;
+                (event.code, event.state)
+            }
+        };
+
+        self.update_modifiers(code, state);
+
+        let (key, after) = match state {
+            KeyState::Down if !is_modifier(code) => self.decode(code),
+            _ => (None, None),
+        };
+
+        let event = KeyEvent {
+            code,
+            state,
+            modifiers: self.modifiers,
+            key,
+        };
+        if let Some(after) = after {
+            self.pending = Some(KeyEvent {
+                key: Some(after),
+                ..event
+            });
+        }
+        Some(event)
+    }
+
+    /// Decodes a key pressed with the current modifiers and layout, and the
+    /// key to give after it, if any.
+    ///
+    /// Gives nothing for a dead key, which is kept to be combined with the
+    /// next key. A key that does not combine with it comes after the accent.
+    fn decode(&mut self, code: KeyCode) -> (Option<DecodedKey>, Option<DecodedKey>) {
+        match (self.layout.map(code, self.modifiers), self.dead_key.take()) {
+            // Pressing a dead key twice gives the accent itself
+            (Mapped::Dead(_), Some(pending)) => {
+                (Some(DecodedKey::Unicode(pending.spacing())), None)
+            }
+            (Mapped::Dead(dead_key), None) => {
+                self.dead_key = Some(dead_key);
+                (None, None)
+            }
+            (Mapped::Key(key), Some(pending)) => {
+                let composed = match key {
+                    DecodedKey::Unicode(c) => pending.compose(c),
+                    DecodedKey::RawKey(_) => None,
+                };
+                match composed {
+                    Some(c) => (Some(DecodedKey::Unicode(c)), None),
+                    None => (Some(DecodedKey::Unicode(pending.spacing())), Some(key)),
+                }
+            }
+            (Mapped::Key(key), None) => (Some(key), None),
+        }
+    }
+
+    fn update_modifiers(&mut self, code: KeyCode, state: KeyState) {
+        let down = state == KeyState::Down;
+
+        match code {
+            KeyCode::ShiftLeft => self.modifiers.set(Modifiers::LSHIFT, down),
+            KeyCode::ShiftRight => self.modifiers.set(Modifiers::RSHIFT, down),
+            KeyCode::ControlLeft => self.modifiers.set(Modifiers::LCTRL, down),
+            KeyCode::ControlRight => self.modifiers.set(Modifiers::RCTRL, down),
+            KeyCode::AltLeft => self.modifiers.set(Modifiers::ALT, down),
+            KeyCode::AltRight => self.modifiers.set(Modifiers::ALT_GR, down),
+            KeyCode::CapsLock if down => self.modifiers.toggle(Modifiers::CAPSLOCK),
+            KeyCode::NumpadLock if down => self.modifiers.toggle(Modifiers::NUMLOCK),
+            KeyCode::ScrollLock if down => self.modifiers.toggle(Modifiers::SCROLLLOCK),
+            _ => {}
+        }
+    }
+}
+
+/// Keys that only change the modifiers, so they never produce a decoded key.
+fn is_modifier(code: KeyCode) -> bool {
+    match code {
+        KeyCode::ShiftLeft
+        | KeyCode::ShiftRight
+        | KeyCode::ControlLeft
+        | KeyCode::ControlRight
+        | KeyCode::AltLeft
+        | KeyCode::AltRight
+        | KeyCode::CapsLock
+        | KeyCode::NumpadLock
+        | KeyCode::ScrollLock => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn decoding() {
+    let mut keyboard = PCKeyboard::new(Layout::Us104);
+
+    // 'a' pressed and released
+    let event = keyboard.add_byte(0x1E).unwrap();
+    assert_eq!(event.code, KeyCode::A);
+    assert_eq!(event.state, KeyState::Down);
+    assert_eq!(event.key, Some(DecodedKey::Unicode('a')));
+    let event = keyboard.add_byte(0x9E).unwrap();
+    assert_eq!(event.state, KeyState::Up);
+    assert_eq!(event.key, None);
+
+    // Left shift held while pressing 'a'
+    let event = keyboard.add_byte(0x2A).unwrap();
+    assert_eq!(event.key, None);
+    assert!(event.modifiers.is_shifted());
+    let event = keyboard.add_byte(0x1E).unwrap();
+    assert_eq!(event.key, Some(DecodedKey::Unicode('A')));
+    keyboard.add_byte(0xAA).unwrap();
+    assert!(!keyboard.modifiers().is_shifted());
+
+    // Extended scancode: right control
+    assert_eq!(keyboard.add_byte(0xE0), None);
+    let event = keyboard.add_byte(0x1D).unwrap();
+    assert_eq!(event.code, KeyCode::ControlRight);
+    assert!(event.modifiers.is_ctrl());
+    keyboard.add_byte(0xE0);
+    keyboard.add_byte(0x9D).unwrap();
+
+    // Dead keys on ABNT2: '~' then 'a'
+    keyboard.set_layout(Layout::Abnt2);
+    assert_eq!(keyboard.add_byte(0x28).unwrap().key, None);
+    keyboard.add_byte(0xA8).unwrap();
+    let event = keyboard.add_byte(0x1E).unwrap();
+    assert_eq!(event.key, Some(DecodedKey::Unicode('ã')));
+    assert_eq!(keyboard.take_pending(), None);
+
+    // '~' then 'q', that does not combine: the accent comes first
+    keyboard.add_byte(0x28).unwrap();
+    keyboard.add_byte(0xA8).unwrap();
+    let event = keyboard.add_byte(0x10).unwrap();
+    assert_eq!(event.key, Some(DecodedKey::Unicode('~')));
+    let event = keyboard.take_pending().unwrap();
+    assert_eq!(
+        (event.code, event.key),
+        (KeyCode::Q, Some(DecodedKey::Unicode('q')))
+    );
+    assert_eq!(keyboard.take_pending(), None);
+
+    // '~' then an arrow
+    keyboard.add_byte(0x28).unwrap();
+    keyboard.add_byte(0xA8).unwrap();
+    keyboard.add_byte(0xE0);
+    let event = keyboard.add_byte(0x48).unwrap();
+    assert_eq!(event.key, Some(DecodedKey::Unicode('~')));
+    let event = keyboard.take_pending().unwrap();
+    assert_eq!(event.key, Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
 }
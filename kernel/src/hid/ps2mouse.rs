@@ -0,0 +1,351 @@
+//! # PS/2 Mouse Driver
+//!
+//! Mouse on the auxiliary port of the PS/2 controller, with support for the
+//! IntelliMouse scroll wheel extension.
+//!
+//! Like the keyboard, the interrupt handler only stores the raw bytes, they are
+//! assembled into packets and turned into events through `hid::input`.
+
+use bitflags::bitflags;
+
+use crate::{
+    hid::{
+        input::{self, MouseEvent},
+        queue::{Overflow, Queue},
+    },
+    io::{Pio, PortIo},
+};
+
+/// Data port of the PS/2 controller.
+pub const DATA_PORT: u16 = 0x60;
+/// Status (read) and command (write) port of the PS/2 controller.
+pub const COMMAND_PORT: u16 = 0x64;
+
+/// How many times the controller status is polled before giving up.
+const TIMEOUT: usize = 100_000;
+
+/// Device ID of a mouse with the scroll wheel extension enabled.
+const INTELLIMOUSE_ID: u8 = 3;
+
+/// Raw bytes received by the interrupt handler and not yet decoded.
+static BYTES: Queue<u8, 256> = Queue::new(Overflow::DropNewest);
+
+/// Stores a byte read from the mouse.
+///
+/// Meant to be called from the mouse interrupt handler, it does not lock.
+pub fn add_byte(byte: u8) {
+    if BYTES.push(byte).is_ok() {
+        input::wake_mouse();
+    }
+}
+
+/// Takes the oldest byte not yet decoded.
+pub fn read_byte() -> Option<u8> {
+    BYTES.pop()
+}
+
+bitflags! {
+    /// PS/2 controller status register
+    struct StatusFlags: u8 {
+        const OUTPUT_FULL = 1;
+        const INPUT_FULL = 1 << 1;
+        // 2 to 4 unused here
+        const AUX_DATA = 1 << 5;
+        const TIMEOUT_ERROR = 1 << 6;
+        const PARITY_ERROR = 1 << 7;
+    }
+}
+
+bitflags! {
+    /// Buttons of the mouse
+    pub struct MouseButtons: u8 {
+        const LEFT = 1;
+        const RIGHT = 1 << 1;
+        const MIDDLE = 1 << 2;
+    }
+}
+
+bitflags! {
+    /// First byte of a mouse packet
+    struct PacketFlags: u8 {
+        const LEFT = 1;
+        const RIGHT = 1 << 1;
+        const MIDDLE = 1 << 2;
+        const ALWAYS_ONE = 1 << 3;
+        const X_SIGN = 1 << 4;
+        const Y_SIGN = 1 << 5;
+        const X_OVERFLOW = 1 << 6;
+        const Y_OVERFLOW = 1 << 7;
+    }
+}
+
+/// Controller commands
+mod command {
+    pub const READ_CONFIG: u8 = 0x20;
+    pub const WRITE_CONFIG: u8 = 0x60;
+    pub const ENABLE_AUX: u8 = 0xA8;
+    pub const WRITE_AUX: u8 = 0xD4;
+}
+
+/// Mouse commands
+mod mouse {
+    pub const GET_ID: u8 = 0xF2;
+    pub const SET_SAMPLE_RATE: u8 = 0xF3;
+    pub const ENABLE_REPORTING: u8 = 0xF4;
+    pub const SET_DEFAULTS: u8 = 0xF6;
+    pub const ACK: u8 = 0xFA;
+}
+
+/// Interrupt enable bit for the auxiliary port in the controller configuration.
+const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+/// Clock disable bit for the auxiliary port in the controller configuration.
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+
+/// PS/2 mouse device
+///
+/// Talks to the PS/2 controller through `io`, the real I/O ports by default.
+#[derive(Debug)]
+pub struct Ps2Mouse<P = Pio> {
+    io: P,
+    packet: [u8; 4],
+    received: usize,
+    packet_size: usize,
+}
+
+impl Ps2Mouse {
+    /// Create a new instance of Ps2Mouse, expecting standard 3 bytes packets.
+    pub const fn new() -> Ps2Mouse {
+        Ps2Mouse::with_io(Pio)
+    }
+}
+
+impl<P> Ps2Mouse<P> {
+    /// Create a new instance of Ps2Mouse, accessing the controller through
+    /// `io`.
+    pub const fn with_io(io: P) -> Ps2Mouse<P> {
+        Ps2Mouse {
+            io,
+            packet: [0; 4],
+            received: 0,
+            packet_size: 3,
+        }
+    }
+
+    /// The I/O the controller is accessed through.
+    pub fn io(&self) -> &P {
+        &self.io
+    }
+
+    /// Returns `true` if the scroll wheel extension is enabled.
+    pub fn has_wheel(&self) -> bool {
+        self.packet_size == 4
+    }
+
+    /// Feeds one byte to the packet decoder, returning an event once a full
+    /// packet is received.
+    ///
+    /// Bytes that cannot start a packet are dropped, so a lost byte only
+    /// costs the packet it was part of.
+    pub fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        if self.received == 0
+            && !PacketFlags::from_bits_truncate(byte).contains(PacketFlags::ALWAYS_ONE)
+        {
+            return None;
+        }
+
+        self.packet[self.received] = byte;
+        self.received += 1;
+
+        if self.received < self.packet_size {
+            return None;
+        }
+        self.received = 0;
+
+        self.parse_packet()
+    }
+
+    fn parse_packet(&self) -> Option<MouseEvent> {
+        let flags = PacketFlags::from_bits_truncate(self.packet[0]);
+
+        if flags.intersects(PacketFlags::X_OVERFLOW | PacketFlags::Y_OVERFLOW) {
+            return None;
+        }
+
+        // The movement is a 9 bits two's complement number, whose sign bit is
+        // in the first byte.
+        let sign_extend = |value: u8, negative: bool| {
+            if negative {
+                i16::from(value) - 0x100
+            } else {
+                i16::from(value)
+            }
+        };
+
+        let dx = sign_extend(self.packet[1], flags.contains(PacketFlags::X_SIGN));
+        let dy = sign_extend(self.packet[2], flags.contains(PacketFlags::Y_SIGN));
+
+        // The wheel is a 4 bits two's complement number.
+        let wheel = if self.has_wheel() {
+            ((self.packet[3] << 4) as i8) >> 4
+        } else {
+            0
+        };
+
+        Some(MouseEvent {
+            dx,
+            dy,
+            wheel,
+            buttons: MouseButtons::from_bits_truncate(self.packet[0]),
+        })
+    }
+}
+
+impl<P: PortIo> Ps2Mouse<P> {
+    /// Initialize the mouse, enabling its interrupt and data reporting.
+    ///
+    /// The scroll wheel is enabled if the mouse supports it.
+    ///
+    /// This function is unsafe because it talks to the PS/2 controller, the
+    /// caller must ensure nobody else is using it at the same time, which
+    /// includes the keyboard interrupt handler.
+    pub unsafe fn init(&mut self) -> Result<(), &'static str> {
+        self.send_command(command::ENABLE_AUX)?;
+
+        self.send_command(command::READ_CONFIG)?;
+        let config = self.read()?;
+        let config = (config | CONFIG_AUX_INTERRUPT) & !CONFIG_AUX_CLOCK_DISABLED;
+        self.send_command(command::WRITE_CONFIG)?;
+        self.write(config)?;
+
+        self.send_mouse(mouse::SET_DEFAULTS)?;
+
+        // The magic sample rate sequence that enables the IntelliMouse mode.
+        for &rate in &[200, 100, 80] {
+            self.send_mouse(mouse::SET_SAMPLE_RATE)?;
+            self.send_mouse(rate)?;
+        }
+        self.send_mouse(mouse::GET_ID)?;
+        self.packet_size = if self.read()? == INTELLIMOUSE_ID {
+            4
+        } else {
+            3
+        };
+
+        self.send_mouse(mouse::ENABLE_REPORTING)?;
+        self.received = 0;
+
+        Ok(())
+    }
+
+    unsafe fn status(&mut self) -> StatusFlags {
+        StatusFlags::from_bits_truncate(self.io.read_u8(COMMAND_PORT))
+    }
+
+    unsafe fn wait_input_empty(&mut self) -> Result<(), &'static str> {
+        for _ in 0..TIMEOUT {
+            if !self.status().contains(StatusFlags::INPUT_FULL) {
+                return Ok(());
+            }
+        }
+
+        Err("PS/2 controller input buffer never emptied")
+    }
+
+    unsafe fn read(&mut self) -> Result<u8, &'static str> {
+        for _ in 0..TIMEOUT {
+            if self.status().contains(StatusFlags::OUTPUT_FULL) {
+                return Ok(self.io.read_u8(DATA_PORT));
+            }
+        }
+
+        Err("PS/2 controller did not answer")
+    }
+
+    unsafe fn write(&mut self, value: u8) -> Result<(), &'static str> {
+        self.wait_input_empty()?;
+        self.io.write_u8(DATA_PORT, value);
+
+        Ok(())
+    }
+
+    unsafe fn send_command(&mut self, command: u8) -> Result<(), &'static str> {
+        self.wait_input_empty()?;
+        self.io.write_u8(COMMAND_PORT, command);
+
+        Ok(())
+    }
+
+    /// Sends a byte to the mouse, waiting for its acknowledgement.
+    unsafe fn send_mouse(&mut self, value: u8) -> Result<(), &'static str> {
+        self.send_command(command::WRITE_AUX)?;
+        self.write(value)?;
+
+        match self.read()? {
+            mouse::ACK => Ok(()),
+            _ => Err("PS/2 mouse did not acknowledge command"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn packets() {
+    let mut mouse = Ps2Mouse::new();
+
+    // Left button, moved right 5 and down 3
+    assert_eq!(mouse.add_byte(0b0010_1001), None);
+    assert_eq!(mouse.add_byte(5), None);
+    let event = mouse.add_byte(0xFD).unwrap();
+    assert_eq!((event.dx, event.dy, event.wheel), (5, -3, 0));
+    assert_eq!(event.buttons, MouseButtons::LEFT);
+
+    // Out of sync byte is dropped
+    assert_eq!(mouse.add_byte(0x00), None);
+    assert_eq!(mouse.add_byte(0b0000_1000), None);
+    assert_eq!(mouse.add_byte(0), None);
+    assert!(mouse.add_byte(0).is_some());
+
+    // Overflow is discarded
+    mouse.add_byte(0b0100_1000);
+    mouse.add_byte(0xFF);
+    assert_eq!(mouse.add_byte(0), None);
+
+    // Scroll wheel
+    mouse.packet_size = 4;
+    mouse.add_byte(0b0000_1100);
+    mouse.add_byte(0);
+    mouse.add_byte(0);
+    let event = mouse.add_byte(0x0F).unwrap();
+    assert_eq!(event.wheel, -1);
+    assert_eq!(event.buttons, MouseButtons::MIDDLE);
+}
+
+#[cfg(test)]
+#[test_case]
+fn initialization() {
+    use crate::io::MockPortIo;
+
+    let mut io = MockPortIo::new();
+    // Controller always ready to take a byte, and with one to give
+    io.set_default(COMMAND_PORT, StatusFlags::OUTPUT_FULL.bits().into());
+    // Configuration, then the acknowledgements up to the ID of a wheel mouse
+    io.queue_read(DATA_PORT, 0b0010_0000);
+    for _ in 0..8 {
+        io.queue_read(DATA_PORT, mouse::ACK.into());
+    }
+    io.queue_read(DATA_PORT, INTELLIMOUSE_ID.into());
+    io.queue_read(DATA_PORT, mouse::ACK.into());
+
+    let mut mouse = Ps2Mouse::with_io(io);
+    unsafe { mouse.init().unwrap() };
+    assert!(mouse.has_wheel());
+    assert_eq!(mouse.io().pending_reads(), 0);
+
+    // Interrupt enabled and clock on, in the configuration written back
+    let written = mouse.io().writes_to(DATA_PORT).next().unwrap();
+    assert_eq!(written as u8, CONFIG_AUX_INTERRUPT);
+
+    // A mouse that does not answer
+    let mut mouse = Ps2Mouse::with_io(MockPortIo::new());
+    assert!(unsafe { mouse.init() }.is_err());
+}
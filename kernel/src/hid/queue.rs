@@ -1,46 +1,224 @@
-// TODO Improve this, as this will go boom very quickly
-// Implement a reuse mechanism. Shouldnt be hard using Option<T>
-// Maybe move to a more appropriate module
+//! Fixed-capacity, lock-free single-producer/single-consumer queue.
+//!
+//! The queue is meant to be filled from an interrupt handler (the producer) and
+//! drained from normal kernel code (the consumer), so it never allocates and
+//! never takes a lock. It only needs a `&self`, which means it can live in a
+//! plain `static`.
+//!
+//! `head` and `tail` are free-running counters, the slot for a counter is
+//! `counter % N`, and the queue holds `tail - head` elements.
 
-use core::default::Default;
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-pub struct Queue<'a, T: Copy + Default + Sized> {
-    array: &'a mut [T],
-    front: usize,
-    rear: isize,
-    max: isize
+/// What to do when pushing into a full queue.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overflow {
+    /// Keep what is already queued and give the new element back.
+    DropNewest,
+    /// Discard the oldest element to make room for the new one.
+    OverwriteOldest,
 }
 
-impl<'a, T: Copy + Default + Sized> Queue<'a, T> {
-    pub fn new(size: usize) -> Queue<'a, T> {
-        //Queue {
-            //array: [Default::default(); size],
-            //front: 0,
-            //rear: -1,
-            //max: size
-        //} 
-        
-        unimplemented!()
-    }
-
-    pub fn enqueue(&mut self, obj: &'a T) -> Result<(), &'static str> {
-        if self.rear == self.max - 1 {
-            Err("Queue overflow")
-        } else {
-            self.rear += 1;
-            self.array[self.rear as usize] = *obj;
+/// Lock-free single-producer/single-consumer ring buffer holding up to `N`
+/// elements of `T`.
+///
+/// Only one context may push and only one context may pop at a time, which is
+/// the usual setup of an interrupt handler feeding a driver.
+pub struct Queue<T: Copy, const N: usize> {
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    overflow: Overflow,
+}
+
+// The producer and the consumer only touch disjoint slots, and the counters
+// that hand a slot from one to the other are atomics.
+unsafe impl<T: Copy + Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    /// Creates a new empty Queue with the given overflow policy.
+    pub const fn new(overflow: Overflow) -> Self {
+        Queue {
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            overflow,
+        }
+    }
+
+    /// Maximum number of elements the queue holds.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Number of elements currently queued.
+    pub fn len(&self) -> usize {
+        // `head` first: it never passes `tail`, so the later `tail` is never
+        // behind it. Pushes in between can still overwrite past `N`.
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head).min(N)
+    }
+
+    /// Returns `true` if there is nothing to pop.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the next push will hit the overflow policy.
+    pub fn is_full(&self) -> bool {
+        self.len() >= N
+    }
+
+    /// Overflow policy of the queue.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Pushes an element at the back of the queue. Producer side.
+    ///
+    /// With `Overflow::DropNewest`, a full queue returns `Err(value)`. With
+    /// `Overflow::OverwriteOldest` the push always succeeds.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        if N == 0 {
+            return Err(value);
+        }
+
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if tail.wrapping_sub(head) >= N {
+            match self.overflow {
+                Overflow::DropNewest => return Err(value),
+                Overflow::OverwriteOldest => {
+                    // If it fails, the consumer popped the oldest one already,
+                    // either way the slot is free now.
+                    let _ = self.head.compare_exchange(
+                        head,
+                        head.wrapping_add(1),
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    );
+                }
+            }
+        }
+
+        unsafe { ptr::write_volatile(self.slot(tail), value) };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Pops the element at the front of the queue. Consumer side.
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let tail = self.tail.load(Ordering::Acquire);
+
+            if head == tail {
+                return None;
+            }
 
-            Ok(())
+            // With `Overflow::OverwriteOldest` the producer may be overwriting
+            // this slot right now; it moves `head` before doing so, so if the
+            // exchange below fails the value read is discarded and we retry.
+            let value = unsafe { ptr::read_volatile(self.slot(head)) };
+
+            if self
+                .head
+                .compare_exchange(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                return Some(value);
+            }
         }
     }
 
-    pub fn dequeue(&mut self) -> Option<T> {
-        if self.front == self.rear as usize + 1  {
+    /// Returns a copy of the element at the front of the queue without
+    /// removing it. Consumer side.
+    pub fn peek(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head == tail {
             None
         } else {
-            let item = self.array[self.front];
-            self.front += 1;
-            Some(item)
+            Some(unsafe { ptr::read_volatile(self.slot(head)) })
         }
     }
+
+    /// Drops everything queued. Consumer side.
+    pub fn clear(&self) {
+        while self.pop().is_some() {}
+    }
+
+    /// Raw pointer to the slot of the given counter.
+    fn slot(&self, counter: usize) -> *mut T {
+        let base = self.buffer.get() as *mut T;
+        unsafe { base.add(counter % N) }
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn wraparound() {
+    let queue: Queue<u8, 4> = Queue::new(Overflow::DropNewest);
+
+    // Going around the ring several times must keep the FIFO order.
+    for round in 0..10u8 {
+        for i in 0..3 {
+            assert_eq!(queue.push(round * 3 + i), Ok(()));
+        }
+        assert_eq!(queue.len(), 3);
+        for i in 0..3 {
+            assert_eq!(queue.pop(), Some(round * 3 + i));
+        }
+        assert!(queue.is_empty());
+    }
+    assert_eq!(queue.pop(), None);
+}
+
+#[cfg(test)]
+#[test_case]
+fn drop_newest() {
+    let queue: Queue<u8, 3> = Queue::new(Overflow::DropNewest);
+
+    assert_eq!(queue.push(1), Ok(()));
+    assert_eq!(queue.push(2), Ok(()));
+    assert_eq!(queue.push(3), Ok(()));
+    assert!(queue.is_full());
+    assert_eq!(queue.push(4), Err(4));
+
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.push(5), Ok(()));
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), Some(3));
+    assert_eq!(queue.pop(), Some(5));
+    assert_eq!(queue.pop(), None);
+}
+
+#[cfg(test)]
+#[test_case]
+fn overwrite_oldest() {
+    let queue: Queue<u8, 3> = Queue::new(Overflow::OverwriteOldest);
+
+    for i in 1..=7 {
+        assert_eq!(queue.push(i), Ok(()));
+    }
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.peek(), Some(5));
+    assert_eq!(queue.pop(), Some(5));
+    assert_eq!(queue.pop(), Some(6));
+    assert_eq!(queue.pop(), Some(7));
+    assert_eq!(queue.pop(), None);
 }
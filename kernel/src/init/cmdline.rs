@@ -0,0 +1,56 @@
+//! Kernel command line.
+//!
+//! The bootloader does not pass a command line to the kernel, so it is taken
+//! from the `FERROUS_CMDLINE` environment variable when building the kernel
+//! image. It is a list of `key=value` or `flag` separated by spaces.
+//!
+//! It is a compile-time value, not a real boot parameter: it is built into
+//! the image, and changing it means building the kernel again.
+//!
+//! # Examples
+//! ```sh
+//! FERROUS_CMDLINE="kbd.layout=abnt2" bootimage build --target kernel.json
+//! ```
+
+/// The whole command line, as it was when the kernel was built.
+pub fn cmdline() -> &'static str {
+    option_env!("FERROUS_CMDLINE").unwrap_or("")
+}
+
+/// Value of a parameter in the command line. A flag given without `=` has an
+/// empty value. If a parameter is given more than once, the last one wins.
+pub fn get(key: &str) -> Option<&'static str> {
+    find(cmdline(), key)
+}
+
+/// Returns `true` if the parameter is in the command line.
+pub fn contains(key: &str) -> bool {
+    get(key).is_some()
+}
+
+fn find<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|param| {
+            let mut parts = param.splitn(2, '=');
+            let name = parts.next()?;
+            if name == key {
+                Some(parts.next().unwrap_or(""))
+            } else {
+                None
+            }
+        })
+        .last()
+}
+
+#[cfg(test)]
+#[test_case]
+fn parsing() {
+    let cmdline = "kbd.layout=abnt2 quiet log=debug log=trace";
+
+    assert_eq!(find(cmdline, "kbd.layout"), Some("abnt2"));
+    assert_eq!(find(cmdline, "quiet"), Some(""));
+    assert_eq!(find(cmdline, "log"), Some("trace"));
+    assert_eq!(find(cmdline, "kbd"), None);
+    assert_eq!(find("", "quiet"), None);
+}
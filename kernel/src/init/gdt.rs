@@ -1,6 +1,6 @@
 use x86_64::{
     structures::{
-        gdt::{Descriptor, GlobalDescriptorTable as Gdt, SegmentSelector},
+        gdt::{Descriptor, DescriptorFlags, GlobalDescriptorTable as Gdt, SegmentSelector},
         tss::TaskStateSegment as Tss,
     },
     VirtAddr,
@@ -10,60 +10,134 @@ use lazy_static::lazy_static;
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// Size of the stack the double fault handler runs on.
+pub const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// Size of the stack interrupts from user mode switch to, when the running
+/// thread did not set its own with `set_kernel_stack`.
+pub const PRIVILEGE_STACK_SIZE: usize = 4096 * 5;
+
+static mut DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+static mut PRIVILEGE_STACK: [u8; PRIVILEGE_STACK_SIZE] = [0; PRIVILEGE_STACK_SIZE];
+
 // TSS is used on GDT, so makes sense putting it here instead of their own file.
-lazy_static! {
-    /// Default Task State Segment initialized.
-    static ref TSS: Tss = {
-        let mut tss = Tss::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr( unsafe { &STACK } );
-            stack_start + STACK_SIZE
-        };
-        tss
-    };
-}
+// It is filled in by `init`, and its kernel stack changes with the running
+// thread, so it is not behind a lazy_static.
+/// Default Task State Segment.
+static mut TSS: Tss = Tss::new();
 
 lazy_static! {
     /// Default Global Descriptor Table initialized.
+    ///
+    /// The user data segment comes right before the user code segment, as
+    /// `sysret` expects.
     static ref GDT: (Gdt, Selectors) = {
+        let kernel_data = DescriptorFlags::USER_SEGMENT
+            | DescriptorFlags::PRESENT
+            | DescriptorFlags::WRITABLE;
+
         let mut gdt = Gdt::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let data_selector = gdt.add_entry(Descriptor::UserSegment(kernel_data.bits()));
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &TSS }));
 
-        (gdt, Selectors::new(code_selector, tss_selector))
+        let selectors = Selectors {
+            code_selector,
+            data_selector,
+            user_code_selector,
+            user_data_selector,
+            tss_selector,
+        };
+        (gdt, selectors)
     };
 }
 
 /// Loads the default Global Descriptor Table.
 pub fn init() -> Result<(), &'static str> {
-    use x86_64::instructions::{segmentation::set_cs, tables::load_tss};
+    use x86_64::instructions::{
+        segmentation::{load_ds, load_es, load_ss, set_cs},
+        tables::load_tss,
+    };
+
+    unsafe {
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::from_ptr(&DOUBLE_FAULT_STACK) + DOUBLE_FAULT_STACK_SIZE;
+        set_kernel_stack(privilege_stack_top());
+    }
 
     GDT.0.load();
 
     unsafe {
         set_cs(GDT.1.code_selector);
+        load_ss(GDT.1.data_selector);
+        load_ds(GDT.1.data_selector);
+        load_es(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 
     Ok(())
 }
 
-/// Holds the segments for kernel code and Task State Segment.
+/// Selector of the kernel code segment.
+pub fn code_selector() -> SegmentSelector {
+    GDT.1.code_selector
+}
+
+/// Selector of the kernel data segment.
+pub fn data_selector() -> SegmentSelector {
+    GDT.1.data_selector
+}
+
+/// Selector of the user code segment, with the privilege level 3.
+pub fn user_code_selector() -> SegmentSelector {
+    GDT.1.user_code_selector
+}
+
+/// Selector of the user data segment, with the privilege level 3.
+pub fn user_data_selector() -> SegmentSelector {
+    GDT.1.user_data_selector
+}
+
+/// Selector of the Task State Segment.
+pub fn tss_selector() -> SegmentSelector {
+    GDT.1.tss_selector
+}
+
+/// Top of the stack the double fault handler runs on, that grows down.
+pub fn double_fault_stack_top() -> VirtAddr {
+    unsafe { TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] }
+}
+
+/// Top of the default stack interrupts from user mode switch to.
+pub fn privilege_stack_top() -> VirtAddr {
+    VirtAddr::from_ptr(unsafe { &PRIVILEGE_STACK }) + PRIVILEGE_STACK_SIZE
+}
+
+/// Top of the stack interrupts and system calls from user mode switch to.
+pub fn kernel_stack() -> VirtAddr {
+    unsafe { TSS.privilege_stack_table[0] }
+}
+
+/// Changes the stack interrupts and system calls from user mode switch to.
+///
+/// This function is unsafe because the caller must guarantee that `top` is
+/// the top of a stack that nothing else uses while in user mode. Interrupts
+/// must be disabled.
+pub unsafe fn set_kernel_stack(top: VirtAddr) {
+    TSS.privilege_stack_table[0] = top;
+    crate::syscall::entry::set_kernel_stack(top);
+}
+
+/// Holds the segments for kernel code and data, user code and data, and Task
+/// State Segment.
 #[derive(Debug, Copy, Clone)]
 struct Selectors {
     code_selector: SegmentSelector,
+    data_selector: SegmentSelector,
+    user_code_selector: SegmentSelector,
+    user_data_selector: SegmentSelector,
     tss_selector: SegmentSelector,
 }
-
-impl Selectors {
-    /// Create new instance of Selectors.
-    pub fn new(code_selector: SegmentSelector, tss_selector: SegmentSelector) -> Selectors {
-        Selectors {
-            code_selector,
-            tss_selector,
-        }
-    }
-}
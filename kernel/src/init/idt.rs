@@ -1,9 +1,24 @@
-use crate::{init::pic::PIC_1_OFFSET, prelude::*};
+use crate::{
+    init::pic::{PIC_1_OFFSET, PIC_2_OFFSET},
+    prelude::*,
+    process::signal::{self, SIGBUS, SIGFPE, SIGILL, SIGSEGV},
+    syscall::{self, entry, Registers},
+    user,
+};
+
+use core::{
+    mem,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use x86_64::{
     instructions::port::Port,
     registers::control::Cr2,
-    structures::idt::{InterruptDescriptorTable as Idt, InterruptStackFrame, PageFaultErrorCode},
+    structures::idt::{
+        HandlerFunc, HandlerFuncWithErrCode, InterruptDescriptorTable as Idt, InterruptStackFrame,
+        PageFaultErrorCode, PageFaultHandlerFunc,
+    },
+    PrivilegeLevel,
 };
 
 use lazy_static::lazy_static;
@@ -12,6 +27,19 @@ pub const TIMER_INTERRUPT_ID: u8 = PIC_1_OFFSET; // 32
 
 pub const KEYBOARD_INTERRUPT_ID: u8 = PIC_1_OFFSET + 1; // 33
 
+pub const SERIAL_INTERRUPT_ID: u8 = PIC_1_OFFSET + 4; // 36
+
+pub const MOUSE_INTERRUPT_ID: u8 = PIC_2_OFFSET + 4; // 44
+
+/// Legacy system calls, see `syscall`.
+pub const SYSCALL_INTERRUPT_ID: u8 = 0x80;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// Interrupts and exceptions handled, by vector.
+static COUNTS: [AtomicU64; 256] = [NO_INTERRUPTS; 256];
+
 lazy_static! {
     /// Default Interrupt Descriptor Table initialized.
     static ref IDT: Idt = {
@@ -21,19 +49,15 @@ lazy_static! {
         // dynamically. H O W?
         // Ideally, we should have a fn set_interrupt(n: u8, f: HandlerFunc)
 
-        idt.divide_error.set_handler_fn(divide_error_handler);
         idt.debug.set_handler_fn(debug_handler);
         idt.non_maskable_interrupt.set_handler_fn(non_maskable_handler);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt.overflow.set_handler_fn(overflow_handler);
         idt.bound_range_exceeded.set_handler_fn(bound_range_handler);
-        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
         idt.device_not_available.set_handler_fn(dev_not_available_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
         idt.invalid_tss.set_handler_fn(invalid_tss_handler);
         idt.segment_not_present.set_handler_fn(seg_not_present_handler);
         idt.stack_segment_fault.set_handler_fn(stack_segment_handler);
-        idt.general_protection_fault.set_handler_fn(protection_fault_handler);
         idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
         idt.alignment_check.set_handler_fn(alignment_check_handler);
         idt.machine_check.set_handler_fn(machine_check_handler);
@@ -41,8 +65,38 @@ lazy_static! {
         idt.virtualization.set_handler_fn(virtualization_handler);
         idt.security_exception.set_handler_fn(security_exception_handler);
         idt.simd_floating_point.set_handler_fn(simd_float_handler);
-        idt[usize::from(TIMER_INTERRUPT_ID)].set_handler_fn(timer_interrupt_handler);
         idt[usize::from(KEYBOARD_INTERRUPT_ID)].set_handler_fn(keyboard_interrupt_handler);
+        idt[usize::from(SERIAL_INTERRUPT_ID)].set_handler_fn(serial_interrupt_handler);
+        idt[usize::from(MOUSE_INTERRUPT_ID)].set_handler_fn(mouse_interrupt_handler);
+
+        // These entry points save the registers themselves, so signals can be
+        // delivered to user code, see `syscall::entry`.
+        unsafe {
+            let divide_error: HandlerFunc =
+                mem::transmute(entry::divide_error_entry as unsafe extern "C" fn());
+            let invalid_opcode: HandlerFunc =
+                mem::transmute(entry::invalid_opcode_entry as unsafe extern "C" fn());
+            let protection_fault: HandlerFuncWithErrCode =
+                mem::transmute(entry::protection_fault_entry as unsafe extern "C" fn());
+            let page_fault: PageFaultHandlerFunc =
+                mem::transmute(entry::page_fault_entry as unsafe extern "C" fn());
+            let timer: HandlerFunc =
+                mem::transmute(entry::timer_interrupt_entry as unsafe extern "C" fn());
+
+            idt.divide_error.set_handler_fn(divide_error);
+            idt.invalid_opcode.set_handler_fn(invalid_opcode);
+            idt.general_protection_fault.set_handler_fn(protection_fault);
+            idt.page_fault.set_handler_fn(page_fault);
+            idt[usize::from(TIMER_INTERRUPT_ID)].set_handler_fn(timer);
+        }
+
+        // The entry point saves the registers itself, and user mode may use it.
+        let syscall_handler: HandlerFunc = unsafe {
+            mem::transmute(syscall::int80_entry as unsafe extern "C" fn())
+        };
+        idt[usize::from(SYSCALL_INTERRUPT_ID)]
+            .set_handler_fn(syscall_handler)
+            .set_privilege_level(PrivilegeLevel::Ring3);
 
         // Needs unsafe for the set_stack_index method.
         unsafe {
@@ -61,57 +115,124 @@ pub fn init() -> Result<(), &'static str> {
     Ok(())
 }
 
+/// Number of times the interrupt or exception `vector` was handled since
+/// boot.
+pub fn interrupt_count(vector: u8) -> u64 {
+    COUNTS[usize::from(vector)].load(Ordering::Relaxed)
+}
+
+/// Name of the interrupt or exception `vector`, if it has a handler.
+pub fn interrupt_name(vector: u8) -> Option<&'static str> {
+    let name = match vector {
+        0 => "divide by zero",
+        1 => "debug",
+        2 => "non maskable interrupt",
+        3 => "breakpoint",
+        4 => "overflow",
+        5 => "bound range exceeded",
+        6 => "invalid opcode",
+        7 => "device not available",
+        8 => "double fault",
+        10 => "invalid tss",
+        11 => "segment not present",
+        12 => "stack segment fault",
+        13 => "general protection fault",
+        14 => "page fault",
+        16 => "x87 floating point",
+        17 => "alignment check",
+        18 => "machine check",
+        19 => "simd floating point",
+        20 => "virtualization",
+        30 => "security exception",
+        TIMER_INTERRUPT_ID => "timer",
+        KEYBOARD_INTERRUPT_ID => "keyboard",
+        SERIAL_INTERRUPT_ID => "serial",
+        MOUSE_INTERRUPT_ID => "mouse",
+        SYSCALL_INTERRUPT_ID => "system call",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Counts an interrupt or exception of `vector`, as its handler starts.
+pub(crate) fn count(vector: u8) {
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
 // Exception handler functions
 // Idea behind it: Print the exeption and return to normal activity when possible.
 // If happens to be not possible, print the exception and enter a infinite loop.
+// Exceptions raised by user code are signals to its process instead, see `user`.
 
 /// Divide by Zero exception handler
-extern "x86-interrupt" fn divide_error_handler(stack_frame: &mut InterruptStackFrame) {
-    exception_info("DIVIDE BY ZERO", stack_frame);
+pub(crate) extern "C" fn divide_error_handler(registers: &mut Registers, _error_code: u64) {
+    count(0);
+    if user::handle_fault("DIVIDE BY ZERO", SIGFPE, registers, None) {
+        return;
+    }
+    vgacolor!(Color::Red);
+    kprintln!("EXCEPTION: DIVIDE BY ZERO\n{:#x?}", registers);
+    vgacolor!(Color::Green);
 }
 
 /// Non Maskable Interrupt exception handler
 extern "x86-interrupt" fn non_maskable_handler(stack_frame: &mut InterruptStackFrame) {
+    count(2);
     exception_info("NON MASKABLE INTERRUPT", stack_frame);
 }
 
 /// Debug exception handler
 extern "x86-interrupt" fn debug_handler(stack_frame: &mut InterruptStackFrame) {
+    count(1);
     exception_info("DEBUG", stack_frame);
 }
 
 /// Breakpoint exception handler
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
+    count(3);
     exception_info("BREAKPOINT", stack_frame);
 }
 
 /// Overflow exception handler
 extern "x86-interrupt" fn overflow_handler(stack_frame: &mut InterruptStackFrame) {
+    count(4);
     exception_info("OVERFLOW", stack_frame);
 }
 
 /// Bound Range Exceeded exception handler
 extern "x86-interrupt" fn bound_range_handler(stack_frame: &mut InterruptStackFrame) {
+    count(5);
+    user::check_fault("BOUND RANGE EXCEEDED", SIGSEGV, stack_frame, None);
     exception_info("BOUND RANGE EXCEEDED", stack_frame);
 }
 
 /// Invalid Opcode exception handler
-extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: &mut InterruptStackFrame) {
-    exception_info("INVALID OPTICODE", stack_frame);
+pub(crate) extern "C" fn invalid_opcode_handler(registers: &mut Registers, _error_code: u64) {
+    count(6);
+    if user::handle_fault("INVALID OPTICODE", SIGILL, registers, None) {
+        return;
+    }
+    vgacolor!(Color::Red);
+    kprintln!("EXCEPTION: INVALID OPTICODE\n{:#x?}", registers);
+    vgacolor!(Color::Green);
 }
 
 /// Device Not Available exception handler
 extern "x86-interrupt" fn dev_not_available_handler(stack_frame: &mut InterruptStackFrame) {
+    count(7);
     exception_info("DEVICE NOT AVAILABLE", stack_frame);
 }
 
 /// Device Not Available exception handler
 extern "x86-interrupt" fn x87_floating_point_handler(stack_frame: &mut InterruptStackFrame) {
+    count(16);
+    user::check_fault("X87 FLOATING POINT", SIGFPE, stack_frame, None);
     exception_info("X87 FLOATING POINT", stack_frame);
 }
 
 /// Machine Check exception handler
 extern "x86-interrupt" fn machine_check_handler(stack_frame: &mut InterruptStackFrame) -> ! {
+    count(18);
     exception_info("X87 MACHINE CHECK", stack_frame);
     // Make sure we print only one in this case.
     // Since it is inrecoverable, it will keep getting the same error
@@ -121,11 +242,14 @@ extern "x86-interrupt" fn machine_check_handler(stack_frame: &mut InterruptStack
 
 /// SIMD Floating Point exception handler
 extern "x86-interrupt" fn simd_float_handler(stack_frame: &mut InterruptStackFrame) {
+    count(19);
+    user::check_fault("SIMD FLOATING POINT", SIGFPE, stack_frame, None);
     exception_info("SIMD FLOATING POINT", stack_frame);
 }
 
 /// Virtualization exception handler
 extern "x86-interrupt" fn virtualization_handler(stack_frame: &mut InterruptStackFrame) {
+    count(20);
     exception_info("VIRTUALIZATION", stack_frame);
 }
 
@@ -134,6 +258,9 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: u64,
 ) -> ! {
+    count(8);
+    // Likely a stack overflow, possibly in the middle of printing.
+    crate::console::enter_emergency();
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: DOUBLE FAULT");
     kprintln!("Error code: {:?}", error_code);
@@ -150,6 +277,7 @@ extern "x86-interrupt" fn invalid_tss_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: u64,
 ) {
+    count(10);
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: INVALID TSS");
     kprintln!("Error code: {:?}", error_code);
@@ -164,6 +292,7 @@ extern "x86-interrupt" fn seg_not_present_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: u64,
 ) {
+    count(11);
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: SEGMENT NOT PRESENT");
     kprintln!("Error code: {:?}", error_code);
@@ -178,6 +307,9 @@ extern "x86-interrupt" fn stack_segment_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: u64,
 ) {
+    count(12);
+    user::check_fault("STACK SEGMENT FAULT", SIGBUS, stack_frame, None);
+
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: STACK SEGMENT FAULT");
     kprintln!("Error code: {:?}", error_code);
@@ -188,14 +320,16 @@ extern "x86-interrupt" fn stack_segment_handler(
 }
 
 /// General Protection Fault exception handler
-extern "x86-interrupt" fn protection_fault_handler(
-    stack_frame: &mut InterruptStackFrame,
-    error_code: u64,
-) {
+pub(crate) extern "C" fn protection_fault_handler(registers: &mut Registers, error_code: u64) {
+    count(13);
+    if user::handle_fault("GENERAL PROTECTION FAULT", SIGSEGV, registers, None) {
+        return;
+    }
+
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: GENERAL PROTECTION FAULT");
     kprintln!("Error code: {:?}", error_code);
-    kprintln!("{:#?}", stack_frame);
+    kprintln!("{:#x?}", registers);
 
     vgacolor!(Color::White);
     hlt_loop();
@@ -206,6 +340,9 @@ extern "x86-interrupt" fn alignment_check_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: u64,
 ) {
+    count(17);
+    user::check_fault("ALIGNMENT CHECK", SIGBUS, stack_frame, None);
+
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: ALIGNMENT CHECK");
     kprintln!("Error code: {:?}", error_code);
@@ -220,6 +357,7 @@ extern "x86-interrupt" fn security_exception_handler(
     stack_frame: &mut InterruptStackFrame,
     error_code: u64,
 ) {
+    count(30);
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: ALIGNMENT CHECK");
     kprintln!("Error code: {:?}", error_code);
@@ -230,56 +368,86 @@ extern "x86-interrupt" fn security_exception_handler(
 }
 
 /// Page fault handler
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: &mut InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
+pub(crate) extern "C" fn page_fault_handler(registers: &mut Registers, error_code: u64) {
+    count(14);
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+
+    // Writes to pages shared copy-on-write go on with a copy.
+    let write = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if error_code.contains(write) && crate::mem::copy_on_write(Cr2::read()) == Ok(true) {
+        return;
+    }
+
+    if user::handle_fault("PAGE FAULT", SIGSEGV, registers, Some(Cr2::read())) {
+        return;
+    }
+
     vgacolor!(Color::Red);
     kprintln!("EXCEPTION: PAGE FAULT");
     kprintln!("Error code: {:?}", error_code);
     kprintln!("Accessed Address: {:?}", Cr2::read());
-    kprintln!("{:#?}", stack_frame);
+    kprintln!("{:#x?}", registers);
 
     vgacolor!(Color::White);
     hlt_loop();
 }
 
-/// Time interrupt handler
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
-    // kprint!(".");
+/// Time interrupt handler. User code it interrupted gets its pending signals.
+pub(crate) extern "C" fn timer_interrupt_handler(registers: &mut Registers, _error_code: u64) {
+    count(TIMER_INTERRUPT_ID);
+    crate::time::tick();
+    crate::task::wake_timers();
+    crate::testing::check_timeout();
     unsafe { PICS.lock().notify_end_of_interrupt(TIMER_INTERRUPT_ID) }
+    crate::thread::preempt();
+
+    if registers.cs & 3 == 3 {
+        signal::deliver(registers);
+    }
 }
 
 /// Keyboard interrupt handler
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
-
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(
-                layouts::Us104Key,
-                ScancodeSet1,
-                HandleControl::MapLettersToUnicode
-            ));
-    }
+    count(KEYBOARD_INTERRUPT_ID);
+    use crate::hid::pckbd;
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+    let mut port = Port::new(pckbd::DATA_PORT);
 
     let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => kprint!("{}", character),
-                DecodedKey::RawKey(key) => kprint!("{:?}", key),
-            }
-        }
-    }
+    pckbd::add_scancode(scancode);
 
     unsafe { PICS.lock().notify_end_of_interrupt(KEYBOARD_INTERRUPT_ID) }
 }
 
+/// Serial port (COM1) interrupt handler
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    count(SERIAL_INTERRUPT_ID);
+    use crate::uart::rx;
+
+    let mut line_sts: Port<u8> = Port::new(rx::LINE_STS_PORT);
+    let mut data: Port<u8> = Port::new(rx::DATA_PORT);
+
+    // The FIFO may hold more than one byte.
+    while unsafe { line_sts.read() } & 1 != 0 {
+        rx::add_byte(unsafe { data.read() });
+    }
+
+    unsafe { PICS.lock().notify_end_of_interrupt(SERIAL_INTERRUPT_ID) }
+}
+
+/// Mouse interrupt handler
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    count(MOUSE_INTERRUPT_ID);
+    use crate::hid::ps2mouse;
+
+    let mut port = Port::new(ps2mouse::DATA_PORT);
+
+    let byte: u8 = unsafe { port.read() };
+    ps2mouse::add_byte(byte);
+
+    unsafe { PICS.lock().notify_end_of_interrupt(MOUSE_INTERRUPT_ID) }
+}
+
 /// Helper function to the exception handler functions
 fn exception_info(type_str: &str, stack_frame: &mut InterruptStackFrame) {
     vgacolor!(Color::Red);
@@ -1,5 +1,8 @@
+pub mod cmdline;
 pub mod gdt;
 pub mod idt;
 pub mod pic;
-pub mod vga;
+pub mod pit;
 pub mod serial;
+pub mod syscall;
+pub mod vga;
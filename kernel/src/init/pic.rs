@@ -1,8 +1,52 @@
 use pic8259_simple::ChainedPics;
 use spin::Mutex;
+use x86_64::instructions::port::Port;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+/// Data ports of the master and slave PICs, where the IRQ masks are.
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_DATA: u16 = 0xA1;
+
+/// IRQ line of the slave PIC on the master PIC.
+const CASCADE_IRQ: u8 = 2;
+
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// Unmasks an IRQ line (0 to 15), so its interrupts are delivered. Unmasking
+/// an IRQ of the slave PIC also unmasks the cascade line.
+///
+/// This function is unsafe because the caller must ensure there is a handler
+/// for the interrupt in the IDT.
+pub unsafe fn unmask(irq: u8) {
+    if irq >= 8 {
+        set_mask(PIC_2_DATA, irq - 8, false);
+        set_mask(PIC_1_DATA, CASCADE_IRQ, false);
+    } else {
+        set_mask(PIC_1_DATA, irq, false);
+    }
+}
+
+/// Masks an IRQ line (0 to 15), so its interrupts are ignored.
+pub fn mask(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            set_mask(PIC_2_DATA, irq - 8, true);
+        } else {
+            set_mask(PIC_1_DATA, irq, true);
+        }
+    }
+}
+
+unsafe fn set_mask(port: u16, line: u8, masked: bool) {
+    let mut port: Port<u8> = Port::new(port);
+    let mask = port.read();
+
+    if masked {
+        port.write(mask | (1 << line));
+    } else {
+        port.write(mask & !(1 << line));
+    }
+}
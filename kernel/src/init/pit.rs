@@ -0,0 +1,28 @@
+//! Programmable Interval Timer (PIT), the source of the timer interrupt.
+
+use x86_64::instructions::port::Port;
+
+/// Frequency of the PIT oscillator, in Hz.
+const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// Frequency the timer interrupt fires at, in Hz.
+pub const TIMER_HZ: u32 = 100;
+
+/// Channel 0, low byte then high byte, rate generator, binary mode.
+const CHANNEL0_RATE_GENERATOR: u8 = 0x34;
+
+/// Programs the channel 0 of the PIT to fire the timer interrupt `TIMER_HZ`
+/// times per second.
+pub fn init() -> Result<(), &'static str> {
+    let divisor = PIT_FREQUENCY / TIMER_HZ;
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+
+    unsafe {
+        command.write(CHANNEL0_RATE_GENERATOR);
+        channel0.write(divisor as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+
+    Ok(())
+}
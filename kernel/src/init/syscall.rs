@@ -0,0 +1,30 @@
+//! Setup of `syscall` and `sysret`: the segments they load, the entry point,
+//! and the flags masked on entry.
+
+use x86_64::{
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    VirtAddr,
+};
+
+use crate::{init::gdt, syscall};
+
+/// Enables `syscall`, which enters `syscall::syscall_entry`. The GDT must be
+/// loaded.
+pub fn init() -> Result<(), &'static str> {
+    Star::write(
+        gdt::user_code_selector(),
+        gdt::user_data_selector(),
+        gdt::code_selector(),
+        gdt::data_selector(),
+    )?;
+    LStar::write(VirtAddr::new(syscall::syscall_entry as usize as u64));
+    // Interrupts stay disabled until the kernel stack is switched to.
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG);
+
+    unsafe { Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS) };
+
+    Ok(())
+}
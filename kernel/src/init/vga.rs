@@ -1,14 +1,15 @@
-use crate::vga::Vga;
+use crate::{
+    io::MmioRegion,
+    vga::{Vga, COLS, ROWS},
+};
 
 use lazy_static::lazy_static;
 use spin::Mutex;
 
 lazy_static! {
     /// Default VGA output initialized
-    pub static ref VGA: Mutex<Vga<&'static mut [u8]>> = {
-        let slice = unsafe {
-            core::slice::from_raw_parts_mut(0xb8000 as *mut u8, 4000)
-        };
-        Mutex::new(Vga::new(slice))
+    pub static ref VGA: Mutex<Vga<MmioRegion>> = {
+        let buffer = unsafe { MmioRegion::new(0xb8000, ROWS * COLS * 2) };
+        Mutex::new(Vga::new(buffer))
     };
 }
@@ -0,0 +1,310 @@
+//! # Hardware I/O abstraction
+//!
+//! Drivers talk to their device through `PortIo` (the x86 I/O port space) and
+//! `Mmio` (memory-mapped I/O) instead of doing the accesses themselves, so the
+//! same driver logic runs against the real hardware, with `Pio` and
+//! `MmioRegion`, and against mocks in the tests, on the host as well as in
+//! QEMU.
+//!
+//! # Examples
+//! ```no_run
+//! let mut io = MockPortIo::new();
+//! io.queue_read(0x64, 0x01);
+//!
+//! assert_eq!(unsafe { io.read_u8(0x64) }, 0x01);
+//! unsafe { io.write_u8(0x60, 0xF4) };
+//! assert_eq!(io.writes(), &[(0x60, 0xF4)]);
+//! ```
+
+use core::ptr;
+
+use x86_64::instructions::port::Port;
+
+/// Access to the I/O port space.
+///
+/// The methods are unsafe because an I/O port access can have side effects
+/// that violate memory safety, like reprogramming a DMA controller.
+pub trait PortIo {
+    unsafe fn read_u8(&mut self, port: u16) -> u8;
+    unsafe fn write_u8(&mut self, port: u16, value: u8);
+    unsafe fn read_u16(&mut self, port: u16) -> u16;
+    unsafe fn write_u16(&mut self, port: u16, value: u16);
+    unsafe fn read_u32(&mut self, port: u16) -> u32;
+    unsafe fn write_u32(&mut self, port: u16, value: u32);
+}
+
+/// The real I/O port space, accessed with the `in` and `out` instructions.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Pio;
+
+impl PortIo for Pio {
+    unsafe fn read_u8(&mut self, port: u16) -> u8 {
+        Port::new(port).read()
+    }
+
+    unsafe fn write_u8(&mut self, port: u16, value: u8) {
+        Port::new(port).write(value);
+    }
+
+    unsafe fn read_u16(&mut self, port: u16) -> u16 {
+        Port::new(port).read()
+    }
+
+    unsafe fn write_u16(&mut self, port: u16, value: u16) {
+        Port::new(port).write(value);
+    }
+
+    unsafe fn read_u32(&mut self, port: u16) -> u32 {
+        Port::new(port).read()
+    }
+
+    unsafe fn write_u32(&mut self, port: u16, value: u32) {
+        Port::new(port).write(value);
+    }
+}
+
+/// Maximum number of reads queued, and of writes recorded, by `MockPortIo`.
+pub const MOCK_CAPACITY: usize = 64;
+
+/// Maximum number of ports with a default value in `MockPortIo`.
+pub const MOCK_DEFAULTS: usize = 8;
+
+/// Value read from a port nothing answers, like a floating bus.
+pub const FLOATING: u32 = 0xFFFF_FFFF;
+
+/// Fake I/O port space for the tests.
+///
+/// Reads return the values queued for their port, in order, then the default
+/// value of the port, or `FLOATING` if it has none. Writes are recorded.
+#[derive(Debug, Clone)]
+pub struct MockPortIo {
+    reads: [(u16, u32); MOCK_CAPACITY],
+    reads_len: usize,
+    defaults: [Option<(u16, u32)>; MOCK_DEFAULTS],
+    writes: [(u16, u32); MOCK_CAPACITY],
+    writes_len: usize,
+}
+
+impl MockPortIo {
+    /// Creates a new instance of MockPortIo, with nothing to read.
+    pub const fn new() -> MockPortIo {
+        MockPortIo {
+            reads: [(0, 0); MOCK_CAPACITY],
+            reads_len: 0,
+            defaults: [None; MOCK_DEFAULTS],
+            writes: [(0, 0); MOCK_CAPACITY],
+            writes_len: 0,
+        }
+    }
+
+    /// Queues a value the next read of `port` returns.
+    ///
+    /// # Panics
+    /// If `MOCK_CAPACITY` reads are already queued.
+    pub fn queue_read(&mut self, port: u16, value: u32) -> &mut MockPortIo {
+        assert!(self.reads_len < MOCK_CAPACITY, "Too many mock port reads");
+        self.reads[self.reads_len] = (port, value);
+        self.reads_len += 1;
+
+        self
+    }
+
+    /// Sets the value reads of `port` return when nothing is queued for it.
+    ///
+    /// # Panics
+    /// If `MOCK_DEFAULTS` other ports already have a default.
+    pub fn set_default(&mut self, port: u16, value: u32) -> &mut MockPortIo {
+        let slot = self
+            .defaults
+            .iter_mut()
+            .find(|slot| slot.map_or(true, |(p, _)| p == port))
+            .expect("Too many mock port defaults");
+        *slot = Some((port, value));
+
+        self
+    }
+
+    /// Number of queued reads not done yet.
+    pub fn pending_reads(&self) -> usize {
+        self.reads_len
+    }
+
+    /// Writes done, oldest first, as `(port, value)`.
+    pub fn writes(&self) -> &[(u16, u32)] {
+        &self.writes[..self.writes_len]
+    }
+
+    /// Values written to `port`, oldest first.
+    pub fn writes_to(&self, port: u16) -> impl Iterator<Item = u32> + '_ {
+        self.writes()
+            .iter()
+            .filter(move |&&(p, _)| p == port)
+            .map(|&(_, value)| value)
+    }
+
+    /// Forgets the writes done.
+    pub fn clear_writes(&mut self) {
+        self.writes_len = 0;
+    }
+
+    fn read(&mut self, port: u16) -> u32 {
+        let queued = self.reads[..self.reads_len]
+            .iter()
+            .position(|&(p, _)| p == port);
+
+        match queued {
+            Some(index) => {
+                let (_, value) = self.reads[index];
+                self.reads.copy_within(index + 1..self.reads_len, index);
+                self.reads_len -= 1;
+                value
+            }
+            None => self
+                .defaults
+                .iter()
+                .flatten()
+                .find(|&&(p, _)| p == port)
+                .map_or(FLOATING, |&(_, value)| value),
+        }
+    }
+
+    fn write(&mut self, port: u16, value: u32) {
+        assert!(self.writes_len < MOCK_CAPACITY, "Too many mock port writes");
+        self.writes[self.writes_len] = (port, value);
+        self.writes_len += 1;
+    }
+}
+
+impl Default for MockPortIo {
+    fn default() -> MockPortIo {
+        MockPortIo::new()
+    }
+}
+
+impl PortIo for MockPortIo {
+    unsafe fn read_u8(&mut self, port: u16) -> u8 {
+        self.read(port) as u8
+    }
+
+    unsafe fn write_u8(&mut self, port: u16, value: u8) {
+        self.write(port, u32::from(value));
+    }
+
+    unsafe fn read_u16(&mut self, port: u16) -> u16 {
+        self.read(port) as u16
+    }
+
+    unsafe fn write_u16(&mut self, port: u16, value: u16) {
+        self.write(port, u32::from(value));
+    }
+
+    unsafe fn read_u32(&mut self, port: u16) -> u32 {
+        self.read(port)
+    }
+
+    unsafe fn write_u32(&mut self, port: u16, value: u32) {
+        self.write(port, value);
+    }
+}
+
+/// Access to a region of memory-mapped I/O, byte by byte.
+///
+/// Offsets out of the region panic.
+pub trait Mmio {
+    /// Size of the region, in bytes.
+    fn len(&self) -> usize;
+
+    fn read(&self, offset: usize) -> u8;
+
+    fn write(&mut self, offset: usize, value: u8);
+
+    /// Returns `true` if the region has no bytes.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A real region of memory-mapped I/O, accessed with volatile operations so
+/// they are never optimized out.
+#[derive(Debug)]
+pub struct MmioRegion {
+    base: *mut u8,
+    len: usize,
+}
+
+// The region is only reachable through its owner.
+unsafe impl Send for MmioRegion {}
+
+impl MmioRegion {
+    /// Creates a new instance of MmioRegion.
+    ///
+    /// This function is unsafe because the caller must ensure that the region
+    /// is mapped, at the same address, and owned by nobody else.
+    pub const unsafe fn new(address: usize, len: usize) -> MmioRegion {
+        MmioRegion {
+            base: address as *mut u8,
+            len,
+        }
+    }
+}
+
+impl Mmio for MmioRegion {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        assert!(offset < self.len, "MMIO read out of the region");
+        unsafe { ptr::read_volatile(self.base.add(offset)) }
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        assert!(offset < self.len, "MMIO write out of the region");
+        unsafe { ptr::write_volatile(self.base.add(offset), value) }
+    }
+}
+
+/// Plain memory standing for a region of memory-mapped I/O, for the tests.
+impl<const N: usize> Mmio for [u8; N] {
+    fn len(&self) -> usize {
+        N
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        self[offset]
+    }
+
+    fn write(&mut self, offset: usize, value: u8) {
+        self[offset] = value;
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn mock_port_io() {
+    let mut io = MockPortIo::new();
+    io.queue_read(0x64, 1)
+        .queue_read(0x60, 0xFA)
+        .queue_read(0x64, 2);
+    io.set_default(0x64, 3);
+
+    unsafe {
+        // Queued per port, in order, then the default or a floating bus
+        assert_eq!(io.read_u8(0x60), 0xFA);
+        assert_eq!(io.read_u8(0x64), 1);
+        assert_eq!(io.read_u8(0x64), 2);
+        assert_eq!(io.read_u8(0x64), 3);
+        assert_eq!(io.read_u8(0x60), 0xFF);
+        assert_eq!(io.pending_reads(), 0);
+
+        io.write_u8(0x60, 0xF4);
+        io.write_u32(0xf4, 0x10);
+        io.write_u8(0x60, 0xF5);
+    }
+
+    assert_eq!(io.writes(), &[(0x60, 0xF4), (0xf4, 0x10), (0x60, 0xF5)]);
+    assert!(io.writes_to(0x60).eq([0xF4, 0xF5].iter().copied()));
+
+    io.clear_writes();
+    assert!(io.writes().is_empty());
+}
@@ -1,18 +1,42 @@
 #![no_std]
 #![feature(abi_x86_interrupt)]
-#![cfg_attr(test, no_main)]
+#![feature(alloc_error_handler)]
+#![feature(asm)]
+#![feature(naked_functions)]
+#![feature(min_const_generics)]
+#![cfg_attr(all(test, target_os = "none"), no_main)]
 #![feature(custom_test_frameworks)]
-#![test_runner(crate::test_runner)]
-#![reexport_test_harness_main = "test_main"]
+#![test_runner(crate::testing::test_runner)]
+#![cfg_attr(target_os = "none", reexport_test_harness_main = "test_main")]
 
+// The unit tests also run on the host, see `testing`.
+#[cfg(all(test, not(target_os = "none")))]
+extern crate std;
+
+extern crate alloc;
+
+pub mod console;
+pub mod elf;
+pub mod fs;
 pub mod hid;
 pub mod init;
-mod macros;
+pub mod io;
+pub mod logger;
+#[doc(hidden)]
+pub mod macros;
+pub mod mem;
 pub mod prelude;
+pub mod process;
+pub mod qemu;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod testing;
+pub mod thread;
+pub mod time;
 pub mod uart;
+pub mod user;
 pub mod vga;
-pub mod mem;
-
 
 /// A loop that doesn't let the CPU cores at max clock
 /// halting the CPU usage when in a dead loop
@@ -22,40 +46,29 @@ pub fn hlt_loop() -> ! {
     }
 }
 
-pub fn test_runner(tests: &[&dyn Fn()]) {
-    use crate::prelude::*;
-
-    testprintln!(Color::Green; "Running {} tests", tests.len());
-
-    for test in tests {
-        test();
-    }
+/// Called when the kernel heap can not satisfy an allocation.
+#[cfg(target_os = "none")]
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    panic!("Out of heap memory, allocating {:?}", layout)
 }
 
-#[cfg(test)]
-use bootloader::{BootInfo, entry_point};
+#[cfg(all(test, target_os = "none"))]
+use bootloader::{entry_point, BootInfo};
 
-#[cfg(test)]
+#[cfg(all(test, target_os = "none"))]
 entry_point!(test_kmain);
 
 /// Entry point for `cargo xtest`
-#[cfg(test)]
+#[cfg(all(test, target_os = "none"))]
 fn test_kmain(_boot_info: &'static BootInfo) -> ! {
+    testing::init();
     test_main();
     hlt_loop();
 }
 
-#[cfg(test)]
+#[cfg(all(test, target_os = "none"))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    use crate::prelude::*;
-    s1println!("[failed]\n");
-    s1println!("Error: {}\n", info);
-
-    vgacolor!(Color::Red);
-    kprintln!("[failed]\n");
-    kprintln!("Error: {}\n", info);
-    vgacolor!(Color::White);
-
-    hlt_loop()
+    testing::test_panic_handler(info)
 }
@@ -0,0 +1,331 @@
+//! In-memory kernel log, like `dmesg`.
+//!
+//! Keeps the last `CAPACITY` lines logged, both through the `ring` sink of the
+//! logger, always enabled, and printed to the console with `kprint!`, each one with a sequence
+//! number. It needs no initialization, so it captures everything from the very
+//! start of the boot.
+//!
+//! # Examples
+//! ```no_run
+//! // Print everything after the line 42
+//! dmesg::read(42, |line| kprintln!("{}", line));
+//! ```
+
+use core::{
+    fmt::{self, Write},
+    str,
+    time::Duration,
+};
+
+use log::Level;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::time;
+
+/// Number of lines kept.
+pub const CAPACITY: usize = 256;
+
+/// Maximum length of a line, in bytes. Longer lines are truncated.
+pub const LINE_MAX: usize = 120;
+
+static DMESG: Mutex<Dmesg> = Mutex::new(Dmesg::new());
+
+/// A line of the kernel log.
+#[derive(Copy, Clone)]
+pub struct Line {
+    seq: u64,
+    uptime: Duration,
+    level: Option<Level>,
+    len: usize,
+    text: [u8; LINE_MAX],
+}
+
+impl Line {
+    const EMPTY: Line = Line {
+        seq: 0,
+        uptime: Duration::from_secs(0),
+        level: None,
+        len: 0,
+        text: [0; LINE_MAX],
+    };
+
+    /// Sequence number, starting from 0 at boot.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Time since boot when the line was logged.
+    pub fn uptime(&self) -> Duration {
+        self.uptime
+    }
+
+    /// Level of the message, `None` for text printed to the console.
+    pub fn level(&self) -> Option<Level> {
+        self.level
+    }
+
+    /// Text of the line, without the line break.
+    pub fn text(&self) -> &str {
+        // Only whole characters are ever copied into `text`.
+        unsafe { str::from_utf8_unchecked(&self.text[..self.len]) }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.len + len > LINE_MAX {
+                return;
+            }
+            c.encode_utf8(&mut self.text[self.len..]);
+            self.len += len;
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<{}> [{:>5}.{:06}] ",
+            self.seq,
+            self.uptime.as_secs(),
+            self.uptime.subsec_micros()
+        )?;
+
+        if let Some(level) = self.level {
+            write!(f, "{:<5} ", level)?;
+        }
+
+        f.write_str(self.text())
+    }
+}
+
+impl fmt::Debug for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Line")
+            .field("seq", &self.seq)
+            .field("uptime", &self.uptime)
+            .field("level", &self.level)
+            .field("text", &self.text())
+            .finish()
+    }
+}
+
+struct Dmesg {
+    lines: [Line; CAPACITY],
+    next_seq: u64,
+    /// Line being written, committed on a line break.
+    current: Line,
+    /// Text printed to the console that did not end with a line break yet.
+    console: Line,
+}
+
+impl Dmesg {
+    const fn new() -> Dmesg {
+        Dmesg {
+            lines: [Line::EMPTY; CAPACITY],
+            next_seq: 0,
+            current: Line::EMPTY,
+            console: Line::EMPTY,
+        }
+    }
+
+    /// Sequence number of the oldest line kept.
+    fn first_seq(&self) -> u64 {
+        self.next_seq.saturating_sub(CAPACITY as u64)
+    }
+
+    fn commit(&mut self, mut line: Line) {
+        line.seq = self.next_seq;
+        self.lines[(self.next_seq % CAPACITY as u64) as usize] = line;
+        self.next_seq += 1;
+    }
+
+    fn get(&self, seq: u64) -> Option<&Line> {
+        if seq < self.first_seq() || seq >= self.next_seq {
+            None
+        } else {
+            Some(&self.lines[(seq % CAPACITY as u64) as usize])
+        }
+    }
+}
+
+/// Writes a message through `Dmesg::current`, one line per line break.
+struct LogWriter<'a> {
+    dmesg: &'a mut Dmesg,
+}
+
+impl Write for LogWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut parts = s.split('\n');
+
+        if let Some(first) = parts.next() {
+            self.dmesg.current.push_str(first);
+        }
+
+        for part in parts {
+            let current = self.dmesg.current;
+            self.dmesg.commit(current);
+            self.dmesg.current.len = 0;
+            self.dmesg.current.push_str(part);
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes console text through `Dmesg::console`, which stays pending until a
+/// line break comes.
+struct ConsoleWriter<'a> {
+    dmesg: &'a mut Dmesg,
+}
+
+impl Write for ConsoleWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut parts = s.split('\n');
+
+        if let Some(first) = parts.next() {
+            self.dmesg.console.push_str(first);
+        }
+
+        for part in parts {
+            let console = self.dmesg.console;
+            self.dmesg.commit(console);
+            self.dmesg.console = Line::EMPTY;
+            self.dmesg.console.uptime = time::uptime();
+            self.dmesg.console.push_str(part);
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds a message of the logger. Messages with line breaks take many lines.
+///
+/// The log is never waited for: if a fault interrupted a writer, messages
+/// from the fault are dropped.
+pub fn log(level: Level, args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        let mut dmesg = match DMESG.try_lock() {
+            Some(dmesg) => dmesg,
+            None => return,
+        };
+        dmesg.current = Line::EMPTY;
+        dmesg.current.level = Some(level);
+        dmesg.current.uptime = time::uptime();
+
+        let _ = LogWriter { dmesg: &mut dmesg }.write_fmt(args);
+
+        let current = dmesg.current;
+        dmesg.commit(current);
+    });
+}
+
+/// Adds text printed to the console. Lines are only committed when their
+/// line break is printed.
+pub fn write_console(args: fmt::Arguments) {
+    interrupts::without_interrupts(|| {
+        let mut dmesg = match DMESG.try_lock() {
+            Some(dmesg) => dmesg,
+            None => return,
+        };
+
+        if dmesg.console.len == 0 {
+            dmesg.console.uptime = time::uptime();
+        }
+
+        let _ = ConsoleWriter { dmesg: &mut dmesg }.write_fmt(args);
+    });
+}
+
+/// Sequence number of the oldest line kept.
+pub fn first_seq() -> u64 {
+    interrupts::without_interrupts(|| DMESG.lock().first_seq())
+}
+
+/// Sequence number the next line will have.
+pub fn next_seq() -> u64 {
+    interrupts::without_interrupts(|| DMESG.lock().next_seq)
+}
+
+/// Copies a line, if it is still kept.
+///
+/// Returns `None` too if the log is busy, which only happens when reading it
+/// from a fault that interrupted a writer.
+pub fn get(seq: u64) -> Option<Line> {
+    interrupts::without_interrupts(|| DMESG.try_lock()?.get(seq).copied())
+}
+
+/// Calls `f` for every line kept with a sequence number of at least `from`,
+/// oldest first. Returns the sequence number to read from next time.
+///
+/// The lines are copied one at a time, so `f` can log without deadlocking.
+pub fn read(from: u64, mut f: impl FnMut(&Line)) -> u64 {
+    let first = interrupts::without_interrupts(|| DMESG.try_lock().map(|dmesg| dmesg.first_seq()));
+    let mut seq = from.max(first.unwrap_or(from));
+
+    while let Some(line) = get(seq) {
+        f(&line);
+        seq += 1;
+    }
+
+    seq
+}
+
+/// Writes every line kept to `out`.
+pub fn dump(out: &mut impl Write) -> fmt::Result {
+    let mut result = Ok(());
+    read(0, |line| {
+        if result.is_ok() {
+            result = writeln!(out, "{}", line);
+        }
+    });
+
+    result
+}
+
+/// Writes every line kept to `SERIAL1`, as when the kernel panics.
+pub fn dump_to_serial() {
+    use crate::{console, init::serial::SERIAL1};
+
+    console::print(&*SERIAL1, format_args!("--- kernel log ---\n"));
+    read(0, |line| {
+        console::print(&*SERIAL1, format_args!("{}\n", line))
+    });
+    console::print(&*SERIAL1, format_args!("--- end of kernel log ---\n"));
+}
+
+#[cfg(test)]
+#[test_case]
+fn lines() {
+    let mut dmesg = Dmesg::new();
+
+    {
+        let mut writer = ConsoleWriter { dmesg: &mut dmesg };
+        write!(writer, "Hello ").unwrap();
+        write!(writer, "world\nsecond {}", 2).unwrap();
+    }
+    assert_eq!(dmesg.next_seq, 1);
+    assert_eq!(dmesg.get(0).unwrap().text(), "Hello world");
+    assert_eq!(dmesg.console.text(), "second 2");
+
+    // Wrapping around keeps the newest lines
+    for i in 0..CAPACITY as u64 + 10 {
+        let mut line = Line::EMPTY;
+        line.push_str(if i % 2 == 0 { "even" } else { "odd" });
+        dmesg.commit(line);
+    }
+    assert_eq!(dmesg.first_seq(), 11);
+    assert!(dmesg.get(10).is_none());
+    assert_eq!(dmesg.get(11).unwrap().seq(), 11);
+    assert_eq!(dmesg.get(11).unwrap().text(), "even");
+    assert!(dmesg.get(dmesg.next_seq).is_none());
+
+    // Long lines are truncated on a character boundary
+    let mut line = Line::EMPTY;
+    for _ in 0..LINE_MAX {
+        line.push_str("é");
+    }
+    assert_eq!(line.text().chars().count(), LINE_MAX / 2);
+}
@@ -0,0 +1,135 @@
+//! Per-module level filtering.
+//!
+//! A filter is written as a list of directives separated by commas, each one
+//! being either a level, that applies to every module, or `module=level`.
+//! The most specific module wins.
+//!
+//! # Examples
+//! ```no_run
+//! // Info by default, everything in `kernel::hid` and `kernel::hid::input` too.
+//! let filter = Filter::parse("info,kernel::hid=trace");
+//! ```
+
+use log::{Level, LevelFilter};
+
+/// Maximum number of per-module directives a filter holds.
+pub const MAX_DIRECTIVES: usize = 16;
+
+/// Level filter for the kernel logger.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    directives: [Option<(&'static str, LevelFilter)>; MAX_DIRECTIVES],
+}
+
+impl Filter {
+    /// Creates a new instance of Filter, with the same level for all modules.
+    pub const fn new(default: LevelFilter) -> Filter {
+        Filter {
+            default,
+            directives: [None; MAX_DIRECTIVES],
+        }
+    }
+
+    /// Parses a filter, ignoring malformed directives.
+    pub fn parse(spec: &'static str) -> Filter {
+        let mut filter = Filter::new(LevelFilter::Info);
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(level), None) => {
+                    if let Ok(level) = level.parse() {
+                        filter.default = level;
+                    }
+                }
+                (Some(module), Some(level)) => {
+                    if let Ok(level) = level.parse() {
+                        // Full of directives; nothing sensible to do here.
+                        let _ = filter.set_module(module, level);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        filter
+    }
+
+    /// Level used by modules without a directive.
+    pub fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    /// Changes the level used by modules without a directive.
+    pub fn set_default(&mut self, level: LevelFilter) {
+        self.default = level;
+    }
+
+    /// Sets the level of a module and its submodules.
+    pub fn set_module(
+        &mut self,
+        module: &'static str,
+        level: LevelFilter,
+    ) -> Result<(), &'static str> {
+        let slot = self
+            .directives
+            .iter_mut()
+            .find(|slot| match slot {
+                Some((name, _)) => *name == module,
+                None => true,
+            })
+            .ok_or("Too many log filter directives")?;
+
+        *slot = Some((module, level));
+        Ok(())
+    }
+
+    /// Level filter that applies to the given module.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .filter(|(module, _)| is_within(target, module))
+            .max_by_key(|(module, _)| module.len())
+            .map(|&(_, level)| level)
+            .unwrap_or(self.default)
+    }
+
+    /// Returns `true` if a message of that level in that module is logged.
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= self.level_for(target)
+    }
+
+    /// Most verbose level any module may log at.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .map(|&(_, level)| level)
+            .fold(self.default, core::cmp::max)
+    }
+}
+
+/// Returns `true` if `target` is `module` or one of its submodules.
+fn is_within(target: &str, module: &str) -> bool {
+    target == module || (target.starts_with(module) && target[module.len()..].starts_with("::"))
+}
+
+#[cfg(test)]
+#[test_case]
+fn filtering() {
+    let filter = Filter::parse("warn,kernel::hid=trace,kernel::hid::ps2mouse=off,bogus=loud");
+
+    assert_eq!(filter.default_level(), LevelFilter::Warn);
+    assert_eq!(filter.level_for("kernel"), LevelFilter::Warn);
+    assert_eq!(filter.level_for("kernel::hid"), LevelFilter::Trace);
+    assert_eq!(filter.level_for("kernel::hid::input"), LevelFilter::Trace);
+    assert_eq!(filter.level_for("kernel::hidden"), LevelFilter::Warn);
+    assert_eq!(filter.level_for("kernel::hid::ps2mouse"), LevelFilter::Off);
+    assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+    assert!(filter.enabled(Level::Error, "kernel::mem"));
+    assert!(!filter.enabled(Level::Info, "kernel::mem"));
+    assert!(filter.enabled(Level::Debug, "kernel::hid::pckbd"));
+}
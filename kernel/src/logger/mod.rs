@@ -0,0 +1,168 @@
+//! # Kernel logger
+//!
+//! Implementation of the `log` facade: messages have a level, are filtered per
+//! module, stamped with the uptime and written to every enabled sink. The
+//! `ring` sink, that keeps them in `dmesg`, is always enabled, whatever the
+//! other sinks are.
+//!
+//! It is configured at boot by two parameters:
+//! - `log`: the filter, see `filter::Filter` (default: `info`)
+//! - `log.sinks`: names of the sinks to enable, separated by commas
+//!   (default: `vga,serial`)
+//!
+//! # Examples
+//! ```no_run
+//! logger::init().unwrap();
+//! log::info!("Hello Kernel World!!");
+//! ```
+
+pub mod dmesg;
+pub mod filter;
+pub mod sink;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    console,
+    init::{cmdline, serial::SERIAL1},
+    time,
+};
+
+pub use self::{
+    filter::Filter,
+    sink::{Entry, RingSink, SerialSink, Sink, VgaSink},
+};
+
+/// Maximum number of sinks enabled at the same time.
+pub const MAX_SINKS: usize = 8;
+
+/// Screen sink.
+pub static VGA_SINK: VgaSink = VgaSink;
+/// `SERIAL1` sink.
+pub static SERIAL_SINK: SerialSink = SerialSink;
+/// In-memory sink, see `dmesg`. Always enabled.
+pub static RING_SINK: RingSink = RingSink;
+
+/// Sinks known by name, that can be enabled at boot.
+static BUILTIN_SINKS: [&dyn Sink; 3] = [&VGA_SINK, &SERIAL_SINK, &RING_SINK];
+
+static LOGGER: Logger = Logger {
+    filter: Mutex::new(Filter::new(LevelFilter::Info)),
+    sinks: Mutex::new([None; MAX_SINKS]),
+};
+
+struct Logger {
+    filter: Mutex<Filter>,
+    sinks: Mutex<[Option<&'static dyn Sink>; MAX_SINKS]>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // The filter is only busy if a fault interrupted its replacement, then
+        // the global maximum level is good enough.
+        interrupts::without_interrupts(|| match self.filter.try_lock() {
+            Some(filter) => filter.enabled(metadata.level(), metadata.target()),
+            None => metadata.level() <= log::max_level(),
+        })
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let entry = Entry {
+            level: record.level(),
+            target: record.target(),
+            uptime: time::uptime(),
+            args: *record.args(),
+        };
+
+        RING_SINK.write(&entry);
+        interrupts::without_interrupts(|| match self.sinks.try_lock() {
+            Some(sinks) => {
+                for sink in sinks.iter().flatten() {
+                    sink.write(&entry);
+                }
+            }
+            None => console::print(&*SERIAL1, format_args!("{}\n", entry)),
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the kernel logger, configured by the boot parameters.
+pub fn init() -> Result<(), &'static str> {
+    let filter = cmdline::get("log").map_or(Filter::new(LevelFilter::Info), Filter::parse);
+    set_filter(filter);
+
+    let sinks = cmdline::get("log.sinks").unwrap_or("vga,serial");
+    for name in sinks.split(',').filter(|name| !name.is_empty()) {
+        let sink = BUILTIN_SINKS
+            .iter()
+            .find(|sink| sink.name() == name)
+            .ok_or("Unknown log sink")?;
+        add_sink(*sink)?;
+    }
+
+    log::set_logger(&LOGGER).map_err(|_| "Logger already installed")
+}
+
+/// Replaces the filter.
+pub fn set_filter(filter: Filter) {
+    log::set_max_level(filter.max_level());
+    interrupts::without_interrupts(|| *LOGGER.filter.lock() = filter);
+}
+
+/// Current filter.
+pub fn filter() -> Filter {
+    interrupts::without_interrupts(|| *LOGGER.filter.lock())
+}
+
+/// Changes the level of a module and its submodules.
+pub fn set_module_level(module: &'static str, level: LevelFilter) -> Result<(), &'static str> {
+    let mut filter = filter();
+    filter.set_module(module, level)?;
+    set_filter(filter);
+
+    Ok(())
+}
+
+/// Starts writing the log to a sink too. Adding the same sink twice, or the
+/// ring sink, does nothing.
+pub fn add_sink(sink: &'static dyn Sink) -> Result<(), &'static str> {
+    interrupts::without_interrupts(|| {
+        let mut sinks = LOGGER.sinks.lock();
+
+        if same_sink(sink, &RING_SINK) || sinks.iter().flatten().any(|s| same_sink(*s, sink)) {
+            return Ok(());
+        }
+
+        let slot = sinks
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or("Too many log sinks")?;
+        *slot = Some(sink);
+
+        Ok(())
+    })
+}
+
+/// Stops writing the log to a sink. The ring sink can not be removed.
+pub fn remove_sink(sink: &'static dyn Sink) {
+    interrupts::without_interrupts(|| {
+        for slot in LOGGER.sinks.lock().iter_mut() {
+            if slot.map_or(false, |s| same_sink(s, sink)) {
+                *slot = None;
+            }
+        }
+    });
+}
+
+/// Compares sinks by address, ignoring their vtables.
+fn same_sink(a: &dyn Sink, b: &dyn Sink) -> bool {
+    a as *const dyn Sink as *const u8 == b as *const dyn Sink as *const u8
+}
@@ -0,0 +1,114 @@
+//! Destinations of the kernel log.
+
+use core::{
+    fmt::{self, Write},
+    time::Duration,
+};
+
+use log::Level;
+
+use super::dmesg;
+use crate::{
+    console::{self, Fallback},
+    init::{serial::SERIAL1, vga::VGA},
+    vga::Color,
+};
+
+/// A message being logged, as handed to the sinks.
+#[derive(Debug, Copy, Clone)]
+pub struct Entry<'a> {
+    pub level: Level,
+    pub target: &'a str,
+    pub uptime: Duration,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:>5}.{:06}] {:<5} {}: {}",
+            self.uptime.as_secs(),
+            self.uptime.subsec_micros(),
+            self.level,
+            self.target,
+            self.args
+        )
+    }
+}
+
+/// Something the kernel log can be written to.
+///
+/// Sinks are called with the interrupts disabled, and must not wait for
+/// locks, as messages may be logged from exception context. See `console`.
+pub trait Sink: Sync {
+    /// Name used to select the sink at boot.
+    fn name(&self) -> &'static str;
+
+    /// Writes a message. The sink adds the line break.
+    fn write(&self, entry: &Entry);
+}
+
+/// Color used on VGA for each level.
+pub fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::Red,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::White,
+        Level::Debug => Color::Cyan,
+        Level::Trace => Color::DarkGray,
+    }
+}
+
+/// Writes to the screen, colored by level.
+#[derive(Debug)]
+pub struct VgaSink;
+
+impl Sink for VgaSink {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write(&self, entry: &Entry) {
+        match console::lock(&*VGA) {
+            Some(mut vga) => {
+                vga.set_foreground(level_color(entry.level));
+                let _ = writeln!(vga, "{}", entry);
+                vga.set_foreground(Color::White);
+                vga.flush();
+            }
+            None => Fallback::Vga.print(format_args!("{}\n", entry)),
+        }
+    }
+}
+
+/// Writes to `SERIAL1`.
+#[derive(Debug)]
+pub struct SerialSink;
+
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn write(&self, entry: &Entry) {
+        console::print(&*SERIAL1, format_args!("{}\n", entry));
+    }
+}
+
+/// Keeps the messages in memory, in the lines of `dmesg`.
+#[derive(Debug)]
+pub struct RingSink;
+
+impl Sink for RingSink {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    fn write(&self, entry: &Entry) {
+        dmesg::log(
+            entry.level,
+            format_args!("{}: {}", entry.target, entry.args),
+        );
+    }
+}
@@ -30,6 +30,9 @@ macro_rules! kprintln {
 /// There is 3 ways of using it: Especifying where to print, or
 /// using the standard VGA output in the kernel.
 ///
+/// It never waits for the output lock, so it is safe to use from interrupt
+/// and panic context, see `console`. Outputs are always flushed.
+///
 /// # Examples
 /// ```no_run
 /// // Especifying (Assumes no flush method)
@@ -43,43 +46,31 @@ macro_rules! kprintln {
 /// ```
 #[macro_export]
 macro_rules! kprint {
-    ($ctx:expr; $($arg:tt)*) => {{
-        use core::fmt::Write;
-        use x86_64::instructions::interrupts;
-        interrupts::without_interrupts(|| {
-            $ctx.lock().write_fmt(format_args!($($arg)*)).unwrap();
-            // $ctx.lock().flush();
-        });
-    }};
-    ($ctx:expr; flush; $($arg:tt)*) => {{
-        use core::fmt::Write;
-        use x86_64::instructions::interrupts;
-        interrupts::without_interrupts(|| {
-            $ctx.lock().write_fmt(format_args!($($arg)*)).unwrap();
-            $ctx.lock().flush();
-        });
-    }};
-    ($($arg:tt)*) => {{
-        use core::fmt::Write;
-        use x86_64::instructions::interrupts;
-        interrupts::without_interrupts(|| {
-            $crate::init::vga::VGA.lock().write_fmt(format_args!($($arg)*)).unwrap();
-            $crate::init::vga::VGA.lock().flush();
-        });
-    }};
+    ($ctx:expr; flush; $($arg:tt)*) => ( $crate::console::print(&*$ctx, format_args!($($arg)*)) );
+    ($ctx:expr; $($arg:tt)*) => ( $crate::console::print(&*$ctx, format_args!($($arg)*)) );
+    ($($arg:tt)*) => ( $crate::macros::_print(format_args!($($arg)*)) );
+}
+
+/// Prints to the standard VGA output, keeping a copy in the kernel log.
+///
+/// Used by `kprint!`, which should be used instead.
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    crate::console::print(&*crate::init::vga::VGA, args);
+    crate::logger::dmesg::write_console(args);
 }
 
 /// Same as `kprintln!`, but using `SERIAL1` as default output
 #[macro_export]
 macro_rules! s1println {
-    ($fmt:expr) => ( $crate::kprintln!(SERIAL1; $fmt) );
-    ($fmt:expr, $($arg:tt)*) => ( $crate::kprintln!(SERIAL1; $fmt, $($arg)*) );
+    ($fmt:expr) => ( $crate::kprintln!($crate::init::serial::SERIAL1; $fmt) );
+    ($fmt:expr, $($arg:tt)*) => ( $crate::kprintln!($crate::init::serial::SERIAL1; $fmt, $($arg)*) );
 }
 
 /// Same as `kprint!`, but using `SERIAL1` as default output
 #[macro_export]
 macro_rules! s1print {
-    ($($arg:tt)*) => ( $crate::kprint!(SERIAL1; $($arg)*) );
+    ($($arg:tt)*) => ( $crate::kprint!($crate::init::serial::SERIAL1; $($arg)*) );
 }
 
 /// Printing macro that prints the same thing into `VGA` and `SERIAL1`
@@ -145,10 +136,10 @@ macro_rules! testprint {
 #[macro_export]
 macro_rules! vgacolor {
     ($fg:expr) => {{
-        $crate::init::vga::VGA.lock().set_foreground($fg);
+        $crate::console::set_foreground($fg);
     }};
     ($fg:expr, $bg:expr) => {{
-        $crate::init::vga::VGA.lock().set_foreground($fg);
-        $crate::init::vga::VGA.lock().set_background($fg);
+        $crate::console::set_foreground($fg);
+        $crate::console::set_background($bg);
     }};
 }
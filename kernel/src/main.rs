@@ -2,15 +2,24 @@
 #![feature(asm)]
 #![no_std]
 #![no_main]
-
 #![feature(custom_test_frameworks)]
-#![test_runner(crate::test_runner)]
+#![test_runner(kernel::testing::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-use kernel::{self, mem::{self, *}, prelude::*};
-
-use x86_64::{structures::paging::{mapper::MapperAllSizes, PageTable}, VirtAddr};
-use bootloader::{BootInfo, entry_point};
+use kernel::{
+    self, fs,
+    hid::input::{self, DecodedKey, KeyStream},
+    init::pit,
+    logger,
+    mem::{self, *},
+    prelude::*,
+    task::Executor,
+    thread, uart,
+};
+
+use bootloader::{entry_point, BootInfo};
+use futures_util::pin_mut;
+use x86_64::{structures::paging::PageTable, VirtAddr};
 
 #[cfg(not(test))]
 pub mod panic;
@@ -18,15 +27,25 @@ pub mod panic;
 entry_point!(kmain);
 
 fn kmain(boot_info: &'static BootInfo) -> ! {
+    // First of all, so everything logged during the boot is kept in `dmesg`.
+    logger::init().unwrap();
     gdt::init().unwrap();
     idt::init().unwrap();
+    kernel::init::syscall::init().unwrap();
     unsafe { PICS.lock().initialize() };
-    // x86_64::instructions::interrupts::enable();
+    pit::init().unwrap();
+    x86_64::instructions::interrupts::enable();
+    thread::init().unwrap();
 
-    kprintln!("Hello Kernel World!!");
+    log::info!("Hello Kernel World!!");
 
-    let mapper = unsafe { mem::init(boot_info.physical_memory_offset) };
+    if let Err(err) = input::init_mouse() {
+        log::warn!("PS/2 mouse not available: {}", err);
+    }
+    uart::rx::init().unwrap();
 
+    unsafe { mem::init(boot_info) }.unwrap();
+    fs::init().unwrap();
 
     let addresses = [
         // the identity-mapped vga buffer page
@@ -41,7 +60,7 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
 
     for &address in &addresses {
         let virt = VirtAddr::new(address);
-        let phys = mapper.translate_addr(virt);
+        let phys = mem::translate(virt);
         kprintln!("{:?} -> {:?}", virt, phys);
     }
 
@@ -68,30 +87,6 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
     #[cfg(test)]
     test_main();
 
-    // invoke a breakpoint exception
-    // x86_64::instructions::interrupts::int3();
-
-    // Trigger a page fault
-    // Double fault exception
-    // unsafe {
-    //     *(0xdeadbeef as *mut u64) = 42;
-    // };
-
-    // Trigger a stack overflow
-    // fn stack_overflow() {
-    //     stack_overflow(); // for each recursion, the return address is pushed
-    // }
-    //
-    // stack_overflow();
-
-    // Page fault test
-    // new
-    // let ptr = 0x204370 as *mut u32;
-    // read from a code page -> works
-    // unsafe { let x = *ptr; }
-    // write to a code page -> page fault
-    // unsafe { *ptr = 42; }
-
     // Testing accessing page tables
     // let level_4_table_ptr = 0xffff_ffff_ffff_f000 as *const PageTable;
     // let level_4_table = unsafe {&*level_4_table_ptr};
@@ -99,16 +94,31 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
     //     kprintln!("Entry {}: {:?}", i, level_4_table[i]);
     // }
 
-    hlt_loop();
-}
+    let echo = echo();
+    pin_mut!(echo);
 
+    let mut executor = Executor::new().unwrap();
+    executor.spawn(echo).unwrap();
+    executor.run();
 
-pub fn test_runner(tests: &[&dyn Fn()]) {
-    use kernel::prelude::*;
+    hlt_loop();
+}
 
-    testprintln!(Color::Green; "Running {} tests", tests.len());
+/// Echoes what is typed.
+async fn echo() {
+    let mut keyboard = KeyStream::new();
 
-    for test in tests {
-        test();
+    loop {
+        match keyboard.next_key().await.key {
+            Some(DecodedKey::Unicode(character)) => kprint!("{}", character),
+            Some(DecodedKey::RawKey(key)) => kprint!("{:?}", key),
+            None => {}
+        }
     }
 }
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    kernel::testing::test_panic_handler(info)
+}
@@ -0,0 +1,211 @@
+//! Physical frames allocator, over the memory map given by the bootloader.
+//!
+//! Freed frames are kept in a list threaded through the frames themselves,
+//! each holding the address of the next one, and handed out first.
+//!
+//! Frames have reference counts, so address spaces can share them: a frame
+//! is only freed once every reference is dropped. The counts are kept in the
+//! first frames of a usable region.
+
+use core::{mem, ptr, slice};
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+/// Size of a frame, in bytes.
+pub const FRAME_SIZE: u64 = 4096;
+
+/// End of the list of freed frames.
+const END_OF_LIST: u64 = u64::MAX;
+
+/// Hands out the usable frames of the memory map, in order.
+#[derive(Debug)]
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    /// Index of the region frames are taken from.
+    region: usize,
+    /// Address of the next frame to hand out in that region.
+    next: u64,
+    /// Frames handed out and not freed.
+    allocated: usize,
+    physical_memory_offset: u64,
+    /// First frame of the list of freed frames.
+    free: Option<PhysFrame>,
+    free_count: usize,
+    /// Reference counts of the frames, by frame number.
+    counts: &'static mut [u16],
+    /// Region whose first frames hold the counts.
+    counts_region: usize,
+    counts_size: u64,
+}
+
+impl BootInfoFrameAllocator {
+    /// Creates a new instance of BootInfoFrameAllocator, taking the frames
+    /// that hold the reference counts.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// frames marked as `Usable` in `memory_map` are really unused, and that
+    /// the complete physical memory is mapped at `physical_memory_offset`.
+    pub unsafe fn new(
+        memory_map: &'static MemoryMap,
+        physical_memory_offset: u64,
+    ) -> BootInfoFrameAllocator {
+        let frames = memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .map(|region| region.range.end_frame_number)
+            .max()
+            .unwrap_or(0) as usize;
+        let size = (frames * mem::size_of::<u16>()) as u64;
+        let size = (size + FRAME_SIZE - 1) & !(FRAME_SIZE - 1);
+
+        // Without room for them, frames can not be shared.
+        let region = memory_map.iter().position(|region| {
+            region.region_type == MemoryRegionType::Usable
+                && region.range.end_addr() - region.range.start_addr() >= size
+        });
+        let (counts, counts_region, counts_size) = match region {
+            Some(index) => {
+                let start = memory_map[index].range.start_addr() + physical_memory_offset;
+                ptr::write_bytes(start as *mut u8, 0, size as usize);
+                let counts = slice::from_raw_parts_mut(start as *mut u16, frames);
+                (counts, index, size)
+            }
+            None => (&mut [][..], 0, 0),
+        };
+
+        BootInfoFrameAllocator {
+            memory_map,
+            region: 0,
+            next: 0,
+            allocated: (counts_size / FRAME_SIZE) as usize,
+            physical_memory_offset,
+            free: None,
+            free_count: 0,
+            counts,
+            counts_region,
+            counts_size,
+        }
+    }
+
+    /// Number of frames handed out and not freed.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Number of freed frames, waiting to be handed out again.
+    pub fn free(&self) -> usize {
+        self.free_count
+    }
+
+    /// Number of references to `frame`, 0 if it is free.
+    pub fn references(&self, frame: PhysFrame) -> usize {
+        let number = frame.start_address().as_u64() / FRAME_SIZE;
+        self.counts
+            .get(number as usize)
+            .map_or(0, |count| usize::from(*count))
+    }
+
+    /// Adds a reference to `frame`, that then needs one more deallocation to
+    /// be freed.
+    pub fn share(&mut self, frame: PhysFrame) -> Result<(), &'static str> {
+        let count = self.count(frame).ok_or("Frame without a reference count")?;
+        *count = count.checked_add(1).ok_or("Frame shared too many times")?;
+        Ok(())
+    }
+
+    fn count(&mut self, frame: PhysFrame) -> Option<&mut u16> {
+        let number = frame.start_address().as_u64() / FRAME_SIZE;
+        self.counts.get_mut(number as usize)
+    }
+
+    /// Where `frame` holds the address of the next freed frame.
+    fn link(&self, frame: PhysFrame) -> *mut u64 {
+        let virt = VirtAddr::new(frame.start_address().as_u64() + self.physical_memory_offset);
+        virt.as_mut_ptr()
+    }
+
+    /// Memory map given by the bootloader, that the frames are taken from.
+    pub fn memory_map(&self) -> &'static MemoryMap {
+        self.memory_map
+    }
+
+    /// Number of usable frames in the memory map.
+    pub fn usable(&self) -> usize {
+        self.memory_map
+            .iter()
+            .filter(|region| region.region_type == MemoryRegionType::Usable)
+            .map(|region| {
+                let range = region.range;
+                (range.end_frame_number - range.start_frame_number) as usize
+            })
+            .sum()
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free {
+            let next = unsafe { *self.link(frame) };
+            self.free = match next {
+                END_OF_LIST => None,
+                next => Some(PhysFrame::containing_address(PhysAddr::new(next))),
+            };
+            self.free_count -= 1;
+            self.allocated += 1;
+            if let Some(count) = self.count(frame) {
+                *count = 1;
+            }
+            return Some(frame);
+        }
+
+        while let Some(region) = self.memory_map.get(self.region) {
+            let mut start = region.range.start_addr();
+            let end = region.range.end_addr();
+            if self.region == self.counts_region {
+                start += self.counts_size;
+            }
+
+            if region.region_type == MemoryRegionType::Usable {
+                let address = self.next.max(start);
+                if address + FRAME_SIZE <= end {
+                    self.next = address + FRAME_SIZE;
+                    self.allocated += 1;
+                    let frame = PhysFrame::containing_address(PhysAddr::new(address));
+                    if let Some(count) = self.count(frame) {
+                        *count = 1;
+                    }
+                    return Some(frame);
+                }
+            }
+
+            self.region += 1;
+        }
+
+        None
+    }
+}
+
+/// Drops a reference to the frame, and frees it if it was the last one.
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Some(count) = self.count(frame) {
+            if *count > 1 {
+                *count -= 1;
+                return;
+            }
+            *count = 0;
+        }
+
+        let next = self
+            .free
+            .map_or(END_OF_LIST, |next| next.start_address().as_u64());
+        *self.link(frame) = next;
+        self.free = Some(frame);
+        self.free_count += 1;
+        self.allocated -= 1;
+    }
+}
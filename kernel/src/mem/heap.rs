@@ -0,0 +1,256 @@
+//! Kernel heap, that `alloc` allocates from.
+//!
+//! The heap is mapped at `HEAP_START` by `mem::init`, before any address space
+//! is created, so they all share it with the other kernel mappings. Its free
+//! blocks are kept in a list sorted by address, the first that fits being
+//! used, and merged with their neighbours when freed.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem,
+    ptr::{self, NonNull},
+};
+
+use x86_64::{
+    structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags},
+    VirtAddr,
+};
+
+use super::Memory;
+use crate::sync::IrqSafeSpinlock;
+
+/// Start of the heap, in a level 4 entry of its own.
+pub const HEAP_START: u64 = 0x4444_4444_0000;
+
+/// Size of the heap, mapped at once.
+pub const HEAP_SIZE: usize = 4 * 1024 * 1024;
+
+/// Sizes and addresses of blocks are multiples of it, so a free block always
+/// fits in what is left of one.
+const BLOCK_ALIGN: usize = mem::size_of::<Block>();
+
+/// Use of the heap.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes of the heap.
+    pub size: usize,
+    /// Bytes allocated, rounded up to blocks.
+    pub used: usize,
+}
+
+/// A free block, at its start.
+struct Block {
+    size: usize,
+    next: *mut Block,
+}
+
+/// First-fit allocator of a region of memory.
+pub struct Heap {
+    /// First free block.
+    free: *mut Block,
+    size: usize,
+    used: usize,
+}
+
+// Only reached through the lock of the allocator.
+unsafe impl Send for Heap {}
+
+impl Heap {
+    /// A heap without memory, until `init`.
+    pub const fn empty() -> Heap {
+        Heap {
+            free: ptr::null_mut(),
+            size: 0,
+            used: 0,
+        }
+    }
+
+    /// Gives the `size` bytes at `start` to the heap, cut to whole blocks.
+    ///
+    /// This function is unsafe because the caller must guarantee that the
+    /// memory is unused and stays valid, and that it is only called once.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        let aligned = align_up(start, BLOCK_ALIGN);
+        let size = size.saturating_sub(aligned - start) & !(BLOCK_ALIGN - 1);
+        if size == 0 {
+            return;
+        }
+
+        let block = aligned as *mut Block;
+        block.write(Block {
+            size,
+            next: ptr::null_mut(),
+        });
+        self.free = block;
+        self.size = size;
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.size,
+            used: self.used,
+        }
+    }
+
+    /// Allocates memory for `layout`, or returns `None` if no free block is
+    /// large enough.
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let (size, align) = block_layout(layout);
+
+        let mut link: *mut *mut Block = &mut self.free;
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                let (start, block_size, next) = (block as usize, (*block).size, (*block).next);
+                let end = start + block_size;
+                let used = align_up(start, align);
+
+                if used + size <= end {
+                    // What is left before and after stays free.
+                    let mut rest = next;
+                    if used + size < end {
+                        let after = (used + size) as *mut Block;
+                        after.write(Block {
+                            size: end - used - size,
+                            next: rest,
+                        });
+                        rest = after;
+                    }
+                    if used > start {
+                        (*block).size = used - start;
+                        (*block).next = rest;
+                    } else {
+                        *link = rest;
+                    }
+
+                    self.used += size;
+                    return NonNull::new(used as *mut u8);
+                }
+                link = &mut (*block).next;
+            }
+        }
+
+        None
+    }
+
+    /// Frees the memory at `ptr`, allocated for `layout`.
+    ///
+    /// This function is unsafe because the caller must guarantee that `ptr`
+    /// was allocated by this heap with the same layout, and is not used
+    /// anymore.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let (size, _) = block_layout(layout);
+        let start = ptr.as_ptr() as usize;
+        self.used -= size;
+
+        // Blocks before and after it in the list.
+        let mut previous: *mut Block = ptr::null_mut();
+        let mut next = self.free;
+        while !next.is_null() && (next as usize) < start {
+            previous = next;
+            next = (*next).next;
+        }
+
+        let block = start as *mut Block;
+        block.write(Block { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if previous.is_null() {
+            self.free = block;
+        } else if previous as usize + (*previous).size == start {
+            (*previous).size += (*block).size;
+            (*previous).next = (*block).next;
+        } else {
+            (*previous).next = block;
+        }
+    }
+}
+
+/// Size and alignment of the block allocated for `layout`.
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+    (size, layout.align().max(BLOCK_ALIGN))
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// The heap, behind a lock.
+pub struct Allocator(IrqSafeSpinlock<Heap>);
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .lock()
+            .allocate(layout)
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.0.lock().deallocate(ptr, layout);
+        }
+    }
+}
+
+#[cfg_attr(target_os = "none", global_allocator)]
+static ALLOCATOR: Allocator = Allocator(IrqSafeSpinlock::new(Heap::empty()));
+
+/// Maps the heap, then gives it to the allocator. Only `mem::init` calls it.
+pub(super) fn init(memory: &mut Memory) -> Result<(), &'static str> {
+    let first = Page::containing_address(VirtAddr::new(HEAP_START));
+    let last = Page::containing_address(VirtAddr::new(HEAP_START + HEAP_SIZE as u64 - 1));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    for page in Page::range_inclusive(first, last) {
+        let frame = memory.frames.allocate_frame().ok_or("Out of memory")?;
+        unsafe { memory.mapper.map_to(page, frame, flags, &mut memory.frames) }
+            .map_err(|_| "Heap already mapped")?
+            .flush();
+    }
+
+    unsafe { ALLOCATOR.0.lock().init(HEAP_START as usize, HEAP_SIZE) };
+    Ok(())
+}
+
+/// Use of the kernel heap.
+pub fn stats() -> HeapStats {
+    ALLOCATOR.0.lock().stats()
+}
+
+#[cfg(test)]
+#[test_case]
+fn first_fit_and_merging() {
+    #[repr(align(16))]
+    struct Region([u8; 256]);
+    let mut region = Region([0; 256]);
+
+    let mut heap = Heap::empty();
+    unsafe { heap.init(region.0.as_mut_ptr() as usize, 256) };
+    let small = Layout::from_size_align(10, 1).unwrap();
+    let aligned = Layout::from_size_align(32, 64).unwrap();
+
+    let a = heap.allocate(small).unwrap();
+    let b = heap.allocate(aligned).unwrap();
+    assert_eq!(b.as_ptr() as usize % 64, 0);
+    assert_eq!(heap.stats().used, 16 + 32);
+    assert!(heap
+        .allocate(Layout::from_size_align(256, 1).unwrap())
+        .is_none());
+
+    unsafe {
+        heap.deallocate(a, small);
+        heap.deallocate(b, aligned);
+    }
+    assert_eq!(heap.stats().used, 0);
+
+    // Freed blocks were merged back into one.
+    let whole = Layout::from_size_align(256, 16).unwrap();
+    let all = heap.allocate(whole).unwrap();
+    assert_eq!(all.as_ptr(), region.0.as_mut_ptr());
+    unsafe { heap.deallocate(all, whole) };
+}
@@ -1,25 +1,332 @@
+//! Paging and physical memory.
+//!
+//! `init` sets up the mapper of the active page table and the frame
+//! allocator, that the rest of the kernel maps memory with. User programs get
+//! their own page tables, see `AddressSpace`. It also maps the kernel heap,
+//! see `heap`.
+//!
+//! Forked address spaces share their pages copy-on-write: writable pages are
+//! mapped read-only with the `COPY_ON_WRITE` flag, and the page fault handler
+//! gives the writer a copy of its own with `copy_on_write`.
+
+use core::{
+    ops::Range,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bootloader::{bootinfo::MemoryMap, BootInfo};
 use x86_64::{
-    structures::paging::{PageTable, PhysFrame, MapperAllSizes, MappedPageTable},
+    instructions::tlb,
     registers::control::Cr3,
-    VirtAddr,
-    PhysAddr
+    structures::paging::{
+        mapper::{MapToError, MapperFlush, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, MapperAllSizes, OffsetPageTable, Page, PageTable,
+        PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use crate::sync::IrqSafeSpinlock;
+
+pub mod frame;
+pub mod heap;
+mod space;
+
+pub use self::{
+    frame::{BootInfoFrameAllocator, FRAME_SIZE},
+    space::AddressSpace,
 };
 
+/// Flag of the pages that are writable, but shared with another address space
+/// until written to.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Where the user mappings made without an address go in address spaces.
+const USER_MAPPINGS: Range<u64> = 0x6000_0000_0000..0x7000_0000_0000;
+
+/// Where they go in the kernel page table. Its entries are shared with every
+/// address space, so it is apart from `USER_MAPPINGS`.
+const KERNEL_USER_MAPPINGS: Range<u64> = 0x7000_0000_0000..0x7800_0000_0000;
+
+/// Page table and frames the kernel maps memory with.
+struct Memory {
+    mapper: OffsetPageTable<'static>,
+    frames: BootInfoFrameAllocator,
+    physical_memory_offset: u64,
+    /// Level 4 table active at `init`, whose entries every address space
+    /// shares.
+    kernel_level4: PhysFrame,
+}
+
+impl Memory {
+    /// Returns a mutable reference to the page table in `frame`.
+    ///
+    /// This function is unsafe because the caller must guarantee that `frame`
+    /// holds a page table, and that no other reference to it is alive.
+    unsafe fn table_mut(&self, frame: PhysFrame) -> &'static mut PageTable {
+        let virt = VirtAddr::new(frame.start_address().as_u64() + self.physical_memory_offset);
+        &mut *virt.as_mut_ptr::<PageTable>()
+    }
+}
+
+static MEMORY: IrqSafeSpinlock<Option<Memory>> = IrqSafeSpinlock::new(None);
 
-/// Initialize a new MappedPageTable.
+/// Address of the kernel level 4 table, known without locking `MEMORY`, or 0
+/// before `init`.
+static KERNEL_LEVEL4: AtomicU64 = AtomicU64::new(0);
+
+/// Next address `reserve_user` hands out in the kernel page table.
+static KERNEL_USER_NEXT: AtomicU64 = AtomicU64::new(KERNEL_USER_MAPPINGS.start);
+
+/// Frames of physical memory, in use and usable.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Usable frames of the memory map.
+    pub usable: usize,
+    /// Frames handed out and not freed.
+    pub allocated: usize,
+    /// Freed frames, waiting to be handed out again.
+    pub free: usize,
+}
+
+/// Initializes the mapper of the active page table and the frame allocator,
+/// then maps the kernel heap.
 ///
 /// This function is unsafe because the caller must guarantee that the
-/// complete physical memory is mapped to virtual memory at the passed
-/// `physical_memory_offset`. Also, this function must be only called once
-/// to avoid aliasing `&mut` references (which is undefined behavior).
-pub unsafe fn init(physical_memory_offset: u64) -> impl MapperAllSizes {
-    let level_4_table = active_level4_table(physical_memory_offset);
-    let phys_to_virt = move |frame: PhysFrame| -> *mut PageTable {
-        let phys = frame.start_address().as_u64();
-        let virt = VirtAddr::new(phys + physical_memory_offset);
-        virt.as_mut_ptr()
+/// complete physical memory is mapped to virtual memory at the
+/// `physical_memory_offset` of `boot_info`, and that the usable regions of its
+/// memory map are unused.
+pub unsafe fn init(boot_info: &'static BootInfo) -> Result<(), &'static str> {
+    let mut memory = MEMORY.lock();
+    if memory.is_some() {
+        return Err("Memory already initialized");
+    }
+
+    let offset = boot_info.physical_memory_offset;
+    let (kernel_level4, _) = Cr3::read();
+    *memory = Some(Memory {
+        mapper: OffsetPageTable::new(active_level4_table(offset), VirtAddr::new(offset)),
+        frames: BootInfoFrameAllocator::new(&boot_info.memory_map, offset),
+        physical_memory_offset: offset,
+        kernel_level4,
+    });
+    KERNEL_LEVEL4.store(kernel_level4.start_address().as_u64(), Ordering::SeqCst);
+
+    heap::init(memory.as_mut().unwrap())
+}
+
+/// Translates the given virtual address to the mapped physical address, or
+/// `None` if the address is not mapped or memory is not initialized.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    MEMORY.lock().as_ref()?.mapper.translate_addr(addr)
+}
+
+/// Use of the physical frames, or `None` if memory is not initialized.
+pub fn frame_stats() -> Option<FrameStats> {
+    let memory = MEMORY.lock();
+    let frames = &memory.as_ref()?.frames;
+    Some(FrameStats {
+        usable: frames.usable(),
+        allocated: frames.allocated(),
+        free: frames.free(),
+    })
+}
+
+/// Memory map given by the bootloader, or `None` if memory is not
+/// initialized.
+pub fn memory_map() -> Option<&'static MemoryMap> {
+    Some(MEMORY.lock().as_ref()?.frames.memory_map())
+}
+
+/// Loads `level4` in CR3 if it is not there yet, or the kernel level 4 table
+/// if `None`. Does nothing before `init` if `level4` is `None`.
+///
+/// This function is unsafe because the caller must guarantee that `level4` is
+/// the level 4 table of an address space, and that nothing the running code
+/// uses is only mapped in the address space it replaces.
+pub unsafe fn switch_level4(level4: Option<PhysFrame>) {
+    let level4 = match level4 {
+        Some(level4) => level4,
+        None => match KERNEL_LEVEL4.load(Ordering::SeqCst) {
+            0 => return,
+            addr => PhysFrame::containing_address(PhysAddr::new(addr)),
+        },
     };
-    MappedPageTable::new(level_4_table, phys_to_virt)
+
+    let (active, flags) = Cr3::read();
+    if active != level4 {
+        Cr3::write(level4, flags);
+    }
+}
+
+/// Maps `page` to a new zeroed frame that user mode can access, with `flags`
+/// on top of `PRESENT` and `USER_ACCESSIBLE`.
+pub fn map_user_page(page: Page, flags: PageTableFlags) -> Result<(), &'static str> {
+    let mut memory = MEMORY.lock();
+    let memory = memory.as_mut().ok_or("Memory not initialized")?;
+
+    let offset = memory.physical_memory_offset;
+    map_user(&mut memory.mapper, &mut memory.frames, offset, page, flags)?.flush();
+
+    Ok(())
+}
+
+/// Unmaps `page`, mapped by `map_user_page`, and frees its frame.
+pub fn unmap_user_page(page: Page) -> Result<(), &'static str> {
+    let mut memory = MEMORY.lock();
+    let memory = memory.as_mut().ok_or("Memory not initialized")?;
+
+    unmap_user(&mut memory.mapper, &mut memory.frames, page)?.flush();
+
+    Ok(())
+}
+
+/// Reserves `size` bytes of user addresses in the kernel page table, for
+/// mappings made without an address. Returns where they start, or `None` if
+/// there is no room left.
+pub fn reserve_user(size: u64) -> Option<VirtAddr> {
+    reserve(&KERNEL_USER_NEXT, size, KERNEL_USER_MAPPINGS.end)
+}
+
+/// Maps `page` in `mapper` to a new zeroed frame from `frames`, that user mode
+/// can access, with `flags` on top of `PRESENT` and `USER_ACCESSIBLE`.
+fn map_user(
+    mapper: &mut OffsetPageTable,
+    frames: &mut BootInfoFrameAllocator,
+    physical_memory_offset: u64,
+    page: Page,
+    flags: PageTableFlags,
+) -> Result<MapperFlush<Size4KiB>, &'static str> {
+    let frame = frames.allocate_frame().ok_or("Out of memory")?;
+    let virt = VirtAddr::new(frame.start_address().as_u64() + physical_memory_offset);
+    unsafe { ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, FRAME_SIZE as usize) };
+
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    // The tables on the way must let user mode through too.
+    let parent_flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    unsafe { mapper.map_to_with_table_flags(page, frame, flags, parent_flags, frames) }.map_err(
+        |err| match err {
+            MapToError::FrameAllocationFailed => "Out of memory",
+            MapToError::ParentEntryHugePage => "Page inside a huge page",
+            MapToError::PageAlreadyMapped(_) => "Page already mapped",
+        },
+    )
+}
+
+/// Unmaps `page` from `mapper`, and gives its frame back to `frames`.
+fn unmap_user(
+    mapper: &mut OffsetPageTable,
+    frames: &mut BootInfoFrameAllocator,
+    page: Page,
+) -> Result<MapperFlush<Size4KiB>, &'static str> {
+    let (frame, flush) = mapper.unmap(page).map_err(|err| match err {
+        UnmapError::PageNotMapped => "Page not mapped",
+        UnmapError::ParentEntryHugePage => "Page inside a huge page",
+        UnmapError::InvalidFrameAddress(_) => "Invalid frame address",
+    })?;
+    unsafe { frames.deallocate_frame(frame) };
+
+    Ok(flush)
+}
+
+/// Moves `next` past `size` bytes if they end before `limit`, and returns
+/// where they start. Nothing is reserved if they do not fit.
+fn reserve(next: &AtomicU64, size: u64, limit: u64) -> Option<VirtAddr> {
+    let mut start = next.load(Ordering::SeqCst);
+    loop {
+        let end = start.checked_add(size).filter(|&end| end <= limit)?;
+        match next.compare_exchange(start, end, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => return Some(VirtAddr::new(start)),
+            Err(current) => start = current,
+        }
+    }
+}
+
+/// What user mode may do with the page of `addr` in the active page table:
+/// `PRESENT` and `USER_ACCESSIBLE`, with `WRITABLE` if every table on the way
+/// allows writes, and `NO_EXECUTE` if one of them forbids execution. `None` if
+/// user mode can not access it at all, or memory is not initialized.
+pub fn user_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    let offset = MEMORY.lock().as_ref()?.physical_memory_offset;
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    let table_indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let (mut frame, _) = Cr3::read();
+    let mut writable = true;
+    let mut no_execute = false;
+
+    for &index in &table_indexes {
+        let virt = VirtAddr::new(frame.start_address().as_u64() + offset);
+        let table = unsafe { &*virt.as_ptr::<PageTable>() };
+        let flags = table[index].flags();
+
+        // User pages are never huge.
+        if !flags.contains(user) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        writable &= flags.contains(PageTableFlags::WRITABLE);
+        no_execute |= flags.contains(PageTableFlags::NO_EXECUTE);
+        frame = PhysFrame::containing_address(table[index].addr());
+    }
+
+    let mut flags = user;
+    flags.set(PageTableFlags::WRITABLE, writable);
+    flags.set(PageTableFlags::NO_EXECUTE, no_execute);
+    Some(flags)
+}
+
+/// Gives the active address space a copy of its own of the page of `addr` if
+/// it is copy-on-write, or only makes it writable again if no other address
+/// space shares it anymore. Returns `false` if the page is not copy-on-write.
+pub fn copy_on_write(addr: VirtAddr) -> Result<bool, &'static str> {
+    let mut memory = MEMORY.lock();
+    let memory = memory.as_mut().ok_or("Memory not initialized")?;
+    let user = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    let table_indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index()];
+    let (mut frame, _) = Cr3::read();
+    for &index in &table_indexes {
+        let table = unsafe { memory.table_mut(frame) };
+        let flags = table[index].flags();
+        if !flags.contains(user) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return Ok(false);
+        }
+        frame = PhysFrame::containing_address(table[index].addr());
+    }
+
+    let entry = &mut unsafe { memory.table_mut(frame) }[addr.p1_index()];
+    let flags = entry.flags();
+    if !flags.contains(user | COPY_ON_WRITE) {
+        return Ok(false);
+    }
+
+    let shared = PhysFrame::containing_address(entry.addr());
+    let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+    if memory.frames.references(shared) > 1 {
+        let copy = memory.frames.allocate_frame().ok_or("Out of memory")?;
+        let offset = memory.physical_memory_offset;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (shared.start_address().as_u64() + offset) as *const u8,
+                (copy.start_address().as_u64() + offset) as *mut u8,
+                FRAME_SIZE as usize,
+            );
+            memory.frames.deallocate_frame(shared);
+        }
+        entry.set_addr(copy.start_address(), flags);
+    } else {
+        entry.set_flags(flags);
+    }
+    tlb::flush(addr);
+
+    Ok(true)
 }
 
 /// Returns a mutable reference to the active level 4 table.
@@ -38,7 +345,6 @@ unsafe fn active_level4_table(physical_memory_offset: u64) -> &'static mut PageT
     &mut *page_table_ptr // unsafe
 }
 
-
 /// Translates the given virtual address to the mapped physical address, or
 /// `None` if the address is not mapped.
 ///
@@ -54,16 +360,17 @@ pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: u64) -> Opt
 /// This function is safe to limit the scope of `unsafe` because Rust treats
 /// the whole body of unsafe functions as an unsafe block. This function must
 /// only be reachable through `unsafe fn` from outside of this module.
-fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: u64)
-    -> Option<PhysAddr>
-{
+fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: u64) -> Option<PhysAddr> {
     use x86_64::structures::paging::page_table::FrameError;
 
     // read the active level 4 frame from the CR3 register
     let (level_4_table_frame, _) = Cr3::read();
 
     let table_indexes = [
-        addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
     ];
     let mut frame = level_4_table_frame;
 
@@ -72,7 +379,7 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: u64)
         // convert the frame into a page table reference
         let virt = frame.start_address().as_u64() + physical_memory_offset;
         let table_ptr: *const PageTable = VirtAddr::new(virt).as_ptr();
-        let table = unsafe {&*table_ptr};
+        let table = unsafe { &*table_ptr };
 
         // read the page table entry and update `frame`
         let entry = &table[index];
@@ -0,0 +1,358 @@
+//! Address spaces of user programs.
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::paging::{
+        page_table::PageTableEntry, FrameAllocator, FrameDeallocator, MapperAllSizes,
+        OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    map_user, reserve, unmap_user, Memory, COPY_ON_WRITE, FRAME_SIZE, MEMORY, USER_MAPPINGS,
+};
+
+/// Page tables of a user program.
+///
+/// Its level 4 table has the entries of the kernel one, so the kernel stays
+/// mapped once it is active, and user pages go in the entries the kernel does
+/// not use. Dropping it frees those pages, and the tables that map them.
+#[derive(Debug)]
+pub struct AddressSpace {
+    level4: PhysFrame,
+    /// Next address `reserve_user` hands out.
+    user_next: AtomicU64,
+}
+
+impl AddressSpace {
+    /// Creates an address space with only the kernel mapped.
+    pub fn new() -> Result<AddressSpace, &'static str> {
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut().ok_or("Memory not initialized")?;
+
+        let level4 = memory.frames.allocate_frame().ok_or("Out of memory")?;
+        let (table, kernel) = unsafe {
+            (
+                memory.table_mut(level4),
+                memory.table_mut(memory.kernel_level4),
+            )
+        };
+        table.zero();
+        for (entry, kernel_entry) in table.iter_mut().zip(kernel.iter()) {
+            if kernel_entry.flags().contains(PageTableFlags::PRESENT) {
+                *entry = kernel_entry.clone();
+            }
+        }
+
+        Ok(AddressSpace {
+            level4,
+            user_next: AtomicU64::new(USER_MAPPINGS.start),
+        })
+    }
+
+    /// Creates a copy of the address space that shares its pages: the
+    /// writable ones become copy-on-write in both, see `mem::copy_on_write`.
+    pub fn fork(&mut self) -> Result<AddressSpace, &'static str> {
+        let child = AddressSpace::new()?;
+        child
+            .user_next
+            .store(self.user_next.load(Ordering::SeqCst), Ordering::SeqCst);
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut().ok_or("Memory not initialized")?;
+
+        let (kernel, parent, copy) = unsafe {
+            (
+                table(memory.physical_memory_offset, memory.kernel_level4),
+                memory.table_mut(self.level4),
+                memory.table_mut(child.level4),
+            )
+        };
+        for ((entry, copy), kernel_entry) in
+            parent.iter_mut().zip(copy.iter_mut()).zip(kernel.iter())
+        {
+            let shared = kernel_entry.flags().contains(PageTableFlags::PRESENT);
+            if !shared && entry.flags().contains(PageTableFlags::PRESENT) {
+                fork_table(memory, entry, copy, 3)?;
+            }
+        }
+
+        // The pages made read-only may still be writable in the TLB.
+        if self.is_active() {
+            tlb::flush_all();
+        }
+
+        Ok(child)
+    }
+
+    /// Frame of the level 4 table, loaded in CR3 to activate the address
+    /// space.
+    pub fn level4_frame(&self) -> PhysFrame {
+        self.level4
+    }
+
+    /// Maps `page` to a new zeroed frame that user mode can access, with
+    /// `flags` on top of `PRESENT` and `USER_ACCESSIBLE`.
+    ///
+    /// Fails if the page is in a part of the address space that the kernel
+    /// uses.
+    pub fn map_user_page(&mut self, page: Page, flags: PageTableFlags) -> Result<(), &'static str> {
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut().ok_or("Memory not initialized")?;
+
+        let kernel = unsafe { memory.table_mut(memory.kernel_level4) };
+        if kernel[page.p4_index()]
+            .flags()
+            .contains(PageTableFlags::PRESENT)
+        {
+            return Err("Page used by the kernel");
+        }
+
+        let offset = memory.physical_memory_offset;
+        let mut mapper = unsafe { self.mapper(memory) };
+        let flush = map_user(&mut mapper, &mut memory.frames, offset, page, flags)?;
+        if self.is_active() {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+
+        Ok(())
+    }
+
+    /// Unmaps `page`, mapped by `map_user_page`, and frees its frame.
+    pub fn unmap_user_page(&mut self, page: Page) -> Result<(), &'static str> {
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut().ok_or("Memory not initialized")?;
+
+        let kernel = unsafe { memory.table_mut(memory.kernel_level4) };
+        if kernel[page.p4_index()]
+            .flags()
+            .contains(PageTableFlags::PRESENT)
+        {
+            return Err("Page used by the kernel");
+        }
+
+        let mut mapper = unsafe { self.mapper(memory) };
+        let flush = unmap_user(&mut mapper, &mut memory.frames, page)?;
+        if self.is_active() {
+            flush.flush();
+        } else {
+            flush.ignore();
+        }
+
+        Ok(())
+    }
+
+    /// Reserves `size` bytes of user addresses, for mappings made without an
+    /// address. Returns where they start, or `None` if there is no room left.
+    pub fn reserve_user(&self, size: u64) -> Option<VirtAddr> {
+        reserve(&self.user_next, size, USER_MAPPINGS.end)
+    }
+
+    /// Translates `addr` to the mapped physical address, or `None` if it is
+    /// not mapped.
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        let memory = MEMORY.lock();
+        let memory = memory.as_ref()?;
+        unsafe { self.mapper(memory) }.translate_addr(addr)
+    }
+
+    /// Copies `bytes` to `addr`, through the physical memory mapping, so it
+    /// does not need to be active, and the pages do not need to be writable.
+    pub fn write(&mut self, addr: VirtAddr, bytes: &[u8]) -> Result<(), &'static str> {
+        let mut memory = MEMORY.lock();
+        let memory = memory.as_mut().ok_or("Memory not initialized")?;
+        let mapper = unsafe { self.mapper(memory) };
+
+        let mut addr = addr;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let phys = mapper.translate_addr(addr).ok_or("Page not mapped")?;
+            let len = bytes
+                .len()
+                .min((FRAME_SIZE - addr.as_u64() % FRAME_SIZE) as usize);
+            let virt = VirtAddr::new(phys.as_u64() + memory.physical_memory_offset);
+            unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), virt.as_mut_ptr(), len) };
+
+            addr += len;
+            bytes = &bytes[len..];
+        }
+
+        Ok(())
+    }
+
+    /// Number of pages mapped for user mode.
+    pub fn user_pages(&self) -> usize {
+        let memory = MEMORY.lock();
+        let memory = match memory.as_ref() {
+            Some(memory) => memory,
+            None => return 0,
+        };
+
+        let mut pages = 0;
+        walk(
+            memory.physical_memory_offset,
+            memory.kernel_level4,
+            self.level4,
+            &mut |_, level| {
+                if level == 0 {
+                    pages += 1;
+                }
+            },
+        );
+        pages
+    }
+
+    /// Returns `true` if the address space is the one in CR3.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level4
+    }
+
+    /// Loads the address space in CR3.
+    ///
+    /// This function is unsafe because the caller must guarantee that nothing
+    /// the running code uses is only mapped in the address space it replaces.
+    pub unsafe fn activate(&self) {
+        let (_, flags) = Cr3::read();
+        Cr3::write(self.level4, flags);
+    }
+
+    /// Mapper of the tables of the address space.
+    ///
+    /// This function is unsafe because the caller must guarantee that no other
+    /// mapper of the address space is alive.
+    unsafe fn mapper(&self, memory: &Memory) -> OffsetPageTable<'static> {
+        OffsetPageTable::new(
+            memory.table_mut(self.level4),
+            VirtAddr::new(memory.physical_memory_offset),
+        )
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "Dropping the active address space");
+
+        let mut memory = MEMORY.lock();
+        let memory = match memory.as_mut() {
+            Some(memory) => memory,
+            None => return,
+        };
+
+        let offset = memory.physical_memory_offset;
+        let frames = &mut memory.frames;
+        // A table is visited once done with, so freeing it does not disturb
+        // the walk.
+        walk(
+            offset,
+            memory.kernel_level4,
+            self.level4,
+            &mut |frame, _| unsafe { frames.deallocate_frame(frame) },
+        );
+        unsafe { frames.deallocate_frame(self.level4) };
+    }
+}
+
+/// Points `copy` to a new copy of the table of `level` that `entry` points to,
+/// whose pages are shared, and copy-on-write if writable.
+fn fork_table(
+    memory: &mut Memory,
+    entry: &PageTableEntry,
+    copy: &mut PageTableEntry,
+    level: u8,
+) -> Result<(), &'static str> {
+    let frame = memory.frames.allocate_frame().ok_or("Out of memory")?;
+    let (table, parent) = unsafe {
+        (
+            memory.table_mut(frame),
+            memory.table_mut(PhysFrame::containing_address(entry.addr())),
+        )
+    };
+    table.zero();
+    // Freed with the copy from now on, even if the rest fails.
+    copy.set_addr(frame.start_address(), entry.flags());
+
+    for (entry, copy) in parent.iter_mut().zip(table.iter_mut()) {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        if level > 1 {
+            fork_table(memory, entry, copy, level - 1)?;
+            continue;
+        }
+
+        let mut flags = entry.flags();
+        if flags.contains(PageTableFlags::WRITABLE) {
+            flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+            entry.set_flags(flags);
+        }
+        memory
+            .frames
+            .share(PhysFrame::containing_address(entry.addr()))?;
+        copy.set_addr(entry.addr(), flags);
+    }
+
+    Ok(())
+}
+
+/// Calls `visit` with the frames of the user part of the address space of
+/// `level4`: the pages, with the level 0, then the tables, with their level,
+/// each after the frames it maps.
+fn walk(
+    physical_memory_offset: u64,
+    kernel_level4: PhysFrame,
+    level4: PhysFrame,
+    visit: &mut dyn FnMut(PhysFrame, u8),
+) {
+    let kernel = unsafe { table(physical_memory_offset, kernel_level4) };
+    let user = unsafe { table(physical_memory_offset, level4) };
+
+    for (entry, kernel_entry) in user.iter().zip(kernel.iter()) {
+        let shared = kernel_entry.flags().contains(PageTableFlags::PRESENT);
+        if !shared && entry.flags().contains(PageTableFlags::PRESENT) {
+            let frame = PhysFrame::containing_address(entry.addr());
+            walk_table(physical_memory_offset, frame, 3, visit);
+        }
+    }
+}
+
+/// Visits the frames mapped by the table of `level` in `frame`, then the
+/// table.
+fn walk_table(
+    physical_memory_offset: u64,
+    frame: PhysFrame,
+    level: u8,
+    visit: &mut dyn FnMut(PhysFrame, u8),
+) {
+    let entries = unsafe { table(physical_memory_offset, frame) };
+    for entry in entries.iter() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        // User pages are never huge.
+        let mapped = PhysFrame::containing_address(entry.addr());
+        match level {
+            1 => visit(mapped, 0),
+            _ => walk_table(physical_memory_offset, mapped, level - 1, visit),
+        }
+    }
+
+    visit(frame, level);
+}
+
+/// Returns the page table in `frame`.
+///
+/// This function is unsafe because the caller must guarantee that `frame`
+/// holds a page table, that nothing changes while the reference is alive.
+unsafe fn table(physical_memory_offset: u64, frame: PhysFrame) -> &'static PageTable {
+    let virt = VirtAddr::new(frame.start_address().as_u64() + physical_memory_offset);
+    &*virt.as_ptr::<PageTable>()
+}
@@ -1,13 +1,12 @@
-use core::{
-    intrinsics,
-    panic::PanicInfo,
-};
+use core::{intrinsics, panic::PanicInfo};
 
-use kernel::kprintln;
+use kernel::{console, kprintln, logger::dmesg};
 
 #[panic_handler]
 #[no_mangle]
 fn panic(info: &PanicInfo) -> ! {
+    console::enter_emergency();
     kprintln!("{:?}", info);
+    dmesg::dump_to_serial();
     unsafe { intrinsics::abort() }
 }
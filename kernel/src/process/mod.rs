@@ -0,0 +1,668 @@
+//! # Processes
+//!
+//! A process runs a user program, loaded from an executable in its own address
+//! space, on a thread of its own. The process table keeps every process until
+//! it is waited for, so its exit status can be read.
+//!
+//! A process can `fork` a child, that gets a copy-on-write copy of its address
+//! space, and replace its program with `exec`. Its parent waits for it with
+//! `waitpid`. The processes spawned by the kernel are the children of kernel
+//! threads, and those whose parent exits are removed once they exit.
+//!
+//! Processes use files through the descriptors of their `FileTable`, a
+//! forked child sharing the open files of its parent.
+//!
+//! Processes are interrupted by signals, sent with `signal::kill` or raised
+//! by the exceptions of their code, see `signal`.
+//!
+//! When a process exits, or is killed by a signal, its address space is freed:
+//! its pages and the page tables that mapped them. Its files are closed.
+//!
+//! # Examples
+//! ```no_run
+//! let pid = process::spawn("init", bytes, &["init"], &[]).unwrap();
+//! process::ps();
+//! assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(0)));
+//! ```
+
+pub mod signal;
+
+use core::{fmt, str};
+
+use lazy_static::lazy_static;
+use x86_64::{
+    registers::model_specific::FsBase,
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+use crate::{
+    elf::{self, Elf, Image},
+    fs::FileTable,
+    mem::{self, AddressSpace},
+    prelude::*,
+    sync::{IrqSafeSpinlock, WaitQueue},
+    syscall::Registers,
+    thread::{self, JoinHandle, ThreadId},
+    user,
+};
+
+pub use self::signal::Signal;
+use self::signal::{Action, Signals};
+
+/// Maximum number of processes, exited ones not waited for included.
+pub const MAX_PROCESSES: usize = 16;
+
+/// Bytes of the name of a process that are kept.
+pub const NAME_LEN: usize = 16;
+
+/// Identifier of a process, never reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(u64);
+
+impl Pid {
+    /// The identifier `pid`, of a process that may not exist.
+    pub fn new(pid: u64) -> Pid {
+        Pid(pid)
+    }
+
+    /// The identifier as a number.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How a process ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// It called exit with this code.
+    Exited(i32),
+    /// It was killed by this signal.
+    Signaled(Signal),
+}
+
+impl ExitStatus {
+    /// The status as `waitpid` stores it: the exit code in the second byte,
+    /// or the number of the signal that killed it, with 0x80 if it would have
+    /// dumped its core.
+    pub fn raw(self) -> i32 {
+        match self {
+            ExitStatus::Exited(code) => (code & 0xff) << 8,
+            ExitStatus::Signaled(signal) => match signal.default_action() {
+                Action::Core => signal.as_u64() as i32 | 0x80,
+                _ => signal.as_u64() as i32,
+            },
+        }
+    }
+}
+
+/// Life of a process.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// Its thread runs, or waits to run.
+    Running,
+    /// Stopped by a signal, until it gets `SIGCONT`.
+    Stopped,
+    /// Exited, until it is waited for.
+    Zombie(ExitStatus),
+}
+
+/// Name of a process, cut to `NAME_LEN` bytes.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Name {
+    bytes: [u8; NAME_LEN],
+    len: usize,
+}
+
+impl Name {
+    fn new(name: &str) -> Name {
+        // Cut on a character boundary.
+        let mut len = name.len().min(NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut bytes = [0; NAME_LEN];
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Name { bytes, len }
+    }
+
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.bytes[..self.len]).unwrap_or("?")
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// What `ps` shows of a process.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    /// Process that spawned it, if any.
+    pub parent: Option<Pid>,
+    pub name: Name,
+    pub state: State,
+    /// Thread running it.
+    pub thread: ThreadId,
+    /// Pages mapped in its address space for user mode.
+    pub pages: usize,
+}
+
+/// Where the thread of a process enters user mode.
+#[derive(Debug, Copy, Clone)]
+enum Start {
+    /// At the entry point of a program.
+    Entry {
+        entry: VirtAddr,
+        stack: VirtAddr,
+        thread_pointer: Option<VirtAddr>,
+    },
+    /// Where its parent forked it.
+    Fork {
+        registers: Registers,
+        fs_base: VirtAddr,
+    },
+}
+
+impl Start {
+    fn new(image: &Image) -> Start {
+        Start::Entry {
+            entry: image.entry(),
+            stack: image.stack(),
+            thread_pointer: image.thread_pointer(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Process {
+    pid: Pid,
+    parent: Option<Pid>,
+    name: Name,
+    state: State,
+    thread: ThreadId,
+    /// Until it exits.
+    space: Option<AddressSpace>,
+    /// Until its thread starts.
+    start: Option<Start>,
+    /// Until it is waited for.
+    handle: Option<JoinHandle>,
+    /// Its parent exited, so it is removed as soon as it exits.
+    orphaned: bool,
+    signals: Signals,
+    files: FileTable,
+}
+
+impl Process {
+    fn info(&self) -> ProcessInfo {
+        ProcessInfo {
+            pid: self.pid,
+            parent: self.parent,
+            name: self.name,
+            state: self.state,
+            thread: self.thread,
+            pages: self.space.as_ref().map_or(0, AddressSpace::user_pages),
+        }
+    }
+}
+
+/// Processes, by slot.
+struct Table {
+    processes: [Option<Process>; MAX_PROCESSES],
+    next_pid: u64,
+}
+
+impl Table {
+    fn new() -> Table {
+        Table {
+            processes: Default::default(),
+            next_pid: 1,
+        }
+    }
+
+    fn find(&mut self, pid: Pid) -> Option<&mut Process> {
+        self.processes
+            .iter_mut()
+            .flatten()
+            .find(|process| process.pid == pid)
+    }
+
+    fn find_thread(&mut self, thread: ThreadId) -> Option<&mut Process> {
+        self.processes
+            .iter_mut()
+            .flatten()
+            .find(|process| process.thread == thread)
+    }
+
+    fn remove(&mut self, pid: Pid) -> Option<Process> {
+        self.processes
+            .iter_mut()
+            .find(|slot| slot.as_ref().map_or(false, |process| process.pid == pid))?
+            .take()
+    }
+
+    /// Adds a process running on a new thread, that starts at `start`.
+    fn insert(
+        &mut self,
+        name: Name,
+        parent: Option<Pid>,
+        space: AddressSpace,
+        start: Start,
+        signals: Signals,
+        files: FileTable,
+    ) -> Result<Pid, &'static str> {
+        let slot = self
+            .processes
+            .iter()
+            .position(Option::is_none)
+            .ok_or("Too many processes")?;
+        let handle = thread::spawn("user", run)?;
+
+        let pid = Pid(self.next_pid);
+        self.next_pid += 1;
+        self.processes[slot] = Some(Process {
+            pid,
+            parent,
+            name,
+            state: State::Running,
+            thread: handle.id(),
+            space: Some(space),
+            start: Some(start),
+            handle: Some(handle),
+            orphaned: false,
+            signals,
+            files,
+        });
+
+        Ok(pid)
+    }
+
+    /// Removes the exited children of `parent`, and the others once they
+    /// exit.
+    fn orphan_children(&mut self, parent: Pid) {
+        for slot in self.processes.iter_mut() {
+            if let Some(process) = slot {
+                if process.parent == Some(parent) {
+                    process.parent = None;
+                    process.orphaned = true;
+                    if let State::Zombie(_) = process.state {
+                        *slot = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref TABLE: IrqSafeSpinlock<Table> = IrqSafeSpinlock::new(Table::new());
+}
+
+/// Threads waiting for a process to exit.
+static EXITED: WaitQueue = WaitQueue::new();
+
+/// Loads `executable` in a new address space, and starts a process running it
+/// with the arguments `argv` and the environment `envp`.
+pub fn spawn(
+    name: &str,
+    executable: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<Pid, &'static str> {
+    let elf = Elf::parse(executable)?;
+    let image = elf::load(&elf, argv, envp)?;
+    let start = Start::new(&image);
+    let parent = current();
+    let files = FileTable::with_console();
+
+    // The thread does not run before its process is in the table, that is
+    // locked with the interrupts disabled.
+    TABLE.lock().insert(
+        Name::new(name),
+        parent,
+        image.into_space(),
+        start,
+        Signals::new(),
+        files,
+    )
+}
+
+/// Creates a child of the running process, with a copy-on-write copy of its
+/// address space, its signal actions and its files, that goes back to user mode with
+/// `registers` but 0 in `rax`. Returns the child.
+pub fn fork(registers: &Registers) -> Result<Pid, &'static str> {
+    let mut registers = *registers;
+    registers.rax = 0;
+    let start = Start::Fork {
+        registers,
+        fs_base: FsBase::read(),
+    };
+
+    let mut table = TABLE.lock();
+    let parent = table
+        .find_thread(thread::current())
+        .ok_or("Not a process")?;
+    let (pid, name, signals) = (parent.pid, parent.name, parent.signals.fork());
+    let files = parent.files.clone();
+    let space = parent.space.as_mut().ok_or("Process exiting")?.fork()?;
+
+    table.insert(name, Some(pid), space, start, signals, files)
+}
+
+/// Replaces the program of the running process with `executable`, loaded in
+/// a new address space, with the arguments `argv` and the environment `envp`.
+/// The handlers of signals are reset. Only returns if that fails, with the
+/// reason, the process then running its old program still.
+pub fn exec(name: &str, executable: &[u8], argv: &[&str], envp: &[&str]) -> &'static str {
+    let image = match Elf::parse(executable).and_then(|elf| elf::load(&elf, argv, envp)) {
+        Ok(image) => image,
+        Err(err) => return err,
+    };
+    let start = Start::new(&image);
+    let level4 = image.space().level4_frame();
+
+    let old = {
+        let mut table = TABLE.lock();
+        let process = match table.find_thread(thread::current()) {
+            Some(process) => process,
+            None => return "Not a process",
+        };
+        process.name = Name::new(name);
+        process.signals.exec();
+        process.space.replace(image.into_space())
+    };
+
+    // What the arguments pointed to is gone with the old address space.
+    unsafe { thread::set_address_space(Some(level4)) };
+    drop(old);
+    unsafe { enter(start) }
+}
+
+/// First code of the thread of a process: switches to its address space and
+/// enters user mode.
+fn run() {
+    let (level4, start) = {
+        let mut table = TABLE.lock();
+        let process = table
+            .find_thread(thread::current())
+            .expect("Process thread without a process");
+        let level4 = process.space.as_ref().map(AddressSpace::level4_frame);
+        (level4, process.start.take())
+    };
+    let start = start.expect("Process started twice");
+
+    // The address space lives until the process exits, on this thread.
+    unsafe {
+        thread::set_address_space(level4);
+        enter(start)
+    }
+}
+
+/// Enters user mode at `start`, with its thread pointer.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// address space of the process is active.
+unsafe fn enter(start: Start) -> ! {
+    match start {
+        Start::Entry {
+            entry,
+            stack,
+            thread_pointer,
+        } => {
+            thread::set_fs_base(thread_pointer.unwrap_or_else(VirtAddr::zero));
+            user::enter_user_mode(entry, stack)
+        }
+        Start::Fork { registers, fs_base } => {
+            thread::set_fs_base(fs_base);
+            user::resume(&registers)
+        }
+    }
+}
+
+/// Ends the running process with `status`, freeing its address space and
+/// closing its files, or only the running thread if it is not a process. Its
+/// parent gets `SIGCHLD`.
+pub fn exit(status: ExitStatus) -> ! {
+    let (space, files, removed) = {
+        let mut table = TABLE.lock();
+        match table.find_thread(thread::current()) {
+            Some(process) => {
+                process.state = State::Zombie(status);
+                let (pid, parent, orphaned) = (process.pid, process.parent, process.orphaned);
+                let space = process.space.take();
+                let files = core::mem::take(&mut process.files);
+
+                if let Some(parent) = parent.and_then(|parent| table.find(parent)) {
+                    parent.signals.post(signal::SIGCHLD);
+                }
+
+                table.orphan_children(pid);
+                // Nobody waits for it, its thread is detached.
+                let removed = if orphaned { table.remove(pid) } else { None };
+                (space, Some(files), removed)
+            }
+            None => (None, None, None),
+        }
+    };
+    drop(files);
+    drop(removed);
+
+    if let Some(space) = space {
+        // Not freed while in use.
+        unsafe { thread::set_address_space(None) };
+        drop(space);
+    }
+
+    EXITED.notify_all();
+    thread::exit();
+}
+
+/// Waits for the process `pid` to exit, then removes it from the table and
+/// returns its exit status.
+pub fn wait(pid: Pid) -> Result<ExitStatus, &'static str> {
+    let handle = {
+        let mut table = TABLE.lock();
+        let process = table.find(pid).ok_or("No such process")?;
+        process.handle.take().ok_or("Process already waited for")?
+    };
+
+    reap(pid, handle)
+}
+
+/// Waits for a child of the running process to exit, `pid` or any of them if
+/// `None`, then removes it from the table and returns it with its exit status.
+/// If `block` is `false`, returns `None` at once when none has exited yet.
+///
+/// The children of kernel threads are the processes spawned by the kernel.
+pub fn waitpid(pid: Option<Pid>, block: bool) -> Result<Option<(Pid, ExitStatus)>, &'static str> {
+    let parent = current();
+    let mut result = Ok(None);
+
+    EXITED.wait_until(|| {
+        let mut table = TABLE.lock();
+        let children = table.processes.iter_mut().flatten().filter(|process| {
+            process.parent == parent
+                && !process.orphaned
+                && process.handle.is_some()
+                && pid.map_or(true, |pid| process.pid == pid)
+        });
+
+        let mut found = false;
+        for child in children {
+            found = true;
+            if let State::Zombie(_) = child.state {
+                result = Ok(child.handle.take().map(|handle| (child.pid, handle)));
+                return true;
+            }
+        }
+
+        if !found {
+            result = Err("No child processes");
+            return true;
+        }
+        !block
+    });
+
+    match result? {
+        Some((pid, handle)) => Ok(Some((pid, reap(pid, handle)?))),
+        None => Ok(None),
+    }
+}
+
+/// Waits for the thread of the exited process `pid` to end, then removes the
+/// process from the table and returns its exit status.
+fn reap(pid: Pid, handle: JoinHandle) -> Result<ExitStatus, &'static str> {
+    handle.join()?;
+
+    let process = TABLE.lock().remove(pid).ok_or("No such process")?;
+    match process.state {
+        State::Zombie(status) => Ok(status),
+        State::Running | State::Stopped => Err("Process thread exited without the process"),
+    }
+}
+
+/// Maps `page` for user mode in the address space of the running process, or
+/// in the kernel page table if the running thread is not a process, see
+/// `mem::map_user_page`.
+pub fn map_user_page(page: Page, flags: PageTableFlags) -> Result<(), &'static str> {
+    let thread = thread::try_current();
+    let mut table = TABLE.lock();
+    let space = thread
+        .and_then(|thread| table.find_thread(thread))
+        .and_then(|process| process.space.as_mut());
+    match space {
+        Some(space) => space.map_user_page(page, flags),
+        None => mem::map_user_page(page, flags),
+    }
+}
+
+/// Unmaps `page`, mapped by `map_user_page`, and frees its frame.
+pub fn unmap_user_page(page: Page) -> Result<(), &'static str> {
+    let thread = thread::try_current();
+    let mut table = TABLE.lock();
+    let space = thread
+        .and_then(|thread| table.find_thread(thread))
+        .and_then(|process| process.space.as_mut());
+    match space {
+        Some(space) => space.unmap_user_page(page),
+        None => mem::unmap_user_page(page),
+    }
+}
+
+/// Reserves `size` bytes of user addresses in the address space of the
+/// running process, or in the kernel page table if the running thread is not
+/// a process, see `mem::reserve_user`.
+pub fn reserve_user(size: u64) -> Option<VirtAddr> {
+    let thread = thread::try_current();
+    let mut table = TABLE.lock();
+    let space = thread
+        .and_then(|thread| table.find_thread(thread))
+        .and_then(|process| process.space.as_ref());
+    match space {
+        Some(space) => space.reserve_user(size),
+        None => mem::reserve_user(size),
+    }
+}
+
+/// Runs `f` on the file table of the running process, or returns `None` if
+/// the running thread is not a process.
+///
+/// The table is locked meanwhile: files `f` removes are to be dropped after.
+pub fn files<T>(f: impl FnOnce(&mut FileTable) -> T) -> Option<T> {
+    let thread = thread::try_current()?;
+    let mut table = TABLE.lock();
+    table
+        .find_thread(thread)
+        .map(|process| f(&mut process.files))
+}
+
+/// Process the running thread belongs to, if any.
+pub fn current() -> Option<Pid> {
+    let thread = thread::try_current()?;
+    let mut table = TABLE.lock();
+    table.find_thread(thread).map(|process| process.pid)
+}
+
+/// State of the process `pid`, if it is in the table.
+pub fn info(pid: Pid) -> Option<ProcessInfo> {
+    TABLE.lock().find(pid).map(|process| process.info())
+}
+
+/// Every process in the table, by slot.
+pub fn list() -> [Option<ProcessInfo>; MAX_PROCESSES] {
+    let mut list = [None; MAX_PROCESSES];
+    let table = TABLE.lock();
+    for (info, process) in list.iter_mut().zip(table.processes.iter()) {
+        *info = process.as_ref().map(Process::info);
+    }
+    list
+}
+
+/// Prints the process table.
+pub fn ps() {
+    struct Console;
+
+    impl fmt::Write for Console {
+        fn write_str(&mut self, text: &str) -> fmt::Result {
+            kprint!("{}", text);
+            Ok(())
+        }
+    }
+
+    let _ = write_table(&mut Console);
+}
+
+/// Writes the process table to `out`, as `ps` prints it.
+pub fn write_table(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "{:>5} {:>5} {:<16} {:>6} {:>6} STATE",
+        "PID", "PPID", "NAME", "THREAD", "PAGES"
+    )?;
+    for info in list().iter().flatten() {
+        write!(
+            out,
+            "{:>5} {:>5} {:<16} {:>6} {:>6} ",
+            info.pid,
+            info.parent.map_or(0, Pid::as_u64),
+            info.name,
+            info.thread.as_u64(),
+            info.pages
+        )?;
+        match info.state {
+            State::Running => writeln!(out, "running")?,
+            State::Stopped => writeln!(out, "stopped")?,
+            State::Zombie(ExitStatus::Exited(code)) => writeln!(out, "exited {}", code)?,
+            State::Zombie(ExitStatus::Signaled(signal)) => writeln!(out, "killed ({})", signal)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[test_case]
+fn names() {
+    assert_eq!(Name::new("init").as_str(), "init");
+    assert_eq!(
+        Name::new("a-rather-long-process-name").as_str(),
+        "a-rather-long-pr"
+    );
+    // Not in the middle of a character
+    assert_eq!(Name::new("ééééééééé").as_str(), "éééééééé");
+}
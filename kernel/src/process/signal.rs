@@ -0,0 +1,652 @@
+//! # Signals
+//!
+//! A signal interrupts a process: `kill` makes it pending, and it is delivered
+//! the next time the process goes back to user mode, from a system call, an
+//! interrupt or an exception, unless the process blocks it. Signals are
+//! numbered as on Linux, and a process blocks them with `sigprocmask`.
+//!
+//! A signal does its default action, see `Action`, unless the process ignores
+//! it or catches it with a handler set by `sigaction`. The registers of the
+//! process are then saved in a `SignalFrame` on its stack, and the handler is
+//! called with the number of the signal in `rdi`. It returns to the restorer
+//! of the action, that calls `sigreturn` to go back where the signal
+//! interrupted. `SIGKILL` and `SIGSTOP` can be neither caught nor blocked.
+//!
+//! Exceptions raised by user code are signals too: `SIGFPE` for a division by
+//! zero, `SIGILL` for an invalid opcode, and `SIGSEGV` for general protection
+//! and page faults. The process is killed if it blocks or ignores them, as the
+//! faulting instruction would run again.
+//!
+//! # Examples
+//! ```no_run
+//! let pid = process::spawn("server", bytes, &["server"], &[]).unwrap();
+//! signal::kill(pid, signal::SIGTERM).unwrap();
+//! assert_eq!(process::wait(pid), Ok(ExitStatus::Signaled(signal::SIGTERM)));
+//! ```
+
+use core::{fmt, mem, slice};
+
+use super::{ExitStatus, Pid, State, TABLE};
+use crate::{
+    sync::WaitQueue,
+    syscall::{user_slice, user_slice_mut, Registers},
+    thread,
+    user::{self, USER_END},
+};
+
+pub const SIGHUP: Signal = Signal(1);
+pub const SIGINT: Signal = Signal(2);
+pub const SIGQUIT: Signal = Signal(3);
+pub const SIGILL: Signal = Signal(4);
+pub const SIGTRAP: Signal = Signal(5);
+pub const SIGABRT: Signal = Signal(6);
+pub const SIGBUS: Signal = Signal(7);
+pub const SIGFPE: Signal = Signal(8);
+pub const SIGKILL: Signal = Signal(9);
+pub const SIGUSR1: Signal = Signal(10);
+pub const SIGSEGV: Signal = Signal(11);
+pub const SIGUSR2: Signal = Signal(12);
+pub const SIGPIPE: Signal = Signal(13);
+pub const SIGALRM: Signal = Signal(14);
+pub const SIGTERM: Signal = Signal(15);
+pub const SIGSTKFLT: Signal = Signal(16);
+pub const SIGCHLD: Signal = Signal(17);
+pub const SIGCONT: Signal = Signal(18);
+pub const SIGSTOP: Signal = Signal(19);
+pub const SIGTSTP: Signal = Signal(20);
+pub const SIGTTIN: Signal = Signal(21);
+pub const SIGTTOU: Signal = Signal(22);
+pub const SIGURG: Signal = Signal(23);
+pub const SIGXCPU: Signal = Signal(24);
+pub const SIGXFSZ: Signal = Signal(25);
+pub const SIGVTALRM: Signal = Signal(26);
+pub const SIGPROF: Signal = Signal(27);
+pub const SIGWINCH: Signal = Signal(28);
+pub const SIGIO: Signal = Signal(29);
+pub const SIGPWR: Signal = Signal(30);
+pub const SIGSYS: Signal = Signal(31);
+
+/// Number of the last signal.
+pub const MAX_SIGNAL: u64 = 31;
+
+/// Handler of the signals that do their default action.
+pub const SIG_DFL: u64 = 0;
+/// Handler of the signals that are ignored.
+pub const SIG_IGN: u64 = 1;
+
+/// The action has a restorer, which it needs if it has a handler.
+pub const SA_RESTORER: u64 = 0x0400_0000;
+/// The signal is not blocked while its handler runs.
+pub const SA_NODEFER: u64 = 0x4000_0000;
+/// The action goes back to the default one once the handler is called.
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// `sigprocmask` blocks the signals of the set.
+pub const SIG_BLOCK: u64 = 0;
+/// `sigprocmask` unblocks the signals of the set.
+pub const SIG_UNBLOCK: u64 = 1;
+/// `sigprocmask` blocks the signals of the set only.
+pub const SIG_SETMASK: u64 = 2;
+
+/// Bytes below the stack pointer that the code of a process may use without
+/// moving it, skipped by signal frames.
+const RED_ZONE: u64 = 128;
+
+/// Flags of RFLAGS cleared for handlers: the trap and direction flags.
+const HANDLER_RFLAGS_CLEAR: u64 = 0x500;
+
+const NAMES: [&str; MAX_SIGNAL as usize] = [
+    "SIGHUP",
+    "SIGINT",
+    "SIGQUIT",
+    "SIGILL",
+    "SIGTRAP",
+    "SIGABRT",
+    "SIGBUS",
+    "SIGFPE",
+    "SIGKILL",
+    "SIGUSR1",
+    "SIGSEGV",
+    "SIGUSR2",
+    "SIGPIPE",
+    "SIGALRM",
+    "SIGTERM",
+    "SIGSTKFLT",
+    "SIGCHLD",
+    "SIGCONT",
+    "SIGSTOP",
+    "SIGTSTP",
+    "SIGTTIN",
+    "SIGTTOU",
+    "SIGURG",
+    "SIGXCPU",
+    "SIGXFSZ",
+    "SIGVTALRM",
+    "SIGPROF",
+    "SIGWINCH",
+    "SIGIO",
+    "SIGPWR",
+    "SIGSYS",
+];
+
+/// A signal, numbered from 1 to `MAX_SIGNAL`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Signal(u8);
+
+impl Signal {
+    /// The signal `number`, if there is one.
+    pub fn new(number: u64) -> Option<Signal> {
+        match number {
+            1..=MAX_SIGNAL => Some(Signal(number as u8)),
+            _ => None,
+        }
+    }
+
+    /// The number of the signal.
+    pub fn as_u64(self) -> u64 {
+        u64::from(self.0)
+    }
+
+    pub fn name(self) -> &'static str {
+        NAMES[usize::from(self.0) - 1]
+    }
+
+    /// What the signal does if it is not ignored nor caught.
+    pub fn default_action(self) -> Action {
+        match self {
+            SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU
+            | SIGXFSZ | SIGSYS => Action::Core,
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => Action::Stop,
+            SIGCONT => Action::Continue,
+            SIGCHLD | SIGURG | SIGWINCH => Action::Ignore,
+            _ => Action::Terminate,
+        }
+    }
+
+    /// Returns `false` for `SIGKILL` and `SIGSTOP`, that always do their
+    /// default action.
+    pub fn can_be_caught(self) -> bool {
+        self != SIGKILL && self != SIGSTOP
+    }
+
+    fn bit(self) -> u64 {
+        1 << (self.0 - 1)
+    }
+}
+
+impl fmt::Debug for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// Default action of a signal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Kills the process.
+    Terminate,
+    /// Kills the process, that would dump its core.
+    Core,
+    /// Stops the process until it gets `SIGCONT`.
+    Stop,
+    /// Continues the process if it is stopped.
+    Continue,
+    Ignore,
+}
+
+/// A set of signals, the signal `n` being the bit `n - 1`.
+#[repr(transparent)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SigSet(u64);
+
+impl SigSet {
+    pub const fn empty() -> SigSet {
+        SigSet(0)
+    }
+
+    /// The set of the signals whose bits are set in `bits`, the unused high
+    /// ones cleared.
+    pub const fn from_bits(bits: u64) -> SigSet {
+        SigSet(bits & ((1 << MAX_SIGNAL) - 1))
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, signal: Signal) -> bool {
+        self.0 & signal.bit() != 0
+    }
+
+    pub fn insert(&mut self, signal: Signal) {
+        self.0 |= signal.bit();
+    }
+
+    pub fn remove(&mut self, signal: Signal) {
+        self.0 &= !signal.bit();
+    }
+
+    /// The signal of the set with the lowest number, if any.
+    pub fn first(self) -> Option<Signal> {
+        Signal::new(u64::from(self.0.trailing_zeros()) + 1)
+    }
+
+    /// The set without the signals that can not be blocked.
+    fn blockable(mut self) -> SigSet {
+        self.remove(SIGKILL);
+        self.remove(SIGSTOP);
+        self
+    }
+}
+
+/// What a process does with a signal, laid out as `sigaction` takes it.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of the handler.
+    pub handler: u64,
+    /// `SA_*` flags.
+    pub flags: u64,
+    /// Where the handler returns to, with `SA_RESTORER`.
+    pub restorer: u64,
+    /// Signals blocked while the handler runs, besides the signal itself.
+    pub mask: SigSet,
+}
+
+impl SigAction {
+    fn ignores(&self, signal: Signal) -> bool {
+        match self.handler {
+            SIG_DFL => signal.default_action() == Action::Ignore,
+            SIG_IGN => true,
+            _ => false,
+        }
+    }
+}
+
+/// Saved on the user stack when a handler is called, the stack pointer
+/// pointing at it.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SignalFrame {
+    /// Where the handler returns to.
+    pub restorer: u64,
+    pub signal: u64,
+    /// Registers of the code the signal interrupted.
+    pub registers: Registers,
+    /// Signals blocked before the handler was called.
+    pub blocked: SigSet,
+}
+
+/// Signals of a process.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Signals {
+    pending: SigSet,
+    blocked: SigSet,
+    /// By signal number, the first one unused.
+    actions: [SigAction; MAX_SIGNAL as usize + 1],
+}
+
+/// What a pending signal does.
+enum Delivery {
+    Ignore,
+    Stop,
+    Kill,
+    Handler(SigAction, SigSet),
+}
+
+impl Signals {
+    pub(super) fn new() -> Signals {
+        Signals {
+            pending: SigSet::empty(),
+            blocked: SigSet::empty(),
+            actions: [SigAction::default(); MAX_SIGNAL as usize + 1],
+        }
+    }
+
+    /// Signals of a child forked by the process: the same, none pending.
+    pub(super) fn fork(&self) -> Signals {
+        Signals {
+            pending: SigSet::empty(),
+            ..*self
+        }
+    }
+
+    /// Forgets the handlers, gone with the program of the process. Ignored
+    /// signals stay ignored.
+    pub(super) fn exec(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    /// Makes `signal` pending. Stopping discards a pending `SIGCONT`, and
+    /// continuing discards the pending stop signals.
+    pub(super) fn post(&mut self, signal: Signal) {
+        match signal.default_action() {
+            Action::Stop => self.pending.remove(SIGCONT),
+            Action::Continue => {
+                for &stop in [SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU].iter() {
+                    self.pending.remove(stop);
+                }
+            }
+            _ => {}
+        }
+        self.pending.insert(signal);
+    }
+
+    fn action(&self, signal: Signal) -> SigAction {
+        self.actions[usize::from(signal.0)]
+    }
+
+    /// Takes the pending signal with the lowest number that is not blocked,
+    /// returning what it does. Blocks the signals of its handler if it has
+    /// one, returning the signals blocked before.
+    fn take(&mut self) -> Option<(Signal, Delivery)> {
+        let signal = SigSet(self.pending.0 & !self.blocked.0).first()?;
+        self.pending.remove(signal);
+
+        let action = self.action(signal);
+        let delivery = match action.handler {
+            _ if action.ignores(signal) => Delivery::Ignore,
+            SIG_DFL | SIG_IGN => match signal.default_action() {
+                Action::Terminate | Action::Core => Delivery::Kill,
+                Action::Stop => Delivery::Stop,
+                Action::Continue | Action::Ignore => Delivery::Ignore,
+            },
+            _ => {
+                let blocked = self.blocked;
+                self.blocked.0 |= action.mask.0;
+                if action.flags & SA_NODEFER == 0 {
+                    self.blocked.insert(signal);
+                }
+                self.blocked = self.blocked.blockable();
+                if action.flags & SA_RESETHAND != 0 {
+                    self.actions[usize::from(signal.0)] = SigAction::default();
+                }
+                Delivery::Handler(action, blocked)
+            }
+        };
+
+        Some((signal, delivery))
+    }
+}
+
+/// Stopped processes waiting for `SIGCONT`.
+static CONTINUED: WaitQueue = WaitQueue::new();
+
+/// Sends `signal` to the process `pid`. A stopped process is continued by
+/// `SIGCONT` and `SIGKILL`. Signals sent to a process that exited are lost.
+pub fn kill(pid: Pid, signal: Signal) -> Result<(), &'static str> {
+    let continued = {
+        let mut table = TABLE.lock();
+        let process = table.find(pid).ok_or("No such process")?;
+        if let State::Zombie(_) = process.state {
+            return Ok(());
+        }
+
+        process.signals.post(signal);
+        let continues = signal == SIGCONT || signal == SIGKILL;
+        if continues && process.state == State::Stopped {
+            process.state = State::Running;
+            true
+        } else {
+            false
+        }
+    };
+
+    if continued {
+        CONTINUED.notify_all();
+    }
+    Ok(())
+}
+
+/// Sets what the running process does with `signal` to `action`, if any, and
+/// returns what it did.
+pub fn sigaction(signal: Signal, action: Option<SigAction>) -> Result<SigAction, &'static str> {
+    if let Some(action) = action {
+        if !signal.can_be_caught() {
+            return Err("Signal can not be caught");
+        }
+        if action.flags & !(SA_RESTORER | SA_NODEFER | SA_RESETHAND) != 0 {
+            return Err("Unsupported flags");
+        }
+        // The stack is not executable, the handler returns to the restorer.
+        let handler = action.handler != SIG_DFL && action.handler != SIG_IGN;
+        if handler
+            && (action.handler >= USER_END
+                || action.flags & SA_RESTORER == 0
+                || action.restorer >= USER_END)
+        {
+            return Err("Invalid handler");
+        }
+    }
+
+    let mut table = TABLE.lock();
+    let process = table
+        .find_thread(thread::current())
+        .ok_or("Not a process")?;
+    let old = process.signals.action(signal);
+    if let Some(mut action) = action {
+        action.mask = action.mask.blockable();
+        process.signals.actions[usize::from(signal.0)] = action;
+    }
+
+    Ok(old)
+}
+
+/// Changes the signals the running process blocks with `set`, according to
+/// `how`, one of `SIG_BLOCK`, `SIG_UNBLOCK` and `SIG_SETMASK`. Returns the
+/// signals it blocked.
+pub fn sigprocmask(how: u64, set: Option<SigSet>) -> Result<SigSet, &'static str> {
+    let mut table = TABLE.lock();
+    let signals = &mut table
+        .find_thread(thread::current())
+        .ok_or("Not a process")?
+        .signals;
+    let old = signals.blocked;
+
+    if let Some(set) = set {
+        let blocked = match how {
+            SIG_BLOCK => old.0 | set.0,
+            SIG_UNBLOCK => old.0 & !set.0,
+            SIG_SETMASK => set.0,
+            _ => return Err("Invalid argument"),
+        };
+        signals.blocked = SigSet(blocked).blockable();
+    }
+
+    Ok(old)
+}
+
+/// Signals of the running process that are pending.
+pub fn sigpending() -> Result<SigSet, &'static str> {
+    let mut table = TABLE.lock();
+    let process = table
+        .find_thread(thread::current())
+        .ok_or("Not a process")?;
+    Ok(process.signals.pending)
+}
+
+/// Delivers the pending signals of the running process that it does not
+/// block, before it goes back to user mode with `registers`. A handler is
+/// called by changing the registers, and the other pending signals wait for
+/// its `sigreturn`.
+///
+/// Does not return if a signal kills the process, and blocks while a signal
+/// stops it. Does nothing if the running thread is not a process.
+pub fn deliver(registers: &mut Registers) {
+    loop {
+        let (signal, delivery) = {
+            let mut table = TABLE.lock();
+            let process = match table.find_thread(thread::current()) {
+                Some(process) => process,
+                None => return,
+            };
+            let (signal, delivery) = match process.signals.take() {
+                Some(taken) => taken,
+                None => return,
+            };
+            if let Delivery::Stop = delivery {
+                process.state = State::Stopped;
+            }
+            (signal, delivery)
+        };
+
+        match delivery {
+            Delivery::Ignore => {}
+            Delivery::Stop => {
+                log::info!("Process stopped by {}", signal);
+                CONTINUED.wait_until(|| {
+                    let mut table = TABLE.lock();
+                    let process = table.find_thread(thread::current());
+                    process.map_or(true, |process| process.state != State::Stopped)
+                });
+            }
+            Delivery::Kill => {
+                log::info!("Process killed by {}", signal);
+                super::exit(ExitStatus::Signaled(signal));
+            }
+            Delivery::Handler(action, blocked) => {
+                if push_frame(registers, signal, &action, blocked).is_err() {
+                    // Nowhere to save the registers.
+                    super::exit(ExitStatus::Signaled(SIGSEGV));
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Saves `registers` in a signal frame on the user stack, then makes them call
+/// the handler of `action`.
+fn push_frame(
+    registers: &mut Registers,
+    signal: Signal,
+    action: &SigAction,
+    blocked: SigSet,
+) -> Result<(), ()> {
+    let frame = SignalFrame {
+        restorer: action.restorer,
+        signal: signal.as_u64(),
+        registers: *registers,
+        blocked,
+    };
+    let size = mem::size_of::<SignalFrame>() as u64;
+
+    // At a handler's entry, the stack pointer plus 8 is 16-byte aligned, as
+    // if it was called.
+    let top = registers.rsp.checked_sub(RED_ZONE + size).ok_or(())?;
+    let addr = (top & !0xF) - 8;
+    let bytes = user_slice_mut(addr, size as usize).map_err(|_| ())?;
+    let frame = unsafe { slice::from_raw_parts(&frame as *const _ as *const u8, size as usize) };
+    bytes.copy_from_slice(frame);
+
+    registers.rip = action.handler;
+    registers.rsp = addr;
+    registers.rdi = signal.as_u64();
+    registers.rflags &= !HANDLER_RFLAGS_CLEAR;
+    Ok(())
+}
+
+/// Ends the handler of a signal the running process returned from: restores
+/// the registers and the blocked signals saved in the signal frame the
+/// handler returned above, then goes back where the signal interrupted.
+///
+/// The process is killed with `SIGSEGV` if the frame is not valid.
+pub fn sigreturn(registers: &Registers) -> ! {
+    let frame = registers
+        .rsp
+        .checked_sub(8)
+        .and_then(|addr| user_slice(addr, mem::size_of::<SignalFrame>()).ok())
+        .map(|bytes| unsafe { (bytes.as_ptr() as *const SignalFrame).read_unaligned() })
+        .filter(|frame| frame.registers.rip < USER_END && frame.registers.rsp < USER_END);
+    let mut frame = match frame {
+        Some(frame) => frame,
+        None => super::exit(ExitStatus::Signaled(SIGSEGV)),
+    };
+
+    if let Some(process) = TABLE.lock().find_thread(thread::current()) {
+        process.signals.blocked = frame.blocked.blockable();
+    }
+
+    // The signals unblocked may be pending.
+    deliver(&mut frame.registers);
+    unsafe { user::resume(&frame.registers) }
+}
+
+/// Raises `signal` for an exception the running thread raised in user mode,
+/// with `registers`. The process is killed if it does not catch it, or if it
+/// is not a process, the thread exits.
+pub fn raise_fault(signal: Signal, registers: &mut Registers) {
+    let caught = {
+        let mut table = TABLE.lock();
+        match table.find_thread(thread::current()) {
+            Some(process) => {
+                let signals = &mut process.signals;
+                let action = signals.action(signal);
+                let caught = action.handler != SIG_DFL
+                    && !action.ignores(signal)
+                    && !signals.blocked.contains(signal);
+                if caught {
+                    signals.post(signal);
+                }
+                caught
+            }
+            None => false,
+        }
+    };
+
+    if !caught {
+        super::exit(ExitStatus::Signaled(signal));
+    }
+    deliver(registers);
+}
+
+#[cfg(test)]
+#[test_case]
+fn signal_sets() {
+    let mut set = SigSet::empty();
+    set.insert(SIGTERM);
+    set.insert(SIGINT);
+    assert_eq!(set.bits(), 1 << 14 | 1 << 1);
+    assert_eq!(set.first(), Some(SIGINT));
+    set.remove(SIGINT);
+    assert!(!set.contains(SIGINT));
+    assert_eq!(set.first(), Some(SIGTERM));
+    assert_eq!(SigSet::empty().first(), None);
+
+    assert_eq!(SigSet::from_bits(u64::MAX).bits(), 0x7fff_ffff);
+    assert!(!SigSet::from_bits(u64::MAX).blockable().contains(SIGKILL));
+}
+
+#[cfg(test)]
+#[test_case]
+fn pending_signals() {
+    let mut signals = Signals::new();
+    signals.blocked.insert(SIGUSR1);
+    signals.actions[usize::from(SIGUSR2.0)].handler = SIG_IGN;
+    signals.post(SIGUSR1);
+    signals.post(SIGUSR2);
+    signals.post(SIGTERM);
+
+    // Blocked signals stay pending, and ignored ones do nothing.
+    assert!(matches!(signals.take(), Some((SIGUSR2, Delivery::Ignore))));
+    assert!(matches!(signals.take(), Some((SIGTERM, Delivery::Kill))));
+    assert!(signals.take().is_none());
+    assert!(signals.pending.contains(SIGUSR1));
+
+    // Stopping and continuing cancel each other.
+    signals.post(SIGSTOP);
+    signals.post(SIGCONT);
+    assert!(!signals.pending.contains(SIGSTOP));
+    signals.post(SIGTSTP);
+    assert!(!signals.pending.contains(SIGCONT));
+}
@@ -0,0 +1,29 @@
+//! # QEMU exit device
+//!
+//! The `isa-debug-exit` device QEMU is started with when testing (see
+//! `Cargo.toml`) exits QEMU when a value is written to its port, with
+//! `(value << 1) | 1` as exit status.
+
+use x86_64::instructions::port::Port;
+
+/// I/O port of the `isa-debug-exit` device, as in its `iobase`.
+pub const EXIT_PORT: u16 = 0xf4;
+
+/// Exit codes written to the exit device.
+///
+/// `Success` makes QEMU exit with status 33, the `test-success-exit-code`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// Exits QEMU with the given code.
+///
+/// Returns if the kernel is not running on QEMU with the exit device.
+pub fn exit_qemu(code: QemuExitCode) {
+    let mut port = Port::new(EXIT_PORT);
+
+    unsafe { port.write(code as u32) };
+}
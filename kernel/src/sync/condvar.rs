@@ -0,0 +1,70 @@
+//! Condition variable, to sleep until the data behind a `Mutex` changes.
+
+use core::sync::atomic;
+
+use super::{lockdep, mutex::MutexGuard, wait_queue::WaitQueue};
+use crate::thread;
+
+/// Threads waiting for a condition on the data of a `Mutex`.
+///
+/// Like any condition variable, waits can end spuriously: check the
+/// condition in a loop, or use `wait_while`.
+#[derive(Debug, Default)]
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    /// Creates a new instance of Condvar, without waiters.
+    pub const fn new() -> Condvar {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex and sleeps until notified, then locks it again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+
+        match thread::try_current() {
+            Some(id) => {
+                // Queued before unlocking, so a notification is not lost.
+                self.waiters.push(id);
+                drop(guard);
+
+                lockdep::check_may_sleep();
+                thread::park();
+                self.waiters.remove(id);
+            }
+            None => {
+                drop(guard);
+                atomic::spin_loop_hint();
+            }
+        }
+
+        mutex.lock()
+    }
+
+    /// Waits as long as `condition` returns `true`.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    /// Wakes the oldest waiting thread up, returning `false` if there was none.
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    /// Wakes every waiting thread up, returning how many there were.
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
@@ -0,0 +1,120 @@
+//! Deadlock checks of the locks, only done in debug builds.
+//!
+//! The locks each thread holds are tracked by address, interrupt handlers
+//! count as the thread they interrupted.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::thread::{self, MAX_THREADS};
+
+/// Maximum number of locks tracked per thread. Taking more panics.
+pub const MAX_HELD: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Spin,
+    Sleep,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Held {
+    lock: usize,
+    rank: u32,
+    kind: Kind,
+}
+
+/// Locks held, by thread slot.
+static HELD: Mutex<[[Option<Held>; MAX_HELD]; MAX_THREADS]> =
+    Mutex::new([[None; MAX_HELD]; MAX_THREADS]);
+
+/// Checks that the current thread can take `lock`, then counts it as held.
+/// Called before waiting for the lock, which could be forever.
+///
+/// # Panics
+/// If the thread already holds `lock`, a ranked lock of a rank not lower than
+/// `rank`, or `MAX_HELD` locks.
+pub fn acquire(lock: usize, rank: u32, kind: Kind) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let error = interrupts::without_interrupts(|| {
+        // Taken by an interrupted check, this one is skipped.
+        let mut held = HELD.try_lock()?;
+        let held = &mut held[thread::current_slot()];
+
+        for other in held.iter().flatten() {
+            if other.lock == lock {
+                return Some("Deadlock: lock already held by this thread");
+            }
+            if rank != 0 && other.rank >= rank {
+                return Some("Lock order violation: lock ranked lower than one held");
+            }
+        }
+
+        match held.iter_mut().find(|held| held.is_none()) {
+            Some(free) => *free = Some(Held { lock, rank, kind }),
+            None => return Some("Too many locks held to check them"),
+        }
+        None
+    });
+
+    if let Some(error) = error {
+        panic!("{}", error);
+    }
+}
+
+/// Counts `lock` as no longer held by the current thread.
+pub fn release(lock: usize) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    interrupts::without_interrupts(|| {
+        if let Some(mut held) = HELD.try_lock() {
+            let held = &mut held[thread::current_slot()];
+            if let Some(entry) = held
+                .iter_mut()
+                .find(|held| held.map_or(false, |held| held.lock == lock))
+            {
+                *entry = None;
+            }
+        }
+    });
+}
+
+/// Forgets the locks the current thread holds, whose guards were lost by a
+/// panic that did not unwind.
+pub fn forget_held() {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    interrupts::without_interrupts(|| {
+        if let Some(mut held) = HELD.try_lock() {
+            held[thread::current_slot()] = [None; MAX_HELD];
+        }
+    });
+}
+
+/// Checks that the current thread can sleep.
+///
+/// # Panics
+/// If the thread holds a spinlock, which nobody could take meanwhile.
+pub fn check_may_sleep() {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let spinning = interrupts::without_interrupts(|| {
+        HELD.try_lock().map_or(false, |held| {
+            held[thread::current_slot()]
+                .iter()
+                .flatten()
+                .any(|held| held.kind == Kind::Spin)
+        })
+    });
+
+    assert!(!spinning, "Deadlock: sleeping while holding a spinlock");
+}
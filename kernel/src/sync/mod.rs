@@ -0,0 +1,38 @@
+//! # Synchronization primitives
+//!
+//! `IrqSafeSpinlock` busy-waits with interrupts disabled while it is held, for
+//! short critical sections shared with interrupt handlers. The others put the
+//! waiting thread to sleep on a `WaitQueue` until it can go on, and must not
+//! be used from interrupt handlers.
+//!
+//! In debug builds the locks are checked for deadlocks: taking a lock the
+//! thread already holds, taking ranked locks out of order (a lock must have a
+//! higher rank than the ranked ones held, unranked locks are not ordered) or
+//! sleeping while holding a spinlock panic.
+//!
+//! # Examples
+//! ```no_run
+//! static DEVICES: Mutex<Devices> = Mutex::ranked(Devices::new(), 1);
+//! static IRQ_STATE: IrqSafeSpinlock<IrqState> = IrqSafeSpinlock::ranked(IrqState::new(), 2);
+//!
+//! let devices = DEVICES.lock();
+//! let state = IRQ_STATE.lock();
+//! // Taking DEVICES now would panic
+//! ```
+
+mod condvar;
+mod lockdep;
+mod mutex;
+mod semaphore;
+mod spinlock;
+mod wait_queue;
+
+pub use self::{
+    condvar::Condvar,
+    mutex::{Mutex, MutexGuard},
+    semaphore::Semaphore,
+    spinlock::{IrqSafeSpinlock, IrqSafeSpinlockGuard},
+    wait_queue::WaitQueue,
+};
+
+pub(crate) use self::lockdep::forget_held;
@@ -0,0 +1,220 @@
+//! Mutex that puts the threads waiting for it to sleep.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::{
+    lockdep::{self, Kind},
+    wait_queue::WaitQueue,
+};
+
+/// A mutual exclusion lock whose waiters sleep instead of spinning.
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    rank: u32,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+// Only the owner of the lock accesses the data.
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+/// Access to the data of a locked `Mutex`, which is unlocked when it is
+/// dropped. It stays on the thread that locked it.
+pub struct MutexGuard<'a, T: ?Sized> {
+    pub(super) mutex: &'a Mutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Mutex<T> {
+    /// Creates a new unranked Mutex.
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex::ranked(data, 0)
+    }
+
+    /// Creates a new Mutex, with a rank above 0 for the lock order checks.
+    pub const fn ranked(data: T, rank: u32) -> Mutex<T> {
+        Mutex {
+            locked: AtomicBool::new(false),
+            rank,
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes the mutex, returning its data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Sleeps until the lock is free, then takes it.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        lockdep::acquire(self.id(), self.rank, Kind::Sleep);
+        self.waiters.wait_until(|| self.try_acquire());
+
+        MutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Takes the lock if nobody holds it.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if !self.try_acquire() {
+            return None;
+        }
+        lockdep::acquire(self.id(), self.rank, Kind::Sleep);
+
+        Some(MutexGuard {
+            mutex: self,
+            _not_send: PhantomData,
+        })
+    }
+
+    /// Returns `true` if someone holds the lock.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Mutable access to the data, which needs no locking.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+        lockdep::release(self.id());
+        self.waiters.notify_one();
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "Mutex {{ data: {:?} }}", &*guard),
+            None => write!(f, "Mutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Mutex<T> {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+#[cfg(all(test, target_os = "none"))]
+#[test_case]
+fn locking() {
+    let mutex = Mutex::new([0; 4]);
+
+    {
+        let mut guard = mutex.lock();
+        guard[1] = 1;
+        assert!(mutex.is_locked());
+        assert!(mutex.try_lock().is_none());
+    }
+
+    assert_eq!(*mutex.lock(), [0, 1, 0, 0]);
+    assert!(!mutex.is_locked());
+    assert_eq!(mutex.into_inner(), [0, 1, 0, 0]);
+}
+
+#[cfg(all(test, target_os = "none"))]
+static RELOCKED: Mutex<()> = Mutex::new(());
+
+#[cfg(all(test, target_os = "none"))]
+fn relocking() {
+    let _guard = RELOCKED.lock();
+    let _again = RELOCKED.lock();
+}
+
+#[cfg(all(test, target_os = "none", debug_assertions))]
+#[test_case]
+const RELOCKING: crate::testing::Test = crate::test!(relocking).should_panic();
+
+#[cfg(all(test, target_os = "none"))]
+static OUTER: Mutex<()> = Mutex::ranked((), 2);
+#[cfg(all(test, target_os = "none"))]
+static INNER: Mutex<()> = Mutex::ranked((), 1);
+
+#[cfg(all(test, target_os = "none"))]
+fn out_of_order() {
+    let _outer = OUTER.lock();
+    let _inner = INNER.lock();
+}
+
+#[cfg(all(test, target_os = "none", debug_assertions))]
+#[test_case]
+const OUT_OF_ORDER: crate::testing::Test = crate::test!(out_of_order).should_panic();
+
+/// One more than the deadlock checks track.
+#[cfg(all(test, target_os = "none"))]
+static MANY: [Mutex<()>; 9] = [
+    Mutex::new(()),
+    Mutex::new(()),
+    Mutex::new(()),
+    Mutex::new(()),
+    Mutex::new(()),
+    Mutex::new(()),
+    Mutex::new(()),
+    Mutex::new(()),
+    Mutex::new(()),
+];
+
+#[cfg(all(test, target_os = "none"))]
+fn too_many() {
+    fn lock_all(locks: &[Mutex<()>]) {
+        if let Some((first, rest)) = locks.split_first() {
+            let _guard = first.lock();
+            lock_all(rest);
+        }
+    }
+
+    assert!(MANY.len() > super::lockdep::MAX_HELD);
+    lock_all(&MANY);
+}
+
+#[cfg(all(test, target_os = "none", debug_assertions))]
+#[test_case]
+const TOO_MANY: crate::testing::Test = crate::test!(too_many).should_panic();
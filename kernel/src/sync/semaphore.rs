@@ -0,0 +1,72 @@
+//! Counting semaphore whose waiters sleep.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::wait_queue::WaitQueue;
+
+/// A number of permits, taken by `acquire` and given back by `release`.
+#[derive(Debug)]
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    /// Creates a new instance of Semaphore, with `permits` available.
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Sleeps until a permit is available, then takes it.
+    pub fn acquire(&self) {
+        self.waiters.wait_until(|| self.try_acquire());
+    }
+
+    /// Takes a permit if one is available.
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+
+        false
+    }
+
+    /// Gives a permit back, waking a waiter up.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    /// Number of permits available.
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(all(test, target_os = "none"))]
+#[test_case]
+fn permits() {
+    let semaphore = Semaphore::new(2);
+
+    semaphore.acquire();
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.try_acquire());
+    assert_eq!(semaphore.available_permits(), 0);
+
+    semaphore.release();
+    assert_eq!(semaphore.available_permits(), 1);
+    semaphore.acquire();
+    assert_eq!(semaphore.available_permits(), 0);
+}
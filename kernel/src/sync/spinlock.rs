@@ -0,0 +1,141 @@
+//! Spinlock that keeps interrupts disabled while it is held.
+
+use core::{
+    fmt,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+};
+
+use x86_64::instructions::interrupts;
+
+use super::lockdep::{self, Kind};
+
+/// A spinlock that can be shared with interrupt handlers: interrupts are
+/// disabled while it is held, so a handler can not interrupt its owner and
+/// then spin forever.
+pub struct IrqSafeSpinlock<T: ?Sized> {
+    rank: u32,
+    inner: spin::Mutex<T>,
+}
+
+/// Access to the data of a locked `IrqSafeSpinlock`. Interrupts are enabled
+/// again when it is dropped, if they were when it was locked.
+pub struct IrqSafeSpinlockGuard<'a, T: ?Sized> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, T>>,
+    lock: usize,
+    interrupts: bool,
+}
+
+impl<T> IrqSafeSpinlock<T> {
+    /// Creates a new unranked IrqSafeSpinlock.
+    pub const fn new(data: T) -> IrqSafeSpinlock<T> {
+        IrqSafeSpinlock::ranked(data, 0)
+    }
+
+    /// Creates a new IrqSafeSpinlock, with a rank above 0 for the lock order
+    /// checks.
+    pub const fn ranked(data: T, rank: u32) -> IrqSafeSpinlock<T> {
+        IrqSafeSpinlock {
+            rank,
+            inner: spin::Mutex::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSafeSpinlock<T> {
+    /// Disables interrupts then waits for the lock.
+    pub fn lock(&self) -> IrqSafeSpinlockGuard<'_, T> {
+        let interrupts = interrupts::are_enabled();
+        interrupts::disable();
+        lockdep::acquire(self.id(), self.rank, Kind::Spin);
+
+        IrqSafeSpinlockGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            lock: self.id(),
+            interrupts,
+        }
+    }
+
+    /// Takes the lock if nobody holds it.
+    pub fn try_lock(&self) -> Option<IrqSafeSpinlockGuard<'_, T>> {
+        let interrupts = interrupts::are_enabled();
+        interrupts::disable();
+
+        match self.inner.try_lock() {
+            Some(guard) => {
+                lockdep::acquire(self.id(), self.rank, Kind::Spin);
+                Some(IrqSafeSpinlockGuard {
+                    guard: ManuallyDrop::new(guard),
+                    lock: self.id(),
+                    interrupts,
+                })
+            }
+            None => {
+                if interrupts {
+                    interrupts::enable();
+                }
+                None
+            }
+        }
+    }
+
+    /// Returns `true` if someone holds the lock.
+    pub fn is_locked(&self) -> bool {
+        self.inner.try_lock().is_none()
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for IrqSafeSpinlock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "IrqSafeSpinlock {{ data: {:?} }}", &*guard),
+            None => write!(f, "IrqSafeSpinlock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSafeSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSafeSpinlockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSafeSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        // The lock is released before interrupts can come back.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        lockdep::release(self.lock);
+
+        if self.interrupts {
+            interrupts::enable();
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "none"))]
+#[test_case]
+fn spinlock() {
+    let lock = IrqSafeSpinlock::new(1);
+
+    {
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_none());
+    }
+
+    assert_eq!(*lock.try_lock().unwrap(), 2);
+    assert!(!lock.is_locked());
+}
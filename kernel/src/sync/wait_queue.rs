@@ -0,0 +1,130 @@
+//! Threads waiting for something, woken up in order.
+
+use core::sync::atomic;
+
+use super::{lockdep, spinlock::IrqSafeSpinlock};
+use crate::thread::{self, ThreadId, MAX_THREADS};
+
+/// Waiting threads, oldest first.
+#[derive(Debug)]
+struct Waiters {
+    threads: [Option<ThreadId>; MAX_THREADS],
+    len: usize,
+}
+
+/// A queue of threads sleeping until they are notified.
+///
+/// Before threads are initialized, waiting busy-waits.
+#[derive(Debug)]
+pub struct WaitQueue {
+    waiters: IrqSafeSpinlock<Waiters>,
+}
+
+impl WaitQueue {
+    /// Creates a new instance of WaitQueue, without waiters.
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: IrqSafeSpinlock::new(Waiters {
+                threads: [None; MAX_THREADS],
+                len: 0,
+            }),
+        }
+    }
+
+    /// Sleeps until `condition` returns `true`, checking it each time the
+    /// thread is notified.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        while !condition() {
+            let id = match thread::try_current() {
+                Some(id) => id,
+                None => {
+                    atomic::spin_loop_hint();
+                    continue;
+                }
+            };
+
+            // Queued before checking again, so a notification in between
+            // unparks the thread and is not lost.
+            self.push(id);
+            if condition() {
+                self.remove(id);
+                return;
+            }
+
+            lockdep::check_may_sleep();
+            thread::park();
+            self.remove(id);
+        }
+    }
+
+    /// Wakes the oldest waiting thread up, returning `false` if there was none.
+    pub fn notify_one(&self) -> bool {
+        let next = {
+            let mut waiters = self.waiters.lock();
+            let next = waiters.threads[0];
+            if next.is_some() {
+                let len = waiters.len;
+                waiters.threads.copy_within(1..len, 0);
+                waiters.threads[len - 1] = None;
+                waiters.len -= 1;
+            }
+            next
+        };
+
+        match next {
+            Some(id) => {
+                thread::unpark(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wakes every waiting thread up, returning how many there were.
+    pub fn notify_all(&self) -> usize {
+        let mut woken = 0;
+        while self.notify_one() {
+            woken += 1;
+        }
+
+        woken
+    }
+
+    /// Number of waiting threads.
+    pub fn len(&self) -> usize {
+        self.waiters.lock().len
+    }
+
+    /// Returns `true` if no thread waits.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queues a thread, which must then park.
+    pub(super) fn push(&self, id: ThreadId) {
+        let mut waiters = self.waiters.lock();
+        let len = waiters.len;
+        if !waiters.threads[..len].contains(&Some(id)) {
+            // There is a slot per thread.
+            waiters.threads[len] = Some(id);
+            waiters.len += 1;
+        }
+    }
+
+    /// Removes a thread from the queue, if it was not notified.
+    pub(super) fn remove(&self, id: ThreadId) {
+        let mut waiters = self.waiters.lock();
+        let len = waiters.len;
+        if let Some(index) = waiters.threads[..len].iter().position(|&t| t == Some(id)) {
+            waiters.threads.copy_within(index + 1..len, index);
+            waiters.threads[len - 1] = None;
+            waiters.len -= 1;
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> WaitQueue {
+        WaitQueue::new()
+    }
+}
@@ -0,0 +1,427 @@
+//! The system calls themselves, see the table in `syscall`.
+
+use core::{mem::size_of, ptr, time::Duration};
+
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+use super::{
+    user_registers, user_slice, user_slice_mut, user_str, Args, Dirent, Errno, PROT_EXEC,
+    PROT_WRITE, SEEK_CUR, SEEK_END, SEEK_SET, WNOHANG,
+};
+use crate::{
+    fs::{self, console, OpenFile, SeekFrom},
+    mem,
+    process::{
+        self,
+        signal::{self, SigAction, SigSet, Signal},
+        ExitStatus, Pid,
+    },
+    thread,
+    user::USER_END,
+};
+
+/// Most arguments, and most environment variables, `exec` takes.
+const MAX_ARGS: usize = 32;
+
+/// Longest argument or environment variable `exec` takes, in bytes.
+const MAX_ARG_LEN: usize = 4096;
+
+/// `read(fd, buffer, length)`: reads from the file `fd` at its offset, waiting
+/// for input on the console.
+pub fn read(args: &Args) -> Result<u64, Errno> {
+    let (fd, buffer, len) = (args[0], args[1], args[2] as usize);
+    let file = file(fd)?;
+    let buffer = user_slice_mut(buffer, len)?;
+    file.read(buffer).map(|read| read as u64)
+}
+
+/// `write(fd, buffer, length)`: writes to the file `fd` at its offset. The
+/// console replaces invalid UTF-8 sequences.
+pub fn write(args: &Args) -> Result<u64, Errno> {
+    let (fd, buffer, len) = (args[0], args[1], args[2] as usize);
+    let file = file(fd)?;
+    let buffer = user_slice(buffer, len)?;
+    file.write(buffer).map(|written| written as u64)
+}
+
+/// `exit(status)`: ends the calling process, or thread if it is not a
+/// process.
+pub fn exit(args: &Args) -> Result<u64, Errno> {
+    let status = args[0] as i32;
+    match process::current() {
+        Some(pid) => log::info!("Process {} exited with status {}", pid, status),
+        None => log::info!("Thread {} exited with status {}", thread::current(), status),
+    }
+    process::exit(ExitStatus::Exited(status));
+}
+
+/// `yield()`: lets the other threads run.
+pub fn yield_now(_args: &Args) -> Result<u64, Errno> {
+    thread::yield_now();
+    Ok(0)
+}
+
+/// `sleep(milliseconds)`: blocks the calling thread.
+pub fn sleep(args: &Args) -> Result<u64, Errno> {
+    thread::sleep(Duration::from_millis(args[0]));
+    Ok(0)
+}
+
+/// `mmap(address, length, protection)`: maps zeroed pages, at `address` if
+/// not 0, else after the pages it mapped before. Returns the address of the
+/// first page. Nothing stays mapped if it fails.
+pub fn mmap(args: &Args) -> Result<u64, Errno> {
+    let (address, len, protection) = (args[0], args[1], args[2]);
+    if len == 0 || len > USER_END {
+        return Err(Errno::EINVAL);
+    }
+    let size = (len + mem::FRAME_SIZE - 1) & !(mem::FRAME_SIZE - 1);
+
+    let start = match address {
+        0 => process::reserve_user(size).ok_or(Errno::ENOMEM)?.as_u64(),
+        _ if address % mem::FRAME_SIZE != 0 => return Err(Errno::EINVAL),
+        _ if address.checked_add(size).map_or(true, |end| end > USER_END) => {
+            return Err(Errno::EINVAL)
+        }
+        _ => address,
+    };
+
+    let mut flags = PageTableFlags::empty();
+    if protection & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if protection & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let first: Page = Page::containing_address(VirtAddr::new(start));
+    for page in Page::range(first, first + size / mem::FRAME_SIZE) {
+        if process::map_user_page(page, flags).is_err() {
+            for mapped in Page::range(first, page) {
+                let _ = process::unmap_user_page(mapped);
+            }
+            return Err(Errno::ENOMEM);
+        }
+    }
+
+    Ok(start)
+}
+
+/// `getpid()`: identifier of the calling process, or thread if it is not a
+/// process.
+pub fn getpid(_args: &Args) -> Result<u64, Errno> {
+    match process::current() {
+        Some(pid) => Ok(pid.as_u64()),
+        None => Ok(thread::current().as_u64()),
+    }
+}
+
+/// `fork()`: creates a copy of the calling process, sharing its pages
+/// copy-on-write. Returns the id of the child, and 0 in the child.
+pub fn fork(_args: &Args) -> Result<u64, Errno> {
+    // Only processes made the call from user mode, with their registers saved.
+    if process::current().is_none() {
+        return Err(Errno::EPERM);
+    }
+    let registers = unsafe { user_registers() };
+
+    match process::fork(registers) {
+        Ok(pid) => Ok(pid.as_u64()),
+        Err("Out of memory") => Err(Errno::ENOMEM),
+        Err(_) => Err(Errno::EAGAIN),
+    }
+}
+
+/// `exec(executable, length, argv, envp)`: replaces the program of the calling
+/// process with the executable in its memory. `argv` and `envp` are arrays of
+/// pointers to strings, ending with a null pointer, or null if empty. Only
+/// returns if it fails.
+pub fn exec(args: &Args) -> Result<u64, Errno> {
+    if process::current().is_none() {
+        return Err(Errno::EPERM);
+    }
+    let executable = user_slice(args[0], args[1] as usize)?;
+
+    let mut argv = [""; MAX_ARGS];
+    let mut envp = [""; MAX_ARGS];
+    let argc = user_strings(args[2], &mut argv)?;
+    let envc = user_strings(args[3], &mut envp)?;
+    let (argv, envp) = (&argv[..argc], &envp[..envc]);
+
+    let name = argv.first().copied().unwrap_or("?");
+    match process::exec(name, executable, argv, envp) {
+        "Out of memory" => Err(Errno::ENOMEM),
+        _ => Err(Errno::ENOEXEC),
+    }
+}
+
+/// Reads the array of pointers to strings at `addr`, ending with a null
+/// pointer, to `strings`. Returns the number of strings.
+fn user_strings<'a>(addr: u64, strings: &mut [&'a str]) -> Result<usize, Errno> {
+    if addr == 0 {
+        return Ok(0);
+    }
+
+    for (index, string) in strings.iter_mut().enumerate() {
+        let pointer = addr.checked_add(8 * index as u64).ok_or(Errno::EFAULT)?;
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(user_slice(pointer, 8)?);
+        match u64::from_le_bytes(bytes) {
+            0 => return Ok(index),
+            pointer => *string = user_str(pointer, MAX_ARG_LEN)?,
+        }
+    }
+
+    Err(Errno::E2BIG)
+}
+
+/// `waitpid(pid, status, options)`: waits for the child `pid` to exit, or any
+/// child if -1, and stores its status at `status` if not null, see
+/// `ExitStatus::raw`. Returns the id of the child, or 0 with `WNOHANG` if none
+/// has exited yet.
+pub fn waitpid(args: &Args) -> Result<u64, Errno> {
+    let (pid, status, options) = (args[0] as i64, args[1], args[2]);
+    if options & !WNOHANG != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pid = match pid {
+        -1 => None,
+        pid if pid > 0 => Some(Pid::new(pid as u64)),
+        _ => return Err(Errno::EINVAL),
+    };
+    // Checked before a child is removed.
+    let status = match status {
+        0 => None,
+        status => Some(user_slice_mut(status, 4)?),
+    };
+
+    match process::waitpid(pid, options & WNOHANG == 0) {
+        Ok(Some((pid, exit_status))) => {
+            if let Some(status) = status {
+                status.copy_from_slice(&exit_status.raw().to_le_bytes());
+            }
+            Ok(pid.as_u64())
+        }
+        Ok(None) => Ok(0),
+        Err(_) => Err(Errno::ECHILD),
+    }
+}
+
+/// `kill(pid, signal)`: sends `signal` to the process `pid`, or only checks
+/// that it exists if `signal` is 0.
+pub fn kill(args: &Args) -> Result<u64, Errno> {
+    let (pid, signal) = (args[0] as i64, args[1]);
+    if pid <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pid = Pid::new(pid as u64);
+
+    match signal {
+        0 => process::info(pid).map(|_| 0).ok_or(Errno::ESRCH),
+        signal => {
+            let signal = Signal::new(signal).ok_or(Errno::EINVAL)?;
+            signal::kill(pid, signal)
+                .map(|_| 0)
+                .map_err(|_| Errno::ESRCH)
+        }
+    }
+}
+
+/// `sigaction(signal, action, old)`: sets what the calling process does with
+/// `signal` to the `SigAction` at `action`, and stores what it did at `old`.
+/// Either may be null.
+pub fn sigaction(args: &Args) -> Result<u64, Errno> {
+    let signal = Signal::new(args[0]).ok_or(Errno::EINVAL)?;
+    let action = match args[1] {
+        0 => None,
+        action => Some(read_user::<SigAction>(action)?),
+    };
+    let old = user_out::<SigAction>(args[2])?;
+
+    match signal::sigaction(signal, action) {
+        Ok(action) => {
+            write_user(old, &action);
+            Ok(0)
+        }
+        Err("Not a process") => Err(Errno::EPERM),
+        Err(_) => Err(Errno::EINVAL),
+    }
+}
+
+/// `sigprocmask(how, set, old)`: changes the signals the calling process
+/// blocks with the `SigSet` at `set`, and stores those it blocked at `old`.
+/// Either may be null.
+pub fn sigprocmask(args: &Args) -> Result<u64, Errno> {
+    let how = args[0];
+    let set = match args[1] {
+        0 => None,
+        set => Some(read_user::<SigSet>(set)?),
+    };
+    let old = user_out::<SigSet>(args[2])?;
+
+    match signal::sigprocmask(how, set) {
+        Ok(blocked) => {
+            write_user(old, &blocked);
+            Ok(0)
+        }
+        Err("Not a process") => Err(Errno::EPERM),
+        Err(_) => Err(Errno::EINVAL),
+    }
+}
+
+/// `sigreturn()`: called by the restorer of a handler, goes back to where the
+/// signal interrupted the calling process.
+pub fn sigreturn(_args: &Args) -> Result<u64, Errno> {
+    if process::current().is_none() {
+        return Err(Errno::EPERM);
+    }
+    let registers = unsafe { user_registers() };
+    signal::sigreturn(registers);
+}
+
+/// `sigpending(set)`: stores the signals pending for the calling process at
+/// `set`.
+pub fn sigpending(args: &Args) -> Result<u64, Errno> {
+    let set = user_out::<SigSet>(args[0])?.ok_or(Errno::EFAULT)?;
+    let pending = signal::sigpending().map_err(|_| Errno::EPERM)?;
+    write_user(Some(set), &pending);
+    Ok(0)
+}
+
+/// `open(path, flags)`: opens the file at `path` with the `O_*` `flags`, see
+/// `fs::open`. Returns the lowest free descriptor.
+pub fn open(args: &Args) -> Result<u64, Errno> {
+    if process::current().is_none() {
+        return Err(Errno::EPERM);
+    }
+    let path = user_path(args[0])?;
+    let file = fs::open(path, args[1] as u32)?;
+
+    // Closed after the table is unlocked if it is not inserted.
+    process::files(|files| files.insert(file.clone())).unwrap_or(Err(Errno::EPERM))
+}
+
+/// `close(fd)`: frees the descriptor `fd`.
+pub fn close(args: &Args) -> Result<u64, Errno> {
+    let file = process::files(|files| files.remove(args[0])).ok_or(Errno::EBADF)??;
+    drop(file);
+    Ok(0)
+}
+
+/// `lseek(fd, offset, whence)`: moves the offset of the file `fd` to
+/// `offset`, from the start, the current offset or the end for `SEEK_SET`,
+/// `SEEK_CUR` and `SEEK_END`. Returns the new offset.
+pub fn lseek(args: &Args) -> Result<u64, Errno> {
+    let (fd, offset, whence) = (args[0], args[1], args[2]);
+    let pos = match whence {
+        SEEK_SET if (offset as i64) >= 0 => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::EINVAL),
+    };
+    file(fd)?.seek(pos)
+}
+
+/// `stat(path, stat)`: stores what `fs::stat` tells of the file at `path` at
+/// `stat`.
+pub fn stat(args: &Args) -> Result<u64, Errno> {
+    let path = user_path(args[0])?;
+    let out = user_out::<fs::Stat>(args[1])?.ok_or(Errno::EFAULT)?;
+    write_user(Some(out), &fs::stat(path)?);
+    Ok(0)
+}
+
+/// `fstat(fd, stat)`: stores what `stat` tells of the file `fd` at `stat`.
+pub fn fstat(args: &Args) -> Result<u64, Errno> {
+    let file = file(args[0])?;
+    let out = user_out::<fs::Stat>(args[1])?.ok_or(Errno::EFAULT)?;
+    write_user(Some(out), &file.stat()?);
+    Ok(0)
+}
+
+/// `readdir(fd, dirent)`: stores the next entry of the directory `fd` at
+/// `dirent`. Returns 1, or 0 past the last entry.
+pub fn readdir(args: &Args) -> Result<u64, Errno> {
+    let file = file(args[0])?;
+    let out = user_out::<Dirent>(args[1])?.ok_or(Errno::EFAULT)?;
+
+    match file.readdir()? {
+        Some(entry) => {
+            let mut dirent = Dirent {
+                ino: entry.ino,
+                kind: entry.kind as u32,
+                name_len: entry.name.len() as u32,
+                name: [0; fs::NAME_MAX + 1],
+            };
+            dirent.name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+            write_user(Some(out), &dirent);
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
+
+/// `mkdir(path)`: creates the directory `path`.
+pub fn mkdir(args: &Args) -> Result<u64, Errno> {
+    fs::mkdir(user_path(args[0])?).map(|_| 0)
+}
+
+/// `unlink(path)`: removes the file at `path`, that is not a directory.
+pub fn unlink(args: &Args) -> Result<u64, Errno> {
+    fs::unlink(user_path(args[0])?).map(|_| 0)
+}
+
+/// `rmdir(path)`: removes the empty directory `path`.
+pub fn rmdir(args: &Args) -> Result<u64, Errno> {
+    fs::rmdir(user_path(args[0])?).map(|_| 0)
+}
+
+/// `truncate(path, length)`: cuts or grows the file at `path` to `length`
+/// bytes.
+pub fn truncate(args: &Args) -> Result<u64, Errno> {
+    fs::truncate(user_path(args[0])?, args[1]).map(|_| 0)
+}
+
+/// `ioctl(fd, request, arg)`: runs the device specific `request` on the file
+/// `fd`, with `arg`. Returns its result.
+pub fn ioctl(args: &Args) -> Result<u64, Errno> {
+    file(args[0])?.ioctl(args[1], args[2])
+}
+
+/// The open file `fd` of the calling process, or the standard one on the
+/// console if it is not a process.
+fn file(fd: u64) -> Result<OpenFile, Errno> {
+    process::files(|files| files.get(fd)).unwrap_or_else(|| console::standard(fd))
+}
+
+/// The path at `addr`, ending with a NUL byte.
+fn user_path<'a>(addr: u64) -> Result<&'a str, Errno> {
+    match user_str(addr, fs::PATH_MAX + 1) {
+        Err(Errno::E2BIG) => Err(Errno::ENAMETOOLONG),
+        path => path,
+    }
+}
+
+/// Reads a `T` from user memory at `addr`. Any bytes must be valid for `T`.
+fn read_user<T: Copy>(addr: u64) -> Result<T, Errno> {
+    let bytes = user_slice(addr, size_of::<T>())?;
+    Ok(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Where a `T` is stored in user memory at `addr`, if not null.
+fn user_out<'a, T>(addr: u64) -> Result<Option<&'a mut [u8]>, Errno> {
+    match addr {
+        0 => Ok(None),
+        addr => user_slice_mut(addr, size_of::<T>()).map(Some),
+    }
+}
+
+/// Stores `value` at `out`, from `user_out`, if not null.
+fn write_user<T: Copy>(out: Option<&mut [u8]>, value: &T) {
+    if let Some(out) = out {
+        unsafe { ptr::write_unaligned(out.as_mut_ptr() as *mut T, *value) };
+    }
+}
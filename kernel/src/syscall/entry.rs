@@ -0,0 +1,290 @@
+//! Entry points of system calls: they save the registers of user code on the
+//! kernel stack, call `dispatch`, then go back to user code.
+//!
+//! The exceptions user code can catch as signals, and the timer interrupt, go
+//! through entry points that save the registers the same way, so signals can
+//! be delivered before going back to user mode.
+
+use core::mem;
+
+use x86_64::{instructions::interrupts, VirtAddr};
+
+use crate::{
+    init::{gdt, idt},
+    process::signal,
+};
+
+/// Registers of user code, saved by the entry points at the top of the kernel
+/// stack. The arguments and the result of the system call are taken from and
+/// given back through them, and user code resumes where they say.
+///
+/// The last fields are laid out like the frame of `iretq`. After `syscall`,
+/// `rcx` and `r11` hold the instruction pointer and the flags too, and `cs`
+/// and `ss` are 0, `sysretq` loading them from STAR.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Top of the stack `syscall_entry` switches to, the same one as interrupts
+/// from user mode.
+static mut KERNEL_STACK: u64 = 0;
+
+/// Stack pointer of user code, until `syscall_entry` saves it on the kernel
+/// stack.
+static mut USER_STACK: u64 = 0;
+
+/// Changes the stack `syscall_entry` switches to. Only `gdt` calls it, along
+/// with the stack of the TSS.
+///
+/// This function is unsafe for the same reasons as `gdt::set_kernel_stack`.
+pub(crate) unsafe fn set_kernel_stack(top: VirtAddr) {
+    KERNEL_STACK = top.as_u64();
+}
+
+/// Where `syscall` jumps to, with `rcx` holding the instruction pointer and
+/// `r11` the RFLAGS of user code.
+///
+/// Interrupts are masked by SFMASK until the kernel stack is switched to, and
+/// disabled again by `handler` before it is switched back, so the user stack
+/// is never used in ring 0. `rcx` must stay canonical, or `sysretq` faults in
+/// ring 0.
+#[naked]
+pub unsafe extern "C" fn syscall_entry() {
+    asm!(
+        "mov [rip + {user_stack}], rsp",
+        "mov rsp, [rip + {kernel_stack}]",
+        "and rsp, -16",
+        "push 0",
+        "push qword ptr [rip + {user_stack}]",
+        "push r11",
+        "push 0",
+        "push rcx",
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "call {handler}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "pop rcx",
+        "add rsp, 8",
+        "pop r11",
+        "pop rsp",
+        "sysretq",
+        user_stack = sym USER_STACK,
+        kernel_stack = sym KERNEL_STACK,
+        handler = sym handler,
+        options(noreturn)
+    );
+}
+
+/// Handler of `int 0x80`, the legacy way to make system calls, with the same
+/// registers as `syscall`. The CPU already switched to the kernel stack.
+#[naked]
+pub unsafe extern "C" fn int80_entry() {
+    asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "call {handler}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "iretq",
+        handler = sym int80_handler,
+        options(noreturn)
+    );
+}
+
+/// Counts the interrupt of `int80_entry`, then handles its system call.
+extern "C" fn int80_handler(registers: &mut Registers) {
+    idt::count(idt::SYSCALL_INTERRUPT_ID);
+    handler(registers);
+}
+
+/// Defines an entry point saving the registers of the interrupted code as
+/// `Registers`, that calls `$handler` with them and the error code of the
+/// exception, 0 if it has none.
+macro_rules! interrupt_entry {
+    ($(#[$attr:meta])* $name:ident, $handler:path, error_code) => {
+        interrupt_entry!(@entry $(#[$attr])* $name, $handler, "xchg rax, [rsp]");
+    };
+    ($(#[$attr:meta])* $name:ident, $handler:path) => {
+        interrupt_entry!(@entry $(#[$attr])* $name, $handler, "push rax", "xor eax, eax");
+    };
+    (@entry $(#[$attr:meta])* $name:ident, $handler:path, $($prologue:literal),+) => {
+        $(#[$attr])*
+        #[naked]
+        pub unsafe extern "C" fn $name() {
+            // The prologue leaves the error code in `rax` and `rax` in its
+            // place, below the frame of the CPU, aligned on 16 bytes.
+            asm!(
+                $($prologue,)+
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rsi, rax",
+                "mov rdi, rsp",
+                "call {handler}",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "iretq",
+                handler = sym $handler,
+                options(noreturn)
+            );
+        }
+    };
+}
+
+interrupt_entry!(
+    /// Entry point of the divide error exception.
+    divide_error_entry,
+    idt::divide_error_handler
+);
+interrupt_entry!(
+    /// Entry point of the invalid opcode exception.
+    invalid_opcode_entry,
+    idt::invalid_opcode_handler
+);
+interrupt_entry!(
+    /// Entry point of the general protection fault.
+    protection_fault_entry,
+    idt::protection_fault_handler,
+    error_code
+);
+interrupt_entry!(
+    /// Entry point of the page fault.
+    page_fault_entry,
+    idt::page_fault_handler,
+    error_code
+);
+interrupt_entry!(
+    /// Entry point of the timer interrupt.
+    timer_interrupt_entry,
+    idt::timer_interrupt_handler
+);
+
+/// Registers saved by the system call the running thread is in.
+///
+/// This function is unsafe because the caller must guarantee that the thread
+/// entered the kernel with a system call from user mode, and that no other
+/// reference to the registers is alive.
+pub unsafe fn user_registers() -> &'static mut Registers {
+    // Both entry points align the stack before saving the registers.
+    let top = gdt::kernel_stack().as_u64() & !0xF;
+    &mut *((top - mem::size_of::<Registers>() as u64) as *mut Registers)
+}
+
+/// Runs the system call with interrupts enabled, as it may sleep, then
+/// delivers the pending signals.
+extern "C" fn handler(registers: &mut Registers) {
+    interrupts::enable();
+
+    let args = [
+        registers.rdi,
+        registers.rsi,
+        registers.rdx,
+        registers.r10,
+        registers.r8,
+        registers.r9,
+    ];
+    registers.rax = super::dispatch(registers.rax, &args);
+    signal::deliver(registers);
+
+    interrupts::disable();
+}
@@ -0,0 +1,234 @@
+//! # System calls
+//!
+//! User code asks the kernel for services with the `syscall` instruction, or
+//! the slower `int 0x80`. The number of the call goes in `rax`, its arguments
+//! in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`, and the result comes back in
+//! `rax`. An error is returned as a negated `Errno`.
+//!
+//! | Number | Call          | Arguments                      | Result               |
+//! |--------|---------------|--------------------------------|----------------------|
+//! | 0      | `read`        | fd, buffer, length             | bytes read           |
+//! | 1      | `write`       | fd, buffer, length             | bytes written        |
+//! | 2      | `exit`        | status                         | does not return      |
+//! | 3      | `yield`       |                                | 0                    |
+//! | 4      | `sleep`       | milliseconds                   | 0                    |
+//! | 5      | `mmap`        | address, length, `PROT_*`      | address of the pages |
+//! | 6      | `getpid`      |                                | id of the process    |
+//! | 7      | `fork`        |                                | child id, 0 in child |
+//! | 8      | `exec`        | executable, length, argv, envp | does not return      |
+//! | 9      | `waitpid`     | pid or -1, status, `WNOHANG`   | id of the child      |
+//! | 10     | `kill`        | pid, signal                    | 0                    |
+//! | 11     | `sigaction`   | signal, action, old action     | 0                    |
+//! | 12     | `sigprocmask` | `SIG_*`, set, old set          | 0                    |
+//! | 13     | `sigreturn`   |                                | does not return      |
+//! | 14     | `sigpending`  | set                            | 0                    |
+//! | 15     | `open`        | path, `O_*`                    | file descriptor      |
+//! | 16     | `close`       | fd                             | 0                    |
+//! | 17     | `lseek`       | fd, offset, `SEEK_*`           | new offset           |
+//! | 18     | `stat`        | path, stat                     | 0                    |
+//! | 19     | `fstat`       | fd, stat                       | 0                    |
+//! | 20     | `readdir`     | fd, dirent                     | 1, 0 past the last   |
+//! | 21     | `mkdir`       | path                           | 0                    |
+//! | 22     | `unlink`      | path                           | 0                    |
+//! | 23     | `rmdir`       | path                           | 0                    |
+//! | 24     | `truncate`    | path, length                   | 0                    |
+//! | 25     | `ioctl`       | fd, request, argument          | result of request    |
+//!
+//! Files are reached through the descriptors of the calling process, see
+//! `fs`. The standard input reads from `SERIAL1` and the keyboard, the
+//! standard output and error write to the console, also for kernel threads.
+//! Paths end with a NUL byte, `stat` stores an `fs::Stat` and `readdir` a
+//! `Dirent`. The requests of `ioctl` are those of the devices, see
+//! `fs::devfs`. Pointers from user code are checked before the kernel touches
+//! them, see `user_slice`. Signals are delivered when a call returns, see
+//! `process::signal` for the layout of the actions and sets.
+//!
+//! # Examples
+//! ```no_run
+//! // write(1, "Hello", 5), from user mode
+//! asm!("syscall", inout("rax") syscall::WRITE => written, in("rdi") 1,
+//!      in("rsi") message.as_ptr(), in("rdx") 5, out("rcx") _, out("r11") _);
+//! ```
+
+mod calls;
+pub(crate) mod entry;
+mod uaccess;
+
+pub use self::{
+    entry::{int80_entry, syscall_entry, user_registers, Registers},
+    uaccess::{user_slice, user_slice_mut, user_str},
+};
+use crate::fs;
+
+pub const READ: u64 = 0;
+pub const WRITE: u64 = 1;
+pub const EXIT: u64 = 2;
+pub const YIELD: u64 = 3;
+pub const SLEEP: u64 = 4;
+pub const MMAP: u64 = 5;
+pub const GETPID: u64 = 6;
+pub const FORK: u64 = 7;
+pub const EXEC: u64 = 8;
+pub const WAITPID: u64 = 9;
+pub const KILL: u64 = 10;
+pub const SIGACTION: u64 = 11;
+pub const SIGPROCMASK: u64 = 12;
+pub const SIGRETURN: u64 = 13;
+pub const SIGPENDING: u64 = 14;
+pub const OPEN: u64 = 15;
+pub const CLOSE: u64 = 16;
+pub const LSEEK: u64 = 17;
+pub const STAT: u64 = 18;
+pub const FSTAT: u64 = 19;
+pub const READDIR: u64 = 20;
+pub const MKDIR: u64 = 21;
+pub const UNLINK: u64 = 22;
+pub const RMDIR: u64 = 23;
+pub const TRUNCATE: u64 = 24;
+pub const IOCTL: u64 = 25;
+
+/// Pages mapped by `mmap` can be read.
+pub const PROT_READ: u64 = 1;
+/// Pages mapped by `mmap` can be written.
+pub const PROT_WRITE: u64 = 2;
+/// Pages mapped by `mmap` can be executed.
+pub const PROT_EXEC: u64 = 4;
+
+/// `waitpid` returns 0 at once if no child has exited.
+pub const WNOHANG: u64 = 1;
+
+/// `lseek` moves to the offset it is given.
+pub const SEEK_SET: u64 = 0;
+/// `lseek` moves the offset by what it is given.
+pub const SEEK_CUR: u64 = 1;
+/// `lseek` moves to the end of the file, plus what it is given.
+pub const SEEK_END: u64 = 2;
+
+/// Entry of a directory, as `readdir` stores it.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Dirent {
+    pub ino: u64,
+    /// An `fs::FileType`.
+    pub kind: u32,
+    /// Bytes of the name.
+    pub name_len: u32,
+    /// The name, ending with a NUL byte.
+    pub name: [u8; fs::NAME_MAX + 1],
+}
+
+/// Arguments of a system call.
+pub type Args = [u64; 6];
+
+type Call = fn(&Args) -> Result<u64, Errno>;
+
+/// System calls, by number.
+static CALLS: [Call; 26] = [
+    calls::read,
+    calls::write,
+    calls::exit,
+    calls::yield_now,
+    calls::sleep,
+    calls::mmap,
+    calls::getpid,
+    calls::fork,
+    calls::exec,
+    calls::waitpid,
+    calls::kill,
+    calls::sigaction,
+    calls::sigprocmask,
+    calls::sigreturn,
+    calls::sigpending,
+    calls::open,
+    calls::close,
+    calls::lseek,
+    calls::stat,
+    calls::fstat,
+    calls::readdir,
+    calls::mkdir,
+    calls::unlink,
+    calls::rmdir,
+    calls::truncate,
+    calls::ioctl,
+];
+
+/// Errors of system calls, numbered as on Linux.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Errno {
+    /// Operation not permitted.
+    EPERM = 1,
+    /// No such file or directory.
+    ENOENT = 2,
+    /// No such process.
+    ESRCH = 3,
+    /// Argument list too long.
+    E2BIG = 7,
+    /// Exec format error.
+    ENOEXEC = 8,
+    /// Bad file descriptor.
+    EBADF = 9,
+    /// No child processes.
+    ECHILD = 10,
+    /// Resource temporarily unavailable.
+    EAGAIN = 11,
+    /// Out of memory.
+    ENOMEM = 12,
+    /// Bad address.
+    EFAULT = 14,
+    /// Device or resource busy.
+    EBUSY = 16,
+    /// File exists.
+    EEXIST = 17,
+    /// Not a directory.
+    ENOTDIR = 20,
+    /// Is a directory.
+    EISDIR = 21,
+    /// Invalid argument.
+    EINVAL = 22,
+    /// Too many open files.
+    EMFILE = 24,
+    /// Inappropriate ioctl for device.
+    ENOTTY = 25,
+    /// File too large.
+    EFBIG = 27,
+    /// No space left on device.
+    ENOSPC = 28,
+    /// Illegal seek.
+    ESPIPE = 29,
+    /// File name too long.
+    ENAMETOOLONG = 36,
+    /// No such system call.
+    ENOSYS = 38,
+    /// Directory not empty.
+    ENOTEMPTY = 39,
+    /// Too many levels of symbolic links.
+    ELOOP = 40,
+}
+
+impl Errno {
+    /// Value returned in `rax` for the error.
+    pub fn raw(self) -> u64 {
+        (self as u64).wrapping_neg()
+    }
+}
+
+/// Runs the system call `number`, returning what goes back in `rax`.
+pub fn dispatch(number: u64, args: &Args) -> u64 {
+    let result = match CALLS.get(number as usize) {
+        Some(call) => call(args),
+        None => Err(Errno::ENOSYS),
+    };
+
+    match result {
+        Ok(value) => value,
+        Err(errno) => errno.raw(),
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn unknown_call() {
+    assert_eq!(dispatch(CALLS.len() as u64, &[0; 6]), Errno::ENOSYS.raw());
+    assert_eq!(dispatch(u64::MAX, &[0; 6]) as i64, -38);
+}
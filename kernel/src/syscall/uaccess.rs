@@ -0,0 +1,97 @@
+//! Checks of the pointers user code passes to system calls.
+
+use core::{slice, str};
+
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+use super::Errno;
+use crate::{mem, user::USER_END};
+
+/// The `len` bytes at `addr`, if user mode can read all of them.
+///
+/// They stay valid as long as the pages are mapped.
+pub fn user_slice<'a>(addr: u64, len: usize) -> Result<&'a [u8], Errno> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    check(addr, len, false)?;
+
+    Ok(unsafe { slice::from_raw_parts(addr as *const u8, len) })
+}
+
+/// The `len` bytes at `addr`, if user mode can write all of them.
+///
+/// They stay valid as long as the pages are mapped.
+pub fn user_slice_mut<'a>(addr: u64, len: usize) -> Result<&'a mut [u8], Errno> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+    check(addr, len, true)?;
+
+    Ok(unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) })
+}
+
+/// The string at `addr`, ending with a NUL byte within `max` bytes, if user
+/// mode can read it.
+pub fn user_str<'a>(addr: u64, max: usize) -> Result<&'a str, Errno> {
+    let mut len = 0;
+    loop {
+        if len == max {
+            return Err(Errno::E2BIG);
+        }
+        let start = addr.checked_add(len as u64).ok_or(Errno::EFAULT)?;
+        let chunk = (mem::FRAME_SIZE - start % mem::FRAME_SIZE).min((max - len) as u64);
+        let bytes = user_slice(start, chunk as usize)?;
+        match bytes.iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                len += end;
+                break;
+            }
+            None => len += bytes.len(),
+        }
+    }
+
+    str::from_utf8(user_slice(addr, len)?).map_err(|_| Errno::EINVAL)
+}
+
+/// Checks that every page of the range is in the user half of the address
+/// space and mapped for user mode, writable if `write`. Pages shared
+/// copy-on-write are copied first.
+fn check(addr: u64, len: usize, write: bool) -> Result<(), Errno> {
+    let end = addr.checked_add(len as u64).ok_or(Errno::EFAULT)?;
+    if end > USER_END {
+        return Err(Errno::EFAULT);
+    }
+
+    let first: Page = Page::containing_address(VirtAddr::new(addr));
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        let flags = mem::user_flags(page.start_address()).ok_or(Errno::EFAULT)?;
+        if write
+            && !flags.contains(PageTableFlags::WRITABLE)
+            && mem::copy_on_write(page.start_address()) != Ok(true)
+        {
+            return Err(Errno::EFAULT);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "none"))]
+#[test_case]
+fn bad_pointers() {
+    // Kernel half, overflowing and unmapped ranges
+    assert_eq!(user_slice(0xffff_8000_0000_0000, 1), Err(Errno::EFAULT));
+    assert_eq!(user_slice(u64::MAX - 1, 4).err(), Some(Errno::EFAULT));
+    assert_eq!(user_slice_mut(USER_END - 1, 2).err(), Some(Errno::EFAULT));
+    assert_eq!(user_slice(0, 1), Err(Errno::EFAULT));
+
+    assert_eq!(user_str(0, 16), Err(Errno::EFAULT));
+
+    // Nothing to check
+    assert_eq!(user_slice(0, 0), Ok(&[][..]));
+}
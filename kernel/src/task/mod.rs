@@ -0,0 +1,229 @@
+//! # Async tasks
+//!
+//! An alternative to threads: tasks are futures polled by an `Executor`, and
+//! give the CPU away at each `.await` that is not ready. Interrupt handlers
+//! wake the tasks waiting on them, through `Waker`s that only set a bit, so
+//! waking never locks nor allocates.
+//!
+//! When no task is ready the executor halts the CPU until the next interrupt.
+//!
+//! Tasks are borrowed, not owned, by the executor: pin them first, on the
+//! stack with `pin_mut!` for example. Only one executor may exist at a time.
+//!
+//! # Examples
+//! ```no_run
+//! async fn echo() {
+//!     let mut keyboard = KeyStream::new();
+//!     loop {
+//!         let event = keyboard.next_key().await;
+//!         kprint!("{:?}", event.key);
+//!     }
+//! }
+//!
+//! async fn ticker() {
+//!     loop {
+//!         task::sleep(Duration::from_secs(1)).await;
+//!         kprintln!("tick");
+//!     }
+//! }
+//!
+//! let (echo, ticker) = (echo(), ticker());
+//! pin_mut!(echo, ticker);
+//!
+//! let mut executor = Executor::new().unwrap();
+//! executor.spawn(echo).unwrap();
+//! executor.spawn(ticker).unwrap();
+//! executor.run();
+//! ```
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::{Context, RawWaker, RawWakerVTable, Waker},
+};
+
+use x86_64::instructions::interrupts;
+
+mod sleep;
+
+pub use self::sleep::{sleep, wake_timers, Sleep};
+
+/// Maximum number of tasks of the executor.
+pub const MAX_TASKS: usize = 32;
+
+/// Tasks woken and not polled yet, 1 bit per slot.
+static WOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// Set while an executor exists.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Identifier of a task, its slot in the executor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(usize);
+
+/// A task: a pinned future that runs until it is done.
+pub type Task<'a> = Pin<&'a mut dyn Future<Output = ()>>;
+
+/// Polls the tasks as they are woken, round robin.
+pub struct Executor<'a> {
+    tasks: [Option<Task<'a>>; MAX_TASKS],
+    /// Slot after the last one polled, where the next round starts.
+    next: usize,
+}
+
+impl<'a> Executor<'a> {
+    /// Creates a new instance of Executor, without tasks.
+    pub fn new() -> Result<Executor<'a>, &'static str> {
+        if ACTIVE.swap(true, Ordering::SeqCst) {
+            return Err("Only one executor may exist at a time");
+        }
+        WOKEN.store(0, Ordering::SeqCst);
+
+        Ok(Executor {
+            tasks: Default::default(),
+            next: 0,
+        })
+    }
+
+    /// Adds a task, to be polled once the executor runs.
+    pub fn spawn(&mut self, task: Task<'a>) -> Result<TaskId, &'static str> {
+        let slot = self
+            .tasks
+            .iter()
+            .position(Option::is_none)
+            .ok_or("Too many tasks")?;
+        self.tasks[slot] = Some(task);
+        wake(slot);
+
+        Ok(TaskId(slot))
+    }
+
+    /// Returns `true` if no task is left.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.iter().all(Option::is_none)
+    }
+
+    /// Polls the woken tasks once each, returning the number of tasks polled.
+    pub fn run_ready(&mut self) -> usize {
+        let woken = WOKEN.swap(0, Ordering::SeqCst);
+        let start = self.next;
+        let mut polled = 0;
+
+        for offset in 0..MAX_TASKS {
+            let slot = (start + offset) % MAX_TASKS;
+            if woken & (1 << slot) == 0 {
+                continue;
+            }
+
+            // Wakers outlive their task, the slot may be empty or reused.
+            if let Some(task) = self.tasks[slot].as_mut() {
+                let waker = waker(slot);
+                let mut cx = Context::from_waker(&waker);
+                if task.as_mut().poll(&mut cx).is_ready() {
+                    self.tasks[slot] = None;
+                }
+                polled += 1;
+                self.next = slot + 1;
+            }
+        }
+
+        polled
+    }
+
+    /// Runs the tasks until they are all done, halting the CPU while none is
+    /// woken.
+    pub fn run(&mut self) {
+        while !self.is_empty() {
+            self.run_ready();
+
+            // A wakeup between the check and the halt would be lost until
+            // the next interrupt, so interrupts are only enabled by the halt.
+            interrupts::disable();
+            if WOKEN.load(Ordering::SeqCst) == 0 && !self.is_empty() {
+                interrupts::enable_interrupts_and_hlt();
+            } else {
+                interrupts::enable();
+            }
+        }
+    }
+}
+
+impl Drop for Executor<'_> {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Marks the task in `slot` as woken.
+fn wake(slot: usize) {
+    WOKEN.fetch_or(1 << slot, Ordering::SeqCst);
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake_raw, wake_raw, drop_raw);
+
+/// A waker of the task in `slot`, which only carries the slot.
+fn waker(slot: usize) -> Waker {
+    unsafe { Waker::from_raw(RawWaker::new(slot as *const (), &VTABLE)) }
+}
+
+unsafe fn clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn wake_raw(data: *const ()) {
+    wake(data as usize);
+}
+
+unsafe fn drop_raw(_data: *const ()) {}
+
+#[cfg(test)]
+#[test_case]
+fn polling() {
+    use core::{sync::atomic::AtomicUsize, task::Poll};
+
+    /// Pending the given number of polls, then ready.
+    struct Countdown<'a>(usize, &'a AtomicUsize);
+
+    impl Future for Countdown<'_> {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            match self.0 {
+                0 => Poll::Ready(()),
+                _ => {
+                    self.0 -= 1;
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    let (first_polls, second_polls) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let mut first = Countdown(0, &first_polls);
+    let mut second = Countdown(1, &second_polls);
+    let (first, second) = (Pin::new(&mut first), Pin::new(&mut second));
+
+    let mut executor = Executor::new().unwrap();
+    assert!(Executor::new().is_err());
+    executor.spawn(first).unwrap();
+    let id = executor.spawn(second).unwrap();
+
+    // Every new task is polled once, then only the woken ones
+    assert_eq!(executor.run_ready(), 2);
+    assert_eq!(executor.run_ready(), 0);
+    waker(id.0).wake();
+    waker(id.0).wake_by_ref();
+    assert_eq!(executor.run_ready(), 1);
+    assert!(executor.is_empty());
+
+    // Stale wakers do nothing
+    waker(id.0).wake();
+    assert_eq!(executor.run_ready(), 0);
+    assert_eq!(first_polls.load(Ordering::SeqCst), 1);
+    assert_eq!(second_polls.load(Ordering::SeqCst), 2);
+
+    drop(executor);
+    assert!(Executor::new().is_ok());
+}
@@ -0,0 +1,153 @@
+//! Futures waiting for the timer.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::time;
+
+/// Maximum number of `Sleep`s waiting at the same time. Past it, they poll
+/// the time instead of waiting for the timer.
+pub const MAX_TIMERS: usize = 32;
+
+#[derive(Debug)]
+struct Timer {
+    /// Tick at which the timer expires.
+    deadline: u64,
+    /// Waker of the task, until the timer expires.
+    waker: Option<Waker>,
+}
+
+lazy_static! {
+    /// Timers, each owned by a `Sleep` until it is dropped.
+    ///
+    /// Locked with interrupts disabled, as the timer interrupt handler
+    /// locks it too.
+    static ref TIMERS: Mutex<[Option<Timer>; MAX_TIMERS]> = Mutex::new(Default::default());
+}
+
+/// Earliest deadline of the timers with a waker, `u64::MAX` if none.
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Wakes the tasks whose timer expired. Only the timer interrupt handler calls
+/// it, after `time::tick`.
+pub fn wake_timers() {
+    let now = time::ticks();
+    if now < NEXT_DEADLINE.load(Ordering::SeqCst) {
+        return;
+    }
+
+    // A task is registering a timer, the expired ones are woken next tick.
+    let mut timers = match TIMERS.try_lock() {
+        Some(timers) => timers,
+        None => return,
+    };
+
+    let mut next = u64::MAX;
+    for timer in timers.iter_mut().flatten() {
+        if timer.deadline <= now {
+            if let Some(waker) = timer.waker.take() {
+                waker.wake();
+            }
+        } else if timer.waker.is_some() {
+            next = next.min(timer.deadline);
+        }
+    }
+    NEXT_DEADLINE.store(next, Ordering::SeqCst);
+}
+
+/// Waits for `duration`, with the precision of one tick.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: time::ticks() + time::duration_to_ticks(duration),
+        timer: None,
+    }
+}
+
+/// Future returned by `sleep`.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: u64,
+    /// Index of its timer in `TIMERS`, once it has one.
+    timer: Option<usize>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if time::ticks() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let index = interrupts::without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            let index = self
+                .timer
+                .or_else(|| timers.iter().position(Option::is_none))?;
+
+            timers[index] = Some(Timer {
+                deadline,
+                waker: Some(cx.waker().clone()),
+            });
+            NEXT_DEADLINE.fetch_min(deadline, Ordering::SeqCst);
+
+            Some(index)
+        });
+
+        match index {
+            Some(index) => self.timer = Some(index),
+            // No timer left, polls again as soon as possible.
+            None => cx.waker().wake_by_ref(),
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(index) = self.timer {
+            interrupts::without_interrupts(|| TIMERS.lock()[index] = None);
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "none"))]
+#[test_case]
+fn sleeping() {
+    use super::Executor;
+
+    // The real timer must not tick meanwhile.
+    interrupts::without_interrupts(|| {
+        let mut nap = sleep(time::ticks_to_duration(3));
+        let pinned = Pin::new(&mut nap);
+        let mut executor = Executor::new().unwrap();
+        executor.spawn(pinned).unwrap();
+
+        // Woken by the timer only once the deadline is reached
+        assert_eq!(executor.run_ready(), 1);
+        for _ in 0..2 {
+            time::tick();
+            wake_timers();
+            assert_eq!(executor.run_ready(), 0);
+        }
+        time::tick();
+        wake_timers();
+        assert_eq!(executor.run_ready(), 1);
+        assert!(executor.is_empty());
+
+        drop(executor);
+        drop(nap);
+        assert!(TIMERS.lock().iter().all(Option::is_none));
+    });
+}
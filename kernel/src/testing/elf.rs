@@ -0,0 +1,36 @@
+//! Executables for the tests, built from their program headers.
+
+use crate::elf::{
+    ProgramHeader, CLASS_64, DATA_LITTLE_ENDIAN, EM_X86_64, ET_EXEC, HEADER_SIZE, MAGIC,
+    PROGRAM_HEADER_SIZE, VERSION_CURRENT,
+};
+
+/// Writes at the start of `file` the header of an x86_64 executable entered at
+/// `entry`, then `headers`. The rest of the file is left as it is.
+pub fn build(file: &mut [u8], entry: u64, headers: &[ProgramHeader]) {
+    file[..4].copy_from_slice(&MAGIC);
+    file[4] = CLASS_64;
+    file[5] = DATA_LITTLE_ENDIAN;
+    file[6] = VERSION_CURRENT;
+    file[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+    file[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+    file[20..24].copy_from_slice(&u32::from(VERSION_CURRENT).to_le_bytes());
+    file[24..32].copy_from_slice(&entry.to_le_bytes());
+    file[32..40].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    file[52..54].copy_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    file[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    file[56..58].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+
+    for (index, header) in headers.iter().enumerate() {
+        let start = HEADER_SIZE + index * PROGRAM_HEADER_SIZE;
+        let fields = &mut file[start..start + PROGRAM_HEADER_SIZE];
+        fields[0..4].copy_from_slice(&header.kind.to_le_bytes());
+        fields[4..8].copy_from_slice(&header.flags.to_le_bytes());
+        fields[8..16].copy_from_slice(&header.offset.to_le_bytes());
+        fields[16..24].copy_from_slice(&header.vaddr.to_le_bytes());
+        fields[24..32].copy_from_slice(&header.vaddr.to_le_bytes());
+        fields[32..40].copy_from_slice(&header.file_size.to_le_bytes());
+        fields[40..48].copy_from_slice(&header.mem_size.to_le_bytes());
+        fields[48..56].copy_from_slice(&header.align.to_le_bytes());
+    }
+}
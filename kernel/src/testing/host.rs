@@ -0,0 +1,36 @@
+//! Runner of the unit tests on the host, where panics unwind.
+
+use std::{panic, println, process};
+
+use super::Testable;
+
+/// Runs the tests, printing the results in the TAP format, then exits with
+/// failure if any test failed.
+pub fn test_runner(tests: &'static [&'static dyn Testable]) {
+    println!("TAP version 13");
+    println!("1..{}", tests.len());
+
+    let mut failed = 0;
+    for (index, test) in tests.iter().enumerate() {
+        // The panic message is printed by the default hook.
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| test.run())).is_err();
+
+        if panicked == test.should_panic() {
+            println!("ok {} - {}", index + 1, test.name());
+        } else {
+            failed += 1;
+            println!("not ok {} - {}", index + 1, test.name());
+            if !panicked {
+                println!("# did not panic");
+            }
+        }
+    }
+
+    println!("# tests {}", tests.len());
+    println!("# pass {}", tests.len() - failed);
+    println!("# fail {}", failed);
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
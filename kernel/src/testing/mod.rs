@@ -0,0 +1,333 @@
+//! # Kernel test framework
+//!
+//! Runner for the `#[test_case]`s of the kernel and of the integration tests.
+//!
+//! Tests are named after their path. Every test runs with a timeout enforced
+//! by the timer interrupt, and a failed test (panic or timeout) is reported
+//! before going on with the next one. Results are printed on screen and, in
+//! the TAP format, on `SERIAL1`, with the summary after the last test. QEMU
+//! exits with success only if every test passed.
+//!
+//! Panics do not unwind: the tests after a panic run from the panic handler,
+//! on top of the stack of the test that panicked, and the locks it held stay
+//! taken. A test must not panic while holding a lock shared with other tests.
+//! The deadlock checks of `sync` forget them before each test.
+//!
+//! The unit tests of the kernel library also run on the host, with
+//! `cargo test --lib`, so they must not touch the hardware: drivers are tested
+//! against the mocks of `io`. Timeouts are not enforced there. Tests that
+//! mask interrupts, even through a lock, are kernel only:
+//! `#[cfg(all(test, target_os = "none"))]`.
+//!
+//! The executables the tests run are built with `elf::build`.
+//!
+//! # Examples
+//! ```no_run
+//! // A plain test
+//! #[test_case]
+//! fn creation() {
+//!     assert_eq!(1 + 1, 2);
+//! }
+//!
+//! // A test with options, declared through `test!`
+//! #[test_case]
+//! const OVERFLOW: Test = test!(overflow)
+//!     .should_panic()
+//!     .timeout(Duration::from_secs(1));
+//! ```
+
+use core::{
+    any,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use spin::Once;
+use x86_64::instructions::interrupts;
+
+use crate::{
+    init::{idt::TIMER_INTERRUPT_ID, pit},
+    prelude::*,
+    qemu::{exit_qemu, QemuExitCode},
+    sync, time,
+};
+
+pub mod elf;
+#[cfg(all(test, not(target_os = "none")))]
+mod host;
+
+#[cfg(all(test, not(target_os = "none")))]
+pub use self::host::test_runner;
+
+/// Timeout of tests that do not set their own.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tests given to `test_runner`.
+static TESTS: Once<&'static [&'static dyn Testable]> = Once::new();
+
+/// Index of the next test to run.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+
+/// Set while a test body runs, so panics elsewhere are not blamed on it.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// Set when the running test was stopped by its timeout.
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+/// Tick at which the running test times out, 0 when none runs.
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+/// Something the test runner can run.
+pub trait Testable: Sync {
+    /// Name printed in the results.
+    fn name(&self) -> &'static str;
+
+    /// Runs the test, which fails by panicking.
+    fn run(&self);
+
+    /// Returns `true` if the test passes by panicking.
+    fn should_panic(&self) -> bool {
+        false
+    }
+
+    /// Time the test may run before failing.
+    fn timeout(&self) -> Duration {
+        DEFAULT_TIMEOUT
+    }
+}
+
+impl<T: Fn() + Sync> Testable for T {
+    fn name(&self) -> &'static str {
+        any::type_name::<T>()
+    }
+
+    fn run(&self) {
+        self();
+    }
+}
+
+/// A test with options.
+///
+/// Use `test!` to create one, it fills the name in.
+#[derive(Debug, Copy, Clone)]
+pub struct Test {
+    name: &'static str,
+    test: fn(),
+    should_panic: bool,
+    timeout: Duration,
+}
+
+impl Test {
+    /// Creates a new instance of Test, without options.
+    pub const fn new(name: &'static str, test: fn()) -> Test {
+        Test {
+            name,
+            test,
+            should_panic: false,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Makes the test pass only if it panics.
+    pub const fn should_panic(self) -> Test {
+        Test {
+            should_panic: true,
+            ..self
+        }
+    }
+
+    /// Changes the time the test may run before failing.
+    pub const fn timeout(self, timeout: Duration) -> Test {
+        Test { timeout, ..self }
+    }
+}
+
+impl Testable for Test {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self) {
+        (self.test)();
+    }
+
+    fn should_panic(&self) -> bool {
+        self.should_panic
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// Creates a `Test` running the given function, named after its path.
+#[macro_export]
+macro_rules! test {
+    ($test:path) => {
+        $crate::testing::Test::new(concat!(module_path!(), "::", stringify!($test)), $test)
+    };
+}
+
+/// Initializes what the tests rely on: the descriptor tables, the interrupts,
+/// the system calls and the timer, which enforces the timeouts.
+pub fn init() {
+    gdt::init().unwrap();
+    idt::init().unwrap();
+    crate::init::syscall::init().unwrap();
+    unsafe { PICS.lock().initialize() };
+    pit::init().unwrap();
+    interrupts::enable();
+}
+
+/// Runs the tests, then exits QEMU with the result.
+#[cfg(target_os = "none")]
+pub fn test_runner(tests: &'static [&'static dyn Testable]) {
+    TESTS.call_once(|| tests);
+
+    vgacolor!(Color::Green);
+    kprintln!("Running {} tests", tests.len());
+    vgacolor!(Color::White);
+    s1println!("TAP version 13");
+    s1println!("1..{}", tests.len());
+
+    run_from(tests, 0);
+}
+
+/// Reports the panic of a test, then goes on with the next ones.
+///
+/// Panics outside of a test end the run.
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    DEADLINE.store(0, Ordering::SeqCst);
+
+    match TESTS.r#try() {
+        Some(tests) if RUNNING.swap(false, Ordering::SeqCst) => {
+            let index = NEXT.load(Ordering::SeqCst) - 1;
+
+            if TIMED_OUT.swap(false, Ordering::SeqCst) {
+                failed(index, tests[index], format_args!("timed out"));
+            } else if tests[index].should_panic() {
+                passed(index, tests[index]);
+            } else {
+                failed(index, tests[index], format_args!("{}", info));
+            }
+
+            run_from(tests, index + 1);
+        }
+        _ => {
+            testprintln!(Color::Red; "Bail out! {}", info);
+            exit_qemu(QemuExitCode::Failed);
+        }
+    }
+
+    hlt_loop()
+}
+
+/// Fails the running test if it went past its timeout.
+///
+/// Called by the timer interrupt handler, after counting the tick.
+pub fn check_timeout() {
+    let deadline = DEADLINE.load(Ordering::SeqCst);
+
+    if deadline != 0 && time::ticks() >= deadline {
+        DEADLINE.store(0, Ordering::SeqCst);
+        TIMED_OUT.store(true, Ordering::SeqCst);
+
+        // The handler never gets to do it, the run goes on from the panic.
+        unsafe { PICS.lock().notify_end_of_interrupt(TIMER_INTERRUPT_ID) };
+        panic!("test timed out");
+    }
+}
+
+fn run_from(tests: &'static [&'static dyn Testable], start: usize) {
+    for (index, test) in tests.iter().enumerate().skip(start) {
+        NEXT.store(index + 1, Ordering::SeqCst);
+        kprint!("{}... ", test.name());
+
+        let deadline = time::ticks() + time::duration_to_ticks(test.timeout()).max(1);
+        DEADLINE.store(deadline, Ordering::SeqCst);
+        RUNNING.store(true, Ordering::SeqCst);
+        // A test that panicked may have stopped with them disabled, in the
+        // timer interrupt handler if it timed out.
+        interrupts::enable();
+        sync::forget_held();
+
+        test.run();
+
+        RUNNING.store(false, Ordering::SeqCst);
+        DEADLINE.store(0, Ordering::SeqCst);
+
+        if test.should_panic() {
+            failed(index, *test, format_args!("did not panic"));
+        } else {
+            passed(index, *test);
+        }
+    }
+
+    summary(tests.len());
+}
+
+fn passed(index: usize, test: &dyn Testable) {
+    PASSED.fetch_add(1, Ordering::SeqCst);
+
+    vgacolor!(Color::Green);
+    kprintln!("[ok]");
+    vgacolor!(Color::White);
+    s1println!("ok {} - {}", index + 1, test.name());
+}
+
+fn failed(index: usize, test: &dyn Testable, reason: core::fmt::Arguments) {
+    FAILED.fetch_add(1, Ordering::SeqCst);
+
+    vgacolor!(Color::Red);
+    kprintln!("[failed]");
+    kprintln!("Error: {}", reason);
+    vgacolor!(Color::White);
+    s1println!("not ok {} - {}", index + 1, test.name());
+    s1println!("# {}", reason);
+}
+
+/// Prints the results of the tests, then exits QEMU.
+fn summary(total: usize) -> ! {
+    let passed = PASSED.load(Ordering::SeqCst);
+    let failed = FAILED.load(Ordering::SeqCst);
+
+    s1println!("# tests {}", total);
+    s1println!("# pass {}", passed);
+    s1println!("# fail {}", failed);
+
+    if failed == 0 {
+        vgacolor!(Color::Green);
+        kprintln!("All {} tests passed", total);
+        vgacolor!(Color::White);
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        vgacolor!(Color::Red);
+        kprintln!("{} of {} tests failed", failed, total);
+        vgacolor!(Color::White);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    hlt_loop()
+}
+
+#[cfg(test)]
+#[test_case]
+fn names() {
+    assert!(Testable::name(&panics).ends_with("::testing::panics"));
+
+    let test = test!(panics);
+    assert_eq!(test.name(), concat!(module_path!(), "::panics"));
+    assert!(!Testable::should_panic(&test));
+    assert!(Testable::should_panic(&test.should_panic()));
+    assert_eq!(Testable::timeout(&test), DEFAULT_TIMEOUT);
+}
+
+#[cfg(test)]
+fn panics() {
+    panic!("expected to panic");
+}
+
+#[cfg(test)]
+#[test_case]
+const PANICS: Test = test!(panics).should_panic();
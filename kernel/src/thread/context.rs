@@ -0,0 +1,63 @@
+//! Switch between the stacks, and so the execution contexts, of two threads.
+//!
+//! Only the callee-saved registers are saved: a switch is a function call, so
+//! the caller already saved the others, and the timer interrupt handler saves
+//! every register it uses before it gets to the scheduler.
+
+use core::mem;
+
+/// Callee-saved registers pushed by `switch`: rbp, rbx and r12 to r15.
+const SAVED_REGISTERS: usize = 6;
+
+/// Saves the callee-saved registers on the running stack, stores the stack
+/// pointer in `old`, then loads `new` as the stack pointer and resumes what
+/// was saved there.
+///
+/// This function is unsafe because `new` must be a stack pointer saved by
+/// `switch`, or prepared by `prepare`, whose stack is not in use.
+#[naked]
+pub unsafe extern "C" fn switch(old: *mut u64, new: u64) {
+    asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+        options(noreturn)
+    );
+}
+
+/// Prepares an unused stack so that switching to it calls `start`, and
+/// returns its stack pointer.
+pub fn prepare(stack: &mut [u8], start: extern "C" fn() -> !) -> u64 {
+    let slot = mem::size_of::<u64>();
+    assert!(
+        stack.len() >= 16 + slot * (2 + SAVED_REGISTERS),
+        "Stack too small"
+    );
+    let top = (stack.as_mut_ptr() as usize + stack.len()) & !0xF;
+
+    // From the top: a null return address, so `start` is entered with the
+    // stack aligned as after a call, then its address and the registers.
+    let rsp = top - slot * (2 + SAVED_REGISTERS);
+    let frame = rsp as *mut u64;
+    unsafe {
+        for register in 0..SAVED_REGISTERS {
+            frame.add(register).write(0);
+        }
+        frame.add(SAVED_REGISTERS).write(start as usize as u64);
+        frame.add(SAVED_REGISTERS + 1).write(0);
+    }
+
+    rsp as u64
+}
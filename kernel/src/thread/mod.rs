@@ -0,0 +1,613 @@
+//! # Kernel threads
+//!
+//! Threads run in the kernel, each on its own stack, and are scheduled round
+//! robin: the timer interrupt preempts the running thread once its time slice
+//! is over, and a thread can give the CPU away sooner with `yield_now`. When
+//! no thread is ready, the idle thread halts the CPU until the next interrupt.
+//!
+//! The thread that calls `init`, usually `kmain`, becomes the main thread.
+//!
+//! A thread running a user program has the address space and the FS base of
+//! the program, loaded whenever it is switched to. The others run in the
+//! kernel address space.
+//!
+//! Stacks are `STACK_SIZE` bytes, without guard page: a thread overflowing its
+//! stack corrupts the one below it.
+//!
+//! # Examples
+//! ```no_run
+//! thread::init().unwrap();
+//!
+//! let worker = thread::spawn("worker", || kprintln!("Hello from a thread")).unwrap();
+//! worker.join().unwrap();
+//! ```
+
+use core::{
+    fmt, mem,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use spin::Mutex;
+use x86_64::{
+    instructions::{self as cpu, interrupts},
+    registers::model_specific::FsBase,
+    structures::paging::PhysFrame,
+    VirtAddr,
+};
+
+use crate::{init::gdt, time};
+
+mod context;
+
+/// Maximum number of threads, the main and idle threads included.
+pub const MAX_THREADS: usize = 16;
+
+/// Size of the stack of a thread, in bytes.
+pub const STACK_SIZE: usize = 4096 * 4;
+
+/// Time a thread runs before being preempted.
+pub const TIMESLICE: Duration = Duration::from_millis(20);
+
+/// Slot of the main thread.
+const MAIN: usize = 0;
+
+/// Slot of the idle thread.
+const IDLE: usize = 1;
+
+/// Identifier of a thread, never reused.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    /// The identifier as a number.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Ready,
+    Running,
+    /// Waits for another thread to exit, to be unparked, or for its wake up
+    /// tick.
+    Blocked,
+    /// Done, until it is joined.
+    Exited,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Thread {
+    id: ThreadId,
+    name: &'static str,
+    state: State,
+    entry: Option<fn()>,
+    /// Stack pointer, saved while the thread does not run.
+    rsp: u64,
+    /// Slot of the thread waiting for this one to exit.
+    joiner: Option<usize>,
+    /// Nobody will join the thread, its slot is free once it exited.
+    detached: bool,
+    /// Unparked while not parked, the next `park` returns at once.
+    unparked: bool,
+    /// Tick at which a sleeping thread is woken up.
+    wake_at: Option<u64>,
+    /// Level 4 table of the address space the thread runs in, `None` for the
+    /// kernel one.
+    level4: Option<PhysFrame>,
+    /// Base of the FS segment, the thread pointer of user code.
+    fs_base: u64,
+}
+
+/// Stacks of the threads, by slot. The main thread keeps the boot stack.
+static mut STACKS: [[u8; STACK_SIZE]; MAX_THREADS] = [[0; STACK_SIZE]; MAX_THREADS];
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+
+/// Slot of the running thread, known without locking the scheduler.
+static CURRENT: AtomicUsize = AtomicUsize::new(MAIN);
+
+/// Table of the threads and round robin choice of the next one to run.
+struct Scheduler {
+    threads: [Option<Thread>; MAX_THREADS],
+    current: usize,
+    next_id: u64,
+    /// Ticks left to the time slice of the current thread.
+    slice: u64,
+}
+
+impl Scheduler {
+    const fn new() -> Scheduler {
+        Scheduler {
+            threads: [None; MAX_THREADS],
+            current: MAIN,
+            next_id: 0,
+            slice: 0,
+        }
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.threads[MAIN].is_some()
+    }
+
+    /// Puts a thread in `slot`, ready to run from `rsp`.
+    fn insert(
+        &mut self,
+        slot: usize,
+        name: &'static str,
+        entry: Option<fn()>,
+        rsp: u64,
+    ) -> ThreadId {
+        let id = ThreadId(self.next_id);
+        self.next_id += 1;
+        self.threads[slot] = Some(Thread {
+            id,
+            name,
+            state: State::Ready,
+            entry,
+            rsp,
+            joiner: None,
+            detached: false,
+            unparked: false,
+            wake_at: None,
+            level4: None,
+            fs_base: 0,
+        });
+
+        id
+    }
+
+    /// Finds a slot for a new thread, reusing the ones of detached threads that
+    /// exited.
+    fn free_slot(&self) -> Option<usize> {
+        self.threads.iter().position(|thread| match thread {
+            None => true,
+            Some(thread) => thread.detached && thread.state == State::Exited,
+        })
+    }
+
+    fn find(&self, id: ThreadId) -> Option<usize> {
+        self.threads
+            .iter()
+            .position(|thread| thread.map_or(false, |thread| thread.id == id))
+    }
+
+    fn current(&mut self) -> &mut Thread {
+        self.threads[self.current]
+            .as_mut()
+            .expect("No current thread")
+    }
+
+    fn thread(&mut self, slot: usize) -> &mut Thread {
+        self.threads[slot].as_mut().expect("No thread in the slot")
+    }
+
+    /// Counts a tick of the current time slice, returning `true` if the
+    /// current thread should be preempted.
+    fn tick(&mut self) -> bool {
+        let now = time::ticks();
+        for thread in self.threads.iter_mut().flatten() {
+            if thread.state == State::Blocked && thread.wake_at.map_or(false, |at| now >= at) {
+                thread.wake_at = None;
+                thread.state = State::Ready;
+            }
+        }
+
+        self.slice = self.slice.saturating_sub(1);
+        self.slice == 0 || self.current == IDLE
+    }
+
+    /// Chooses the next thread to run: the first ready one after the current
+    /// thread, else the current thread if it can go on, else the idle thread.
+    ///
+    /// Returns where to save the stack pointer of the current thread and the
+    /// stack pointer to switch to, or `None` to keep the current thread.
+    fn switch_next(&mut self) -> Option<(*mut u64, u64)> {
+        let current = self.current;
+        let ready = (1..MAX_THREADS)
+            .map(|offset| (current + offset) % MAX_THREADS)
+            .filter(|&slot| slot != IDLE)
+            .find(|&slot| self.threads[slot].map_or(false, |t| t.state == State::Ready));
+
+        let next = match ready {
+            Some(slot) => slot,
+            None if self.current().state == State::Running => current,
+            None => IDLE,
+        };
+
+        self.slice = time::duration_to_ticks(TIMESLICE);
+        if next == current {
+            self.current().state = State::Running;
+            return None;
+        }
+
+        let old = self.current();
+        if old.state == State::Running {
+            old.state = State::Ready;
+        }
+        let old_rsp = &mut old.rsp as *mut u64;
+
+        self.current = next;
+        CURRENT.store(next, Ordering::SeqCst);
+        let new = self.current();
+        new.state = State::Running;
+
+        Some((old_rsp, new.rsp))
+    }
+}
+
+/// A thread that can be waited for. Dropping it detaches the thread.
+#[derive(Debug)]
+pub struct JoinHandle {
+    id: ThreadId,
+}
+
+impl JoinHandle {
+    /// Identifier of the thread.
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    /// Waits for the thread to exit.
+    pub fn join(self) -> Result<(), &'static str> {
+        let id = self.id;
+        mem::forget(self);
+
+        loop {
+            let exited = interrupts::without_interrupts(|| {
+                let mut scheduler = SCHEDULER.lock();
+                let slot = scheduler.find(id).ok_or("No such thread")?;
+                if slot == scheduler.current {
+                    return Err("A thread can not join itself");
+                }
+
+                let current = scheduler.current;
+                let thread = scheduler.thread(slot);
+                if thread.state == State::Exited {
+                    scheduler.threads[slot] = None;
+                    return Ok(true);
+                }
+
+                thread.joiner = Some(current);
+                scheduler.current().state = State::Blocked;
+                drop(scheduler);
+
+                // Woken up by `exit`.
+                schedule();
+                Ok(false)
+            })?;
+
+            if exited {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            if let Some(slot) = scheduler.find(self.id) {
+                scheduler.thread(slot).detached = true;
+            }
+        });
+    }
+}
+
+/// Makes the running code the main thread and creates the idle thread.
+pub fn init() -> Result<(), &'static str> {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if scheduler.is_initialized() {
+            return Err("Threads already initialized");
+        }
+
+        scheduler.insert(MAIN, "main", None, 0);
+        scheduler.thread(MAIN).state = State::Running;
+        scheduler.current = MAIN;
+
+        let rsp = unsafe { context::prepare(&mut STACKS[IDLE], start) };
+        scheduler.insert(IDLE, "idle", Some(idle), rsp);
+        scheduler.thread(IDLE).detached = true;
+        scheduler.slice = time::duration_to_ticks(TIMESLICE);
+
+        Ok(())
+    })
+}
+
+/// Creates a new thread running `entry`, ready to run.
+pub fn spawn(name: &'static str, entry: fn()) -> Result<JoinHandle, &'static str> {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if !scheduler.is_initialized() {
+            return Err("Threads not initialized");
+        }
+
+        let slot = scheduler.free_slot().ok_or("Too many threads")?;
+        // Nothing runs on the stack of a free slot.
+        let rsp = unsafe { context::prepare(&mut STACKS[slot], start) };
+        let id = scheduler.insert(slot, name, Some(entry), rsp);
+
+        Ok(JoinHandle { id })
+    })
+}
+
+/// Gives the CPU to the next ready thread, if any.
+pub fn yield_now() {
+    interrupts::without_interrupts(schedule);
+}
+
+/// Ends the current thread.
+///
+/// # Panics
+/// If called from the main thread, which has nowhere to return to.
+pub fn exit() -> ! {
+    interrupts::disable();
+    {
+        let mut scheduler = SCHEDULER.lock();
+        assert!(scheduler.current != MAIN, "The main thread can not exit");
+
+        let thread = scheduler.current();
+        thread.state = State::Exited;
+        if let Some(joiner) = thread.joiner.take() {
+            scheduler.thread(joiner).state = State::Ready;
+        }
+    }
+
+    schedule();
+    unreachable!("Exited thread resumed");
+}
+
+/// Blocks the current thread for at least `duration`.
+///
+/// Waits for the ticks to pass without sleeping if threads are not
+/// initialized.
+pub fn sleep(duration: Duration) {
+    let deadline = time::ticks() + time::duration_to_ticks(duration).max(1);
+
+    while time::ticks() < deadline {
+        interrupts::without_interrupts(|| {
+            let mut scheduler = SCHEDULER.lock();
+            if !scheduler.is_initialized() {
+                return;
+            }
+
+            let thread = scheduler.current();
+            thread.wake_at = Some(deadline);
+            thread.state = State::Blocked;
+            drop(scheduler);
+
+            // Woken up by the timer interrupt, or by `unpark`.
+            schedule();
+            SCHEDULER.lock().current().wake_at = None;
+        });
+    }
+}
+
+/// Blocks the current thread until it is unparked, or returns at once if it
+/// was unparked since its last `park`. It may also return spuriously, so
+/// callers check what they wait for in a loop.
+///
+/// Returns at once if threads are not initialized.
+pub fn park() {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if !scheduler.is_initialized() {
+            return;
+        }
+
+        let thread = scheduler.current();
+        if mem::replace(&mut thread.unparked, false) {
+            return;
+        }
+        thread.state = State::Blocked;
+        drop(scheduler);
+
+        schedule();
+    });
+}
+
+/// Makes a parked thread ready to run again. If it is not parked, its next
+/// `park` returns at once.
+///
+/// Not for interrupt handlers, which may have interrupted the scheduler.
+pub fn unpark(id: ThreadId) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if let Some(slot) = scheduler.find(id) {
+            let thread = scheduler.thread(slot);
+            match thread.state {
+                State::Blocked => thread.state = State::Ready,
+                _ => thread.unparked = true,
+            }
+        }
+    });
+}
+
+/// Makes the running thread use the address space of the level 4 table
+/// `level4`, or the kernel one if `None`, from now on.
+///
+/// This function is unsafe because the caller must guarantee that `level4` is
+/// the level 4 table of an address space that outlives its use by the thread,
+/// and that the running code is mapped in it.
+pub unsafe fn set_address_space(level4: Option<PhysFrame>) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if scheduler.is_initialized() {
+            scheduler.current().level4 = level4;
+        }
+        crate::mem::switch_level4(level4);
+    });
+}
+
+/// Sets the base of the FS segment of the running thread, that user code
+/// finds its thread local storage with.
+pub fn set_fs_base(base: VirtAddr) {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if scheduler.is_initialized() {
+            scheduler.current().fs_base = base.as_u64();
+        }
+        FsBase::write(base);
+    });
+}
+
+/// Identifier of the running thread.
+///
+/// # Panics
+/// If threads are not initialized.
+pub fn current() -> ThreadId {
+    try_current().expect("Threads not initialized")
+}
+
+/// Identifier of the running thread, or `None` if threads are not
+/// initialized.
+pub fn try_current() -> Option<ThreadId> {
+    interrupts::without_interrupts(|| {
+        let mut scheduler = SCHEDULER.lock();
+        if scheduler.is_initialized() {
+            Some(scheduler.current().id)
+        } else {
+            None
+        }
+    })
+}
+
+/// Slot of the running thread in the table, below `MAX_THREADS`. The main
+/// thread has the slot 0, also before threads are initialized.
+pub fn current_slot() -> usize {
+    CURRENT.load(Ordering::SeqCst)
+}
+
+/// Name of the running thread.
+pub fn name() -> &'static str {
+    interrupts::without_interrupts(|| SCHEDULER.lock().current().name)
+}
+
+/// Preempts the running thread if its time slice is over. Only the timer
+/// interrupt handler calls it, after the end of interrupt.
+pub fn preempt() {
+    // The interrupted code may hold the scheduler, it then keeps running.
+    let switch = match SCHEDULER.try_lock() {
+        Some(mut scheduler) => scheduler.is_initialized() && scheduler.tick(),
+        None => false,
+    };
+
+    if switch {
+        schedule();
+    }
+}
+
+/// Switches to the next thread. Interrupts must be disabled.
+fn schedule() {
+    let (old, new, level4, fs_base) = {
+        let mut scheduler = SCHEDULER.lock();
+        let (old, new) = match scheduler.switch_next() {
+            Some(switch) => switch,
+            None => return,
+        };
+        let thread = scheduler.current();
+        (old, new, thread.level4, thread.fs_base)
+    };
+
+    // Interrupts from user mode run on the kernel stack of the new thread, in
+    // its address space. The kernel is mapped in all of them.
+    unsafe {
+        gdt::set_kernel_stack(kernel_stack_top(current_slot()));
+        crate::mem::switch_level4(level4);
+    }
+    FsBase::write(VirtAddr::new(fs_base));
+
+    // The scheduler is unlocked, but its table stays where it is, and nothing
+    // else runs until the switch is done.
+    unsafe { context::switch(old, new) };
+}
+
+/// Top of the stack of the thread in `slot`, that interrupts from user mode
+/// switch to. Once in user mode, nothing is left on it. The main thread keeps
+/// the default one of the TSS.
+fn kernel_stack_top(slot: usize) -> VirtAddr {
+    match slot {
+        MAIN => gdt::privilege_stack_top(),
+        _ => VirtAddr::from_ptr(unsafe { &STACKS[slot] }) + STACK_SIZE,
+    }
+}
+
+/// First code run by a new thread.
+extern "C" fn start() -> ! {
+    let entry = SCHEDULER.lock().current().entry;
+
+    // Threads are switched to with interrupts disabled.
+    interrupts::enable();
+    if let Some(entry) = entry {
+        entry();
+    }
+
+    exit();
+}
+
+/// Runs when no other thread is ready.
+fn idle() {
+    loop {
+        cpu::hlt();
+    }
+}
+
+#[cfg(test)]
+#[test_case]
+fn round_robin() {
+    let mut scheduler = Scheduler::new();
+    scheduler.insert(MAIN, "main", None, 0);
+    scheduler.thread(MAIN).state = State::Running;
+    scheduler.insert(IDLE, "idle", Some(idle), 1);
+    let a = scheduler.insert(2, "a", None, 2);
+    scheduler.insert(3, "b", None, 3);
+
+    // Every ready thread in turn, never the idle one
+    let order: [u64; 4] = [2, 3, 0, 2];
+    for &rsp in order.iter() {
+        assert_eq!(scheduler.switch_next().map(|(_, new)| new), Some(rsp));
+    }
+
+    // Blocked threads are skipped, the idle one runs when nothing else can
+    scheduler.thread(0).state = State::Blocked;
+    scheduler.thread(3).state = State::Exited;
+    assert_eq!(scheduler.switch_next(), None);
+    scheduler.current().state = State::Blocked;
+    assert_eq!(scheduler.switch_next().map(|(_, new)| new), Some(1));
+    assert!(scheduler.tick());
+
+    // Slots of detached exited threads are reused
+    assert_eq!(scheduler.free_slot(), Some(4));
+    scheduler.thread(3).detached = true;
+    assert_eq!(scheduler.free_slot(), Some(3));
+    assert_eq!(scheduler.find(a), Some(2));
+}
+
+#[cfg(test)]
+#[test_case]
+fn waking_sleepers() {
+    let mut scheduler = Scheduler::new();
+    scheduler.insert(MAIN, "main", None, 0);
+    scheduler.thread(MAIN).state = State::Running;
+    scheduler.insert(2, "sleeper", None, 2);
+    let sleeper = scheduler.thread(2);
+    sleeper.state = State::Blocked;
+    sleeper.wake_at = Some(time::ticks() + 1);
+
+    scheduler.tick();
+    assert_eq!(scheduler.thread(2).state, State::Blocked);
+
+    time::tick();
+    scheduler.tick();
+    assert_eq!(scheduler.thread(2).state, State::Ready);
+    assert_eq!(scheduler.thread(2).wake_at, None);
+}
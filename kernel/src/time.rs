@@ -0,0 +1,39 @@
+//! Kernel time keeping, driven by the timer interrupt.
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::init::pit::TIMER_HZ;
+
+/// Timer interrupts since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a timer interrupt. Only the timer interrupt handler calls it.
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Time since the timer started, with the precision of one tick.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Converts a number of ticks into a `Duration`.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let hz = u64::from(TIMER_HZ);
+    Duration::from_secs(ticks / hz) + Duration::from_nanos((ticks % hz) * 1_000_000_000 / hz)
+}
+
+/// Converts a `Duration` into a number of ticks, rounding up.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let hz = u64::from(TIMER_HZ);
+    let nanos = duration.as_nanos() * u128::from(hz);
+    ((nanos + 999_999_999) / 1_000_000_000) as u64
+}
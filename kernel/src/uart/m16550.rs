@@ -3,7 +3,8 @@
 use core::fmt::{self, Write};
 
 use bitflags::bitflags;
-use x86_64::instructions::port::Port;
+
+use crate::io::{Pio, PortIo};
 
 /// The port adresses known.
 ///
@@ -37,15 +38,23 @@ bitflags! {
     }
 }
 
+/// Offsets of the registers from the base port.
+mod register {
+    pub const DATA: u16 = 0;
+    pub const INT_EN: u16 = 1;
+    pub const FIFO_CTRL: u16 = 2;
+    pub const LINE_CTRL: u16 = 3;
+    pub const MODEM_CTRL: u16 = 4;
+    pub const LINE_STS: u16 = 5;
+}
+
 /// Serial Port struct
+///
+/// Talks to the UART through `io`, the real I/O ports by default.
 #[derive(Debug, Clone, PartialEq)]
-pub struct SerialPort {
-    data: Port<u8>,
-    int_en: Port<u8>,
-    fifo_ctrl: Port<u8>,
-    line_ctrl: Port<u8>,
-    modem_ctrl: Port<u8>,
-    line_sts: Port<u8>,
+pub struct SerialPort<P = Pio> {
+    io: P,
+    base: u16,
 }
 
 impl SerialPort {
@@ -54,33 +63,53 @@ impl SerialPort {
     /// This function is unsafe because the caller must ensure that the given base address
     /// really points to a serial port device.
     pub const unsafe fn new(base: PortAddress) -> Self {
-        let base = base as u16;
+        SerialPort::with_io(Pio, base)
+    }
+}
+
+impl<P> SerialPort<P> {
+    /// Create a new instance of SerialPort, accessing its ports through `io`.
+    ///
+    /// This function is unsafe because the caller must ensure that the given base address
+    /// really points to a serial port device.
+    pub const unsafe fn with_io(io: P, base: PortAddress) -> Self {
         SerialPort {
-            data: Port::new(base),
-            int_en: Port::new(base + 1),
-            fifo_ctrl: Port::new(base + 2),
-            line_ctrl: Port::new(base + 3),
-            modem_ctrl: Port::new(base + 4),
-            line_sts: Port::new(base + 5),
+            io,
+            base: base as u16,
         }
     }
 
+    /// The I/O the serial port is accessed through.
+    pub fn io(&self) -> &P {
+        &self.io
+    }
+}
+
+impl<P: PortIo> SerialPort<P> {
     // Initialize serial port
     pub fn init(&mut self) {
         unsafe {
-            self.int_en.write(0x00);
-            self.line_ctrl.write(0x80);
-            self.data.write(0x03);
-            self.int_en.write(0x00);
-            self.line_ctrl.write(0x03);
-            self.fifo_ctrl.write(0xC7);
-            self.modem_ctrl.write(0x0B);
-            self.int_en.write(0x01);
+            self.write(register::INT_EN, 0x00);
+            self.write(register::LINE_CTRL, 0x80);
+            self.write(register::DATA, 0x03);
+            self.write(register::INT_EN, 0x00);
+            self.write(register::LINE_CTRL, 0x03);
+            self.write(register::FIFO_CTRL, 0xC7);
+            self.write(register::MODEM_CTRL, 0x0B);
+            self.write(register::INT_EN, 0x01);
         }
     }
 
+    unsafe fn read(&mut self, register: u16) -> u8 {
+        self.io.read_u8(self.base + register)
+    }
+
+    unsafe fn write(&mut self, register: u16, value: u8) {
+        self.io.write_u8(self.base + register, value);
+    }
+
     fn line_sts(&mut self) -> LineStsFlags {
-        unsafe { LineStsFlags::from_bits_truncate(self.line_sts.read()) }
+        unsafe { LineStsFlags::from_bits_truncate(self.read(register::LINE_STS)) }
     }
 
     /// Put serial port to receive data
@@ -88,7 +117,18 @@ impl SerialPort {
     pub fn receive(&mut self) {
         unsafe {
             while self.line_sts().contains(LineStsFlags::INPUT_FULL) {}
-            self.data.read();
+            self.read(register::DATA);
+        }
+    }
+
+    /// Takes the byte received, or returns `None` if there is none.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        let status = unsafe { self.read(register::LINE_STS) };
+        // Ports without a UART read as all ones.
+        if status != 0xFF && status & LineStsFlags::INPUT_FULL.bits() != 0 {
+            Some(unsafe { self.read(register::DATA) })
+        } else {
+            None
         }
     }
 
@@ -98,22 +138,22 @@ impl SerialPort {
             match data {
                 8 | 0x7F => {
                     while !self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY) {}
-                    self.data.write(8);
+                    self.write(register::DATA, 8);
                     while !self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY) {}
-                    self.data.write(b' ');
+                    self.write(register::DATA, b' ');
                     while !self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY) {}
-                    self.data.write(8);
+                    self.write(register::DATA, 8);
                 }
                 _ => {
                     while !self.line_sts().contains(LineStsFlags::OUTPUT_EMPTY) {}
-                    self.data.write(data);
+                    self.write(register::DATA, data);
                 }
             }
         }
     }
 }
 
-impl Write for SerialPort {
+impl<P: PortIo> Write for SerialPort<P> {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for byte in s.bytes() {
             self.send(byte);
@@ -121,3 +161,58 @@ impl Write for SerialPort {
         Ok(())
     }
 }
+
+#[cfg(test)]
+#[test_case]
+fn sending() {
+    use crate::io::MockPortIo;
+
+    let com1 = PortAddress::COM1 as u16;
+    let mut io = MockPortIo::new();
+    // Transmitter busy once, then always empty
+    io.queue_read(com1 + register::LINE_STS, 0);
+    io.set_default(
+        com1 + register::LINE_STS,
+        LineStsFlags::OUTPUT_EMPTY.bits().into(),
+    );
+
+    let mut serial = unsafe { SerialPort::with_io(io, PortAddress::COM1) };
+    serial.init();
+    assert!(serial
+        .io()
+        .writes_to(com1 + register::LINE_CTRL)
+        .eq([0x80, 0x03].iter().copied()));
+
+    write!(serial, "Hi\n").unwrap();
+    serial.send(0x7F);
+    assert!(serial
+        .io()
+        .writes_to(com1 + register::DATA)
+        .eq([0x03, b'H', b'i', b'\n', 8, b' ', 8]
+            .iter()
+            .map(|&b| u32::from(b))));
+    assert_eq!(serial.io().pending_reads(), 0);
+}
+
+#[cfg(test)]
+#[test_case]
+fn receiving() {
+    use crate::io::MockPortIo;
+
+    let com2 = PortAddress::COM2 as u16;
+    let mut io = MockPortIo::new();
+    io.queue_read(
+        com2 + register::LINE_STS,
+        LineStsFlags::INPUT_FULL.bits().into(),
+    )
+    .queue_read(com2 + register::DATA, b'x'.into())
+    .queue_read(com2 + register::LINE_STS, 0);
+
+    let mut serial = unsafe { SerialPort::with_io(io, PortAddress::COM2) };
+    assert_eq!(serial.try_receive(), Some(b'x'));
+    // Nothing else was received.
+    assert_eq!(serial.try_receive(), None);
+    assert_eq!(serial.io().pending_reads(), 0);
+    // Without a UART, the floating bus reads as all ones.
+    assert_eq!(serial.try_receive(), None);
+}
@@ -1,4 +1,3 @@
-
-
 //! Module for UART model 16550
 pub mod m16550;
+pub mod rx;
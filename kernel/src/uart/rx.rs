@@ -0,0 +1,111 @@
+//! # Serial input
+//!
+//! The interrupt handler of `COM1` only stores the bytes received, they are
+//! read later by polling or as an async `Stream`.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{stream::Stream, task::AtomicWaker};
+
+use crate::{
+    hid::queue::{Overflow, Queue},
+    init::{idt::SERIAL_INTERRUPT_ID, pic, serial::SERIAL1},
+    uart::m16550::PortAddress,
+};
+
+/// Data port of `COM1`, where the received bytes are read from.
+pub const DATA_PORT: u16 = PortAddress::COM1 as u16;
+
+/// Line status port of `COM1`, its lowest bit is set while there is a byte to
+/// read.
+pub const LINE_STS_PORT: u16 = DATA_PORT + 5;
+
+/// Bytes received and not yet read.
+static RECEIVED: Queue<u8, 256> = Queue::new(Overflow::DropNewest);
+
+/// Wakes the task waiting on a `ByteStream`.
+static RX_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Enables the receive interrupt of `COM1`.
+pub fn init() -> Result<(), &'static str> {
+    // Initializing the port enables its receive interrupt.
+    lazy_static::initialize(&SERIAL1);
+    unsafe { pic::unmask(SERIAL_INTERRUPT_ID - pic::PIC_1_OFFSET) };
+
+    Ok(())
+}
+
+/// Stores a byte received on `COM1`.
+///
+/// Meant to be called from the serial interrupt handler, it does not lock.
+pub fn add_byte(byte: u8) {
+    if RECEIVED.push(byte).is_ok() {
+        RX_WAKER.wake();
+    }
+}
+
+/// Takes the oldest byte received, or `None` if there is none.
+pub fn read_byte() -> Option<u8> {
+    RECEIVED.pop()
+}
+
+/// Asynchronous stream of the bytes received on `COM1`.
+///
+/// Like `KeyStream`, a single consumer should own the serial input.
+#[derive(Debug, Default)]
+pub struct ByteStream {
+    _private: (),
+}
+
+impl ByteStream {
+    /// Creates a new instance of ByteStream.
+    pub fn new() -> ByteStream {
+        ByteStream { _private: () }
+    }
+
+    /// Waits for the next byte.
+    pub fn next_byte(&mut self) -> NextByte<'_> {
+        NextByte { stream: self }
+    }
+}
+
+impl Stream for ByteStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        if let Some(byte) = read_byte() {
+            return Poll::Ready(Some(byte));
+        }
+
+        // A byte may have arrived before the waker was registered.
+        RX_WAKER.register(cx.waker());
+        match read_byte() {
+            Some(byte) => {
+                RX_WAKER.take();
+                Poll::Ready(Some(byte))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by `ByteStream::next_byte`.
+#[derive(Debug)]
+pub struct NextByte<'a> {
+    stream: &'a mut ByteStream,
+}
+
+impl Future for NextByte<'_> {
+    type Output = u8;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u8> {
+        match Pin::new(&mut *self.stream).poll_next(cx) {
+            Poll::Ready(Some(byte)) => Poll::Ready(byte),
+            _ => Poll::Pending,
+        }
+    }
+}
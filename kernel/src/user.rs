@@ -0,0 +1,215 @@
+//! # User mode
+//!
+//! Code runs in ring 3 once a thread calls `enter_user_mode`, on pages mapped
+//! with `mem::map_user_page`. Interrupts taken in user mode switch to the
+//! kernel stack of the thread, set in the TSS by the scheduler.
+//!
+//! An exception raised in user mode does not stop the kernel: it is reported,
+//! then raised as a signal in the process that ran the faulting code, which
+//! kills it unless it catches it. A thread that is not a process exits.
+//!
+//! # Examples
+//! ```no_run
+//! let code = Page::containing_address(VirtAddr::new(0x4000_0000));
+//! let stack = Page::containing_address(VirtAddr::new(0x4000_1000));
+//! mem::map_user_page(code, PageTableFlags::WRITABLE).unwrap();
+//! mem::map_user_page(stack, PageTableFlags::WRITABLE).unwrap();
+//! // ... copy the program to `code` ...
+//!
+//! unsafe { user::enter_user_mode(code.start_address(), stack.start_address() + 4096u64) };
+//! ```
+
+use x86_64::{instructions::interrupts, structures::idt::InterruptStackFrame, VirtAddr};
+
+use crate::{
+    prelude::*,
+    process::{self, signal, ExitStatus, Signal},
+    sync::IrqSafeSpinlock,
+    syscall::Registers,
+    thread::{self, ThreadId},
+};
+
+/// End of the lower half of the address space, the part user code can use.
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+/// RFLAGS of user code when it starts: interrupts enabled, and the reserved
+/// bit 1 that is always set.
+const USER_RFLAGS: u64 = 0x202;
+
+/// Flags of RFLAGS user code can set: the arithmetic ones, the trap and
+/// direction flags.
+const USER_RFLAGS_MASK: u64 = 0xDD5;
+
+/// An exception raised by user code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fault {
+    /// Name of the exception.
+    pub exception: &'static str,
+    /// Address of the faulting instruction.
+    pub instruction: VirtAddr,
+    /// Address accessed, for page faults.
+    pub address: Option<VirtAddr>,
+    /// Thread that ran the faulting code.
+    pub thread: ThreadId,
+}
+
+/// Last fault reported.
+static LAST_FAULT: IrqSafeSpinlock<Option<Fault>> = IrqSafeSpinlock::new(None);
+
+/// Jumps to `entry` in ring 3, with the stack pointer at `stack`. The general
+/// purpose registers are cleared, so nothing leaks from the kernel.
+///
+/// Interrupts taken in user mode switch to the kernel stack of the running
+/// thread, so user code should not run on the main thread, which can not exit
+/// if it faults.
+///
+/// This function is unsafe because the caller must guarantee that `entry` and
+/// `stack` are in pages mapped for user mode.
+pub unsafe fn enter_user_mode(entry: VirtAddr, stack: VirtAddr) -> ! {
+    let code = u64::from(gdt::user_code_selector().0);
+    let data = u64::from(gdt::user_data_selector().0);
+
+    // Enabled again by `iretq`, from the RFLAGS of user code.
+    interrupts::disable();
+    asm!(
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "push {data}",
+        "push {stack}",
+        "push {rflags}",
+        "push {code}",
+        "push {entry}",
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq",
+        data = in(reg) data,
+        stack = in(reg) stack.as_u64(),
+        rflags = in(reg) USER_RFLAGS,
+        code = in(reg) code,
+        entry = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
+
+/// Goes back to user mode with the general purpose registers, instruction
+/// pointer, stack pointer and flags of `registers`. The segments are the user
+/// ones, and interrupts get enabled whatever the flags.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// registers are those of user code, in the active address space.
+pub unsafe fn resume(registers: &Registers) -> ! {
+    let data = u64::from(gdt::user_data_selector().0);
+    let mut frame = *registers;
+    frame.cs = u64::from(gdt::user_code_selector().0);
+    frame.ss = data;
+    frame.rflags = (frame.rflags & USER_RFLAGS_MASK) | USER_RFLAGS;
+
+    // The frame is laid out like the stack of `int 0x80` when it saves them.
+    interrupts::disable();
+    asm!(
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "mov rsp, {frame}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "iretq",
+        data = in(reg) data,
+        frame = in(reg) &frame,
+        options(noreturn)
+    );
+}
+
+/// Returns `true` if the interrupted code ran in user mode.
+pub fn from_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
+}
+
+/// Called by the exception handlers: if user code raised the exception,
+/// reports it and kills the running process with `signal`. Returns if the
+/// kernel raised it.
+pub fn check_fault(
+    exception: &'static str,
+    signal: Signal,
+    stack_frame: &InterruptStackFrame,
+    address: Option<VirtAddr>,
+) {
+    if !from_user_mode(stack_frame) {
+        return;
+    }
+
+    report(exception, signal, stack_frame.instruction_pointer, address);
+    process::exit(ExitStatus::Signaled(signal));
+}
+
+/// Called by the handlers of the exceptions user code can catch, with the
+/// registers the exception interrupted: if user code raised it, reports it
+/// and raises `signal` in the running process, then returns `true`. Returns
+/// `false` if the kernel raised it.
+pub fn handle_fault(
+    exception: &'static str,
+    signal: Signal,
+    registers: &mut Registers,
+    address: Option<VirtAddr>,
+) -> bool {
+    if registers.cs & 3 != 3 {
+        return false;
+    }
+
+    report(exception, signal, VirtAddr::new(registers.rip), address);
+    signal::raise_fault(signal, registers);
+    true
+}
+
+fn report(
+    exception: &'static str,
+    signal: Signal,
+    instruction: VirtAddr,
+    address: Option<VirtAddr>,
+) {
+    let fault = Fault {
+        exception,
+        instruction,
+        address,
+        thread: thread::current(),
+    };
+    *LAST_FAULT.lock() = Some(fault);
+
+    vgacolor!(Color::Red);
+    kprint!("USER FAULT: {} at {:?}", exception, fault.instruction);
+    if let Some(address) = address {
+        kprint!(", accessing {:?}", address);
+    }
+    kprintln!(", {} to thread {}", signal, fault.thread);
+    vgacolor!(Color::White);
+}
+
+/// Last exception raised by user code, if any.
+pub fn last_fault() -> Option<Fault> {
+    *LAST_FAULT.lock()
+}
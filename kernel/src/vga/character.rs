@@ -21,6 +21,31 @@ pub enum Color {
     White = 0xF,
 }
 
+impl Color {
+    /// The color numbered `index` in attributes, if there is one.
+    pub fn from_index(index: u8) -> Option<Color> {
+        const COLORS: [Color; 16] = [
+            Color::Black,
+            Color::Blue,
+            Color::Green,
+            Color::Cyan,
+            Color::Red,
+            Color::Magenta,
+            Color::Brown,
+            Color::Gray,
+            Color::DarkGray,
+            Color::BrightBlue,
+            Color::BrightGreen,
+            Color::BrightCyan,
+            Color::BrightRed,
+            Color::BrightMagenta,
+            Color::Yellow,
+            Color::White,
+        ];
+        COLORS.get(index as usize).copied()
+    }
+}
+
 /// In VGA a character is represented by a 16 bits,
 /// the first 8 represent the character in ASCII code
 /// and the last 8 represents the atribute for the character.
@@ -53,9 +78,6 @@ impl Character {
 #[cfg(test)]
 #[test_case]
 fn creation() {
-    use crate::prelude::*;
-
-    testprint!("crate::vga::Character: creation... ");
     let character = Character::new(b'a', Color::Blue, Color::BrightMagenta);
 
     assert_eq!(character.character, b'a');
@@ -70,6 +92,13 @@ fn creation() {
 
     assert_eq!(character.character, b'c');
     assert_eq!(character.attribute, 0xF8);
+}
 
-    testprintln!(Color::Green; "[Ok]");
+#[cfg(test)]
+#[test_case]
+fn colors_by_index() {
+    for index in 0..16 {
+        assert_eq!(Color::from_index(index).unwrap() as u8, index);
+    }
+    assert_eq!(Color::from_index(16), None);
 }
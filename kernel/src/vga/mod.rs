@@ -1,37 +1,36 @@
 //! # Video Graphics Array (VGA) Driver
 //!
 //! It allows to write in screen in ASCII
-use core::{
-    fmt::{self, Write},
-    ptr,
-};
+use core::fmt::{self, Write};
 
 mod character;
 
-use crate::vga::character::Character;
 pub use crate::vga::character::Color;
+use crate::{io::Mmio, vga::character::Character};
 
-const ROWS: usize = 25;
-const COLS: usize = 80;
+/// Number of rows of the text mode.
+pub const ROWS: usize = 25;
+/// Number of columns of the text mode.
+pub const COLS: usize = 80;
 
-/// VGA struct. It needs to receive a mutable slice of u8.
+/// VGA struct. It needs to receive the memory-mapped text buffer.
 #[derive(Copy, Clone)]
-pub struct Vga<T: AsMut<[u8]>> {
-    slice: T,
+pub struct Vga<M: Mmio> {
+    mmio: M,
     buffer: [Character; ROWS * COLS],
     position: usize,
     foreground: Color,
     background: Color,
 }
 
-impl<T: AsMut<[u8]>> Vga<T> {
+impl<M: Mmio> Vga<M> {
     /// Creates a new instance of Vga.
     ///
     /// It have the default color as black for background
     /// and white to foreground.
-    pub fn new(mut slice: T) -> Self {
+    pub fn new(mmio: M) -> Self {
         // We must have enough bytes of backing storage to make this work.
-        assert_eq!(slice.as_mut().len(), ROWS * COLS * 2);
+        assert_eq!(mmio.len(), ROWS * COLS * 2);
 
         // Default colors
         let foreground = Color::White;
@@ -40,7 +39,7 @@ impl<T: AsMut<[u8]>> Vga<T> {
         let buffer = [Character::new(b' ', foreground, background); ROWS * COLS];
 
         Vga {
-            slice,
+            mmio,
             buffer,
             position: 0,
             foreground,
@@ -60,19 +59,11 @@ impl<T: AsMut<[u8]>> Vga<T> {
 
     /// Flush what it holds
     pub fn flush(&mut self) {
-        // we need to use `write_volatile` here so that the writes aren't optimized out
-        unsafe {
-            let p = self.slice.as_mut();
+        for (i, character) in self.buffer.iter().enumerate() {
+            let (ch, attr) = character.as_bytes();
 
-            for (chunk, character) in p.chunks_mut(2).zip(self.buffer.iter()) {
-                let (ch, attr) = character.as_bytes();
-
-                let p = &mut chunk[0] as *mut u8;
-                ptr::write_volatile(p, ch);
-
-                let p = &mut chunk[1] as *mut u8;
-                ptr::write_volatile(p, attr);
-            }
+            self.mmio.write(i * 2, ch);
+            self.mmio.write(i * 2 + 1, attr);
         }
     }
 
@@ -112,7 +103,7 @@ impl<T: AsMut<[u8]>> Vga<T> {
     }
 }
 
-impl<T: AsMut<[u8]>> Write for Vga<T> {
+impl<M: Mmio> Write for Vga<M> {
     fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
         for b in s.bytes() {
             self.write_byte(b);
@@ -121,3 +112,34 @@ impl<T: AsMut<[u8]>> Write for Vga<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+#[test_case]
+fn writing() {
+    let mut vga = Vga::new([0u8; ROWS * COLS * 2]);
+    let row = |vga: &Vga<[u8; ROWS * COLS * 2]>, row: usize| -> [u8; 5] {
+        let mut text = [0; 5];
+        for (col, byte) in text.iter_mut().enumerate() {
+            *byte = vga.mmio.read((row * COLS + col) * 2);
+        }
+        text
+    };
+
+    // Nothing reaches the screen before a flush
+    vga.set_foreground(Color::Yellow);
+    write!(vga, "Hello\nworld").unwrap();
+    assert_eq!(vga.mmio.read(0), 0);
+
+    vga.flush();
+    assert_eq!(&row(&vga, 0), b"Hello");
+    assert_eq!(&row(&vga, 1), b"world");
+    assert_eq!(vga.mmio.read(1), 0x0E);
+
+    // Scrolls once the last row is done
+    for _ in 0..ROWS - 1 {
+        writeln!(vga).unwrap();
+    }
+    vga.flush();
+    assert_eq!(&row(&vga, 0), b"world");
+    assert_eq!(&row(&vga, ROWS - 1), b"     ");
+}
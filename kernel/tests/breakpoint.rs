@@ -0,0 +1,42 @@
+//! Breakpoints are handled and execution goes on after them.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{hlt_loop, testing};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn returns() {
+    x86_64::instructions::interrupts::int3();
+}
+
+#[test_case]
+fn returns_many_times() {
+    let mut count = 0;
+
+    for _ in 0..10 {
+        x86_64::instructions::interrupts::int3();
+        count += 1;
+    }
+
+    assert_eq!(count, 10);
+}
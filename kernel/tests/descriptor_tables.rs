@@ -0,0 +1,93 @@
+//! The GDT, TSS and IDT loaded at boot are the kernel ones.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{hlt_loop, init::gdt, testing};
+use x86_64::{instructions::segmentation, structures::DescriptorTablePointer};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Size in bytes of a segment descriptor, the TSS one takes two.
+const DESCRIPTOR_SIZE: u16 = 8;
+/// Present bit of a segment descriptor.
+const PRESENT: u64 = 1 << 47;
+/// 64-bit code bit of a segment descriptor.
+const LONG_MODE: u64 = 1 << 53;
+/// Size in bytes of an IDT entry.
+const IDT_ENTRY_SIZE: u16 = 16;
+
+fn sgdt() -> DescriptorTablePointer {
+    let mut pointer = DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe { asm!("sgdt [{}]", in(reg) &mut pointer as *mut _, options(nostack, preserves_flags)) };
+    pointer
+}
+
+fn sidt() -> DescriptorTablePointer {
+    let mut pointer = DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe { asm!("sidt [{}]", in(reg) &mut pointer as *mut _, options(nostack, preserves_flags)) };
+    pointer
+}
+
+fn task_register() -> u16 {
+    let selector: u16;
+    unsafe { asm!("str {:x}", out(reg) selector, options(nomem, nostack, preserves_flags)) };
+    selector
+}
+
+#[test_case]
+fn gdt_loaded() {
+    let pointer = sgdt();
+    let (limit, base) = (pointer.limit, pointer.base);
+    let code = gdt::code_selector();
+
+    // Null, kernel code and TSS (two entries) descriptors at least.
+    assert!(limit >= 4 * DESCRIPTOR_SIZE - 1);
+    assert_eq!(segmentation::cs(), code);
+
+    let descriptor = unsafe { *(base as *const u64).add(usize::from(code.index())) };
+    assert_ne!(descriptor & PRESENT, 0);
+    assert_ne!(descriptor & LONG_MODE, 0);
+}
+
+#[test_case]
+fn tss_loaded() {
+    assert_eq!(task_register(), gdt::tss_selector().0);
+    assert_ne!(gdt::double_fault_stack_top().as_u64(), 0);
+}
+
+#[test_case]
+fn idt_loaded() {
+    let pointer = sidt();
+    let (limit, base) = (pointer.limit, pointer.base);
+
+    assert_eq!(limit, 256 * IDT_ENTRY_SIZE - 1);
+    assert_ne!(base, 0);
+}
+
+#[test_case]
+fn interrupts_delivered() {
+    // The timer keeps ticking with the kernel IDT loaded.
+    let start = kernel::time::ticks();
+    while kernel::time::ticks() < start + 2 {
+        x86_64::instructions::hlt();
+    }
+}
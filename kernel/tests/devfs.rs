@@ -0,0 +1,212 @@
+//! The drivers are files of `/dev`, read, written and controlled through the
+//! file system.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    fs::{
+        self,
+        devfs::{
+            KeyRecord, IOCTL_FLUSH_INPUT, IOCTL_GET_LAYOUT, IOCTL_MODIFIERS, IOCTL_SET_COLORS,
+            IOCTL_SET_LAYOUT, IOCTL_WINSIZE,
+        },
+        FileType, O_CREAT, O_RDONLY, O_RDWR, O_WRONLY,
+    },
+    hid::{
+        input::{self, KeyCode},
+        layout::LAYOUTS,
+        pckbd,
+    },
+    hlt_loop, mem,
+    syscall::{self, Errno},
+    testing, thread,
+    uart::rx,
+    vga::{COLS, ROWS},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    fs::init().unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn device_files() {
+    let dev = fs::open("/dev", O_RDONLY).unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = dev.readdir().unwrap() {
+        assert_eq!(entry.kind, FileType::CharDevice);
+        let path = alloc::format!("/dev/{}", entry.name);
+        assert_eq!(fs::stat(&path).unwrap().ino, entry.ino);
+        names.push(entry.name);
+    }
+    assert_eq!(
+        names,
+        ["console", "kbd", "null", "random", "ttyS0", "ttyS1", "vga", "zero"]
+    );
+
+    let null = fs::open("/dev/null", O_RDONLY).unwrap();
+    assert_eq!(null.stat(), fs::stat("/dev/null"));
+    assert_eq!(
+        fs::open("/dev/new", O_WRONLY | O_CREAT).err(),
+        Some(Errno::EPERM)
+    );
+    assert_eq!(fs::mkdir("/dev/new"), Err(Errno::EPERM));
+    assert_eq!(fs::unlink("/dev/null"), Err(Errno::EPERM));
+}
+
+#[test_case]
+fn null_zero_and_random() {
+    let mut buf = [0xff; 64];
+    let null = fs::open("/dev/null", O_RDWR).unwrap();
+    assert_eq!(null.read(&mut buf), Ok(0));
+    assert_eq!(null.write(b"gone"), Ok(4));
+    assert_eq!(null.ioctl(IOCTL_WINSIZE, 0), Err(Errno::ENOTTY));
+
+    let zero = fs::open("/dev/zero", O_RDWR).unwrap();
+    assert_eq!(zero.read(&mut buf), Ok(64));
+    assert_eq!(buf, [0; 64]);
+    assert_eq!(zero.write(b"gone"), Ok(4));
+
+    let random = fs::open("/dev/random", O_RDWR).unwrap();
+    let mut other = [0; 64];
+    assert_eq!(random.read(&mut buf[..61]), Ok(61));
+    assert_eq!(random.write(b"entropy"), Ok(7));
+    assert_eq!(random.read(&mut other), Ok(64));
+    assert_ne!(buf, other);
+    assert_ne!(other, [0; 64]);
+}
+
+#[test_case]
+fn screen() {
+    let vga = fs::open("/dev/vga", O_RDWR).unwrap();
+    assert_eq!(vga.write(b"Written to /dev/vga\n"), Ok(20));
+    assert_eq!(vga.read(&mut [0; 4]), Err(Errno::EINVAL));
+
+    let size = ((ROWS as u64) << 16) | COLS as u64;
+    assert_eq!(vga.ioctl(IOCTL_WINSIZE, 0), Ok(size));
+    let console = fs::open("/dev/console", O_WRONLY).unwrap();
+    assert_eq!(console.ioctl(IOCTL_WINSIZE, 0), Ok(size));
+    // Through the standard output of a kernel thread.
+    let args = [1, IOCTL_WINSIZE, 0, 0, 0, 0];
+    assert_eq!(syscall::dispatch(syscall::IOCTL, &args), size);
+
+    // Yellow on blue, then back to white on black.
+    assert_eq!(vga.ioctl(IOCTL_SET_COLORS, 0x1E), Ok(0));
+    vga.write(b"In colors\n").unwrap();
+    assert_eq!(vga.ioctl(IOCTL_SET_COLORS, 0x0F), Ok(0));
+    assert_eq!(vga.ioctl(IOCTL_SET_COLORS, 0x100), Err(Errno::EINVAL));
+}
+
+#[test_case]
+fn keyboard() {
+    let kbd = fs::open("/dev/kbd", O_RDWR).unwrap();
+    assert_eq!(kbd.write(b"a"), Err(Errno::EINVAL));
+    assert_eq!(kbd.read(&mut [0; KeyRecord::SIZE - 1]), Err(Errno::EINVAL));
+
+    // A pressed and released, in the Scancode Set 1.
+    pckbd::add_scancode(0x1E);
+    pckbd::add_scancode(0x9E);
+    let mut buf = [0; 3 * KeyRecord::SIZE];
+    assert_eq!(kbd.read(&mut buf), Ok(2 * KeyRecord::SIZE));
+    let record = |index: usize| {
+        let mut bytes = [0; KeyRecord::SIZE];
+        bytes.copy_from_slice(&buf[index * KeyRecord::SIZE..][..KeyRecord::SIZE]);
+        KeyRecord::from_bytes(bytes)
+    };
+    let modifiers = input::modifiers().bits();
+    assert_eq!(
+        record(0),
+        KeyRecord {
+            code: KeyCode::A as u8,
+            pressed: 1,
+            modifiers,
+            character: 'a' as u32,
+        }
+    );
+    assert_eq!((record(1).pressed, record(1).character), (0, 0));
+
+    pckbd::add_scancode(0x1E);
+    assert_eq!(kbd.ioctl(IOCTL_FLUSH_INPUT, 0), Ok(0));
+    assert_eq!(input::poll_key(), None);
+
+    assert_eq!(kbd.ioctl(IOCTL_MODIFIERS, 0), Ok(modifiers.into()));
+    let layout = kbd.ioctl(IOCTL_GET_LAYOUT, 0).unwrap();
+    assert_eq!(LAYOUTS[layout as usize].1, input::layout());
+    assert_eq!(kbd.ioctl(IOCTL_SET_LAYOUT, 1), Ok(0));
+    assert_eq!(input::layout(), LAYOUTS[1].1);
+    assert_eq!(
+        kbd.ioctl(IOCTL_SET_LAYOUT, LAYOUTS.len() as u64),
+        Err(Errno::EINVAL)
+    );
+    kbd.ioctl(IOCTL_SET_LAYOUT, layout).unwrap();
+}
+
+#[test_case]
+fn console_input() {
+    let console = fs::open("/dev/console", O_RDONLY).unwrap();
+    let layout = input::layout();
+    input::set_layout(LAYOUTS[2].1);
+
+    // A, then the key typing a ß in the German layout, pressed and released.
+    for &scancode in &[0x1E, 0x9E, 0x0C, 0x8C] {
+        pckbd::add_scancode(scancode);
+    }
+    let mut read = Vec::new();
+    let mut byte = [0; 1];
+    for _ in 0..3 {
+        assert_eq!(console.read(&mut byte), Ok(1));
+        read.push(byte[0]);
+    }
+    assert_eq!(read, "aß".as_bytes());
+
+    pckbd::add_scancode(0x0C);
+    assert_eq!(console.read(&mut byte), Ok(1));
+    assert_eq!(console.ioctl(IOCTL_FLUSH_INPUT, 0), Ok(0));
+    rx::add_byte(b'x');
+    assert_eq!(console.read(&mut byte), Ok(1));
+    assert_eq!(&byte, b"x");
+    input::set_layout(layout);
+}
+
+#[test_case]
+fn serial_ports() {
+    let tty = fs::open("/dev/ttyS0", O_RDWR).unwrap();
+    // A comment in the output of the tests.
+    assert_eq!(tty.write(b"# Written to /dev/ttyS0\n"), Ok(24));
+
+    rx::add_byte(b'h');
+    rx::add_byte(b'i');
+    let mut buf = [0; 8];
+    assert_eq!(tty.read(&mut buf), Ok(2));
+    assert_eq!(&buf[..2], b"hi");
+
+    rx::add_byte(b'x');
+    assert_eq!(tty.ioctl(IOCTL_FLUSH_INPUT, 0), Ok(0));
+    assert_eq!(rx::read_byte(), None);
+    assert_eq!(tty.ioctl(IOCTL_WINSIZE, 0), Err(Errno::ENOTTY));
+
+    let second = fs::open("/dev/ttyS1", O_RDWR).unwrap();
+    assert_eq!(second.ioctl(IOCTL_FLUSH_INPUT, 0), Ok(0));
+    assert_ne!(second.stat().unwrap().ino, tty.stat().unwrap().ino);
+}
@@ -0,0 +1,169 @@
+//! Executables are loaded in their own address space and start in ring 3,
+//! with their arguments, environment and thread local storage.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{panic::PanicInfo, slice};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    elf::{self, Elf, ProgramHeader, PF_R, PF_W, PF_X, PT_LOAD, PT_TLS},
+    hlt_loop, mem,
+    process::{self, ExitStatus},
+    testing, thread,
+};
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Page the program stores its results in, mapped in the kernel page table,
+/// so in every address space.
+const RESULTS: u64 = 0x4002_0000;
+
+/// Where the program is linked.
+const TEXT: u64 = 0x4000_0000_0000;
+const DATA: u64 = TEXT + 0x1200;
+
+/// Size of the executable file.
+const FILE_SIZE: usize = 0x210;
+/// Offsets of the code, and of the data, that ends with the TLS template.
+const CODE_OFFSET: usize = 0x100;
+const DATA_OFFSET: usize = 0x200;
+
+/// Stores what it finds from the results page on, then exits.
+#[rustfmt::skip]
+const CODE: [u8; 104] = [
+    0x48, 0xbb, 0x00, 0x00, 0x02, 0x40, 0x00, 0x00, 0x00, 0x00, // mov rbx, RESULTS
+    0x48, 0x8b, 0x04, 0x24,                         // mov rax, [rsp]
+    0x48, 0x89, 0x03,                               // mov [rbx], rax
+    0x48, 0x8b, 0x44, 0x24, 0x08,                   // mov rax, [rsp + 8]
+    0x0f, 0xb6, 0x00,                               // movzx eax, byte ptr [rax]
+    0x48, 0x89, 0x43, 0x08,                         // mov [rbx + 8], rax
+    0x48, 0x8b, 0x44, 0x24, 0x18,                   // mov rax, [rsp + 24]
+    0x0f, 0xb6, 0x00,                               // movzx eax, byte ptr [rax]
+    0x48, 0x89, 0x43, 0x10,                         // mov [rbx + 16], rax
+    0x64, 0x48, 0x8b, 0x04, 0x25, 0x00, 0x00, 0x00, 0x00, // mov rax, fs:0
+    0x48, 0x89, 0x43, 0x18,                         // mov [rbx + 24], rax
+    0x64, 0x48, 0x8b, 0x04, 0x25, 0xf0, 0xff, 0xff, 0xff, // mov rax, fs:-16
+    0x48, 0x89, 0x43, 0x20,                         // mov [rbx + 32], rax
+    0x48, 0x8b, 0x05, 0xb6, 0x10, 0x00, 0x00,       // mov rax, [rip + DATA]
+    0x48, 0x89, 0x43, 0x28,                         // mov [rbx + 40], rax
+    0x48, 0x8b, 0x05, 0xbb, 0x10, 0x00, 0x00,       // mov rax, [rip + DATA + 16]
+    0x48, 0x89, 0x43, 0x30,                         // mov [rbx + 48], rax
+    0x48, 0x89, 0x63, 0x38,                         // mov [rbx + 56], rsp
+    0x31, 0xff,                                     // xor edi, edi
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// The text, the data, and the TLS template at the end of the data.
+const SEGMENTS: [ProgramHeader; 3] = [
+    ProgramHeader {
+        kind: PT_LOAD,
+        flags: PF_R | PF_X,
+        offset: 0,
+        vaddr: TEXT,
+        file_size: 0x168,
+        mem_size: 0x168,
+        align: 0x1000,
+    },
+    ProgramHeader {
+        kind: PT_LOAD,
+        flags: PF_R | PF_W,
+        offset: 0x200,
+        vaddr: DATA,
+        file_size: 0x10,
+        mem_size: 0x1000,
+        align: 0x1000,
+    },
+    ProgramHeader {
+        kind: PT_TLS,
+        flags: PF_R,
+        offset: 0x208,
+        vaddr: DATA + 8,
+        file_size: 8,
+        mem_size: 16,
+        align: 16,
+    },
+];
+
+/// Builds the executable: the header, the program headers, the code, then the
+/// data.
+fn executable() -> [u8; FILE_SIZE] {
+    let mut file = [0; FILE_SIZE];
+    testing::elf::build(&mut file, TEXT + CODE_OFFSET as u64, &SEGMENTS);
+    file[CODE_OFFSET..][..CODE.len()].copy_from_slice(&CODE);
+    file[DATA_OFFSET..][..8].copy_from_slice(&0xda7au64.to_le_bytes());
+    file[DATA_OFFSET + 8..][..8].copy_from_slice(&0x715u64.to_le_bytes());
+    file
+}
+
+#[test_case]
+fn run_executable() {
+    let results = Page::containing_address(VirtAddr::new(RESULTS));
+    mem::map_user_page(results, PageTableFlags::WRITABLE).unwrap();
+
+    let file = executable();
+    let elf = Elf::parse(&file).unwrap();
+    let image = elf::load(&elf, &["argument"], &["environment"]).unwrap();
+    let stack = image.stack().as_u64();
+    let thread_pointer = image.thread_pointer().unwrap().as_u64();
+    assert_eq!(stack % 16, 0);
+    assert!(stack < elf::STACK_TOP && stack >= elf::STACK_TOP - elf::STACK_SIZE);
+    // After the data segment, at the end of the 16 bytes TLS block
+    assert_eq!(thread_pointer, TEXT + 0x3000 + 16);
+    drop(image);
+
+    // Loaded again the same way
+    let pid = process::spawn("program", &file, &["argument"], &["environment"]).unwrap();
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(0)));
+
+    let results = unsafe { slice::from_raw_parts(RESULTS as *const u64, 8) };
+    // argc, first letters of argv[0] and envp[0]
+    assert_eq!(results[0], 1);
+    assert_eq!(results[1], u64::from(b'a'));
+    assert_eq!(results[2], u64::from(b'e'));
+    // The thread pointer points to itself, after the TLS block
+    assert_eq!(results[3], thread_pointer);
+    assert_eq!(results[4], 0x715);
+    // Data, then zeroed memory past its contents
+    assert_eq!(results[5], 0xda7a);
+    assert_eq!(results[6], 0);
+    assert_eq!(results[7], stack);
+}
+
+#[test_case]
+fn reject_kernel_addresses() {
+    let mut file = executable();
+    // Below 512 GiB, where the kernel is
+    let low = 0x40_0000u64;
+    file[64 + 16..][..8].copy_from_slice(&low.to_le_bytes());
+    file[24..32].copy_from_slice(&(low + CODE_OFFSET as u64).to_le_bytes());
+
+    let elf = Elf::parse(&file).unwrap();
+    assert_eq!(
+        elf::load(&elf, &[], &[]).err(),
+        Some("Page used by the kernel")
+    );
+}
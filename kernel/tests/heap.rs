@@ -0,0 +1,75 @@
+//! The kernel heap, that `alloc` allocates from, is mapped by `mem::init` and
+//! reuses what is freed.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    hlt_loop,
+    mem::{
+        self,
+        heap::{self, HEAP_SIZE},
+    },
+    testing,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    unsafe { mem::init(boot_info) }.unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn boxes_and_vectors() {
+    let a = Box::new(41);
+    let b = Box::new(13);
+    assert_eq!(*a + *b, 54);
+
+    let mut numbers = Vec::new();
+    for n in 0..1000 {
+        numbers.push(n);
+    }
+    assert_eq!(numbers.iter().sum::<u64>(), 999 * 1000 / 2);
+}
+
+#[test_case]
+fn freed_memory_is_reused() {
+    let before = heap::stats();
+    assert_eq!(before.size, HEAP_SIZE);
+
+    let block = alloc::vec![0u8; 64 * 1024];
+    assert!(heap::stats().used >= before.used + block.len());
+    drop(block);
+    assert_eq!(heap::stats(), before);
+
+    // Far more than the heap holds, a block at a time.
+    for n in 0..HEAP_SIZE / 1024 {
+        let block = Box::new([n as u8; 4096]);
+        assert_eq!(block[4095], n as u8);
+    }
+    assert_eq!(heap::stats(), before);
+}
+
+#[test_case]
+fn large_allocation() {
+    let mut large = Vec::<u8>::with_capacity(HEAP_SIZE / 2);
+    large.resize(HEAP_SIZE / 2, 0xAB);
+    assert!(large.iter().all(|&byte| byte == 0xAB));
+}
@@ -0,0 +1,87 @@
+//! The files of the initial ramdisk linked into the kernel are unpacked into
+//! the root when it is mounted.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, vec::Vec};
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    fs::{
+        self,
+        initrd::{self, ARCHIVE},
+        FileType, O_RDONLY,
+    },
+    hlt_loop, mem,
+    syscall::Errno,
+    testing, thread,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    fs::init().unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Contents of the file at `path`.
+fn read(path: &str) -> Vec<u8> {
+    let file = fs::open(path, O_RDONLY).unwrap();
+    let mut contents = alloc::vec![0; file.stat().unwrap().size as usize];
+    assert_eq!(file.read(&mut contents), Ok(contents.len()));
+    contents
+}
+
+#[test_case]
+fn files_of_the_image() {
+    assert_eq!(fs::stat("/etc").unwrap().kind, FileType::Directory);
+    assert_eq!(read("/etc/motd"), &include_bytes!("../initrd/etc/motd")[..]);
+    assert_eq!(
+        read("/etc/hostname"),
+        &include_bytes!("../initrd/etc/hostname")[..]
+    );
+}
+
+#[test_case]
+fn unpacking() {
+    fs::mkdir("/copy").unwrap();
+    let count = initrd::entries(ARCHIVE).unwrap().count();
+    assert_eq!(initrd::unpack(ARCHIVE, "/copy"), Ok(count));
+    // Again, over the same files.
+    assert_eq!(initrd::unpack(ARCHIVE, "/copy/"), Ok(count));
+
+    for entry in initrd::entries(ARCHIVE).unwrap() {
+        let entry = entry.unwrap();
+        let path = format!("/copy/{}", entry.name);
+        assert_eq!(fs::lstat(&path).unwrap().kind, entry.kind);
+        if entry.kind == FileType::Regular {
+            assert_eq!(read(&path), entry.data);
+        }
+    }
+}
+
+#[test_case]
+fn broken_archives() {
+    assert_eq!(initrd::unpack(b"Not an archive", "/"), Err(Errno::EINVAL));
+    assert_eq!(
+        initrd::unpack(&ARCHIVE[..ARCHIVE.len() - 4], "/"),
+        Err(Errno::EINVAL)
+    );
+    assert_eq!(initrd::unpack(ARCHIVE, "/missing"), Err(Errno::ENOENT));
+}
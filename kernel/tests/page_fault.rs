@@ -0,0 +1,83 @@
+//! Writing to a page of the kernel code raises a page fault, as the code is
+//! mapped read-only.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::{
+    panic::PanicInfo,
+    ptr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    hlt_loop,
+    init::gdt,
+    qemu::{exit_qemu, QemuExitCode},
+    s1println,
+};
+use lazy_static::lazy_static;
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable as Idt, InterruptStackFrame, PageFaultErrorCode},
+};
+
+lazy_static! {
+    static ref TEST_IDT: Idt = {
+        let mut idt = Idt::new();
+        idt.page_fault.set_handler_fn(page_fault_handler);
+
+        idt
+    };
+}
+
+/// Address written to.
+static TARGET: AtomicU64 = AtomicU64::new(0);
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    s1println!("TAP version 13");
+    s1println!("1..1");
+
+    gdt::init().unwrap();
+    TEST_IDT.load();
+
+    let target = code_page as usize as *mut u8;
+    TARGET.store(target as u64, Ordering::SeqCst);
+    unsafe { ptr::write_volatile(target, 0x90) };
+
+    panic!("Write to a code page did not fault");
+}
+
+/// Some function, whose code is written to.
+fn code_page() {}
+
+extern "x86-interrupt" fn page_fault_handler(
+    _stack_frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let address = Cr2::read().as_u64();
+    let expected = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+
+    if address == TARGET.load(Ordering::SeqCst) && error_code.contains(expected) {
+        s1println!("ok 1 - page_fault::write_to_code_page");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        s1println!("not ok 1 - page_fault::write_to_code_page");
+        s1println!("# fault at {:#x}, {:?}", address, error_code);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    s1println!("not ok 1 - page_fault::write_to_code_page");
+    s1println!("# {}", info);
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
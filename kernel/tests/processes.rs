@@ -0,0 +1,273 @@
+//! Processes run in address spaces of their own, freed when they exit. They
+//! fork children sharing their pages copy-on-write, wait for them, and
+//! replace their program.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{panic::PanicInfo, time::Duration};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    elf::{ProgramHeader, PF_R, PF_W, PF_X, PT_LOAD},
+    hlt_loop, mem,
+    process::{self, signal, ExitStatus, Pid, State},
+    testing, thread,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Where the programs are linked, followed by a page of data.
+const TEXT: u64 = 0x4000_0000_0000;
+
+/// Offset of the code in the executables.
+const CODE_OFFSET: usize = 0xc0;
+
+/// Stores its pid in its data page, sleeps, then exits with what it finds
+/// there.
+#[rustfmt::skip]
+const PID_KEEPER: [u8; 42] = [
+    0xb8, 0x06, 0x00, 0x00, 0x00,                   // mov eax, GETPID
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x05, 0x32, 0x0f, 0x00, 0x00,       // mov [rip + DATA], rax
+    0xbf, 0x1e, 0x00, 0x00, 0x00,                   // mov edi, 30
+    0xb8, 0x04, 0x00, 0x00, 0x00,                   // mov eax, SLEEP
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x8b, 0x3d, 0x26, 0x0f, 0x00, 0x00,       // mov rdi, [rip + DATA]
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// Forks, then the child writes 2 to the data page and exits with 7, while
+/// the parent writes 1 there, waits for the child, and exits with its status
+/// ORed with what it finds in the data page.
+#[rustfmt::skip]
+const FORKER: [u8; 104] = [
+    0x48, 0xbd, 0x00, 0x10, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, // mov rbp, TEXT + 0x1000
+    0x48, 0xc7, 0x45, 0x00, 0x01, 0x00, 0x00, 0x00, // mov qword ptr [rbp], 1
+    0xb8, 0x07, 0x00, 0x00, 0x00,                   // mov eax, FORK
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x85, 0xc0,                               // test rax, rax
+    0x74, 0x34,                                     // jz child
+    0x48, 0x89, 0xc3,                               // mov rbx, rax
+    0x48, 0x89, 0xc7,                               // mov rdi, rax
+    0x48, 0x8d, 0x74, 0x24, 0xf8,                   // lea rsi, [rsp - 8]
+    0x31, 0xd2,                                     // xor edx, edx
+    0xb8, 0x09, 0x00, 0x00, 0x00,                   // mov eax, WAITPID
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x39, 0xd8,                               // cmp rax, rbx
+    0x75, 0x0f,                                     // jne bad
+    0x8b, 0x7c, 0x24, 0xf8,                         // mov edi, [rsp - 8]
+    0x48, 0x0b, 0x7d, 0x00,                         // or rdi, [rbp]
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0xbf, 0xff, 0x00, 0x00, 0x00,                   // bad: mov edi, 255
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x48, 0xc7, 0x45, 0x00, 0x02, 0x00, 0x00, 0x00, // child: mov qword ptr [rbp], 2
+    0xbf, 0x07, 0x00, 0x00, 0x00,                   // mov edi, 7
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// Where `EXECUTOR` keeps the arguments and the executable it runs, in its
+/// file and in memory.
+const ARGV_OFFSET: usize = 0x180;
+const EXECUTABLE_OFFSET: usize = 0x200;
+
+/// Runs the executable at `EXECUTABLE_OFFSET` with the arguments at
+/// `ARGV_OFFSET`, or exits with the error.
+#[rustfmt::skip]
+const EXECUTOR: [u8; 47] = [
+    0x48, 0xbf, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, // mov rdi, TEXT + 0x200
+    0xbe, 0x00, 0x02, 0x00, 0x00,                   // mov esi, 0x200
+    0x48, 0xba, 0x80, 0x01, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, // mov rdx, TEXT + 0x180
+    0x45, 0x31, 0xd2,                               // xor r10d, r10d
+    0xb8, 0x08, 0x00, 0x00, 0x00,                   // mov eax, EXEC
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0xc7,                               // mov rdi, rax
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// Exits with its argument count plus 40.
+#[rustfmt::skip]
+const ARGUMENT_COUNTER: [u8; 17] = [
+    0x48, 0x8b, 0x3c, 0x24,                         // mov rdi, [rsp]
+    0x48, 0x83, 0xc7, 0x28,                         // add rdi, 40
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// Builds an executable running `code`, with a page of data after it.
+fn executable(code: &[u8]) -> [u8; 0x200] {
+    let mut file = [0; 0x200];
+    build(&mut file, code);
+    file
+}
+
+/// Builds in `file` an executable running `code`, whose text is the whole
+/// file, with a page of data after it.
+fn build(file: &mut [u8], code: &[u8]) {
+    let text_size = file.len() as u64;
+    let segments = [
+        ProgramHeader {
+            kind: PT_LOAD,
+            flags: PF_R | PF_X,
+            offset: 0,
+            vaddr: TEXT,
+            file_size: text_size,
+            mem_size: text_size,
+            align: 0x1000,
+        },
+        ProgramHeader {
+            kind: PT_LOAD,
+            flags: PF_R | PF_W,
+            offset: 0,
+            vaddr: TEXT + 0x1000,
+            file_size: 0,
+            mem_size: 8,
+            align: 0x1000,
+        },
+    ];
+    testing::elf::build(file, TEXT + CODE_OFFSET as u64, &segments);
+
+    file[CODE_OFFSET..][..code.len()].copy_from_slice(code);
+}
+
+/// Builds an executable that runs `ARGUMENT_COUNTER` with the arguments "b"
+/// and "c", breaking its header if `broken`.
+fn executor(broken: bool) -> [u8; 0x400] {
+    let mut file = [0; 0x400];
+    build(&mut file, &EXECUTOR);
+
+    let strings = TEXT + ARGV_OFFSET as u64 + 0x20;
+    for (index, pointer) in [strings, strings + 2, 0].iter().enumerate() {
+        file[ARGV_OFFSET + index * 8..][..8].copy_from_slice(&pointer.to_le_bytes());
+    }
+    file[ARGV_OFFSET + 0x20..][..4].copy_from_slice(b"b\0c\0");
+
+    let counter = executable(&ARGUMENT_COUNTER);
+    file[EXECUTABLE_OFFSET..][..counter.len()].copy_from_slice(&counter);
+    if broken {
+        file[EXECUTABLE_OFFSET] = 0;
+    }
+    file
+}
+
+/// Sleeps until the process `pid` exits.
+fn wait_exit(pid: Pid) {
+    while process::info(pid).unwrap().state == State::Running {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn allocated_frames() -> usize {
+    mem::frame_stats().unwrap().allocated
+}
+
+#[test_case]
+fn separate_address_spaces() {
+    let file = executable(&PID_KEEPER);
+    let allocated = allocated_frames();
+
+    // Both store their pid at the same address, then sleep side by side.
+    let first = process::spawn("first", &file, &["first"], &[]).unwrap();
+    let second = process::spawn("second", &file, &["second"], &[]).unwrap();
+    assert_ne!(first, second);
+
+    let info = process::info(second).unwrap();
+    assert_eq!(info.name.as_str(), "second");
+    assert_eq!(info.state, State::Running);
+    assert_eq!(info.parent, None);
+    assert!(info.pages > 0);
+    assert_eq!(process::list().iter().flatten().count(), 2);
+    process::ps();
+
+    let exited = |pid: process::Pid| ExitStatus::Exited(pid.as_u64() as i32);
+    assert_eq!(process::wait(first), Ok(exited(first)));
+    assert_eq!(process::wait(second), Ok(exited(second)));
+
+    // Every page and table freed
+    assert_eq!(allocated_frames(), allocated);
+    assert!(process::list().iter().all(Option::is_none));
+}
+
+#[test_case]
+fn fault_ends_process() {
+    // hlt
+    let file = executable(&[0xf4]);
+    let allocated = allocated_frames();
+
+    let pid = process::spawn("halter", &file, &[], &[]).unwrap();
+    assert_eq!(
+        process::wait(pid),
+        Ok(ExitStatus::Signaled(signal::SIGSEGV))
+    );
+    assert_eq!(allocated_frames(), allocated);
+
+    assert_eq!(process::wait(pid), Err("No such process"));
+}
+
+#[test_case]
+fn fork_and_wait() {
+    let file = executable(&FORKER);
+    let allocated = allocated_frames();
+
+    let pid = process::spawn("forker", &file, &[], &[]).unwrap();
+    // The child exited with 7, and its write did not reach the parent.
+    assert_eq!(
+        process::waitpid(Some(pid), true),
+        Ok(Some((pid, ExitStatus::Exited(0x701))))
+    );
+    assert_eq!(allocated_frames(), allocated);
+    assert!(process::list().iter().all(Option::is_none));
+}
+
+#[test_case]
+fn wait_without_blocking() {
+    let file = executable(&PID_KEEPER);
+
+    let pid = process::spawn("sleeper", &file, &[], &[]).unwrap();
+    assert_eq!(process::waitpid(Some(pid), false), Ok(None));
+    assert_eq!(
+        process::waitpid(None, true),
+        Ok(Some((pid, ExitStatus::Exited(pid.as_u64() as i32))))
+    );
+    assert_eq!(process::waitpid(None, false), Err("No child processes"));
+}
+
+#[test_case]
+fn exec_replaces_program() {
+    let allocated = allocated_frames();
+
+    let pid = process::spawn("executor", &executor(false), &[], &[]).unwrap();
+    wait_exit(pid);
+    assert_eq!(process::info(pid).unwrap().name.as_str(), "b");
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(42)));
+
+    // Back from exec with ENOEXEC
+    let pid = process::spawn("executor", &executor(true), &[], &[]).unwrap();
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(-8)));
+    assert_eq!(allocated_frames(), allocated);
+}
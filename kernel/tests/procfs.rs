@@ -0,0 +1,206 @@
+//! The state of the kernel is read as text from the files of `/proc`.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::{panic::PanicInfo, time::Duration};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    fs::{self, FileType, SeekFrom, O_CREAT, O_RDONLY, O_RDWR, O_WRONLY},
+    hlt_loop,
+    init::idt::{self, TIMER_INTERRUPT_ID},
+    kprintln, mem,
+    syscall::Errno,
+    testing, thread,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    fs::init().unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Contents of the file at `path`, read a few bytes at a time.
+fn read(path: &str) -> String {
+    let file = fs::open(path, O_RDONLY).unwrap();
+    let mut contents = Vec::new();
+    let mut buf = [0; 7];
+    loop {
+        match file.read(&mut buf).unwrap() {
+            0 => break,
+            read => contents.extend_from_slice(&buf[..read]),
+        }
+    }
+    assert_eq!(file.stat().unwrap().size, contents.len() as u64);
+    String::from_utf8(contents).unwrap()
+}
+
+/// The number after `key` in `text`, on the line starting with it.
+fn field(text: &str, key: &str) -> u64 {
+    let line = text.lines().find(|line| line.starts_with(key)).unwrap();
+    line[key.len()..]
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// Count of `vector` in `/proc/interrupts`.
+fn interrupts(vector: u8) -> u64 {
+    let key = alloc::format!("{:>3}:", vector);
+    field(&read("/proc/interrupts"), &key)
+}
+
+#[test_case]
+fn kernel_state_files() {
+    let proc = fs::open("/proc", O_RDONLY).unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = proc.readdir().unwrap() {
+        assert_eq!(entry.kind, FileType::Regular);
+        let path = alloc::format!("/proc/{}", entry.name);
+        assert_eq!(fs::stat(&path).unwrap().ino, entry.ino);
+        names.push(entry.name);
+    }
+    assert_eq!(
+        names,
+        [
+            "cpuinfo",
+            "interrupts",
+            "kmsg",
+            "meminfo",
+            "memmap",
+            "processes",
+            "uptime"
+        ]
+    );
+
+    assert_eq!(fs::open("/proc/uptime", O_WRONLY).err(), Some(Errno::EPERM));
+    assert_eq!(fs::open("/proc/uptime", O_RDWR).err(), Some(Errno::EPERM));
+    assert_eq!(
+        fs::open("/proc/new", O_WRONLY | O_CREAT).err(),
+        Some(Errno::EPERM)
+    );
+    assert_eq!(fs::unlink("/proc/kmsg"), Err(Errno::EPERM));
+}
+
+#[test_case]
+fn read_as_opened() {
+    let file = fs::open("/proc/meminfo", O_RDONLY).unwrap();
+    let size = file.stat().unwrap().size;
+    assert!(size > 0);
+
+    let mut start = [0; 9];
+    assert_eq!(file.read(&mut start), Ok(9));
+    assert_eq!(&start, b"MemTotal:");
+    assert_eq!(file.seek(SeekFrom::End(-1)), Ok(size - 1));
+    let mut end = [0; 4];
+    assert_eq!(file.read(&mut end), Ok(1));
+    assert_eq!(end[0], b'\n');
+    assert_eq!(file.read(&mut end), Ok(0));
+    assert_eq!(
+        file.seek(SeekFrom::Current(-(size as i64) - 1)),
+        Err(Errno::EINVAL)
+    );
+}
+
+#[test_case]
+fn memory() {
+    let meminfo = read("/proc/meminfo");
+    let frames = mem::frame_stats().unwrap();
+    assert_eq!(field(&meminfo, "FramesUsable:"), frames.usable as u64);
+    assert_eq!(field(&meminfo, "MemTotal:"), frames.usable as u64 * 4);
+    assert!(field(&meminfo, "FramesAllocated:") > 0);
+    assert!(field(&meminfo, "HeapUsed:") <= field(&meminfo, "HeapTotal:"));
+
+    let memmap = read("/proc/memmap");
+    let regions = mem::memory_map().unwrap();
+    assert_eq!(memmap.lines().count(), regions.len());
+    for (line, region) in memmap.lines().zip(regions.iter()) {
+        let mut words = line.split(|c| c == '-' || c == ' ');
+        let start = u64::from_str_radix(words.next().unwrap(), 16).unwrap();
+        let end = u64::from_str_radix(words.next().unwrap(), 16).unwrap();
+        assert_eq!(
+            (start, end),
+            (region.range.start_addr(), region.range.end_addr())
+        );
+    }
+    assert!(memmap.lines().any(|line| line.ends_with(" Usable")));
+}
+
+#[test_case]
+fn interrupt_counts() {
+    let breakpoints = interrupts(3);
+    x86_64::instructions::interrupts::int3();
+    assert_eq!(interrupts(3), breakpoints + 1);
+    assert_eq!(interrupts(3), idt::interrupt_count(3));
+
+    let ticks = interrupts(TIMER_INTERRUPT_ID);
+    thread::sleep(Duration::from_millis(50));
+    assert!(interrupts(TIMER_INTERRUPT_ID) > ticks);
+    assert!(read("/proc/interrupts").contains(" page fault\n"));
+}
+
+#[test_case]
+fn uptime() {
+    let hundredths = |text: &str| {
+        let (secs, fraction) = text.trim_end().split_at(text.trim_end().len() - 3);
+        assert_eq!(&fraction[..1], ".");
+        secs.parse::<u64>().unwrap() * 100 + fraction[1..].parse::<u64>().unwrap()
+    };
+    let file = fs::open("/proc/uptime", O_RDONLY).unwrap();
+    let before = read("/proc/uptime");
+    thread::sleep(Duration::from_millis(100));
+    assert!(hundredths(&read("/proc/uptime")) > hundredths(&before));
+
+    // The open file still reads what it did when opened.
+    let mut buf = [0; 32];
+    let len = file.read(&mut buf).unwrap();
+    let opened = core::str::from_utf8(&buf[..len]).unwrap();
+    assert!(hundredths(opened) <= hundredths(&before));
+}
+
+#[test_case]
+fn processes_and_log() {
+    let processes = read("/proc/processes");
+    let header = processes.lines().next().unwrap();
+    assert_eq!(
+        header.split_whitespace().collect::<Vec<_>>(),
+        ["PID", "PPID", "NAME", "THREAD", "PAGES", "STATE"]
+    );
+
+    kprintln!("Written to the kernel log");
+    assert!(read("/proc/kmsg").contains("] Written to the kernel log\n"));
+}
+
+#[test_case]
+fn cpu_features() {
+    let cpuinfo = read("/proc/cpuinfo");
+    assert!(cpuinfo.starts_with("vendor_id  : "));
+    let flags = cpuinfo
+        .lines()
+        .find(|line| line.starts_with("flags"))
+        .unwrap();
+    let flags: Vec<_> = flags.split_whitespace().collect();
+    // Every x86_64 CPU has these.
+    for flag in ["fpu", "sse2", "syscall", "lm"].iter() {
+        assert!(flags.contains(flag), "{} missing", flag);
+    }
+}
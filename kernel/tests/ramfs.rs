@@ -0,0 +1,203 @@
+//! The root file system lives in memory: files grow sparsely, shrink, and
+//! outlive their names while they are open. They can not fill the heap.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    fs::{
+        self,
+        ramfs::{self, MAX_FILE_SIZE, MAX_PAGES, PAGE_SIZE},
+        FileType, SeekFrom, O_CREAT, O_RDONLY, O_RDWR, O_WRONLY,
+    },
+    hlt_loop,
+    mem::{
+        self,
+        heap::{self, HEAP_SIZE},
+    },
+    syscall::Errno,
+    testing, thread,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    fs::init().unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+fn heap_used() -> usize {
+    heap::stats().used
+}
+
+/// Reads `len` bytes of the file at `path` from `offset`.
+fn read(path: &str, offset: u64, len: usize) -> Vec<u8> {
+    let file = fs::open(path, O_RDONLY).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    let mut buf = alloc::vec![0xff; len];
+    let read = file.read(&mut buf).unwrap();
+    buf.truncate(read);
+    buf
+}
+
+#[test_case]
+fn root_file_system() {
+    assert!(fs::init().is_err());
+    assert_eq!(fs::stat("/").unwrap().kind, FileType::Directory);
+}
+
+#[test_case]
+fn sparse_files() {
+    let file = fs::open("/sparse", O_RDWR | O_CREAT).unwrap();
+    let used = heap_used();
+
+    // Only the page written to takes memory.
+    let end = 1 << 20;
+    file.seek(SeekFrom::Start(end)).unwrap();
+    assert_eq!(file.write(b"end"), Ok(3));
+    assert!(heap_used() - used < 2 * PAGE_SIZE);
+    assert_eq!(fs::stat("/sparse").unwrap().size, end + 3);
+
+    assert_eq!(read("/sparse", 0, 16), [0; 16]);
+    assert_eq!(read("/sparse", end - 2, 16), b"\0\0end");
+    assert_eq!(read("/sparse", end + 3, 16), b"");
+
+    // Across pages
+    let pattern: Vec<u8> = (0..PAGE_SIZE + 100).map(|index| index as u8).collect();
+    file.seek(SeekFrom::Start(PAGE_SIZE as u64 - 50)).unwrap();
+    assert_eq!(file.write(&pattern), Ok(pattern.len()));
+    assert_eq!(
+        read("/sparse", PAGE_SIZE as u64 - 50, pattern.len()),
+        pattern
+    );
+
+    drop(file);
+    fs::unlink("/sparse").unwrap();
+    assert!(heap_used() <= used);
+}
+
+#[test_case]
+fn truncation() {
+    let file = fs::open("/cut", O_WRONLY | O_CREAT).unwrap();
+    file.write(b"abcdef").unwrap();
+
+    fs::truncate("/cut", 3).unwrap();
+    assert_eq!(read("/cut", 0, 16), b"abc");
+    // What was cut off does not come back.
+    fs::truncate("/cut", 6).unwrap();
+    assert_eq!(read("/cut", 0, 16), b"abc\0\0\0");
+    fs::truncate("/cut", 3 * PAGE_SIZE as u64).unwrap();
+    assert_eq!(fs::stat("/cut").unwrap().size, 3 * PAGE_SIZE as u64);
+    assert_eq!(read("/cut", 2 * PAGE_SIZE as u64, 4), [0; 4]);
+
+    let used = heap_used();
+    fs::truncate("/cut", 0).unwrap();
+    assert!(heap_used() < used);
+    assert_eq!(read("/cut", 0, 16), b"");
+
+    assert_eq!(fs::truncate("/cut", MAX_FILE_SIZE + 1), Err(Errno::EFBIG));
+    file.seek(SeekFrom::Start(MAX_FILE_SIZE)).unwrap();
+    assert_eq!(file.write(b"x"), Err(Errno::EFBIG));
+    assert_eq!(fs::truncate("/", 0), Err(Errno::EISDIR));
+}
+
+#[test_case]
+fn directories() {
+    fs::mkdir("/tree").unwrap();
+    for name in &["cherry", "apple", "banana"] {
+        fs::mkdir(&alloc::format!("/tree/{}", name)).unwrap();
+    }
+    fs::open("/tree/apple/seed", O_CREAT).unwrap();
+
+    let tree = fs::open("/tree", O_RDONLY).unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = tree.readdir().unwrap() {
+        assert_eq!(entry.kind, FileType::Directory);
+        names.push(entry.name);
+    }
+    assert_eq!(names, ["apple", "banana", "cherry"]);
+
+    let stat = fs::stat("/tree").unwrap();
+    assert_eq!((stat.size, stat.nlink), (3, 5));
+    assert_eq!(fs::stat("/tree/apple/seed").unwrap().nlink, 1);
+
+    assert_eq!(fs::rmdir("/tree/apple"), Err(Errno::ENOTEMPTY));
+    assert_eq!(fs::mkdir("/tree/apple/seed/x"), Err(Errno::ENOTDIR));
+    assert_eq!(fs::mkdir("/tree/apple"), Err(Errno::EEXIST));
+    fs::unlink("/tree/apple/seed").unwrap();
+    fs::rmdir("/tree/apple").unwrap();
+    assert_eq!(fs::stat("/tree").unwrap().nlink, 4);
+}
+
+#[test_case]
+fn symbolic_links() {
+    fs::mkdir("/var").unwrap();
+    let file = fs::open("/var/hostname", O_WRONLY | O_CREAT).unwrap();
+    file.write(b"kernel").unwrap();
+    fs::symlink("/var/hostname", "/hostname").unwrap();
+    fs::symlink("missing", "/var/dangling").unwrap();
+
+    assert_eq!(read("/hostname", 0, 16), b"kernel");
+    assert_eq!(
+        fs::readlink("/hostname").unwrap(),
+        String::from("/var/hostname")
+    );
+    let stat = fs::lstat("/hostname").unwrap();
+    assert_eq!((stat.kind, stat.size), (FileType::Symlink, 13));
+    assert_eq!(fs::stat("/var/dangling"), Err(Errno::ENOENT));
+    assert_eq!(fs::symlink("x", "/hostname"), Err(Errno::EEXIST));
+}
+
+#[test_case]
+fn unlinked_files_stay_open() {
+    let file = fs::open("/temporary", O_RDWR | O_CREAT).unwrap();
+    file.write(b"still here").unwrap();
+    fs::unlink("/temporary").unwrap();
+    assert_eq!(fs::stat("/temporary"), Err(Errno::ENOENT));
+
+    file.seek(SeekFrom::Start(0)).unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(file.read(&mut buf), Ok(10));
+    assert_eq!(&buf[..10], b"still here");
+
+    // A new file takes the name.
+    let other = fs::open("/temporary", O_RDWR | O_CREAT).unwrap();
+    assert_ne!(other.stat().unwrap().ino, file.stat().unwrap().ino);
+}
+
+#[test_case]
+fn full_file_system() {
+    let file = fs::open("/full", O_WRONLY | O_CREAT).unwrap();
+    let page = [0xaa; PAGE_SIZE];
+    for _ in ramfs::pages()..MAX_PAGES {
+        assert_eq!(file.write(&page), Ok(PAGE_SIZE));
+    }
+    assert_eq!(ramfs::pages(), MAX_PAGES);
+    assert_eq!(file.write(&page), Err(Errno::ENOSPC));
+    // The rest of the kernel still has room.
+    assert!(heap_used() < HEAP_SIZE / 2);
+
+    fs::truncate("/full", 0).unwrap();
+    assert_eq!(file.write(&page), Ok(PAGE_SIZE));
+    drop(file);
+    fs::unlink("/full").unwrap();
+    assert!(ramfs::pages() < MAX_PAGES);
+}
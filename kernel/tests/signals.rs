@@ -0,0 +1,246 @@
+//! Processes get signals from `kill` and from the exceptions of their code,
+//! which kill them, stop them, or run their handlers.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{panic::PanicInfo, time::Duration};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    elf::{ProgramHeader, PF_R, PF_W, PF_X, PT_LOAD},
+    hlt_loop, mem,
+    process::{
+        self,
+        signal::{self, Signal},
+        ExitStatus, Pid, State,
+    },
+    testing, thread,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Where the programs are linked, followed by a page of data.
+const TEXT: u64 = 0x4000_0000_0000;
+
+/// Offset of the code in the executables.
+const CODE_OFFSET: usize = 0xc0;
+
+/// Catches `SIGUSR1` and sends it to itself. The handler stores the signal in
+/// the data page and clobbers `rbx`, then the program exits with the sum of
+/// both, `rbx` being restored by `sigreturn`.
+#[rustfmt::skip]
+const CATCHER: [u8; 149] = [
+    0x48, 0xbd, 0x00, 0x10, 0x00, 0x00, 0x00, 0x40, 0x00, 0x00, // mov rbp, TEXT + 0x1000
+    0x48, 0x83, 0xec, 0x20,                         // sub rsp, 32
+    0x48, 0x8d, 0x05, 0x6d, 0x00, 0x00, 0x00,       // lea rax, [rip + handler]
+    0x48, 0x89, 0x04, 0x24,                         // mov [rsp], rax
+    0x48, 0xc7, 0x44, 0x24, 0x08, 0x00, 0x00, 0x00, 0x04, // mov qword ptr [rsp + 8], SA_RESTORER
+    0x48, 0x8d, 0x05, 0x63, 0x00, 0x00, 0x00,       // lea rax, [rip + restorer]
+    0x48, 0x89, 0x44, 0x24, 0x10,                   // mov [rsp + 16], rax
+    0x48, 0xc7, 0x44, 0x24, 0x18, 0x00, 0x00, 0x00, 0x00, // mov qword ptr [rsp + 24], 0
+    0xbf, 0x0a, 0x00, 0x00, 0x00,                   // mov edi, SIGUSR1
+    0x48, 0x89, 0xe6,                               // mov rsi, rsp
+    0x31, 0xd2,                                     // xor edx, edx
+    0xb8, 0x0b, 0x00, 0x00, 0x00,                   // mov eax, SIGACTION
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x85, 0xc0,                               // test rax, rax
+    0x75, 0x29,                                     // jnz bad
+    0xb8, 0x06, 0x00, 0x00, 0x00,                   // mov eax, GETPID
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0xc7,                               // mov rdi, rax
+    0xbe, 0x0a, 0x00, 0x00, 0x00,                   // mov esi, SIGUSR1
+    0xbb, 0x55, 0x00, 0x00, 0x00,                   // mov ebx, 0x55
+    0xb8, 0x0a, 0x00, 0x00, 0x00,                   // mov eax, KILL
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x8b, 0x7d, 0x00,                         // mov rdi, [rbp]
+    0x48, 0x01, 0xdf,                               // add rdi, rbx
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0xbf, 0xff, 0x00, 0x00, 0x00,                   // bad: mov edi, 255
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x7d, 0x00,                         // handler: mov [rbp], rdi
+    0xbb, 0x01, 0x00, 0x00, 0x00,                   // mov ebx, 1
+    0xc3,                                           // ret
+    0xb8, 0x0d, 0x00, 0x00, 0x00,                   // restorer: mov eax, SIGRETURN
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// Catches `SIGSEGV`, then reads address 0. The handler exits with the signal
+/// plus 100.
+#[rustfmt::skip]
+const SEGV_CATCHER: [u8; 72] = [
+    0x48, 0x83, 0xec, 0x20,                         // sub rsp, 32
+    0x48, 0x8d, 0x05, 0x33, 0x00, 0x00, 0x00,       // lea rax, [rip + handler]
+    0x48, 0x89, 0x04, 0x24,                         // mov [rsp], rax
+    0x48, 0xc7, 0x44, 0x24, 0x08, 0x00, 0x00, 0x00, 0x04, // mov qword ptr [rsp + 8], SA_RESTORER
+    0x48, 0x89, 0x44, 0x24, 0x10,                   // mov [rsp + 16], rax
+    0x48, 0xc7, 0x44, 0x24, 0x18, 0x00, 0x00, 0x00, 0x00, // mov qword ptr [rsp + 24], 0
+    0xbf, 0x0b, 0x00, 0x00, 0x00,                   // mov edi, SIGSEGV
+    0x48, 0x89, 0xe6,                               // mov rsi, rsp
+    0x31, 0xd2,                                     // xor edx, edx
+    0xb8, 0x0b, 0x00, 0x00, 0x00,                   // mov eax, SIGACTION
+    0x0f, 0x05,                                     // syscall
+    0x31, 0xc0,                                     // xor eax, eax
+    0x48, 0x8b, 0x00,                               // mov rax, [rax]
+    0x0f, 0x0b,                                     // ud2
+    0x83, 0xc7, 0x64,                               // handler: add edi, 100
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+];
+
+/// Blocks `SIGINT` and sends it to itself, then exits with the signals
+/// pending.
+#[rustfmt::skip]
+const PENDING: [u8; 73] = [
+    0x48, 0xc7, 0x44, 0x24, 0xf8, 0x02, 0x00, 0x00, 0x00, // mov qword ptr [rsp - 8], 1 << (SIGINT - 1)
+    0x31, 0xff,                                     // xor edi, edi
+    0x48, 0x8d, 0x74, 0x24, 0xf8,                   // lea rsi, [rsp - 8]
+    0x31, 0xd2,                                     // xor edx, edx
+    0xb8, 0x0c, 0x00, 0x00, 0x00,                   // mov eax, SIGPROCMASK
+    0x0f, 0x05,                                     // syscall
+    0xb8, 0x06, 0x00, 0x00, 0x00,                   // mov eax, GETPID
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0xc7,                               // mov rdi, rax
+    0xbe, 0x02, 0x00, 0x00, 0x00,                   // mov esi, SIGINT
+    0xb8, 0x0a, 0x00, 0x00, 0x00,                   // mov eax, KILL
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x8d, 0x7c, 0x24, 0xf0,                   // lea rdi, [rsp - 16]
+    0xb8, 0x0e, 0x00, 0x00, 0x00,                   // mov eax, SIGPENDING
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x8b, 0x7c, 0x24, 0xf0,                   // mov rdi, [rsp - 16]
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// Blocks `SIGINT`, then sleeps forever.
+#[rustfmt::skip]
+const BLOCKER: [u8; 39] = [
+    0x48, 0xc7, 0x44, 0x24, 0xf8, 0x02, 0x00, 0x00, 0x00, // mov qword ptr [rsp - 8], 1 << (SIGINT - 1)
+    0x31, 0xff,                                     // xor edi, edi
+    0x48, 0x8d, 0x74, 0x24, 0xf8,                   // lea rsi, [rsp - 8]
+    0x31, 0xd2,                                     // xor edx, edx
+    0xb8, 0x0c, 0x00, 0x00, 0x00,                   // mov eax, SIGPROCMASK
+    0x0f, 0x05,                                     // syscall
+    0xbf, 0x0a, 0x00, 0x00, 0x00,                   // again: mov edi, 10
+    0xb8, 0x04, 0x00, 0x00, 0x00,                   // mov eax, SLEEP
+    0x0f, 0x05,                                     // syscall
+    0xeb, 0xf2,                                     // jmp again
+];
+
+/// Builds an executable running `code`, with a page of data after it.
+fn executable(code: &[u8]) -> [u8; 0x200] {
+    let mut file = [0; 0x200];
+    let text_size = file.len() as u64;
+    let segments = [
+        ProgramHeader {
+            kind: PT_LOAD,
+            flags: PF_R | PF_X,
+            offset: 0,
+            vaddr: TEXT,
+            file_size: text_size,
+            mem_size: text_size,
+            align: 0x1000,
+        },
+        ProgramHeader {
+            kind: PT_LOAD,
+            flags: PF_R | PF_W,
+            offset: 0,
+            vaddr: TEXT + 0x1000,
+            file_size: 0,
+            mem_size: 8,
+            align: 0x1000,
+        },
+    ];
+    testing::elf::build(&mut file, TEXT + CODE_OFFSET as u64, &segments);
+
+    file[CODE_OFFSET..][..code.len()].copy_from_slice(code);
+    file
+}
+
+fn run(code: &[u8]) -> Result<ExitStatus, &'static str> {
+    let pid = process::spawn("signals", &executable(code), &[], &[])?;
+    process::wait(pid)
+}
+
+/// Sleeps until the process `pid` is in `state`.
+fn wait_state(pid: Pid, state: State) {
+    while process::info(pid).unwrap().state != state {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test_case]
+fn handler_and_sigreturn() {
+    assert_eq!(run(&CATCHER), Ok(ExitStatus::Exited(10 + 0x55)));
+}
+
+#[test_case]
+fn fault_signals() {
+    // xor ecx, ecx; div ecx
+    assert_eq!(
+        run(&[0x31, 0xc9, 0xf7, 0xf1]),
+        Ok(ExitStatus::Signaled(signal::SIGFPE))
+    );
+    // ud2
+    assert_eq!(run(&[0x0f, 0x0b]), Ok(ExitStatus::Signaled(signal::SIGILL)));
+    assert_eq!(ExitStatus::Signaled(signal::SIGILL).raw(), 4 | 0x80);
+
+    assert_eq!(run(&SEGV_CATCHER), Ok(ExitStatus::Exited(11 + 100)));
+}
+
+#[test_case]
+fn blocked_signals_stay_pending() {
+    assert_eq!(run(&PENDING), Ok(ExitStatus::Exited(1 << 1)));
+
+    let pid = process::spawn("blocker", &executable(&BLOCKER), &[], &[]).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    signal::kill(pid, signal::SIGINT).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(process::info(pid).unwrap().state, State::Running);
+
+    // SIGKILL can not be blocked.
+    signal::kill(pid, signal::SIGKILL).unwrap();
+    assert_eq!(
+        process::wait(pid),
+        Ok(ExitStatus::Signaled(signal::SIGKILL))
+    );
+}
+
+#[test_case]
+fn stop_and_continue() {
+    // jmp $, only interrupted by the timer
+    let pid = process::spawn("spinner", &executable(&[0xeb, 0xfe]), &[], &[]).unwrap();
+
+    signal::kill(pid, signal::SIGSTOP).unwrap();
+    wait_state(pid, State::Stopped);
+    signal::kill(pid, signal::SIGCONT).unwrap();
+    assert_eq!(process::info(pid).unwrap().state, State::Running);
+
+    signal::kill(pid, signal::SIGTERM).unwrap();
+    assert_eq!(
+        process::wait(pid),
+        Ok(ExitStatus::Signaled(signal::SIGTERM))
+    );
+    assert_eq!(Signal::new(0), None);
+    assert_eq!(signal::kill(pid, signal::SIGTERM), Err("No such process"));
+}
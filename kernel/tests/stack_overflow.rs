@@ -0,0 +1,84 @@
+//! A kernel stack overflow ends in the double fault handler, running on its
+//! own stack from the Interrupt Stack Table.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::{panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    hlt_loop,
+    init::gdt,
+    qemu::{exit_qemu, QemuExitCode},
+    s1println,
+};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable as Idt, InterruptStackFrame};
+
+lazy_static! {
+    static ref TEST_IDT: Idt = {
+        let mut idt = Idt::new();
+
+        // Needs unsafe for the set_stack_index method.
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+
+        idt
+    };
+}
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    s1println!("TAP version 13");
+    s1println!("1..1");
+
+    gdt::init().unwrap();
+    TEST_IDT.load();
+
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+
+    // Keeps the recursion from becoming a loop.
+    let zero = 0;
+    unsafe { ptr::read_volatile(&zero) };
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    _stack_frame: &mut InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let local = 0u8;
+    let address = &local as *const u8 as u64;
+    let top = gdt::double_fault_stack_top().as_u64();
+
+    if address < top && address >= top - gdt::DOUBLE_FAULT_STACK_SIZE as u64 {
+        s1println!("ok 1 - stack_overflow::double_fault_on_ist");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        s1println!("not ok 1 - stack_overflow::double_fault_on_ist");
+        s1println!("# handler stack at {:#x}, not on the IST", address);
+        exit_qemu(QemuExitCode::Failed);
+    }
+
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    s1println!("not ok 1 - stack_overflow::double_fault_on_ist");
+    s1println!("# {}", info);
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
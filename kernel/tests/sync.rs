@@ -0,0 +1,124 @@
+//! Threads sleep on the locks they wait for, and are woken up in turn.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    hlt_loop,
+    sync::{Condvar, IrqSafeSpinlock, Mutex, Semaphore},
+    testing, thread,
+};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+static COUNTER: Mutex<usize> = Mutex::new(0);
+
+fn increment() {
+    for _ in 0..1000 {
+        let mut counter = COUNTER.lock();
+        let value = *counter;
+        // Lets the other threads run while the lock is held
+        thread::yield_now();
+        *counter = value + 1;
+    }
+}
+
+#[test_case]
+fn mutex_contention() {
+    let first = thread::spawn("first", increment).unwrap();
+    let second = thread::spawn("second", increment).unwrap();
+    let third = thread::spawn("third", increment).unwrap();
+
+    first.join().unwrap();
+    second.join().unwrap();
+    third.join().unwrap();
+    assert_eq!(*COUNTER.lock(), 3000);
+}
+
+static ITEMS: Semaphore = Semaphore::new(0);
+static CONSUMED: AtomicUsize = AtomicUsize::new(0);
+
+fn consume() {
+    for _ in 0..10 {
+        ITEMS.acquire();
+        CONSUMED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn semaphore_handoff() {
+    let consumer = thread::spawn("consumer", consume).unwrap();
+
+    for produced in 1..=10 {
+        ITEMS.release();
+        // The consumer sleeps until there is an item, then takes it
+        while CONSUMED.load(Ordering::SeqCst) < produced {
+            thread::yield_now();
+        }
+    }
+
+    consumer.join().unwrap();
+    assert_eq!(ITEMS.available_permits(), 0);
+}
+
+static READY: Mutex<bool> = Mutex::new(false);
+static READY_CHANGED: Condvar = Condvar::new();
+static WAITED: AtomicUsize = AtomicUsize::new(0);
+
+fn wait_ready() {
+    let ready = READY_CHANGED.wait_while(READY.lock(), |ready| !*ready);
+    assert!(*ready);
+    WAITED.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn condvar_broadcast() {
+    let first = thread::spawn("first", wait_ready).unwrap();
+    let second = thread::spawn("second", wait_ready).unwrap();
+
+    // Both are asleep before the condition changes
+    thread::yield_now();
+    assert_eq!(WAITED.load(Ordering::SeqCst), 0);
+
+    *READY.lock() = true;
+    READY_CHANGED.notify_all();
+
+    first.join().unwrap();
+    second.join().unwrap();
+    assert_eq!(WAITED.load(Ordering::SeqCst), 2);
+}
+
+#[test_case]
+fn spinlock_disables_interrupts() {
+    use x86_64::instructions::interrupts;
+
+    let lock = IrqSafeSpinlock::new(());
+    assert!(interrupts::are_enabled());
+
+    {
+        let _guard = lock.lock();
+        assert!(!interrupts::are_enabled());
+    }
+    assert!(interrupts::are_enabled());
+}
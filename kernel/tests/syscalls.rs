@@ -0,0 +1,221 @@
+//! User programs ask the kernel for services with system calls.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{panic::PanicInfo, ptr, slice, time::Duration};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    hlt_loop,
+    mem::{self, AddressSpace},
+    syscall::{self, Errno},
+    testing, thread, time, uart, user,
+};
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Where the programs of the tests are loaded, followed by a page for their
+/// results and stack.
+const CALLER: u64 = 0x4000_0000;
+const READER: u64 = 0x4001_0000;
+
+/// Makes calls, storing their results from `[rsp - 0x1000]` on, then exits.
+#[rustfmt::skip]
+const CALLER_PROGRAM: [u8; 119] = [
+    0x48, 0x8d, 0x9c, 0x24, 0x00, 0xf0, 0xff, 0xff, // lea rbx, [rsp - 0x1000]
+    0x48, 0x8d, 0x35, 0x62, 0x00, 0x00, 0x00,       // lea rsi, [rip + message]
+    0xbf, 0x01, 0x00, 0x00, 0x00,                   // mov edi, 1
+    0xba, 0x06, 0x00, 0x00, 0x00,                   // mov edx, 6
+    0xb8, 0x01, 0x00, 0x00, 0x00,                   // mov eax, WRITE
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x03,                               // mov [rbx], rax
+    0xb8, 0x06, 0x00, 0x00, 0x00,                   // mov eax, GETPID
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x43, 0x08,                         // mov [rbx + 8], rax
+    0xb8, 0x06, 0x00, 0x00, 0x00,                   // mov eax, GETPID
+    0xcd, 0x80,                                     // int 0x80
+    0x48, 0x89, 0x43, 0x10,                         // mov [rbx + 16], rax
+    0x48, 0xbe, 0x00, 0x00, 0x00, 0x00,             // mov rsi, 0xffff800000000000
+    0x00, 0x80, 0xff, 0xff,
+    0xbf, 0x01, 0x00, 0x00, 0x00,                   // mov edi, 1
+    0xba, 0x01, 0x00, 0x00, 0x00,                   // mov edx, 1
+    0xb8, 0x01, 0x00, 0x00, 0x00,                   // mov eax, WRITE
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x43, 0x18,                         // mov [rbx + 24], rax
+    0xb8, 0xe8, 0x03, 0x00, 0x00,                   // mov eax, 1000
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x43, 0x20,                         // mov [rbx + 32], rax
+    0xbf, 0x03, 0x00, 0x00, 0x00,                   // mov edi, 3
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+    0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x0a,             // message: "hello\n"
+];
+
+/// Reads, maps pages, sleeps and yields, storing the results from
+/// `[rsp - 0x1000]` on, then exits.
+#[rustfmt::skip]
+const READER_PROGRAM: [u8; 94] = [
+    0x48, 0x8d, 0x9c, 0x24, 0x00, 0xf0, 0xff, 0xff, // lea rbx, [rsp - 0x1000]
+    0x31, 0xff,                                     // xor edi, edi
+    0x48, 0x8d, 0x73, 0x40,                         // lea rsi, [rbx + 64]
+    0xba, 0x10, 0x00, 0x00, 0x00,                   // mov edx, 16
+    0x31, 0xc0,                                     // xor eax, eax (READ)
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x03,                               // mov [rbx], rax
+    0x31, 0xff,                                     // xor edi, edi
+    0xbe, 0x00, 0x20, 0x00, 0x00,                   // mov esi, 0x2000
+    0xba, 0x03, 0x00, 0x00, 0x00,                   // mov edx, PROT_READ | PROT_WRITE
+    0xb8, 0x05, 0x00, 0x00, 0x00,                   // mov eax, MMAP
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x43, 0x08,                         // mov [rbx + 8], rax
+    0x48, 0xc7, 0x80, 0x00, 0x10, 0x00, 0x00,       // mov qword ptr [rax + 0x1000], 42
+    0x2a, 0x00, 0x00, 0x00,
+    0xbf, 0x1e, 0x00, 0x00, 0x00,                   // mov edi, 30
+    0xb8, 0x04, 0x00, 0x00, 0x00,                   // mov eax, SLEEP
+    0x0f, 0x05,                                     // syscall
+    0xb8, 0x03, 0x00, 0x00, 0x00,                   // mov eax, YIELD
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x89, 0x43, 0x10,                         // mov [rbx + 16], rax
+    0x31, 0xff,                                     // xor edi, edi
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+];
+
+/// Maps a page with `program` at `base`, and a page for its results and stack
+/// after it.
+fn load(base: u64, program: &[u8]) {
+    let code = Page::containing_address(VirtAddr::new(base));
+    mem::map_user_page(code, PageTableFlags::WRITABLE).unwrap();
+    mem::map_user_page(code + 1, PageTableFlags::WRITABLE).unwrap();
+
+    unsafe { ptr::copy_nonoverlapping(program.as_ptr(), base as *mut u8, program.len()) };
+}
+
+/// Results stored by the program at `base`.
+fn results(base: u64) -> &'static [u64] {
+    unsafe { slice::from_raw_parts((base + mem::FRAME_SIZE) as *const u64, 8) }
+}
+
+fn enter(base: u64) -> ! {
+    let stack_top = VirtAddr::new(base + 2 * mem::FRAME_SIZE);
+    unsafe { user::enter_user_mode(VirtAddr::new(base), stack_top) }
+}
+
+fn run_caller() {
+    enter(CALLER);
+}
+
+fn run_reader() {
+    enter(READER);
+}
+
+#[test_case]
+fn calls_and_errors() {
+    load(CALLER, &CALLER_PROGRAM);
+
+    let caller = thread::spawn("caller", run_caller).unwrap();
+    let id = caller.id().as_u64();
+    caller.join().unwrap();
+
+    let results = results(CALLER);
+    // write, getpid through both entry points, write from kernel memory, and
+    // an unknown call
+    assert_eq!(results[0], 6);
+    assert_eq!(results[1], id);
+    assert_eq!(results[2], id);
+    assert_eq!(results[3], Errno::EFAULT.raw());
+    assert_eq!(results[4], Errno::ENOSYS.raw());
+    // Exited through the call, not by faulting
+    assert!(user::last_fault().is_none());
+}
+
+#[test_case]
+fn reading_mapping_and_sleeping() {
+    load(READER, &READER_PROGRAM);
+    for &byte in b"abc" {
+        uart::rx::add_byte(byte);
+    }
+
+    let start = time::uptime();
+    let reader = thread::spawn("reader", run_reader).unwrap();
+    reader.join().unwrap();
+    assert!(time::uptime() - start >= Duration::from_millis(30));
+
+    let results = results(READER);
+    assert_eq!(results[0], 3);
+    let input = unsafe { slice::from_raw_parts((READER + mem::FRAME_SIZE + 64) as *const u8, 3) };
+    assert_eq!(input, b"abc");
+
+    let mapped = results[1];
+    assert_eq!(mapped % mem::FRAME_SIZE, 0);
+    assert_eq!(unsafe { *((mapped + mem::FRAME_SIZE) as *const u64) }, 42);
+    assert_eq!(results[2], 0);
+
+    let flags = mem::user_flags(VirtAddr::new(mapped)).unwrap();
+    assert!(flags.contains(PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn dispatch_from_kernel() {
+    assert_eq!(
+        syscall::dispatch(syscall::GETPID, &[0; 6]),
+        thread::current().as_u64()
+    );
+    assert_eq!(syscall::dispatch(syscall::YIELD, &[0; 6]), 0);
+    assert_eq!(
+        syscall::dispatch(syscall::WRITE, &[0; 6]),
+        Errno::EBADF.raw()
+    );
+}
+
+#[test_case]
+fn mapping_from_kernel() {
+    let mmap = |address: u64, len: u64| {
+        syscall::dispatch(syscall::MMAP, &[address, len, syscall::PROT_READ, 0, 0, 0])
+    };
+
+    // Past the end of the user addresses, nothing is reserved.
+    assert_eq!(mmap(0, user::USER_END), Errno::ENOMEM.raw());
+    let first = mmap(0, 1);
+    let second = mmap(0, 1);
+    assert_eq!(second, first + mem::FRAME_SIZE);
+
+    // Address spaces created after it still map pages of their own.
+    let mut space = AddressSpace::new().unwrap();
+    let addr = space.reserve_user(mem::FRAME_SIZE).unwrap();
+    space
+        .map_user_page(Page::containing_address(addr), PageTableFlags::empty())
+        .unwrap();
+    assert!(space.translate(addr).is_some());
+    assert!(mem::translate(addr).is_none());
+
+    // The first page is unmapped again when the second one is taken.
+    let taken = Page::containing_address(VirtAddr::new(0x4100_1000));
+    mem::map_user_page(taken, PageTableFlags::empty()).unwrap();
+    assert_eq!(mmap(0x4100_0000, 2 * mem::FRAME_SIZE), Errno::ENOMEM.raw());
+    assert!(mem::user_flags(VirtAddr::new(0x4100_0000)).is_none());
+    assert!(mem::user_flags(taken.start_address()).is_some());
+}
@@ -0,0 +1,73 @@
+//! Async tasks sleep on the timer interrupt, together.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bootloader::{entry_point, BootInfo};
+use futures_util::pin_mut;
+use kernel::{
+    hlt_loop,
+    task::{self, Executor},
+    testing, time,
+};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+#[test_case]
+fn sleeping() {
+    let nap = task::sleep(Duration::from_millis(50));
+    pin_mut!(nap);
+
+    let start = time::uptime();
+    let mut executor = Executor::new().unwrap();
+    executor.spawn(nap).unwrap();
+    executor.run();
+
+    // One tick of precision
+    assert!(time::uptime() - start >= Duration::from_millis(40));
+}
+
+/// Order in which the sleepers of `concurrent_sleeps` woke up.
+static WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+async fn sleeper(millis: u64, digit: usize) {
+    task::sleep(Duration::from_millis(millis)).await;
+    let woken = WOKEN.load(Ordering::SeqCst);
+    WOKEN.store(woken * 10 + digit, Ordering::SeqCst);
+}
+
+#[test_case]
+fn concurrent_sleeps() {
+    let (long, short) = (sleeper(100, 1), sleeper(50, 2));
+    pin_mut!(long, short);
+
+    let start = time::uptime();
+    let mut executor = Executor::new().unwrap();
+    executor.spawn(long).unwrap();
+    executor.spawn(short).unwrap();
+    executor.run();
+
+    // Both slept at the same time, the short one woke up first
+    assert!(time::uptime() - start < Duration::from_millis(150));
+    assert_eq!(WOKEN.load(Ordering::SeqCst), 21);
+}
@@ -0,0 +1,127 @@
+//! Kernel threads run concurrently, preempted by the timer interrupt.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    hlt_loop, testing,
+    thread::{self, MAX_THREADS},
+};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn count() {
+    COUNTER.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn spawn_and_join() {
+    COUNTER.store(0, Ordering::SeqCst);
+    let first = thread::spawn("first", count).unwrap();
+    let second = thread::spawn("second", count).unwrap();
+    assert_ne!(first.id(), second.id());
+
+    first.join().unwrap();
+    second.join().unwrap();
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 2);
+}
+
+/// Turns taken by the threads of `yielding`, 1 bit per turn.
+static TURNS: AtomicUsize = AtomicUsize::new(0);
+static TURN: AtomicUsize = AtomicUsize::new(0);
+
+fn take_turns(bit: usize) {
+    for _ in 0..4 {
+        let turn = TURN.fetch_add(1, Ordering::SeqCst);
+        TURNS.fetch_or(bit << turn, Ordering::SeqCst);
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn yielding() {
+    TURNS.store(0, Ordering::SeqCst);
+    TURN.store(0, Ordering::SeqCst);
+    let ones = thread::spawn("ones", || take_turns(1)).unwrap();
+    let zeros = thread::spawn("zeros", || take_turns(0)).unwrap();
+    ones.join().unwrap();
+    zeros.join().unwrap();
+
+    // Each yield hands the CPU to the other thread, the first one does not
+    // take all its turns at once
+    assert_eq!(TURN.load(Ordering::SeqCst), 8);
+    assert_eq!(TURNS.load(Ordering::SeqCst).count_ones(), 4);
+    assert_ne!(TURNS.load(Ordering::SeqCst), 0b0000_1111);
+}
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn spin() {
+    STARTED.store(true, Ordering::SeqCst);
+    while !STOP.load(Ordering::SeqCst) {}
+}
+
+#[test_case]
+fn preemption() {
+    let spinner = thread::spawn("spinner", spin).unwrap();
+
+    // Neither thread yields, only the timer lets the other one run
+    while !STARTED.load(Ordering::SeqCst) {}
+    STOP.store(true, Ordering::SeqCst);
+    spinner.join().unwrap();
+}
+
+#[test_case]
+fn early_exit() {
+    COUNTER.store(0, Ordering::SeqCst);
+    let exiting = thread::spawn("exiting", || {
+        thread::exit();
+        #[allow(unreachable_code)]
+        count();
+    })
+    .unwrap();
+
+    exiting.join().unwrap();
+    assert_eq!(COUNTER.load(Ordering::SeqCst), 0);
+}
+
+#[test_case]
+fn too_many_threads() {
+    STOP.store(false, Ordering::SeqCst);
+
+    // The main and idle threads take 2 slots
+    for _ in 0..MAX_THREADS - 2 {
+        drop(thread::spawn("detached", spin).unwrap());
+    }
+    assert!(thread::spawn("one too many", spin).is_err());
+
+    // Slots of detached threads are free again once they exited
+    STOP.store(true, Ordering::SeqCst);
+    while thread::spawn("reused", count).is_err() {
+        thread::yield_now();
+    }
+}
@@ -0,0 +1,96 @@
+//! User programs run in ring 3, and their faults only end their own thread.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{hlt_loop, mem, testing, thread, user};
+use x86_64::{
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Where the programs of the tests are loaded, followed by their stack.
+const READER: u64 = 0x4000_0000;
+const HALTER: u64 = 0x4001_0000;
+
+/// Kernel data, out of reach of user mode.
+static SECRET: u64 = 0x5ec2e7;
+
+/// Maps a page with `program` at `base`, and a stack page after it.
+fn load(base: u64, program: &[u8]) {
+    let code = Page::containing_address(VirtAddr::new(base));
+    mem::map_user_page(code, PageTableFlags::WRITABLE).unwrap();
+    mem::map_user_page(code + 1, PageTableFlags::WRITABLE).unwrap();
+
+    unsafe { ptr::copy_nonoverlapping(program.as_ptr(), base as *mut u8, program.len()) };
+}
+
+fn enter(base: u64) -> ! {
+    let stack_top = VirtAddr::new(base + 2 * mem::FRAME_SIZE);
+    unsafe { user::enter_user_mode(VirtAddr::new(base), stack_top) }
+}
+
+fn read_kernel_memory() {
+    enter(READER);
+}
+
+fn halt() {
+    enter(HALTER);
+}
+
+#[test_case]
+fn fault_on_kernel_memory() {
+    let secret = &SECRET as *const u64 as u64;
+    // mov rax, secret; mov rax, [rax]; jmp $
+    let mut program = [
+        0x48, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0x48, 0x8b, 0x00, 0xeb, 0xfe,
+    ];
+    program[2..10].copy_from_slice(&secret.to_le_bytes());
+    load(READER, &program);
+
+    let reader = thread::spawn("reader", read_kernel_memory).unwrap();
+    let id = reader.id();
+    reader.join().unwrap();
+
+    let fault = user::last_fault().unwrap();
+    assert_eq!(fault.exception, "PAGE FAULT");
+    assert_eq!(fault.instruction, VirtAddr::new(READER + 10));
+    assert_eq!(fault.address, Some(VirtAddr::new(secret)));
+    assert_eq!(fault.thread, id);
+}
+
+#[test_case]
+fn privileged_instruction() {
+    // hlt
+    load(HALTER, &[0xf4]);
+
+    let halter = thread::spawn("halter", halt).unwrap();
+    let id = halter.id();
+    halter.join().unwrap();
+
+    let fault = user::last_fault().unwrap();
+    assert_eq!(fault.exception, "GENERAL PROTECTION FAULT");
+    assert_eq!(fault.instruction, VirtAddr::new(HALTER));
+    assert_eq!(fault.thread, id);
+}
@@ -0,0 +1,443 @@
+//! Files are reached by path through the mounted file systems, and used
+//! through descriptors.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    elf::{ProgramHeader, PF_R, PF_X, PT_LOAD},
+    fs::{
+        self, console, DirEntry, FileSystem, FileTable, FileType, Inode, SeekFrom, Stat, MAX_FILES,
+        O_APPEND, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY,
+    },
+    hlt_loop, mem,
+    process::{self, ExitStatus},
+    sync::Mutex,
+    syscall::Errno,
+    testing, thread,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    thread::init().unwrap();
+    unsafe { mem::init(boot_info) }.unwrap();
+    fs::mount("/", Arc::new(TestFs::new())).unwrap();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+/// Numbers of the inodes of every `TestFs`.
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+/// A file system in memory, just enough for the tests.
+struct TestFs {
+    root: Arc<Node>,
+}
+
+impl TestFs {
+    fn new() -> TestFs {
+        TestFs {
+            root: Node::new(FileType::Directory, &[]),
+        }
+    }
+}
+
+impl FileSystem for TestFs {
+    fn name(&self) -> &str {
+        "testfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct Node {
+    ino: u64,
+    kind: FileType,
+    /// Bytes of a file, target of a link.
+    data: Mutex<Vec<u8>>,
+    entries: Mutex<Vec<(String, Arc<Node>)>>,
+}
+
+impl Node {
+    fn new(kind: FileType, data: &[u8]) -> Arc<Node> {
+        Arc::new(Node {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            kind,
+            data: Mutex::new(data.to_vec()),
+            entries: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn add(&self, name: &str, node: Arc<Node>) -> Result<Arc<dyn Inode>, Errno> {
+        self.check_directory()?;
+        let mut entries = self.entries.lock();
+        if entries.iter().any(|(other, _)| other == name) {
+            return Err(Errno::EEXIST);
+        }
+        entries.push((name.to_string(), node.clone()));
+        Ok(node)
+    }
+
+    fn check_directory(&self) -> Result<(), Errno> {
+        match self.kind {
+            FileType::Directory => Ok(()),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+}
+
+impl Inode for Node {
+    fn stat(&self) -> Stat {
+        let size = match self.kind {
+            FileType::Directory => self.entries.lock().len(),
+            _ => self.data.lock().len(),
+        };
+        Stat {
+            ino: self.ino,
+            size: size as u64,
+            kind: self.kind,
+            nlink: 1,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        let data = self.data.lock();
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..][..len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> Result<usize, Errno> {
+        let mut data = self.data.lock();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), Errno> {
+        self.data.lock().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.check_directory()?;
+        let entries = self.entries.lock();
+        match entries.iter().find(|(other, _)| other == name) {
+            Some((_, node)) => Ok(node.clone()),
+            None => Err(Errno::ENOENT),
+        }
+    }
+
+    fn create(&self, name: &str, kind: FileType) -> Result<Arc<dyn Inode>, Errno> {
+        self.add(name, Node::new(kind, &[]))
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>, Errno> {
+        self.add(name, Node::new(FileType::Symlink, target.as_bytes()))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        self.check_directory()?;
+        let mut entries = self.entries.lock();
+        let index = entries
+            .iter()
+            .position(|(other, _)| other == name)
+            .ok_or(Errno::ENOENT)?;
+        if !entries[index].1.entries.lock().is_empty() {
+            return Err(Errno::ENOTEMPTY);
+        }
+        entries.remove(index);
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> Result<Option<DirEntry>, Errno> {
+        self.check_directory()?;
+        Ok(self.entries.lock().get(index).map(|(name, node)| DirEntry {
+            ino: node.ino,
+            kind: node.kind,
+            name: name.clone(),
+        }))
+    }
+
+    fn readlink(&self) -> Result<String, Errno> {
+        match self.kind {
+            FileType::Symlink => Ok(String::from_utf8(self.data.lock().clone()).unwrap()),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
+
+/// Where the program is linked.
+const TEXT: u64 = 0x4000_0000_0000;
+
+/// Offset of the code in the executable.
+const CODE_OFFSET: usize = 0xc0;
+
+/// Opens `/motd`, reads it, then closes it twice. Exits with 16 times its
+/// descriptor plus the bytes read if the second close fails with `EBADF`.
+#[rustfmt::skip]
+const MOTD_READER: [u8; 102] = [
+    0x48, 0x8d, 0x3d, 0x59, 0x00, 0x00, 0x00,       // lea rdi, [rip + path]
+    0x31, 0xf6,                                     // xor esi, esi
+    0xb8, 0x0f, 0x00, 0x00, 0x00,                   // mov eax, OPEN
+    0x0f, 0x05,                                     // syscall
+    0x49, 0x89, 0xc4,                               // mov r12, rax
+    0x4c, 0x89, 0xe7,                               // mov rdi, r12
+    0x48, 0x8d, 0x74, 0x24, 0xc0,                   // lea rsi, [rsp - 64]
+    0xba, 0x40, 0x00, 0x00, 0x00,                   // mov edx, 64
+    0x31, 0xc0,                                     // xor eax, eax
+    0x0f, 0x05,                                     // syscall
+    0x49, 0x89, 0xc5,                               // mov r13, rax
+    0x4c, 0x89, 0xe7,                               // mov rdi, r12
+    0xb8, 0x10, 0x00, 0x00, 0x00,                   // mov eax, CLOSE
+    0x0f, 0x05,                                     // syscall
+    0x4c, 0x89, 0xe7,                               // mov rdi, r12
+    0xb8, 0x10, 0x00, 0x00, 0x00,                   // mov eax, CLOSE
+    0x0f, 0x05,                                     // syscall
+    0x48, 0x83, 0xf8, 0xf7,                         // cmp rax, -EBADF
+    0x75, 0x11,                                     // jne bad
+    0x4c, 0x89, 0xe7,                               // mov rdi, r12
+    0x48, 0xc1, 0xe7, 0x04,                         // shl rdi, 4
+    0x4c, 0x01, 0xef,                               // add rdi, r13
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0xbf, 0xff, 0x00, 0x00, 0x00,                   // bad: mov edi, 255
+    0xb8, 0x02, 0x00, 0x00, 0x00,                   // mov eax, EXIT
+    0x0f, 0x05,                                     // syscall
+    0x0f, 0x0b,                                     // ud2
+    b'/', b'm', b'o', b't', b'd', 0,                // path: "/motd"
+];
+
+/// Builds an executable running `code`.
+fn executable(code: &[u8]) -> [u8; 0x200] {
+    let mut file = [0; 0x200];
+    let text = ProgramHeader {
+        kind: PT_LOAD,
+        flags: PF_R | PF_X,
+        offset: 0,
+        vaddr: TEXT,
+        file_size: 0x200,
+        mem_size: 0x200,
+        align: 0x1000,
+    };
+    testing::elf::build(&mut file, TEXT + CODE_OFFSET as u64, &[text]);
+
+    file[CODE_OFFSET..][..code.len()].copy_from_slice(code);
+    file
+}
+
+/// Reads the whole file at `path`.
+fn contents(path: &str) -> Vec<u8> {
+    let file = fs::open(path, O_RDONLY).unwrap();
+    let mut buf = [0; 64];
+    let read = file.read(&mut buf).unwrap();
+    buf[..read].to_vec()
+}
+
+#[test_case]
+fn mount_points() {
+    fs::mkdir("/mnt").unwrap();
+    fs::mkdir("/mnt/inner").unwrap();
+    fs::open("/mnt/inner/covered", O_CREAT).unwrap();
+    fs::mount("/mnt/./inner/", Arc::new(TestFs::new())).unwrap();
+
+    // The mounted root covers the directory.
+    assert_eq!(fs::stat("/mnt/inner/covered"), Err(Errno::ENOENT));
+    let file = fs::open("/mnt/inner/file", O_WRONLY | O_CREAT).unwrap();
+    assert_eq!(file.write(b"mounted"), Ok(7));
+    assert_eq!(contents("/mnt/../mnt/inner/../inner/file"), b"mounted");
+
+    let dentry = fs::resolve("/mnt/inner/..", true).unwrap();
+    assert_eq!(dentry.path(), "/mnt");
+    assert_eq!(fs::resolve("/..", true).unwrap().path(), "/");
+    let inner = fs::resolve("mnt//inner", true).unwrap();
+    assert_eq!((inner.path(), inner.name()), ("/mnt/inner", "inner"));
+    assert!(inner.is_mount_point());
+
+    assert_eq!(
+        fs::mount("/mnt/inner", Arc::new(TestFs::new())).err(),
+        Some(Errno::EBUSY)
+    );
+    assert_eq!(
+        fs::mount("/mnt/inner/file", Arc::new(TestFs::new())).err(),
+        Some(Errno::ENOTDIR)
+    );
+    assert_eq!(fs::rmdir("/mnt/inner"), Err(Errno::EBUSY));
+}
+
+#[test_case]
+fn reading_writing_and_seeking() {
+    let file = fs::open("/notes", O_RDWR | O_CREAT | O_EXCL).unwrap();
+    assert_eq!(file.write(b"hello world"), Ok(11));
+    assert_eq!(file.seek(SeekFrom::Start(6)), Ok(6));
+    let mut buf = [0; 16];
+    assert_eq!(file.read(&mut buf), Ok(5));
+    assert_eq!(&buf[..5], b"world");
+    assert_eq!(file.read(&mut buf), Ok(0));
+
+    assert_eq!(file.seek(SeekFrom::End(-5)), Ok(6));
+    assert_eq!(file.seek(SeekFrom::Current(-100)), Err(Errno::EINVAL));
+    assert_eq!(file.write(b"there"), Ok(5));
+    assert_eq!(contents("/notes"), b"hello there");
+    let stat = file.stat().unwrap();
+    assert_eq!((stat.kind, stat.size), (FileType::Regular, 11));
+
+    let appender = fs::open("/notes", O_WRONLY | O_APPEND).unwrap();
+    assert_eq!(appender.write(b"!"), Ok(1));
+    assert_eq!(contents("/notes"), b"hello there!");
+    assert_eq!(appender.read(&mut buf), Err(Errno::EBADF));
+    assert_eq!(
+        fs::open("/notes", O_RDONLY).unwrap().write(b"x"),
+        Err(Errno::EBADF)
+    );
+
+    fs::open("/notes", O_WRONLY | O_TRUNC).unwrap();
+    assert_eq!(fs::stat("/notes").unwrap().size, 0);
+
+    assert_eq!(
+        fs::open("/notes", O_CREAT | O_EXCL).err(),
+        Some(Errno::EEXIST)
+    );
+    assert_eq!(fs::open("/missing", O_RDONLY).err(), Some(Errno::ENOENT));
+    assert_eq!(fs::open("/notes/x", O_CREAT).err(), Some(Errno::ENOTDIR));
+    assert_eq!(fs::open("/notes", O_DIRECTORY).err(), Some(Errno::ENOTDIR));
+    assert_eq!(fs::open("/", O_RDWR).err(), Some(Errno::EISDIR));
+    assert_eq!(
+        fs::open("/", O_RDONLY).unwrap().read(&mut buf),
+        Err(Errno::EISDIR)
+    );
+}
+
+#[test_case]
+fn directories() {
+    fs::mkdir("/dir").unwrap();
+    fs::mkdir("/dir/sub").unwrap();
+    fs::open("/dir/file", O_CREAT).unwrap();
+    assert_eq!(fs::mkdir("/dir/sub"), Err(Errno::EEXIST));
+    assert_eq!(fs::mkdir("/dir/none/sub"), Err(Errno::ENOENT));
+
+    let dir = fs::open("/dir", O_RDONLY | O_DIRECTORY).unwrap();
+    let mut names = Vec::new();
+    while let Some(entry) = dir.readdir().unwrap() {
+        names.push((entry.name, entry.kind));
+    }
+    assert_eq!(
+        names,
+        [
+            ("sub".to_string(), FileType::Directory),
+            ("file".to_string(), FileType::Regular)
+        ]
+    );
+    assert_eq!(dir.seek(SeekFrom::Start(1)), Ok(1));
+    assert_eq!(dir.readdir().unwrap().unwrap().name, "file");
+    assert_eq!(
+        fs::open("/dir/file", O_RDONLY).unwrap().readdir(),
+        Err(Errno::ENOTDIR)
+    );
+
+    fs::open("/dir/sub/deep", O_CREAT).unwrap();
+    assert_eq!(fs::unlink("/dir/sub"), Err(Errno::EISDIR));
+    assert_eq!(fs::rmdir("/dir/file"), Err(Errno::ENOTDIR));
+    assert_eq!(fs::rmdir("/dir/sub"), Err(Errno::ENOTEMPTY));
+    fs::unlink("/dir/sub/deep").unwrap();
+    fs::rmdir("/dir/sub").unwrap();
+    fs::unlink("/dir/file").unwrap();
+    assert_eq!(fs::stat("/dir").unwrap().size, 0);
+    assert_eq!(fs::unlink("/dir/file"), Err(Errno::ENOENT));
+    assert_eq!(fs::rmdir("/dir/.."), Err(Errno::EINVAL));
+}
+
+#[test_case]
+fn symbolic_links() {
+    fs::mkdir("/links").unwrap();
+    fs::mkdir("/links/target").unwrap();
+    fs::open("/links/target/file", O_CREAT).unwrap();
+    fs::symlink("/links/target", "/links/absolute").unwrap();
+    fs::symlink("target/file", "/links/relative").unwrap();
+
+    assert_eq!(fs::readlink("/links/absolute").unwrap(), "/links/target");
+    assert_eq!(
+        fs::lstat("/links/absolute").unwrap().kind,
+        FileType::Symlink
+    );
+    assert_eq!(
+        fs::stat("/links/absolute").unwrap().kind,
+        FileType::Directory
+    );
+    assert_eq!(
+        fs::stat("/links/absolute/file").unwrap().kind,
+        FileType::Regular
+    );
+    assert_eq!(fs::stat("/links/relative").unwrap().kind, FileType::Regular);
+    let dentry = fs::resolve("/links/absolute/../relative", true).unwrap();
+    assert_eq!(dentry.path(), "/links/target/file");
+
+    fs::symlink("/links/loop", "/links/loop").unwrap();
+    assert_eq!(fs::stat("/links/loop"), Err(Errno::ELOOP));
+    assert_eq!(fs::readlink("/links/target"), Err(Errno::EINVAL));
+    fs::unlink("/links/absolute").unwrap();
+    assert_eq!(fs::stat("/links/target").unwrap().kind, FileType::Directory);
+}
+
+#[test_case]
+fn file_descriptors() {
+    let mut files = FileTable::with_console();
+    assert!(files.get(0).unwrap().readable());
+    assert!(!files.get(1).unwrap().readable());
+    assert_eq!(files.get(3).err(), Some(Errno::EBADF));
+    assert_eq!(console::standard(3).err(), Some(Errno::EBADF));
+
+    let file = fs::open("/fds", O_RDWR | O_CREAT).unwrap();
+    assert_eq!(files.insert(file.clone()), Ok(3));
+    assert_eq!(files.insert(file.clone()), Ok(4));
+    files.remove(3).unwrap();
+    assert_eq!(files.remove(3).err(), Some(Errno::EBADF));
+    assert_eq!(files.insert(file.clone()), Ok(3));
+
+    // Descriptors of the same open file share its offset.
+    files.get(3).unwrap().write(b"abc").unwrap();
+    assert_eq!(files.get(4).unwrap().seek(SeekFrom::Current(0)), Ok(3));
+
+    for _ in 5..MAX_FILES {
+        files.insert(file.clone()).unwrap();
+    }
+    assert_eq!(files.insert(file).err(), Some(Errno::EMFILE));
+}
+
+#[test_case]
+fn system_calls() {
+    let motd = fs::open("/motd", O_WRONLY | O_CREAT).unwrap();
+    motd.write(b"hello").unwrap();
+
+    let pid = process::spawn("motd", &executable(&MOTD_READER), &[], &[]).unwrap();
+    assert_eq!(process::wait(pid), Ok(ExitStatus::Exited(3 * 16 + 5)));
+}
@@ -0,0 +1,90 @@
+//! What is printed ends up in the VGA text buffer, at `0xb8000`.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::{fmt::Write, panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use kernel::{
+    hlt_loop,
+    init::vga::VGA,
+    prelude::*,
+    testing,
+    vga::{COLS, ROWS},
+};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    testing::init();
+    test_main();
+    hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::test_panic_handler(info)
+}
+
+const BUFFER: *const u8 = 0xb8000 as *const u8;
+
+/// Character and attribute at a row and column of the screen.
+fn read_cell(row: usize, col: usize) -> (u8, u8) {
+    let cell = unsafe { BUFFER.add((row * COLS + col) * 2) };
+    unsafe { (ptr::read_volatile(cell), ptr::read_volatile(cell.add(1))) }
+}
+
+/// Returns `true` if the row of the screen starts with `text`.
+fn row_starts_with(row: usize, text: &str) -> bool {
+    text.bytes()
+        .enumerate()
+        .all(|(col, byte)| read_cell(row, col).0 == byte)
+}
+
+#[test_case]
+fn println_many() {
+    for i in 0..100 {
+        kprintln!("line {}", i);
+    }
+
+    // The cursor is at the start of the last row, the text scrolled above it.
+    assert!(row_starts_with(ROWS - 2, "line 99"));
+    assert!(row_starts_with(ROWS - 3, "line 98"));
+    assert!(row_starts_with(ROWS - 1, "       "));
+}
+
+#[test_case]
+fn long_line_wraps() {
+    // Starts on a new row, after the name of the test.
+    kprintln!("");
+    let line = [b'x'; COLS + 10];
+    kprintln!("{}", core::str::from_utf8(&line).unwrap());
+
+    assert!(row_starts_with(
+        ROWS - 3,
+        core::str::from_utf8(&line[..COLS]).unwrap()
+    ));
+    assert!(row_starts_with(ROWS - 2, "xxxxxxxxxx "));
+}
+
+#[test_case]
+fn colors() {
+    interrupts::without_interrupts(|| {
+        let mut vga = VGA.lock();
+        vga.set_foreground(Color::Yellow);
+        vga.set_background(Color::Blue);
+        writeln!(vga, "\ncolored").unwrap();
+        vga.set_foreground(Color::White);
+        vga.set_background(Color::Black);
+        vga.flush();
+    });
+
+    let (character, attribute) = read_cell(ROWS - 2, 0);
+    assert_eq!(character, b'c');
+    assert_eq!(attribute, 0x1E);
+}